@@ -1,14 +1,68 @@
 use clap::{Parser, Subcommand};
 use colored::Colorize;
-use smbuilder::prelude::{callback_types::LogType, *};
-use std::{fmt::format, path::PathBuf, thread};
+use smbuilder::error::ErrorKind;
+use smbuilder::prelude::{
+    builder_types::{BuildOptions, BuilderResult, Verbosity},
+    callback_types::LogType,
+    *,
+};
+use smbuilder::workspace::{BuildStatus, Workspace};
+use std::{path::PathBuf, thread};
 
 use smbuilder_cli::get_builder;
 
 #[derive(Clone, Subcommand)]
 enum Action {
-    Build { spec_path: PathBuf },
-    Run { spec_path: PathBuf },
+    Build {
+        spec_path: PathBuf,
+        /// Rebuild even if the executable already exists.
+        #[arg(short, long)]
+        force: bool,
+        /// Build one of the named configurations of the spec.
+        #[arg(short, long)]
+        config: Option<String>,
+        /// Only print errors and warnings.
+        #[arg(short, long, conflicts_with = "verbose")]
+        quiet: bool,
+        /// Also print the commands that are run.
+        #[arg(short, long)]
+        verbose: bool,
+        /// Show the build in a terminal UI.
+        #[cfg(feature = "tui")]
+        #[arg(long)]
+        tui: bool,
+    },
+    Run {
+        spec_path: PathBuf,
+    },
+    /// List the builds in a workspace.
+    List {
+        /// The workspace, defaulting to the builds directory.
+        root: Option<PathBuf>,
+    },
+    /// Run builds for launchers in the background.
+    #[cfg(all(unix, feature = "daemon"))]
+    Daemon {
+        /// The socket to listen on, defaulting to
+        /// `smbuilder.sock` in the runtime directory.
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+        /// Also serve over HTTP, on this address (like
+        /// `127.0.0.1:8964`). Addresses other than the
+        /// loopback one need a token.
+        #[cfg(feature = "http")]
+        #[arg(long)]
+        http: Option<String>,
+        /// Where builds submitted over HTTP go,
+        /// defaulting to the builds directory.
+        #[cfg(feature = "http")]
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// The token that HTTP clients must send.
+        #[cfg(feature = "http")]
+        #[arg(long)]
+        token: Option<String>,
+    },
 }
 
 #[derive(Parser)]
@@ -18,50 +72,90 @@ struct Args {
     subcommand: Action,
 }
 
-fn build(base_dir: PathBuf, callbacks: Callbacks<'static>) {
+fn build(
+    base_dir: PathBuf,
+    force: bool,
+    config: Option<String>,
+    verbosity: Verbosity,
+    callbacks: Callbacks<'static>,
+) -> BuilderResult<()> {
+    let options = BuildOptions::new()
+        .force(force)
+        .forward_stdin(true)
+        .verbosity(verbosity);
+    let mut builder = build_builder(base_dir, options, callbacks)?;
+
+    thread::spawn(move || build_with(&mut builder, config.as_deref()))
+        .join()
+        .unwrap()
+}
+
+/// Like `build`, but drawing
+/// the build in a terminal UI.
+#[cfg(feature = "tui")]
+fn build_tui(
+    base_dir: PathBuf,
+    force: bool,
+    config: Option<String>,
+    verbosity: Verbosity,
+) -> BuilderResult<()> {
+    let (callbacks, events) = channel_bridge();
+    // stdin belongs to the UI, so it is
+    // not forwarded to the build.
+    let options = BuildOptions::new().force(force).verbosity(verbosity);
+    let builder = build_builder(base_dir, options, callbacks)?;
+
+    smbuilder::tui::run(builder, events, move |builder| {
+        build_with(builder, config.as_deref())
+    })
+}
+
+fn build_builder(
+    base_dir: PathBuf,
+    options: BuildOptions,
+    callbacks: Callbacks<'static>,
+) -> BuilderResult<Builder<'static>> {
     if !base_dir.is_dir() {
         panic!("{} is not a directory! please enter the path to a directory with an `build.yaml` in the root of it.", base_dir.display());
     }
 
-    let mut builder = get_builder(base_dir.clone(), callbacks);
+    Ok(get_builder(base_dir, callbacks)?.with_options(options))
+}
 
-    thread::spawn(move || match builder.build() {
-        Ok(_) => (),
-        Err(e) => {
-            eprintln!("{}", e.to_string());
-            return;
-        }
-    })
-    .join()
-    .unwrap();
+fn build_with(builder: &mut Builder, config: Option<&str>) -> BuilderResult<()> {
+    match config {
+        Some(name) => builder.build_configuration(name).map(|_| ()),
+        None => builder.build().map(|_| ()),
+    }
 }
 
-fn run(base_dir: PathBuf, mut callbacks: Callbacks) {
+/// Print an error, and exit with the
+/// code of its kind, for wrapper scripts
+/// to branch on.
+fn fail(e: Error) -> ! {
+    eprintln!("{}", e);
+    std::process::exit(e.kind().code());
+}
+
+fn run(base_dir: PathBuf, callbacks: Callbacks) {
+    // the builder holds the base dir lock,
+    // so it is kept until the game exits.
+    let mut builder = get_builder(base_dir, callbacks).unwrap_or_else(|e| fail(e));
+    let path = builder.executable_path();
+
     macro_rules! error {
         ($text:expr) => {
-            if let Some(cb) = &mut callbacks.log_cb {
+            if let Some(cb) = &mut builder.callbacks.log_cb {
                 cb(LogType::Error, $text.as_str());
             };
-            std::process::exit(1);
+            std::process::exit(ErrorKind::Other.code());
         };
     }
 
-    let spec = Spec::from_file_checked(base_dir.join("build.yaml"), &mut callbacks);
-    let spec = match spec {
-        Ok(s) => s,
-        Err(e) => {
-            let msg = format!("failed to load the specfile: {}", e);
-            error!(msg);
-        }
-    };
-    let region = spec.rom.region.to_string();
-    let path = base_dir
-        .join(&spec.repo.name)
-        .join("build")
-        .join(format!("{}_pc", &region))
-        .join(format!("sm64.{}.f3dex2e", &region));
-
-    let mut cmd = std::process::Command::new(path);
+    let mut cmd = std::process::Command::new(&path);
+    if let Some(dir) = path.parent() {
+        cmd.current_dir(dir);
+    }
     let mut child = cmd.spawn().unwrap_or_else(|e| {
         let msg = format!("failed to spawn the command: {}", e);
         error!(msg);
@@ -72,21 +166,87 @@ fn run(base_dir: PathBuf, mut callbacks: Callbacks) {
     });
 }
 
+fn list(root: Option<PathBuf>) {
+    let Some(root) = root.or_else(smbuilder::paths::builds_dir) else {
+        eprintln!("could not find the builds directory! please pass the path to a workspace.");
+        std::process::exit(ErrorKind::Other.code());
+    };
+
+    let mut workspace = Workspace::open(&root).unwrap_or_else(|e| fail(e));
+    workspace.refresh();
+
+    if let Err(e) = workspace.save() {
+        eprintln!("{}", e);
+    }
+
+    for entry in workspace.entries() {
+        let status = match entry.status {
+            BuildStatus::NotBuilt => "not built".normal(),
+            BuildStatus::Built => "built".green(),
+            BuildStatus::Failed => "failed".red(),
+            BuildStatus::Interrupted => "interrupted".yellow(),
+        };
+
+        println!(
+            "{} [{}] {}",
+            entry.name.bold(),
+            status,
+            workspace.spec_path(entry).display()
+        );
+    }
+}
+
+#[cfg(all(unix, feature = "daemon"))]
+fn daemon(socket: Option<PathBuf>, daemon: smbuilder::daemon::Daemon) {
+    let Some(socket) = socket.or_else(smbuilder::daemon::default_socket_path) else {
+        eprintln!("could not find where to put the socket! please pass its path.");
+        std::process::exit(ErrorKind::Other.code());
+    };
+
+    println!("{}{}", "daemon: ".bold().green(), socket.display());
+
+    if let Err(e) = daemon.serve(&socket) {
+        fail(e);
+    }
+}
+
+#[cfg(feature = "http")]
+fn serve_http(
+    addr: String,
+    root: Option<PathBuf>,
+    token: Option<String>,
+    daemon: smbuilder::daemon::Daemon,
+) {
+    let Some(root) = root.or_else(smbuilder::paths::builds_dir) else {
+        eprintln!("could not find the builds directory! please pass the path to one.");
+        std::process::exit(ErrorKind::Other.code());
+    };
+
+    let mut server = smbuilder::daemon::http::HttpServer::new(daemon, root);
+    match token {
+        Some(token) => server = server.token(token),
+        None => eprintln!(
+            "{}anyone on this machine can build (and run the scripts of) any spec!",
+            "warn: ".bold().magenta(),
+        ),
+    }
+
+    println!("{}http://{}", "http: ".bold().green(), addr);
+
+    thread::spawn(move || {
+        if let Err(e) = server.serve(&addr) {
+            fail(e);
+        }
+    });
+}
+
 fn main() {
     color_eyre::install().unwrap();
 
     let args = Args::parse();
 
     let callbacks = Callbacks::new()
-        .log(|log_type, text| {
-            use callback_types::LogType as L;
-            match log_type {
-                L::Error => eprintln!("{}{}", "error: ".bold().red(), text),
-                L::Warn => eprintln!("{}{}", "warn: ".bold().magenta(), text),
-                L::BuildOutput => println!("{}{}", "make: ".bold().cyan(), text),
-                L::Info => println!("{}{}", "info: ".bold().blue(), text),
-            }
-        })
+        .terminal_log()
         .repo_clone_progress(|recv_objs, total_objs, bytes_transferred| {
             print!(
                 "{} {}/{} ({}%) objects transferred ({} KiB transferred)\r",
@@ -98,13 +258,71 @@ fn main() {
             )
         })
         .new_setup_stage(|stage| {
-            println!("{}{}", "stage: ".bold().green(), stage.to_string());
+            println!("{}{}", "stage: ".bold().green(), stage.label());
         });
 
     match args.subcommand {
         Action::Build {
             spec_path: base_dir,
-        } => build(base_dir, callbacks),
+            force,
+            config,
+            quiet,
+            verbose,
+            #[cfg(feature = "tui")]
+            tui,
+        } => {
+            let verbosity = if quiet {
+                Verbosity::Quiet
+            } else if verbose {
+                Verbosity::Verbose
+            } else {
+                Verbosity::Normal
+            };
+
+            #[cfg(feature = "tui")]
+            if tui {
+                if let Err(e) = build_tui(base_dir, force, config, verbosity) {
+                    fail(e);
+                }
+                return;
+            }
+
+            // the bars replace the clone progress
+            // and the stages that are printed.
+            #[cfg(feature = "indicatif")]
+            let bars = smbuilder::callbacks::bars::ProgressBars::new();
+            #[cfg(feature = "indicatif")]
+            let callbacks = bars.callbacks();
+
+            let result = build(base_dir, force, config, verbosity, callbacks);
+
+            #[cfg(feature = "indicatif")]
+            bars.finish();
+
+            if let Err(e) = result {
+                fail(e);
+            }
+        }
         Action::Run { spec_path } => run(spec_path, callbacks),
+        Action::List { root } => list(root),
+        #[cfg(all(unix, feature = "daemon"))]
+        Action::Daemon {
+            socket,
+            #[cfg(feature = "http")]
+            http,
+            #[cfg(feature = "http")]
+            root,
+            #[cfg(feature = "http")]
+            token,
+        } => {
+            let shared = smbuilder::daemon::Daemon::new();
+
+            #[cfg(feature = "http")]
+            if let Some(addr) = http {
+                serve_http(addr, root, token, shared.clone());
+            }
+
+            daemon(socket, shared)
+        }
     };
 }
@@ -0,0 +1,181 @@
+//! Python bindings to smbuilder.
+//!
+//! ```python
+//! import smbuilder
+//!
+//! spec = smbuilder.Spec.load("builds/sm64ex/build.yaml")
+//! for warning in spec.validate():
+//!     print("warning:", warning)
+//!
+//! exe = smbuilder.build(
+//!     spec,
+//!     log=lambda level, text: print(f"{level}: {text}"),
+//!     stage=lambda label: print("stage:", label),
+//! )
+//! ```
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use smb::prelude::{builder_types::BuildOptions, callback_types::LogType, *};
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+create_exception!(smbuilder, SmbuilderError, PyException);
+
+fn to_py_err(e: Error) -> PyErr {
+    SmbuilderError::new_err(e.to_string())
+}
+
+fn level_name(log_type: LogType) -> &'static str {
+    match log_type {
+        LogType::Error => "error",
+        LogType::Warn => "warn",
+        LogType::Info => "info",
+        LogType::Debug => "debug",
+        LogType::BuildOutput { .. } => "output",
+    }
+}
+
+/// Call a python callback from a build,
+/// printing (rather than losing) whatever
+/// it raises.
+fn call(callback: &PyObject, args: impl IntoPy<Py<pyo3::types::PyTuple>>) {
+    Python::with_gil(|py| {
+        if let Err(e) = callback.call1(py, args) {
+            e.print(py);
+        }
+    });
+}
+
+/// A build spec, loaded from a file.
+#[pyclass(name = "Spec")]
+#[derive(Clone)]
+struct PySpec {
+    inner: Spec,
+    path: PathBuf,
+}
+
+#[pymethods]
+impl PySpec {
+    /// Load the spec at `path`.
+    #[staticmethod]
+    fn load(path: PathBuf) -> PyResult<Self> {
+        let inner = Spec::from_file(&path).map_err(to_py_err)?;
+        Ok(PySpec { inner, path })
+    }
+
+    /// The name of the build, if it has one.
+    #[getter]
+    fn name(&self) -> Option<String> {
+        self.inner.name.clone()
+    }
+
+    /// The name of the repo of the port.
+    #[getter]
+    fn repo(&self) -> String {
+        self.inner.repo.name.clone()
+    }
+
+    /// The path that the spec was loaded from.
+    #[getter]
+    fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    /// Check the spec, raising `SmbuilderError`
+    /// if it cannot be built, and returning
+    /// the warnings about it otherwise.
+    fn validate(&mut self) -> PyResult<Vec<String>> {
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+
+        let sink = warnings.clone();
+        let mut callbacks = Callbacks::new().log(move |log_type, text| {
+            if log_type == LogType::Warn {
+                sink.lock().unwrap().push(text.to_owned());
+            }
+        });
+
+        self.inner.check_spec(&mut callbacks).map_err(to_py_err)?;
+
+        let warnings = warnings.lock().unwrap().clone();
+        Ok(warnings)
+    }
+
+    /// Gets the spec as yaml.
+    fn to_yaml(&self) -> PyResult<String> {
+        serde_yaml::to_string(&self.inner).map_err(|e| SmbuilderError::new_err(e.to_string()))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Spec({:?})", self.path.display().to_string())
+    }
+}
+
+/// Build `spec`, in `base_dir` (the directory of
+/// the spec by default), returning the path of
+/// the executable.
+///
+/// `log` is called with the level and text of each
+/// line logged, `stage` with the label of each new
+/// stage, and `clone_progress` with the percentage
+/// of the repo that has been cloned.
+///
+/// The GIL is released whilst building, so
+/// other python threads keep running.
+#[pyfunction]
+#[pyo3(signature = (spec, base_dir=None, *, force=false, log=None, stage=None, clone_progress=None))]
+fn build(
+    py: Python<'_>,
+    spec: &PySpec,
+    base_dir: Option<PathBuf>,
+    force: bool,
+    log: Option<PyObject>,
+    stage: Option<PyObject>,
+    clone_progress: Option<PyObject>,
+) -> PyResult<PathBuf> {
+    let base_dir =
+        base_dir.unwrap_or_else(|| spec.path.parent().map(Path::to_owned).unwrap_or_default());
+
+    let mut callbacks = Callbacks::new();
+
+    if let Some(cb) = log {
+        callbacks = callbacks.log(move |log_type, text| call(&cb, (level_name(log_type), text)));
+    }
+
+    if let Some(cb) = stage {
+        let postbuild_cb = cb.clone_ref(py);
+        callbacks = callbacks
+            .new_setup_stage(move |stage| call(&cb, (stage.label(),)))
+            .new_postbuild_stage(move |stage| call(&postbuild_cb, (stage.label(),)));
+    }
+
+    if let Some(cb) = clone_progress {
+        callbacks = callbacks.repo_clone_progress(move |received, total, _| {
+            if total > 0 {
+                call(&cb, ((received * 100) as f32 / total as f32,));
+            }
+        });
+    }
+
+    let spec = spec.inner.clone();
+
+    py.allow_threads(move || {
+        let mut builder =
+            Builder::new(spec, base_dir, callbacks)?.with_options(BuildOptions::new().force(force));
+
+        builder
+            .build()
+            .map(|build| build.executable_path().to_owned())
+    })
+    .map_err(to_py_err)
+}
+
+#[pymodule]
+fn smbuilder(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("SmbuilderError", m.py().get_type_bound::<SmbuilderError>())?;
+    m.add_class::<PySpec>()?;
+    m.add_function(wrap_pyfunction!(build, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,718 @@
+use super::lock::BaseDirLock;
+use super::process::{self, Limits, Outcome};
+use super::types::BuilderResult;
+use crate::util;
+
+use duct::cmd;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How long a command that checks the host
+/// (like `pkg-config --exists`) may run for.
+pub(crate) const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The filesystem operations that the
+/// builder performs on the base directory.
+///
+/// The builder uses `RealFs` by default;
+/// tests and dry runs can swap in their own
+/// implementation with `Builder::new_with_fs`.
+///
+/// Cloning and reading the repository
+/// goes through libgit2, which always
+/// works on the real disk.
+pub trait FsProvider: Send + Sync {
+    /// Whether anything exists at `path`.
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Whether `path` is a directory.
+    fn is_dir(&self, path: &Path) -> bool;
+
+    /// Read the whole of a file.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Open a file to be read bit by bit,
+    /// for the ones that are too big to
+    /// read all at once.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>>;
+
+    /// Create a file (or truncate it) to be
+    /// written bit by bit, like a download.
+    ///
+    /// Unlike `write`, this is not atomic.
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write + Send>>;
+
+    /// Get the paths of what is
+    /// inside of a directory.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Get the size of a file.
+    fn size(&self, path: &Path) -> io::Result<u64>;
+
+    /// Write `contents` to a file, replacing
+    /// it if it already exists.
+    ///
+    /// The write should be atomic: readers see
+    /// either the old file or the whole new one.
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Copy a file.
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Move a file or directory.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Create a directory, along with
+    /// any missing parents.
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Remove a file.
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Remove a directory and
+    /// everything inside of it.
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Get the last modification
+    /// time of a file.
+    fn modified(&self, path: &Path) -> io::Result<SystemTime>;
+
+    /// Make a file executable.
+    fn make_executable(&self, path: &Path) -> BuilderResult<()>;
+
+    /// Make `link` point to `target`, like
+    /// `util::link_or_copy`.
+    fn link_or_copy(&self, target: &Path, link: &Path) -> io::Result<()>;
+
+    /// Check that files can be created
+    /// in a directory.
+    fn check_writable(&self, dir: &Path) -> io::Result<()>;
+
+    /// Lock a base directory, like
+    /// `BaseDirLock::acquire`.
+    ///
+    /// Returns `None` for filesystems that no
+    /// other process can see, which have
+    /// nothing to lock out.
+    fn lock(&self, base_dir: &Path) -> BuilderResult<Option<BaseDirLock>>;
+}
+
+/// List the files under `root` on `fs`,
+/// relative to it, recursively, like
+/// `util::list_files`.
+pub(crate) fn list_files_on(
+    fs: &dyn FsProvider,
+    root: &Path,
+    relative: &Path,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    for path in fs.read_dir(&root.join(relative))? {
+        let Some(name) = path.file_name() else {
+            continue;
+        };
+        let relative = relative.join(name);
+
+        if fs.is_dir(&path) {
+            list_files_on(fs, root, &relative, files)?;
+        } else {
+            files.push(relative);
+        }
+    }
+
+    Ok(())
+}
+
+impl fmt::Debug for dyn FsProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FsProvider")
+    }
+}
+
+/// The real filesystem.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RealFs;
+
+impl FsProvider for RealFs {
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(io::BufReader::new(fs::File::open(path)?)))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write + Send>> {
+        Ok(Box::new(io::BufWriter::new(fs::File::create(path)?)))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect()
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        fs::metadata(path).map(|m| m.len())
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        util::write_atomically(path, contents)
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::copy(from, to).map(|_| ())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified())
+    }
+
+    fn make_executable(&self, path: &Path) -> BuilderResult<()> {
+        util::make_file_executable(path)
+    }
+
+    fn link_or_copy(&self, target: &Path, link: &Path) -> io::Result<()> {
+        util::link_or_copy(target, link)
+    }
+
+    fn check_writable(&self, dir: &Path) -> io::Result<()> {
+        util::check_dir_writable(dir)
+    }
+
+    fn lock(&self, base_dir: &Path) -> BuilderResult<Option<BaseDirLock>> {
+        BaseDirLock::acquire(base_dir).map(Some)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// A command for a `CommandRunner` to run.
+pub struct Command {
+    /// The program to run.
+    pub program: PathBuf,
+    /// The arguments to pass to it.
+    pub args: Vec<OsString>,
+    /// The directory to run it in, if
+    /// not the current one.
+    pub dir: Option<PathBuf>,
+    /// Extra environment variables.
+    pub env: Vec<(OsString, OsString)>,
+    /// What the command reads from stdin.
+    pub stdin: Stdin,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// What a `Command` reads from stdin.
+pub enum Stdin {
+    /// The stdin of this process, so that
+    /// the user can answer prompts from
+    /// the terminal.
+    ///
+    /// A `PtyRunner` forwards nothing.
+    #[default]
+    Inherit,
+    /// Nothing, so that prompts fail
+    /// straight away instead of hanging.
+    Null,
+    /// Some bytes, such as answers
+    /// to the command's prompts.
+    Bytes(Vec<u8>),
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.program.display())?;
+
+        for arg in &self.args {
+            write!(f, " {}", arg.to_string_lossy())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Command {
+    /// Creates a new `Command`.
+    pub fn new<P: Into<PathBuf>>(program: P) -> Self {
+        Command {
+            program: program.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Add an argument.
+    pub fn arg<S: Into<OsString>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Set the working directory.
+    pub fn dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    /// Set an environment variable.
+    pub fn env<K: Into<OsString>, V: Into<OsString>>(mut self, key: K, value: V) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Set what the command reads from stdin.
+    pub fn stdin(mut self, stdin: Stdin) -> Self {
+        self.stdin = stdin;
+        self
+    }
+}
+
+/// Runs the commands that the builder
+/// starts: the build script, the post-build
+/// scripts, git and the tools that check
+/// the host.
+///
+/// The builder uses `SystemRunner` by
+/// default; tests and dry runs can swap
+/// in their own implementation with
+/// `Builder::with_runner`.
+pub trait CommandRunner: Send + Sync {
+    /// Run a command, calling `on_line` with
+    /// every line that it prints, and stopping
+    /// it once `should_stop` returns true or it
+    /// breaks one of its `limits`.
+    fn run(
+        &self,
+        command: &Command,
+        limits: Limits,
+        should_stop: &dyn Fn() -> bool,
+        on_line: &mut dyn FnMut(&str),
+    ) -> io::Result<Outcome>;
+}
+
+/// Run a command that checks the host with
+/// `runner`, and get whether it exited
+/// successfully along with the lines that it
+/// printed, or `None` if it could not be run.
+pub(crate) fn probe(runner: &dyn CommandRunner, command: Command) -> Option<(bool, Vec<String>)> {
+    let command = command.stdin(Stdin::Null);
+    let limits = Limits {
+        timeout: Some(PROBE_TIMEOUT),
+        inactivity: None,
+    };
+
+    let mut lines = Vec::new();
+    let outcome = runner
+        .run(&command, limits, &|| false, &mut |ln| {
+            lines.push(util::strip_ansi(ln).into_owned())
+        })
+        .ok()?;
+
+    Some((matches!(outcome, Outcome::Exited(true)), lines))
+}
+
+/// Runs commands as real processes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(
+        &self,
+        command: &Command,
+        limits: Limits,
+        should_stop: &dyn Fn() -> bool,
+        on_line: &mut dyn FnMut(&str),
+    ) -> io::Result<Outcome> {
+        // a `Path` would make duct run bare names
+        // (like `git`) from the current directory,
+        // instead of looking them up in `PATH`.
+        let mut expr = cmd(command.program.as_os_str(), &command.args);
+
+        if let Some(dir) = &command.dir {
+            expr = expr.dir(dir);
+        }
+
+        for (key, value) in &command.env {
+            expr = expr.env(key, value);
+        }
+
+        match &command.stdin {
+            Stdin::Inherit => (),
+            Stdin::Null => expr = expr.stdin_null(),
+            Stdin::Bytes(bytes) => expr = expr.stdin_bytes(bytes.clone()),
+        }
+
+        process::run_watched(expr, limits, should_stop, on_line)
+    }
+}
+
+/// Runs commands as real processes, under a
+/// pseudo-terminal, so that tools which check
+/// for a terminal (progress meters, colored
+/// compiler output) behave like they would in
+/// one, and prompts can be told apart from
+/// hung commands (see `Outcome::Prompted`).
+///
+/// Pair it with `AnsiMode::Strip` for
+/// frontends that cannot show colors.
+#[cfg(all(unix, feature = "pty"))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PtyRunner;
+
+#[cfg(all(unix, feature = "pty"))]
+impl CommandRunner for PtyRunner {
+    fn run(
+        &self,
+        command: &Command,
+        limits: Limits,
+        should_stop: &dyn Fn() -> bool,
+        on_line: &mut dyn FnMut(&str),
+    ) -> io::Result<Outcome> {
+        let mut cmd = std::process::Command::new(&command.program);
+        cmd.args(&command.args);
+
+        if let Some(dir) = &command.dir {
+            cmd.current_dir(dir);
+        }
+
+        // GUIs are often started without a
+        // terminal type, which tools need to
+        // know what the terminal can do.
+        if std::env::var_os("TERM").is_none() {
+            cmd.env("TERM", "xterm-256color");
+        }
+
+        for (key, value) in &command.env {
+            cmd.env(key, value);
+        }
+
+        // the terminal reads an EOT at the start
+        // of a line as the end of the input.
+        let input = match &command.stdin {
+            Stdin::Inherit => Vec::new(),
+            Stdin::Null => vec![0x04],
+            Stdin::Bytes(bytes) => bytes.clone(),
+        };
+
+        process::run_in_pty(cmd, input, limits, should_stop, on_line)
+    }
+}
+
+/// A filesystem and a command runner
+/// for the tests, that touch nothing
+/// outside of the process.
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use crate::error::ErrorCause;
+    use crate::prelude::error_macros::*;
+    use crate::prelude::Error;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex, MutexGuard};
+
+    #[derive(Clone, Debug)]
+    enum Entry {
+        Dir,
+        File(Vec<u8>, SystemTime),
+    }
+
+    /// A filesystem that lives in memory.
+    ///
+    /// Clones share the same files, so that a
+    /// test can look at what the builder did.
+    #[derive(Clone, Default)]
+    pub(crate) struct MemoryFs {
+        entries: Arc<Mutex<BTreeMap<PathBuf, Entry>>>,
+    }
+
+    impl MemoryFs {
+        fn entries(&self) -> MutexGuard<'_, BTreeMap<PathBuf, Entry>> {
+            self.entries.lock().unwrap()
+        }
+
+        /// Write a file, along with
+        /// its parent directories.
+        pub(crate) fn add_file<P: AsRef<Path>>(&self, path: P, contents: &[u8]) {
+            let path = path.as_ref();
+            if let Some(parent) = path.parent() {
+                self.create_dir_all(parent).unwrap();
+            }
+            self.write(path, contents).unwrap();
+        }
+
+        fn check_parent(&self, path: &Path) -> io::Result<()> {
+            match path.parent() {
+                Some(parent) if !self.is_dir(parent) => Err(not_found(parent)),
+                _ => Ok(()),
+            }
+        }
+
+        fn insert_file(&self, path: &Path, contents: Vec<u8>) -> io::Result<()> {
+            self.check_parent(path)?;
+            if self.is_dir(path) {
+                return Err(io::Error::other(format!(
+                    "{} is a directory",
+                    path.display()
+                )));
+            }
+
+            let file = Entry::File(contents, SystemTime::now());
+            self.entries().insert(path.to_owned(), file);
+            Ok(())
+        }
+    }
+
+    fn not_found(path: &Path) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} does not exist", path.display()),
+        )
+    }
+
+    /// A file that is being written, which
+    /// lands in the filesystem once dropped.
+    struct MemoryFile {
+        fs: MemoryFs,
+        path: PathBuf,
+        contents: Vec<u8>,
+    }
+
+    impl Write for MemoryFile {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.contents.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.fs.insert_file(&self.path, self.contents.clone())
+        }
+    }
+
+    impl Drop for MemoryFile {
+        fn drop(&mut self) {
+            let _ = self.flush();
+        }
+    }
+
+    impl FsProvider for MemoryFs {
+        fn exists(&self, path: &Path) -> bool {
+            self.entries().contains_key(path)
+        }
+
+        fn is_dir(&self, path: &Path) -> bool {
+            matches!(self.entries().get(path), Some(Entry::Dir))
+        }
+
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            match self.entries().get(path) {
+                Some(Entry::File(contents, _)) => Ok(contents.clone()),
+                Some(Entry::Dir) => Err(io::Error::other(format!(
+                    "{} is a directory",
+                    path.display()
+                ))),
+                None => Err(not_found(path)),
+            }
+        }
+
+        fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+            Ok(Box::new(io::Cursor::new(self.read(path)?)))
+        }
+
+        fn create(&self, path: &Path) -> io::Result<Box<dyn Write + Send>> {
+            self.insert_file(path, Vec::new())?;
+
+            Ok(Box::new(MemoryFile {
+                fs: self.clone(),
+                path: path.to_owned(),
+                contents: Vec::new(),
+            }))
+        }
+
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+            if !self.is_dir(path) {
+                return Err(not_found(path));
+            }
+
+            Ok(self
+                .entries()
+                .keys()
+                .filter(|p| p.parent() == Some(path))
+                .cloned()
+                .collect())
+        }
+
+        fn size(&self, path: &Path) -> io::Result<u64> {
+            self.read(path).map(|contents| contents.len() as u64)
+        }
+
+        fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+            self.insert_file(path, contents.to_vec())
+        }
+
+        fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+            let contents = self.read(from)?;
+            self.insert_file(to, contents)
+        }
+
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            if !self.exists(from) {
+                return Err(not_found(from));
+            }
+            self.check_parent(to)?;
+
+            let mut entries = self.entries();
+            let moved: Vec<PathBuf> = entries
+                .keys()
+                .filter(|p| p.starts_with(from))
+                .cloned()
+                .collect();
+            for path in moved {
+                let entry = entries.remove(&path).unwrap();
+                let dest = to.join(path.strip_prefix(from).unwrap());
+                entries.insert(dest, entry);
+            }
+
+            Ok(())
+        }
+
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            for dir in path.ancestors() {
+                if let Some(Entry::File(..)) = self.entries().get(dir) {
+                    return Err(io::Error::other(format!("{} is a file", dir.display())));
+                }
+            }
+
+            let mut entries = self.entries();
+            for dir in path.ancestors() {
+                entries.insert(dir.to_owned(), Entry::Dir);
+            }
+
+            Ok(())
+        }
+
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+            match self.entries().remove(path) {
+                Some(_) => Ok(()),
+                None => Err(not_found(path)),
+            }
+        }
+
+        fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+            if !self.is_dir(path) {
+                return Err(not_found(path));
+            }
+
+            self.entries().retain(|p, _| !p.starts_with(path));
+            Ok(())
+        }
+
+        fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+            match self.entries().get(path) {
+                Some(Entry::File(_, modified)) => Ok(*modified),
+                Some(Entry::Dir) => Ok(SystemTime::UNIX_EPOCH),
+                None => Err(not_found(path)),
+            }
+        }
+
+        fn make_executable(&self, path: &Path) -> BuilderResult<()> {
+            match self.exists(path) {
+                true => Ok(()),
+                false => Err(err!(c_fs!(not_found(path)))),
+            }
+        }
+
+        fn link_or_copy(&self, target: &Path, link: &Path) -> io::Result<()> {
+            self.copy(target, link)
+        }
+
+        fn check_writable(&self, dir: &Path) -> io::Result<()> {
+            match self.is_dir(dir) {
+                true => Ok(()),
+                false => Err(not_found(dir)),
+            }
+        }
+
+        fn lock(&self, _base_dir: &Path) -> BuilderResult<Option<BaseDirLock>> {
+            Ok(None)
+        }
+    }
+
+    /// A command runner that runs nothing, but
+    /// records the commands, succeeding with
+    /// no output (unless told to fail or
+    /// time out).
+    ///
+    /// Clones share the same record.
+    #[derive(Clone, Default)]
+    pub(crate) struct RecordingRunner {
+        commands: Arc<Mutex<Vec<Command>>>,
+        failing: Arc<Mutex<Vec<OsString>>>,
+        timing_out: Arc<Mutex<Vec<OsString>>>,
+    }
+
+    impl RecordingRunner {
+        /// The commands that were run,
+        /// in the order that they ran.
+        pub(crate) fn commands(&self) -> Vec<Command> {
+            self.commands.lock().unwrap().clone()
+        }
+
+        /// Make the commands that are
+        /// passed `arg` exit with an error.
+        pub(crate) fn fail_with_arg<S: Into<OsString>>(&self, arg: S) {
+            self.failing.lock().unwrap().push(arg.into());
+        }
+
+        /// Make the commands that are
+        /// passed `arg` time out.
+        pub(crate) fn time_out_with_arg<S: Into<OsString>>(&self, arg: S) {
+            self.timing_out.lock().unwrap().push(arg.into());
+        }
+    }
+
+    impl CommandRunner for RecordingRunner {
+        fn run(
+            &self,
+            command: &Command,
+            _limits: Limits,
+            _should_stop: &dyn Fn() -> bool,
+            _on_line: &mut dyn FnMut(&str),
+        ) -> io::Result<Outcome> {
+            self.commands.lock().unwrap().push(command.clone());
+
+            let timing_out = self.timing_out.lock().unwrap();
+            if command.args.iter().any(|arg| timing_out.contains(arg)) {
+                return Ok(Outcome::TimedOut(Duration::from_secs(1)));
+            }
+
+            let failing = self.failing.lock().unwrap();
+            let fails = command.args.iter().any(|arg| failing.contains(arg));
+            Ok(Outcome::Exited(!fails))
+        }
+    }
+}
@@ -1,29 +1,401 @@
-use super::get_needed_setup_tasks;
-use super::types::BuilderResult;
+use super::backend::{self, Command, CommandRunner, FsProvider, RealFs, Stdin, SystemRunner};
+use super::compdb::{self, CompileDatabase, COMPILE_COMMANDS_NAME};
+use super::graph;
+use super::handle::Build;
+use super::history;
+use super::icon;
+use super::lfs;
+use super::lock::BaseDirLock;
+use super::logfile::LogFile;
+use super::process::{Limits, Outcome};
+use super::progress::CompileProgress;
+use super::rebuild::{makeopts_rebuild_kind, RebuildKind};
+use super::release::{latest_release_tag, LATEST_RELEASE};
+use super::stage::{Stage, StageContext, StagePoint};
+use super::state::{
+    BuildPhase, BuildState, BuildStateHandle, SavedProgress, PROGRESS_FILE_NAME,
+    PROGRESS_JSON_FILE_NAME,
+};
+use super::types::{
+    AnsiMode, BuildOptions, BuildReport, BuildStage, BuilderResult, SimulationOptions, Verbosity,
+};
 use super::types::{
-    PostBuildStage::*,
+    PostBuildStage::{self, *},
     SetupStage::{self, *},
 };
+use super::verify::{
+    check_executable_on, detect_executable_format_on, find_missing_libs_with, ExecutableFormat,
+};
+use super::{
+    baserom_is_good_on, get_setup_stage_artifacts, needed_setup_tasks, pending_downloads,
+    python_modules_used, PendingDownload,
+};
 
 use crate::callback_types::LogType::{self, *};
+use crate::callback_types::{DirtyRepoAction, OutputSeverity};
 use crate::callbacks::run_callback;
+use crate::config::{ColorChoice, Config};
+use crate::deps::{self, NativeLib};
 use crate::error::ErrorCause;
+use crate::host::HostCapabilities;
+use crate::integrity::{IntegrityReport, Manifest};
+use crate::lockfile::{LockedBuild, LockedRepo, Lockfile, ReproducibleBuild};
 use crate::prelude::error_macros::*;
-use crate::prelude::{err, Callbacks, Error, Spec};
+use crate::prelude::{
+    err, Callbacks, DiscordSdk, Error, HookPoint, Hooks, Makeopt, Pack, PostBuildScript, Rom,
+    SourceKind, Spec,
+};
+use crate::redact::redact;
+use crate::romconvert::{convert_rom_with_fs, RomType};
+use crate::texturepack;
 use crate::util;
 
-use duct::cmd;
-use git2::build::RepoBuilder;
-use git2::{FetchOptions, RemoteCallbacks};
-use n64romconvert::{byte_swap, endian_swap, RomType};
-use std::io::{BufRead, BufReader};
-use std::sync::Arc;
-use std::{
-    fs,
-    io::Write,
-    path::{Path, PathBuf},
+use git2::build::CheckoutBuilder;
+use git2::{
+    AutotagOption, Direction, FetchOptions, ProxyOptions, Remote, RemoteCallbacks, Repository,
 };
 
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Once;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Log through the log callback, and
+/// record the line in the build state.
+macro_rules! log {
+    ($self:ident, $log_type:expr, $text:expr) => {{
+        let (log_type, text): (LogType, &str) = ($log_type, $text);
+        $self.state.log_line(text);
+        if log_type == LogType::Warn {
+            $self.state.count_warning();
+        }
+        run_callback!($self.callbacks.log_cb, log_type, text);
+    }};
+}
+
+/// The most packs that are
+/// installed at the same time.
+const MAX_PACK_INSTALL_THREADS: usize = 4;
+
+/// What the threads that install
+/// packs report back.
+enum PackEvent<'p> {
+    Progress(&'p str, usize, usize, u64),
+    Done(&'p str, BuilderResult<()>),
+}
+
+/// The most packs and patches that
+/// are downloaded at the same time.
+const MAX_DOWNLOAD_THREADS: usize = 4;
+
+/// What the threads that download
+/// packs and patches report back.
+enum DownloadEvent<'d> {
+    Started(&'d str),
+    Done(&'d str, BuilderResult<()>),
+}
+
+/// The states of the builds that
+/// control-c cancels.
+static INTERRUPTIBLE: Mutex<Vec<BuildStateHandle>> = Mutex::new(Vec::new());
+static INTERRUPT_HANDLER: Once = Once::new();
+
+/// Install a control-c handler that cancels the
+/// build of `state` (and any other running build),
+/// instead of killing the process outright.
+///
+/// A second control-c, or one whilst no
+/// build is running, exits immediately.
+fn install_interrupt_handler(state: &BuildStateHandle) {
+    {
+        let mut states = interruptible();
+        // the builders that have been dropped
+        // are forgotten.
+        states.retain(|s| !s.is_only_handle());
+        if !states.iter().any(|s| s.same_as(state)) {
+            states.push(state.clone());
+        }
+    }
+
+    INTERRUPT_HANDLER.call_once(|| {
+        let result = ctrlc::set_handler(|| {
+            let running: Vec<BuildStateHandle> = interruptible()
+                .iter()
+                .filter(|s| s.is_running())
+                .cloned()
+                .collect();
+
+            if running.is_empty() || running.iter().any(|s| s.is_cancelled()) {
+                std::process::exit(130);
+            }
+
+            eprintln!("stopping on control-c, press it again to exit immediately...");
+            for state in running {
+                state.cancel();
+            }
+        });
+
+        // another handler may have been set
+        // by the app; that is fine.
+        let _ = result;
+    });
+}
+
+fn interruptible() -> std::sync::MutexGuard<'static, Vec<BuildStateHandle>> {
+    INTERRUPTIBLE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Download the release of a prebuilt
+/// port to `dest` on `fs`.
+#[cfg(feature = "remote")]
+fn download_release(fs: &dyn FsProvider, url: &str, dest: &Path) -> BuilderResult<()> {
+    let mut file = match fs.create(dest) {
+        Ok(f) => f,
+        Err(e) => {
+            let msg = format!("failed to create {}", dest.display());
+            return Err(err!(c_fs!(e, msg), "whilst downloading a file"));
+        }
+    };
+
+    let result = crate::net::download_to(url, &mut file).map(|_| ());
+    drop(file);
+
+    // a partial download would be
+    // taken for a bad one.
+    if result.is_err() {
+        let _ = fs.remove_file(dest);
+    }
+
+    result
+}
+
+/// Download the release of a prebuilt
+/// port to `dest` on `fs`.
+#[cfg(not(feature = "remote"))]
+fn download_release(_fs: &dyn FsProvider, _url: &str, _dest: &Path) -> BuilderResult<()> {
+    let e = std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "smbuilder was built without the `remote` feature",
+    );
+    Err(err!(c_other!(e), "cannot download the prebuilt release"))
+}
+
+/// Unpack a zipped release on `fs`.
+#[cfg(feature = "bundle")]
+fn unzip_release(fs: &dyn FsProvider, archive: &Path, dest: &Path) -> BuilderResult<()> {
+    use std::io::{Read, Write};
+
+    let bytes = match fs.read(archive) {
+        Ok(b) => b,
+        Err(e) => {
+            let msg = format!("failed to open {}", archive.display());
+            return Err(err!(c_fs!(e, msg), "whilst unpacking the release"));
+        }
+    };
+
+    let mut zip = match zip::ZipArchive::new(io::Cursor::new(bytes)) {
+        Ok(z) => z,
+        Err(e) => return Err(err!(c_other!(e), "failed to unpack the release")),
+    };
+
+    for i in 0..zip.len() {
+        let mut entry = match zip.by_index(i) {
+            Ok(e) => e,
+            Err(e) => return Err(err!(c_other!(e), "failed to unpack the release")),
+        };
+
+        // like `ZipArchive::extract`, nothing
+        // is written outside of `dest`.
+        let Some(name) = entry.enclosed_name() else {
+            let e = io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is outside of the release", entry.name()),
+            );
+            return Err(err!(c_other!(e), "failed to unpack the release"));
+        };
+        let path = dest.join(name);
+
+        let fs_err = |e: io::Error| {
+            let msg = format!("failed to write to {}", path.display());
+            err!(c_fs!(e, msg), "whilst unpacking the release")
+        };
+
+        if entry.is_dir() {
+            fs.create_dir_all(&path).map_err(fs_err)?;
+            continue;
+        }
+
+        if let Some(parent) = path.parent() {
+            fs.create_dir_all(parent).map_err(fs_err)?;
+        }
+
+        if entry.is_symlink() {
+            let mut target = String::new();
+            entry.read_to_string(&mut target).map_err(fs_err)?;
+            // a symlink is relative to where
+            // it is, but a copy is not.
+            let target = match cfg!(unix) {
+                true => PathBuf::from(target),
+                false => path.parent().unwrap_or(dest).join(target),
+            };
+            fs.link_or_copy(&target, &path).map_err(fs_err)?;
+            continue;
+        }
+
+        let mut file = fs.create(&path).map_err(fs_err)?;
+        io::copy(&mut entry, &mut file)
+            .and_then(|_| file.flush())
+            .map_err(fs_err)?;
+        drop(file);
+
+        if entry.unix_mode().is_some_and(|mode| mode & 0o111 != 0) {
+            fs.make_executable(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Unpack a zipped release on `fs`.
+#[cfg(not(feature = "bundle"))]
+fn unzip_release(_fs: &dyn FsProvider, _archive: &Path, _dest: &Path) -> BuilderResult<()> {
+    let e = std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "smbuilder was built without the `bundle` feature",
+    );
+    Err(err!(c_other!(e), "cannot unpack zipped releases"))
+}
+
+/// Download a pack or patch into the cache (unless
+/// it is cached already), check it against its
+/// checksum, and put it at its path in the spec.
+fn fetch_download(
+    fs: &dyn FsProvider,
+    download: &PendingDownload,
+    cache_dir: &Path,
+) -> BuilderResult<()> {
+    let resource = &download.resource;
+    let fs_err = |e: std::io::Error, path: &Path| {
+        let msg = format!("failed to write to {}", path.display());
+        err!(
+            c_fs!(e, msg),
+            format!("whilst downloading {}", download.name)
+        )
+    };
+
+    let checksum = resource.checksum.trim().to_lowercase();
+    let cached_path = cache_dir.join(&checksum);
+
+    let sha256_hex = |path: &Path| fs.open(path).and_then(util::sha256_read_hex);
+    let cached = sha256_hex(&cached_path)
+        .map(|actual| actual == checksum)
+        .unwrap_or(false);
+
+    if !cached {
+        download_release(fs, &resource.url, &cached_path)?;
+
+        let actual = sha256_hex(&cached_path).map_err(|e| fs_err(e, &cached_path))?;
+        if actual != checksum {
+            let _ = fs.remove_file(&cached_path);
+            return Err(err!(
+                c_checksum!(resource.url.clone(), resource.checksum.clone(), actual),
+                format!("{} does not match the checksum in the spec", download.name)
+            ));
+        }
+    }
+
+    let result = if download.unpack {
+        fs.create_dir_all(&download.dest)
+            .map_err(|e| fs_err(e, &download.dest))
+            .and_then(|_| unzip_release(fs, &cached_path, &download.dest))
+    } else {
+        download
+            .dest
+            .parent()
+            .map_or(Ok(()), |parent| fs.create_dir_all(parent))
+            .and_then(|_| fs.copy(&cached_path, &download.dest))
+            .map_err(|e| fs_err(e, &download.dest))
+    };
+
+    // a half-unpacked pack would be taken
+    // as downloaded by the next build.
+    if result.is_err() {
+        let _ = fs.remove_dir_all(&download.dest);
+        let _ = fs.remove_file(&download.dest);
+    }
+
+    result
+}
+
+/// Download `downloads` side by side, calling
+/// `on_event` (on this thread) as they start
+/// and finish.
+///
+/// After the first download that fails, the
+/// ones that are under way finish, but no more
+/// start, and its error is returned.
+fn download_all(
+    fs: &dyn FsProvider,
+    downloads: &[PendingDownload],
+    cache_dir: &Path,
+    on_event: &mut dyn FnMut(DownloadEvent<'_>),
+) -> BuilderResult<()> {
+    // the network is the slow part, so the
+    // downloads overlap instead of queueing.
+    let total = downloads.len();
+    let workers = total.min(MAX_DOWNLOAD_THREADS);
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+
+    let mut first_err = None;
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let tx = tx.clone();
+            let next = &next;
+
+            scope.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let download = match downloads.get(i) {
+                    Some(download) => download,
+                    None => break,
+                };
+
+                let _ = tx.send(DownloadEvent::Started(&download.name));
+                let result = fetch_download(fs, download, cache_dir);
+
+                if tx
+                    .send(DownloadEvent::Done(&download.name, result))
+                    .is_err()
+                {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+
+        for event in rx {
+            match event {
+                DownloadEvent::Done(_, Err(e)) => {
+                    next.store(total, Ordering::SeqCst);
+                    first_err.get_or_insert(e);
+                }
+                event => on_event(event),
+            }
+        }
+    });
+
+    match first_err {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
 /// The main builder class which takes care of building
 /// a spec.
 ///
@@ -32,20 +404,20 @@ use std::{
 ///
 /// # Example
 ///
-/// ```rust
+/// ```no_run
 /// use smbuilder::prelude::*;
 ///
 /// // set your callbacks up first
-/// let mut callbacks = Callbacks::empty();
+/// let mut callbacks = Callbacks::new();
 ///
 /// // and your spec
-/// let my_spec = Spec::from_file("path/to/my/smbuilder.yaml")
+/// let my_spec = Spec::from_file("path/to/my/smbuilder.yaml").unwrap();
 ///
 /// // set up your builder
-/// let mut builder = Builder::new(my_spec, "path/to/the/base/dir", mut callbacks);
+/// let mut builder = Builder::new(my_spec, "path/to/the/base/dir", callbacks).unwrap();
 ///
 /// // compile the spec, with the specified callbacks.
-/// builder.build();
+/// builder.build().unwrap();
 ///
 /// ```
 ///
@@ -58,6 +430,49 @@ pub struct Builder<'a> {
 
     /// The logger.
     pub callbacks: Callbacks<'a>,
+
+    /// Options that change how the
+    /// build is run.
+    pub options: BuildOptions,
+
+    /// The global config, for whatever
+    /// the spec and options leave unset.
+    config: Config,
+
+    /// The state of the build, for polling.
+    state: BuildStateHandle,
+
+    /// The filesystem that the build
+    /// works on.
+    fs: Arc<dyn FsProvider>,
+
+    /// What runs the build script
+    /// and the post-build scripts.
+    runner: Box<dyn CommandRunner>,
+
+    /// The report of the last
+    /// successful build.
+    report: Option<BuildReport>,
+
+    /// What the host is capable of, which
+    /// is probed once, on the first build.
+    host: Option<HostCapabilities>,
+
+    /// The verbosity of the options, shared
+    /// with the filter around the log callback.
+    verbosity: Arc<AtomicU8>,
+
+    /// The packs that are being downloaded
+    /// whilst the port compiles, if any.
+    background_downloads: Option<thread::JoinHandle<BuilderResult<()>>>,
+
+    /// The custom stages, and where
+    /// in the build they run.
+    stages: Vec<(StagePoint, Box<dyn Stage + 'a>)>,
+
+    /// The lock held over the base directory
+    /// for as long as the builder is alive.
+    _lock: Option<BaseDirLock>,
 }
 
 impl<'a> Builder<'a> {
@@ -66,362 +481,3907 @@ impl<'a> Builder<'a> {
     /// It takes in the callbacks, for events that
     /// may happen during the build process.
     ///
+    /// The base directory is locked for the lifetime
+    /// of the builder; this fails with a `BaseDirLocked`
+    /// error if another build is already using it, and
+    /// with a `BaseDirNotWritable` error (suggesting the
+    /// default base dir) if it is read-only.
+    ///
     /// # Example
     ///
     /// ```no_run
-    /// # fn main() {
-    /// let mut builder = Builder::new(my_spec, my_base_dir, my_callbacks)
+    /// # use smbuilder::prelude::*;
+    /// # let (my_spec, my_base_dir, my_callbacks) = (Spec::default(), "base", Callbacks::new());
+    /// let mut builder = Builder::new(my_spec, my_base_dir, my_callbacks).unwrap();
     /// // you must have your spec, base dir and callbacks set up beforehand!
-    /// # }
     /// ```
     pub fn new<P: Into<PathBuf>>(
         spec: Spec,
         base_dir: P,
-        callbacks: Callbacks,
+        mut callbacks: Callbacks,
     ) -> Result<Builder, Error> {
-        let result = Builder {
-            spec,
-            base_dir: base_dir.into(),
-            callbacks,
-        };
+        let config = Config::load_default().unwrap_or_else(|e| {
+            run_callback!(
+                callbacks.log_cb,
+                Warn,
+                &format!("ignoring the unreadable config: {}", e)
+            );
+            Config::default()
+        });
+        config.color.apply();
 
-        Ok(result)
+        Builder::new_with_fs_and_config(spec, base_dir, callbacks, RealFs, config)
     }
 
-    fn clone_repo(&mut self) -> BuilderResult<PathBuf> {
-        run_callback!(self.callbacks.new_setup_stage_cb, CloneRepo);
-
-        let repo_name = &self.spec.repo.name;
-        let repo_dir = Arc::new(self.base_dir.join(repo_name));
-
-        run_callback!(self.callbacks.log_cb, Info, "cloning the repository");
+    /// Creates a new `Builder`, like `new`, that
+    /// works on the base directory through a
+    /// different filesystem, such as a mock one
+    /// in tests.
+    ///
+    /// The base directory is checked and locked
+    /// through `fs` as well. The config file is
+    /// not read, so the default config is used
+    /// unless another is set with `with_config`.
+    pub fn new_with_fs<P: Into<PathBuf>, F: FsProvider + 'static>(
+        spec: Spec,
+        base_dir: P,
+        callbacks: Callbacks,
+        fs: F,
+    ) -> Result<Builder, Error> {
+        Builder::new_with_fs_and_config(spec, base_dir, callbacks, fs, Config::default())
+    }
 
-        // set up the ctrlc handler
-        let repo_dir_thread = Arc::clone(&repo_dir);
-        ctrlc::set_handler(move || {
-            let repo_dir = (*(repo_dir_thread.clone())).clone();
-            println!("exiting on control-c...");
+    fn new_with_fs_and_config<P: Into<PathBuf>, F: FsProvider + 'static>(
+        spec: Spec,
+        base_dir: P,
+        mut callbacks: Callbacks,
+        fs: F,
+        config: Config,
+    ) -> Result<Builder, Error> {
+        let base_dir = base_dir.into();
 
-            if !repo_dir.exists() {
-                std::process::exit(0);
-            }
+        // checked up front, rather than failing
+        // halfway through setting the build up.
+        if let Err(e) = fs.check_writable(&base_dir) {
+            let fallback = config.base_dir_for(&spec).filter(|dir| *dir != base_dir);
+            return Err(err!(c_dir_not_writable!(base_dir, e, fallback)));
+        }
 
-            fs::remove_dir_all(&repo_dir).unwrap_or_else(|e| {
-                panic!("failed to remove the dir at {}: {}", &repo_dir.display(), e)
-            });
+        let lock = fs.lock(&base_dir)?;
+        let fs: Arc<dyn FsProvider> = Arc::new(fs);
 
-            std::process::exit(0);
-        })
-        .expect("failed to set the control-c handler!");
+        let state = BuildStateHandle::default();
+        state.write_through(fs.clone());
+        state.persist_to(base_dir.join(PROGRESS_FILE_NAME));
 
-        let mut remote_callbacks = RemoteCallbacks::new();
-        remote_callbacks.transfer_progress(|progress| {
-            run_callback!(
-                self.callbacks.repo_clone_progress_cb,
-                progress.received_objects(),
-                progress.total_objects(),
-                progress.received_bytes(),
-            );
+        // the log callback is filtered once, here,
+        // by whatever verbosity is set later on.
+        let verbosity = Arc::new(AtomicU8::new(Verbosity::default() as u8));
+        if let Some(mut log_cb) = callbacks.log_cb.take() {
+            let verbosity = verbosity.clone();
+            callbacks.log_cb = Some(Box::new(move |log_type: LogType, text: &str| {
+                if Verbosity::from_u8(verbosity.load(Ordering::Relaxed)).allows(&log_type) {
+                    log_cb(log_type, text);
+                }
+            }));
+        }
 
-            true
-        });
+        let result = Builder {
+            spec,
+            base_dir,
+            callbacks,
+            options: BuildOptions::default(),
+            config,
+            state,
+            fs,
+            runner: Box::new(SystemRunner),
+            report: None,
+            host: None,
+            verbosity,
+            background_downloads: None,
+            stages: Vec::new(),
+            _lock: lock,
+        };
 
-        let mut fetch_options = FetchOptions::new();
-        fetch_options
-            .remote_callbacks(remote_callbacks)
-            .follow_redirects(git2::RemoteRedirect::All);
+        Ok(result)
+    }
 
-        let repo_clone_result = RepoBuilder::new()
-            .branch(&self.spec.repo.branch)
-            .fetch_options(fetch_options)
-            .clone(&self.spec.repo.url, &repo_dir);
+    /// Creates a new `Builder`, in the default
+    /// base directory for the spec (see
+    /// `Config::base_dir_for`), creating it if
+    /// it does not exist yet.
+    pub fn in_default_dir(spec: Spec, callbacks: Callbacks) -> Result<Builder, Error> {
+        let config = Config::load_default().unwrap_or_default();
 
-        match repo_clone_result {
-            Ok(_) => (),
-            Err(e) => {
-                let msg = e.message().to_string();
-                let err = err!(
-                    c_repo_clone!(self.spec.repo.url.clone(), (*repo_dir).clone(), e),
-                    format!("failed to clone the repository: {}", msg)
+        let base_dir = match config.base_dir_for(&spec) {
+            Some(dir) => dir,
+            None => {
+                let e = std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    "could not find the home directory",
                 );
-                return Err(err);
+                return Err(err!(c_fs!(e), "no base directory was given"));
             }
+        };
+
+        if let Err(e) = fs::create_dir_all(&base_dir) {
+            let msg = format!("failed to create {}", base_dir.display());
+            return Err(err!(c_fs!(e, msg), "whilst creating the base directory"));
         }
 
-        Ok((*repo_dir).clone())
+        Builder::new(spec, base_dir, callbacks)
     }
 
-    fn copy_rom<P: AsRef<Path>>(&mut self, repo_dir: P) -> BuilderResult<()> {
-        run_callback!(self.callbacks.new_setup_stage_cb, CopyRom);
-        use RomType::*;
-
-        let rom_type = self.spec.rom.format;
-        let target_rom_path = repo_dir
-            .as_ref()
-            .join(format!("baserom.{}.z64", self.spec.rom.region.to_string()));
+    /// Use a different global config than
+    /// the one from the config file.
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.options.color.unwrap_or(config.color).apply();
+        self.config = config;
+        self
+    }
 
-        run_callback!(self.callbacks.log_cb, Info, "copying the ROM");
+    /// Set the options that change
+    /// how the build is run.
+    pub fn with_options(mut self, mut options: BuildOptions) -> Self {
+        let json_path = options
+            .progress_file
+            .then(|| self.base_dir.join(PROGRESS_JSON_FILE_NAME));
+        self.state.write_json_to(json_path);
 
-        if rom_type == BigEndian {
-            match fs::copy(&self.spec.rom.path, &target_rom_path) {
-                Ok(_) => Ok(()),
-                Err(e) => {
-                    let msg = format!(
-                        "failed to copy the ROM from {} to {}!",
-                        &self.spec.rom.path.display(),
-                        target_rom_path.display()
-                    );
-                    return Err(err!(c_fs!(e, msg), "whilst copying the ROM file"));
-                }
-            }
-        } else {
-            run_callback!(
-                self.callbacks.log_cb,
-                Warn,
-                "the ROM is not a z64 format ROM!"
-            );
-            run_callback!(
-                self.callbacks.log_cb,
-                Warn,
-                &format!("converting from a {:?} ROM", rom_type)
-            );
+        let log_file = options
+            .log_file
+            .map(|o| LogFile::in_base_dir(self.fs.clone(), &self.base_dir, o));
+        self.state.log_to(log_file);
 
-            match rom_type {
-                LittleEndian => endian_swap(&self.spec.rom.path, &target_rom_path),
-                ByteSwapped => byte_swap(&self.spec.rom.path, &target_rom_path),
-                _ => unreachable!(),
-            };
+        self.verbosity
+            .store(options.verbosity as u8, Ordering::Relaxed);
 
-            Ok(())
+        let color = options.color.unwrap_or(self.config.color);
+        color.apply();
+        if color == ColorChoice::Never {
+            options.ansi = AnsiMode::Strip;
         }
-    }
-
-    fn create_build_script<P: AsRef<Path>>(&mut self, repo_dir: P) -> BuilderResult<()> {
-        run_callback!(self.callbacks.new_setup_stage_cb, CreateBuildScript);
-
-        let file_path = self.base_dir.join("build.sh");
 
-        let mut build_script =
-            fs::File::create(&file_path).expect("failed to create the build script file!");
-
-        let build_script_contents = self.spec.to_script(repo_dir.as_ref());
+        self.options = options;
+        self
+    }
 
-        match build_script.write_all(build_script_contents.as_bytes()) {
-            Ok(_) => (),
-            Err(e) => {
-                let msg = format!(
-                    "failed to write to the build script at {}!",
-                    &file_path.display()
-                );
-                return Err(err!(c_fs!(e, msg), "whilst writing the build script"));
-            }
-        };
+    /// Add a custom stage, to be run at `point`
+    /// in every build, after the built-in stages
+    /// there and any custom ones added before it.
+    pub fn with_stage<S: Stage + 'a>(mut self, point: StagePoint, stage: S) -> Self {
+        self.stages.push((point, Box::new(stage)));
+        self
+    }
 
-        util::make_file_executable(&file_path);
-        Ok(())
+    /// Use a different command runner, such
+    /// as a mock one in tests or dry runs.
+    pub fn with_runner<R: CommandRunner + 'static>(mut self, runner: R) -> Self {
+        self.runner = Box::new(runner);
+        self
     }
 
-    fn create_scripts_dir<P: AsRef<Path>>(&mut self, base_dir: P) -> BuilderResult<PathBuf> {
-        run_callback!(self.callbacks.new_setup_stage_cb, CreateScriptsDir);
+    /// Get a snapshot of the current state
+    /// of the build.
+    pub fn state(&self) -> BuildState {
+        self.state.snapshot()
+    }
 
-        let scripts_dir = base_dir.as_ref().join("scripts");
+    /// Get a handle to the state of the build,
+    /// that can be polled from another thread
+    /// whilst the build runs.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use smbuilder::prelude::*;
+    /// # let builder = Builder::new(Spec::default(), "base", Callbacks::new()).unwrap();
+    /// let state = builder.state_handle();
+    ///
+    /// // in the GUI's update loop:
+    /// let snapshot = state.snapshot();
+    /// println!("{:?} ({:?}%)", snapshot.phase, snapshot.percent);
+    /// ```
+    pub fn state_handle(&self) -> BuildStateHandle {
+        self.state.clone()
+    }
 
-        if !scripts_dir.exists() {
-            match fs::create_dir(&scripts_dir) {
-                Ok(_) => (),
-                Err(e) => {
-                    let msg = format!("failed to create the build scripts dir: {}", e);
-                    return Err(err!(
-                        c_fs!(e, msg),
-                        "whilst trying to create the build script directory"
-                    ));
-                }
-            }
-        }
+    /// Get the progress that the previous build
+    /// in the base dir saved, such as one that
+    /// was running when the launcher crashed.
+    ///
+    /// Building again resumes it. Once this
+    /// builder starts building, its own progress
+    /// replaces the previous build's.
+    pub fn previous_progress(&self) -> Option<SavedProgress> {
+        SavedProgress::load_from(&*self.fs, &self.base_dir)
+    }
 
-        Ok(scripts_dir)
+    /// Get the SHA-256 hash of a file
+    /// on the filesystem of the builder.
+    fn sha256_hex(&self, path: &Path) -> io::Result<String> {
+        util::sha256_read_hex(self.fs.open(path)?)
     }
 
-    fn write_scripts<P: AsRef<Path>>(&mut self, scripts_dir: P) {
-        run_callback!(self.callbacks.new_setup_stage_cb, WritePostBuildScripts);
+    /// Load the lockfile of the base dir.
+    fn load_lockfile(&self) -> BuilderResult<Lockfile> {
+        Lockfile::load_from(&*self.fs, &self.base_dir)
+    }
 
-        if let Some(scripts) = &mut self.spec.scripts {
-            for script in scripts {
-                let script_path = script.save(&scripts_dir).unwrap(); // BUG: unwrap
+    /// Save the lockfile into the base dir.
+    fn save_lockfile(&self, lockfile: &Lockfile) -> BuilderResult<()> {
+        lockfile.save_to(&*self.fs, &self.base_dir)
+    }
 
-                util::make_file_executable(&script_path);
-            }
-        }
+    /// Whether the build should stop, on
+    /// control-c or through `BuildStateHandle::cancel`.
+    fn should_stop(&self) -> bool {
+        self.state.is_cancelled()
     }
 
-    fn setup_build(&mut self) -> BuilderResult<()> {
-        use SetupStage::*;
+    /// Whether `stage` is left to run: it is not
+    /// in the `skip_stages` of the spec, and it
+    /// is in the `only_stages` of the options,
+    /// if those are set.
+    fn stage_enabled<S: Into<BuildStage>>(&self, stage: S) -> bool {
+        let stage = stage.into();
 
-        let needed_targets =
-            get_needed_setup_tasks(&self.spec, &self.base_dir, &mut self.callbacks);
+        let skipped = self
+            .spec
+            .skip_stages
+            .as_ref()
+            .is_some_and(|stages| stages.contains(&stage));
+        let picked = self
+            .options
+            .only_stages
+            .as_ref()
+            .is_none_or(|stages| stages.contains(&stage));
 
-        let repo_dir = self.base_dir.join(&self.spec.repo.name);
-        let scripts_dir = repo_dir.join("scripts");
+        !skipped && picked
+    }
 
-        for target in needed_targets {
-            match target {
-                CloneRepo => {
-                    let _ = self.clone_repo();
-                }
-                CopyRom => {
-                    self.copy_rom(&repo_dir)?;
-                }
-                CreateBuildScript => {
-                    self.create_build_script(&repo_dir)?;
-                }
-                CreateScriptsDir => {
-                    let _ = self.create_scripts_dir(self.base_dir.clone())?;
-                }
-                WritePostBuildScripts => self.write_scripts(&scripts_dir),
-            }
-        }
+    fn enter_setup_stage(&mut self, stage: SetupStage) {
+        self.state.set_phase(BuildPhase::Setup(stage));
+        run_callback!(self.callbacks.new_setup_stage_cb, stage);
+    }
 
-        Ok(())
+    fn enter_postbuild_stage(&mut self, stage: PostBuildStage) {
+        self.state.set_phase(BuildPhase::PostBuild(stage));
+        run_callback!(self.callbacks.new_postbuild_stage_cb, stage);
     }
 
-    fn compile(&mut self) {
-        let build_script_path = self.base_dir.join("build.sh").canonicalize().unwrap();
-        dbg!(&build_script_path);
-        let build_cmd = cmd!(build_script_path).stderr_to_stdout();
-        let output = build_cmd
-            .reader()
-            .unwrap_or_else(|e| panic!("failed to get a reader from the command: {}", e));
-        let reader = BufReader::new(output);
+    fn clone_repo(&mut self) -> BuilderResult<PathBuf> {
+        self.enter_setup_stage(CloneRepo);
 
-        for line in reader.lines() {
-            let ln = match line {
-                Ok(line) => line,
-                Err(e) => panic!("something went wrong: {}", e),
-            }; // exit when there is no more output
+        let repo_name = &self.spec.repo.name;
+        let repo_dir = self.base_dir.join(repo_name);
 
-            run_callback!(self.callbacks.log_cb, BuildOutput, &ln);
-        }
-    }
+        install_interrupt_handler(&self.state);
 
-    fn install_texture_pack(&mut self) -> BuilderResult<()> {
-        run_callback!(self.callbacks.new_postbuild_stage_cb, TexturePack);
+        // the clone is marked as incomplete until
+        // the checkout finishes, so that an interrupted
+        // clone is resumed on the next run instead of
+        // being thrown away.
+        let mut lockfile = self.load_lockfile()?;
+        let resuming = lockfile.repo_incomplete() && self.fs.exists(&repo_dir);
+        lockfile.repo = Some(LockedRepo {
+            url: self.spec.repo.url.clone(),
+            branch: self.spec.repo.branch.clone(),
+            commit: None,
+            complete: false,
+            ..Default::default()
+        });
+        self.save_lockfile(&lockfile)?;
 
-        let pack = if let Some(pack) = &self.spec.texture_pack {
-            pack
+        if resuming {
+            log!(self, Info, "resuming a previously interrupted clone");
         } else {
-            return Ok(());
+            log!(self, Info, "cloning the repository");
+        }
+
+        let repo_url = self.spec.repo.url.clone();
+        let clone_err = |e: git2::Error| {
+            let msg = e.message().to_string();
+            err!(
+                c_repo_clone!(repo_url.clone(), repo_dir.clone(), e),
+                format!("failed to clone the repository: {}", msg)
+            )
         };
 
-        let repo_dir = &self.base_dir.join(&self.spec.repo.name);
+        // with a shared store, the objects are
+        // fetched into it, and the build only gets
+        // a worktree of its own.
+        let shared_repos = self
+            .options
+            .shared_repos
+            .clone()
+            .or_else(|| self.config.cache_dir.as_ref().map(|dir| dir.join("repos")));
+        let store_dir = shared_repos.as_ref().map(|dir| {
+            dir.join(format!(
+                "{}-{}.git",
+                self.spec.repo.name,
+                &util::sha256_hex(&self.spec.repo.url)[..12]
+            ))
+        });
 
-        pack.install(&self.spec, repo_dir)?;
+        let repo = match &store_dir {
+            Some(store_dir) => match Repository::open_bare(store_dir) {
+                Ok(r) => r,
+                Err(_) => Repository::init_bare(store_dir).map_err(clone_err)?,
+            },
+            None => match Repository::open(&repo_dir) {
+                Ok(r) => r,
+                Err(_) => Repository::init(&repo_dir).map_err(clone_err)?,
+            },
+        };
 
-        Ok(())
-    }
+        let mut remote = match repo.find_remote("origin") {
+            Ok(r) => r,
+            Err(_) => repo
+                .remote("origin", &self.spec.repo.url)
+                .map_err(clone_err)?,
+        };
 
-    fn install_dynos_packs(&mut self) -> BuilderResult<()> {
-        run_callback!(self.callbacks.new_postbuild_stage_cb, DynOSPacks);
+        // `latest-release` is resolved to a tag,
+        // which is then fetched like a refspec.
+        let mut source = self.spec.repo.clone();
+        let resolved_tag = if source.branch == LATEST_RELEASE && source.refspec.is_none() {
+            let tag = self
+                .resolve_latest_release(&mut remote)
+                .map_err(clone_err)?;
+            log!(self, Info, &format!("the latest release is {}", tag));
 
-        let packs = if let Some(packs) = &self.spec.dynos_packs {
-            packs
+            source.refspec = Some(format!("refs/tags/{}", tag));
+            Some(tag)
         } else {
-            return Ok(());
+            None
         };
 
-        let repo_dir = &self.base_dir.join(&self.spec.repo.name);
+        let branch = self.spec.repo.branch.clone();
+        let refspecs = source.refspecs();
 
-        for pack in packs {
-            pack.install(&self.spec, repo_dir, &mut self.callbacks)?;
+        // libgit2 cannot make shallow or
+        // partial clones, so git makes them.
+        let has_git = self.has_program("git");
+        let depth = match self.spec.repo.depth {
+            Some(_) if !has_git => {
+                log!(
+                    self,
+                    Warn,
+                    "`git` was not found, so the whole history will be fetched"
+                );
+                None
+            }
+            depth => depth,
+        };
+        let filter = match self.spec.repo.filter.clone() {
+            Some(_) if store_dir.is_some() => {
+                log!(
+                    self,
+                    Warn,
+                    "partial clones are not made into shared repos, so every object will be fetched"
+                );
+                None
+            }
+            Some(_) if !has_git => {
+                log!(
+                    self,
+                    Warn,
+                    "`git` was not found, so every object will be fetched"
+                );
+                None
+            }
+            filter => filter,
+        };
+
+        if let Some(filter) = &filter {
+            // git only fetches with a filter from a
+            // promisor remote, which it later fetches
+            // the objects that were left out from.
+            let mut config = repo.config().map_err(clone_err)?;
+            config
+                .set_bool("remote.origin.promisor", true)
+                .map_err(clone_err)?;
+            config
+                .set_str("remote.origin.partialclonefilter", filter)
+                .map_err(clone_err)?;
         }
 
-        Ok(())
-    }
+        if depth.is_some() || filter.is_some() {
+            drop(remote);
 
-    fn run_postbuild_scripts(&mut self) -> BuilderResult<()> {
-        run_callback!(self.callbacks.new_postbuild_stage_cb, PostBuildScripts);
+            let mut fetch_cmd = self
+                .git_command()
+                .arg("--git-dir")
+                .arg(repo.path())
+                .arg("fetch");
+            if let Some(depth) = depth {
+                fetch_cmd = fetch_cmd.arg(format!("--depth={}", depth));
+            }
+            // servers that do not support filters
+            // ignore them, and send everything.
+            if let Some(filter) = &filter {
+                fetch_cmd = fetch_cmd.arg(format!("--filter={}", filter));
+            }
+            if !self.spec.repo.tags {
+                fetch_cmd = fetch_cmd.arg("--no-tags");
+            }
+            fetch_cmd = fetch_cmd.arg("origin");
+            for refspec in &refspecs {
+                fetch_cmd = fetch_cmd.arg(refspec);
+            }
 
-        let scripts = if let Some(scripts) = &self.spec.scripts {
-            scripts
+            self.run_git(fetch_cmd, "fetching the repository")?;
         } else {
+            let state = self.state.clone();
+            let deadline = self
+                .options
+                .timeouts
+                .clone
+                .map(|timeout| (Instant::now() + timeout, timeout));
+            let mut remote_callbacks = RemoteCallbacks::new();
+            remote_callbacks.transfer_progress(|progress| {
+                state.set_bytes_cloned(progress.received_bytes() as u64);
+
+                if progress.total_objects() > 0 {
+                    state.set_percent(
+                        progress.received_objects() as f32 * 100.0
+                            / progress.total_objects() as f32,
+                    );
+                }
+
+                run_callback!(
+                    self.callbacks.repo_clone_progress_cb,
+                    progress.received_objects(),
+                    progress.total_objects(),
+                    progress.received_bytes(),
+                );
+
+                if let Some((deadline, _)) = deadline {
+                    if Instant::now() > deadline {
+                        return false;
+                    }
+                }
+
+                // returning false aborts the transfer cleanly
+                !state.is_cancelled()
+            });
+
+            let mut fetch_options = FetchOptions::new();
+            fetch_options
+                .remote_callbacks(remote_callbacks)
+                .follow_redirects(git2::RemoteRedirect::All)
+                .download_tags(if self.spec.repo.tags {
+                    AutotagOption::Auto
+                } else {
+                    AutotagOption::None
+                });
+
+            if let Some(proxy) = &self.config.proxy {
+                let mut proxy_options = ProxyOptions::new();
+                proxy_options.url(proxy);
+                fetch_options.proxy_options(proxy_options);
+            }
+
+            let fetch_result = remote.fetch(&refspecs, Some(&mut fetch_options), None);
+            drop(remote);
+            drop(fetch_options);
+
+            if self.state.is_cancelled() {
+                log!(
+                    self,
+                    Warn,
+                    "the clone was interrupted; it has been kept and will be resumed on the next run"
+                );
+                return Err(err!(c_cancelled!()));
+            }
+
+            let timed_out = deadline.filter(|(deadline, _)| Instant::now() > *deadline);
+            if let (Err(_), Some((_, timeout))) = (&fetch_result, timed_out) {
+                return Err(err!(
+                    c_timed_out!(CloneRepo, timeout),
+                    "the clone has been kept, and will be resumed on the next run"
+                ));
+            }
+
+            fetch_result.map_err(clone_err)?;
+        }
+
+        let remote_ref = source.remote_ref();
+        let commit_id = repo
+            .find_reference(&remote_ref)
+            .and_then(|r| r.peel_to_commit())
+            .map(|c| c.id())
+            .map_err(clone_err)?;
+
+        // the checkout below is forced, which
+        // would throw away changes made by hand.
+        let dirty_action = self.handle_dirty_repo(&repo_dir, DirtyRepoAction::Stash)?;
+
+        if dirty_action == Some(DirtyRepoAction::Keep) {
+            log!(self, Warn, "the repo was not updated, to keep the changes");
+
+            lockfile.repo = Some(LockedRepo {
+                url: self.spec.repo.url.clone(),
+                branch: branch.clone(),
+                commit: util::head_commit(&repo_dir).map(|(id, _)| id),
+                complete: true,
+                resolved_tag,
+                dirty_action,
+                ..Default::default()
+            });
+            self.save_lockfile(&lockfile)?;
+
+            return Ok(repo_dir);
+        }
+
+        let (repo, local_branch) = match &store_dir {
+            Some(_) => {
+                let commit = repo.find_commit(commit_id).map_err(clone_err)?;
+                let worktree = self
+                    .add_worktree(&repo, &commit, &repo_dir)
+                    .map_err(clone_err)?;
+                (Repository::open(&repo_dir).map_err(clone_err)?, worktree)
+            }
+            None => (repo, branch.clone()),
+        };
+        let commit = repo.find_commit(commit_id).map_err(clone_err)?;
+
+        // unlike `branch`, this can move a
+        // branch that is already checked out.
+        repo.reference(
+            &format!("refs/heads/{}", local_branch),
+            commit.id(),
+            true,
+            "smbuilder: update the branch",
+        )
+        .map_err(clone_err)?;
+        repo.set_head(&format!("refs/heads/{}", local_branch))
+            .map_err(clone_err)?;
+        self.force_checkout(&repo, &repo_dir)?;
+
+        lockfile.repo = Some(LockedRepo {
+            url: self.spec.repo.url.clone(),
+            branch: branch.clone(),
+            commit: Some(commit.id().to_string()),
+            complete: true,
+            resolved_tag,
+            dirty_action,
+            ..Default::default()
+        });
+        self.save_lockfile(&lockfile)?;
+
+        Ok(repo_dir)
+    }
+
+    /// Check out the HEAD of `repo`, throwing
+    /// away the changes to its files.
+    fn force_checkout(&mut self, repo: &Repository, repo_dir: &Path) -> BuilderResult<()> {
+        // libgit2 cannot fetch the objects that a
+        // partial clone left out, so git checks
+        // out the files of one.
+        let partial = repo
+            .config()
+            .and_then(|config| config.get_bool("remote.origin.promisor"))
+            .unwrap_or(false);
+
+        if partial {
+            let checkout_cmd = self
+                .git_command()
+                .arg("-C")
+                .arg(repo_dir)
+                .arg("checkout")
+                .arg("--force");
+            return self.run_git(checkout_cmd, "checking out the repository");
+        }
+
+        repo.checkout_head(Some(CheckoutBuilder::new().force()))
+            .map_err(|e| {
+                let msg = e.message().to_string();
+                err!(
+                    c_repo_clone!(self.spec.repo.url.clone(), repo_dir.to_owned(), e),
+                    format!("failed to check out the repository: {}", msg)
+                )
+            })
+    }
+
+    /// Gets a `git` command, going
+    /// through the proxy if one is set.
+    fn git_command(&self) -> Command {
+        let git_cmd = Command::new("git");
+
+        match &self.config.proxy {
+            Some(proxy) => git_cmd.arg("-c").arg(format!("http.proxy={}", proxy)),
+            None => git_cmd,
+        }
+    }
+
+    /// Run a `git` command that only looks at
+    /// the repo, and get the lines that it
+    /// prints, or `None` if it failed.
+    fn git_output(&self, git_cmd: &Command) -> Option<Vec<String>> {
+        let limits = Limits {
+            timeout: self.options.timeouts.clone,
+            inactivity: self.options.timeouts.inactivity,
+        };
+
+        let state = self.state.clone();
+        let should_stop = || state.is_cancelled();
+        let mut lines = Vec::new();
+        let outcome = self.runner.run(git_cmd, limits, &should_stop, &mut |ln| {
+            lines.push(util::strip_ansi(ln).into_owned());
+        });
+
+        match outcome {
+            Ok(Outcome::Exited(true)) => Some(lines),
+            _ => None,
+        }
+    }
+
+    /// Run a command that checks the host (like
+    /// `pkg-config --exists`), and get whether it
+    /// exited successfully, or `None` if it could
+    /// not be run at all.
+    fn probe(&self, command: Command) -> Option<bool> {
+        backend::probe(&*self.runner, command).map(|(ok, _)| ok)
+    }
+
+    /// Whether a program can be run at all,
+    /// like `deps::has_program`.
+    fn has_program(&self, program: &str) -> bool {
+        self.probe(Command::new(program).arg("--version")).is_some()
+    }
+
+    /// Get the first line that a program
+    /// prints for `--version`, if it can
+    /// be run, like `deps::program_version`.
+    fn program_version(&self, program: &str) -> Option<String> {
+        let (_, lines) = backend::probe(&*self.runner, Command::new(program).arg("--version"))?;
+
+        lines
+            .iter()
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty())
+            .map(str::to_owned)
+    }
+
+    /// Get the modules of `modules` that
+    /// `python` cannot import, like
+    /// `deps::missing_python_modules`.
+    fn missing_python_modules(&self, python: &str, modules: &[&str]) -> Vec<String> {
+        let command = modules.iter().fold(
+            Command::new(python)
+                .arg("-c")
+                .arg(deps::FIND_MISSING_MODULES),
+            |command, module| command.arg(*module),
+        );
+        let Some((_, lines)) = backend::probe(&*self.runner, command) else {
+            return Vec::new();
+        };
+
+        lines
+            .iter()
+            .flat_map(|line| line.split_whitespace())
+            .filter(|module| modules.contains(module))
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// Get the GNU make command of the
+    /// host, like `deps::make_command`.
+    fn make_command(&self) -> Option<&'static str> {
+        let makes: &[&'static str] = if deps::IS_BSD {
+            &["gmake"]
+        } else {
+            &["gmake", "make"]
+        };
+
+        makes.iter().copied().find(|make| self.has_program(make))
+    }
+
+    /// Run a `git` command that is part
+    /// of cloning or updating the repo.
+    fn run_git(&mut self, git_cmd: Command, action: &str) -> BuilderResult<()> {
+        let repo_dir = self.base_dir.join(&self.spec.repo.name);
+        let limits = Limits {
+            timeout: self.options.timeouts.clone,
+            inactivity: self.options.timeouts.inactivity,
+        };
+
+        log!(self, Debug, &format!("running {}", git_cmd));
+
+        let state = self.state.clone();
+        let should_stop = || state.is_cancelled();
+        let ansi = self.options.ansi;
+        let log_cb = &mut self.callbacks.log_cb;
+        let outcome = self.runner.run(&git_cmd, limits, &should_stop, &mut |ln| {
+            state.log_line(&util::strip_ansi(ln));
+            run_callback!(*log_cb, Info, &ansi.apply(ln));
+        });
+
+        match outcome {
+            Ok(Outcome::Exited(true)) => Ok(()),
+            Ok(Outcome::Exited(false)) => Err(err!(
+                c_repo_clone!(self.spec.repo.url.clone(), repo_dir),
+                format!("git exited with an error whilst {}", action)
+            )),
+            Ok(Outcome::TimedOut(after)) => Err(err!(
+                c_timed_out!(CloneRepo, after),
+                "the clone has been kept, and will be resumed on the next run"
+            )),
+            Ok(Outcome::Inactive(after)) | Ok(Outcome::Prompted(after, _)) => Err(err!(
+                c_timed_out!(CloneRepo, after, true),
+                "the clone has been kept, and will be resumed on the next run"
+            )),
+            Ok(Outcome::Stopped) => {
+                log!(
+                    self,
+                    Warn,
+                    "the clone was interrupted; it has been kept and will be resumed on the next run"
+                );
+                Err(err!(c_cancelled!()))
+            }
+            Err(e) => Err(err!(
+                c_spawn_cmd!(git_cmd.to_string(), "failed to start git", e),
+                format!("whilst {}", action)
+            )),
+        }
+    }
+
+    /// Find the files of the repo in `repo_dir`
+    /// (if it has been checked out before) that
+    /// were changed by hand, and ask the dirty
+    /// repo callback what to do with them.
+    ///
+    /// Without the callback, `default` is done.
+    /// Returns what was done, if there were
+    /// any changes.
+    fn handle_dirty_repo(
+        &mut self,
+        repo_dir: &Path,
+        default: DirtyRepoAction,
+    ) -> BuilderResult<Option<DirtyRepoAction>> {
+        if !self.fs.exists(&repo_dir.join(".git")) {
+            return Ok(None);
+        }
+
+        // untracked files are left alone by the
+        // checkout, so only changes to the files
+        // that git tracks are at risk.
+        let status_cmd = self
+            .git_command()
+            .arg("-c")
+            .arg("core.quotePath=false")
+            .arg("-C")
+            .arg(repo_dir)
+            .arg("status")
+            .arg("--porcelain")
+            .arg("--untracked-files=no")
+            .stdin(Stdin::Null);
+        let Some(lines) = self.git_output(&status_cmd) else {
+            return Ok(None);
+        };
+
+        // each line is the status (two letters),
+        // a space, and the path.
+        let changed: Vec<String> = lines
+            .iter()
+            .filter(|line| line.get(2..3) == Some(" "))
+            .filter_map(|line| line.get(3..))
+            .map(str::to_owned)
+            .collect();
+
+        if changed.is_empty() {
+            return Ok(None);
+        }
+
+        let action = match &mut self.callbacks.dirty_repo_cb {
+            Some(callback) => {
+                let changed: Vec<String> = changed.iter().map(|p| redact(p).into()).collect();
+                callback(&changed)
+            }
+            None => default,
+        };
+
+        match action {
+            DirtyRepoAction::Keep => log!(
+                self,
+                Info,
+                &format!(
+                    "building with the changes to {} file(s) of the repo",
+                    changed.len()
+                )
+            ),
+            DirtyRepoAction::Stash => {
+                // git needs a name to stash under,
+                // which the user may not have set.
+                let stash_cmd = self
+                    .git_command()
+                    .arg("-c")
+                    .arg("user.name=smbuilder")
+                    .arg("-c")
+                    .arg("user.email=smbuilder@localhost")
+                    .arg("-C")
+                    .arg(repo_dir)
+                    .arg("stash")
+                    .arg("push")
+                    .arg("-m")
+                    .arg("smbuilder: changes made by hand")
+                    .stdin(Stdin::Null);
+                self.run_git(stash_cmd, "stashing the changes to the repository")?;
+                log!(
+                    self,
+                    Warn,
+                    &format!(
+                        "stashed the changes to {} file(s) of the repo; `git stash pop` gets them back",
+                        changed.len()
+                    )
+                );
+            }
+            DirtyRepoAction::Discard => {
+                log!(
+                    self,
+                    Warn,
+                    &format!(
+                        "throwing away the changes to {} file(s) of the repo",
+                        changed.len()
+                    )
+                );
+                let checkout_cmd = self
+                    .git_command()
+                    .arg("-C")
+                    .arg(repo_dir)
+                    .arg("checkout")
+                    .arg("--force")
+                    .stdin(Stdin::Null);
+                self.run_git(checkout_cmd, "throwing away the changes to the repository")?;
+            }
+            DirtyRepoAction::Abort => {
+                return Err(err!(
+                    c_cancelled!(),
+                    format!(
+                        "{} file(s) of the repo were changed by hand, and were left alone",
+                        changed.len()
+                    )
+                ))
+            }
+        }
+
+        Ok(Some(action))
+    }
+
+    /// Find the tag of the latest release
+    /// of the repo, by listing its tags.
+    fn resolve_latest_release(&self, remote: &mut Remote) -> Result<String, git2::Error> {
+        let mut proxy_options = ProxyOptions::new();
+        if let Some(proxy) = &self.config.proxy {
+            proxy_options.url(proxy);
+        }
+
+        let connection = remote.connect_auth(Direction::Fetch, None, Some(proxy_options))?;
+        let tags: Vec<String> = connection
+            .list()?
+            .iter()
+            .filter_map(|head| head.name().strip_prefix("refs/tags/"))
+            // the peeled versions of annotated tags
+            .filter(|name| !name.ends_with("^{}"))
+            .map(str::to_owned)
+            .collect();
+
+        latest_release_tag(&tags).ok_or_else(|| {
+            git2::Error::from_str("the repository has no tags to find the latest release in")
+        })
+    }
+
+    /// Download the release of a prebuilt port,
+    /// check it, and unpack it into the build
+    /// directory.
+    fn download_prebuilt(&mut self) -> BuilderResult<()> {
+        self.enter_setup_stage(DownloadPrebuilt);
+
+        let (url, checksum, executable) = match &self.spec.source {
+            SourceKind::Prebuilt {
+                url,
+                checksum,
+                executable,
+            } => (url.clone(), checksum.clone(), executable.clone()),
+            SourceKind::Source => return Ok(()),
+        };
+
+        let fs_err = |e: std::io::Error, path: &Path| {
+            let msg = format!("failed to write to {}", path.display());
+            err!(c_fs!(e, msg), "whilst downloading the prebuilt release")
+        };
+
+        let downloads_dir = self.base_dir.join("downloads");
+        self.fs
+            .create_dir_all(&downloads_dir)
+            .map_err(|e| fs_err(e, &downloads_dir))?;
+
+        let file_name = url
+            .rsplit('/')
+            .next()
+            .and_then(|name| name.split(['?', '#']).next())
+            .filter(|name| !name.is_empty())
+            .unwrap_or("release.zip");
+        let archive = downloads_dir.join(file_name);
+
+        log!(self, Info, &format!("downloading {}", url));
+        download_release(&*self.fs, &url, &archive)?;
+
+        let actual = self.sha256_hex(&archive).map_err(|e| fs_err(e, &archive))?;
+        if !actual.eq_ignore_ascii_case(checksum.trim()) {
+            let _ = self.fs.remove_file(&archive);
+            return Err(err!(
+                c_checksum!(url, checksum, actual),
+                "the release does not match the checksum in the spec"
+            ));
+        }
+
+        // unpacked next to the build dir first, so
+        // that a bad archive does not take the
+        // previous release down with it.
+        let build_dir = self
+            .spec
+            .build_dir(self.base_dir.join(&self.spec.repo.name));
+        let unpack_dir = build_dir.with_extension("part");
+        let _ = self.fs.remove_dir_all(&unpack_dir);
+        self.fs
+            .create_dir_all(&unpack_dir)
+            .map_err(|e| fs_err(e, &unpack_dir))?;
+
+        let lowercase_name = file_name.to_lowercase();
+        if lowercase_name.ends_with(".zip") {
+            unzip_release(&*self.fs, &archive, &unpack_dir)?;
+        } else {
+            self.untar_release(&archive, &unpack_dir)?;
+        }
+
+        // most releases have everything in
+        // a single folder, which is skipped.
+        let entries = self
+            .fs
+            .read_dir(&unpack_dir)
+            .map_err(|e| fs_err(e, &unpack_dir))?;
+        let root = match entries.as_slice() {
+            [only] if self.fs.is_dir(only) => only.clone(),
+            _ => unpack_dir.clone(),
+        };
+
+        let _ = self.fs.remove_dir_all(&build_dir);
+        self.fs
+            .rename(&root, &build_dir)
+            .map_err(|e| fs_err(e, &build_dir))?;
+        let _ = self.fs.remove_dir_all(&unpack_dir);
+        let _ = self.fs.remove_file(&archive);
+
+        if let Some(executable) = executable {
+            self.fs.make_executable(&build_dir.join(executable))?;
+        }
+
+        let mut lockfile = self.load_lockfile()?;
+        lockfile.prebuilt = Some(checksum);
+        self.save_lockfile(&lockfile)
+    }
+
+    /// Unpack a tarball with `tar`, which
+    /// knows every kind of compression.
+    fn untar_release(&mut self, archive: &Path, dest: &Path) -> BuilderResult<()> {
+        let tar_cmd = Command::new("tar")
+            .arg("-xf")
+            .arg(archive)
+            .arg("-C")
+            .arg(dest)
+            .stdin(Stdin::Null);
+        let limits = Limits {
+            timeout: None,
+            inactivity: self.options.timeouts.inactivity,
+        };
+
+        log!(self, Debug, &format!("running {}", tar_cmd));
+
+        let state = self.state.clone();
+        let should_stop = || state.is_cancelled();
+        let log_cb = &mut self.callbacks.log_cb;
+        let outcome = self.runner.run(&tar_cmd, limits, &should_stop, &mut |ln| {
+            run_callback!(*log_cb, Info, ln);
+        });
+
+        match outcome {
+            Ok(Outcome::Exited(true)) => Ok(()),
+            Ok(Outcome::Exited(false)) => Err(err!(
+                c_spawn_cmd!("tar".to_owned()),
+                "failed to unpack the release"
+            )),
+            Ok(Outcome::TimedOut(after)) => Err(err!(c_timed_out!(DownloadPrebuilt, after))),
+            Ok(Outcome::Inactive(after)) | Ok(Outcome::Prompted(after, _)) => {
+                Err(err!(c_timed_out!(DownloadPrebuilt, after, true)))
+            }
+            Ok(Outcome::Stopped) => Err(err!(c_cancelled!())),
+            Err(e) => Err(err!(
+                c_spawn_cmd!("tar".to_owned(), "failed to start tar", e),
+                "whilst unpacking the release"
+            )),
+        }
+    }
+
+    /// Make sure that the repo dir is a worktree
+    /// of the shared store, and return the name of
+    /// the branch that it has checked out.
+    ///
+    /// Every base dir gets a branch of its own, as
+    /// git does not allow one branch to be checked
+    /// out in two worktrees.
+    fn add_worktree(
+        &self,
+        store: &Repository,
+        commit: &git2::Commit,
+        repo_dir: &Path,
+    ) -> Result<String, git2::Error> {
+        let base_dir = self
+            .base_dir
+            .canonicalize()
+            .unwrap_or(self.base_dir.clone());
+        let name = format!(
+            "{}-{}",
+            self.spec.repo.branch,
+            &util::sha256_hex(base_dir.to_string_lossy().as_bytes())[..12]
+        )
+        .replace('/', "-");
+        let local_branch = format!("smbuilder/{}", name);
+
+        if Repository::open(repo_dir).is_ok() {
+            return Ok(local_branch);
+        }
+
+        // the worktree of a repo dir that
+        // was deleted is still registered.
+        if let Ok(worktree) = store.find_worktree(&name) {
+            worktree.prune(Some(
+                git2::WorktreePruneOptions::new()
+                    .valid(true)
+                    .working_tree(true),
+            ))?;
+        }
+
+        let reference = store.branch(&local_branch, commit, true)?.into_reference();
+        store.worktree(
+            &name,
+            repo_dir,
+            Some(git2::WorktreeAddOptions::new().reference(Some(&reference))),
+        )?;
+
+        Ok(local_branch)
+    }
+
+    fn fetch_lfs_objects(&mut self) -> BuilderResult<()> {
+        let repo_dir = self.base_dir.join(&self.spec.repo.name);
+
+        if !lfs::repo_uses_lfs_on(&*self.fs, &repo_dir) {
+            return Ok(());
+        }
+
+        self.enter_setup_stage(FetchLfsObjects);
+        log!(
+            self,
+            Info,
+            "the repository uses Git LFS, fetching the LFS objects"
+        );
+
+        let output = lfs::fetch_lfs_objects_with(&*self.runner, &repo_dir)?;
+        for line in output.lines() {
+            log!(self, Info, line);
+        }
+
+        let mut lockfile = self.load_lockfile()?;
+        if let Some(repo) = &mut lockfile.repo {
+            repo.lfs_fetched = true;
+        }
+        self.save_lockfile(&lockfile)?;
+
+        Ok(())
+    }
+
+    fn download_discord_sdk(&mut self) -> BuilderResult<()> {
+        let Some(sdk) = self.spec.discord_sdk.clone() else {
+            return Ok(());
+        };
+
+        self.enter_setup_stage(DownloadDiscordSdk);
+
+        let fs_err = |e: std::io::Error, path: &Path| {
+            let msg = format!("failed to write to {}", path.display());
+            err!(c_fs!(e, msg), "whilst downloading the Discord game SDK")
+        };
+
+        // the SDK is the same for every build,
+        // so it is kept in the shared cache.
+        let cache_dir = self
+            .config
+            .effective_cache_dir()
+            .map(|dir| dir.join("discord-sdk"))
+            .unwrap_or(self.base_dir.join("downloads"));
+        self.fs
+            .create_dir_all(&cache_dir)
+            .map_err(|e| fs_err(e, &cache_dir))?;
+
+        let checksum = sdk.checksum.trim().to_lowercase();
+        let archive = cache_dir.join(format!("{}.zip", checksum));
+
+        let cached = self
+            .sha256_hex(&archive)
+            .map(|actual| actual == checksum)
+            .unwrap_or(false);
+
+        if cached {
+            log!(self, Info, "using the cached Discord game SDK");
+        } else {
+            log!(self, Info, &format!("downloading {}", sdk.url));
+            download_release(&*self.fs, &sdk.url, &archive)?;
+
+            let actual = self.sha256_hex(&archive).map_err(|e| fs_err(e, &archive))?;
+            if actual != checksum {
+                let _ = self.fs.remove_file(&archive);
+                return Err(err!(
+                    c_checksum!(sdk.url, sdk.checksum, actual),
+                    "the Discord game SDK does not match the checksum in the spec"
+                ));
+            }
+        }
+
+        let unpack_dir = self.base_dir.join("downloads").join("discord-sdk");
+        let _ = self.fs.remove_dir_all(&unpack_dir);
+        self.fs
+            .create_dir_all(&unpack_dir)
+            .map_err(|e| fs_err(e, &unpack_dir))?;
+        unzip_release(&*self.fs, &archive, &unpack_dir)?;
+
+        let install_dir = DiscordSdk::install_dir(self.base_dir.join(&self.spec.repo.name));
+        self.fs
+            .create_dir_all(&install_dir)
+            .map_err(|e| fs_err(e, &install_dir))?;
+
+        for (path, name) in DiscordSdk::host_files() {
+            let dest = install_dir.join(name);
+            self.fs
+                .copy(&unpack_dir.join(&path), &dest)
+                .map_err(|e| fs_err(e, &dest))?;
+        }
+
+        let _ = self.fs.remove_dir_all(&unpack_dir);
+        Ok(())
+    }
+
+    /// Get the directory that downloaded
+    /// packs and patches are cached in.
+    fn download_cache_dir(&self) -> BuilderResult<PathBuf> {
+        let cache_dir = self
+            .config
+            .effective_cache_dir()
+            .map(|dir| dir.join("downloads"))
+            .unwrap_or(self.base_dir.join("downloads"));
+
+        match self.fs.create_dir_all(&cache_dir) {
+            Ok(_) => Ok(cache_dir),
+            Err(e) => {
+                let msg = format!("failed to create {}", cache_dir.display());
+                Err(err!(
+                    c_fs!(e, msg),
+                    "whilst downloading the packs and patches"
+                ))
+            }
+        }
+    }
+
+    fn download_resources(&mut self) -> BuilderResult<()> {
+        let downloads = pending_downloads(&*self.fs, &self.spec);
+        if downloads.is_empty() {
+            return Ok(());
+        }
+
+        self.enter_setup_stage(DownloadResources);
+        let cache_dir = self.download_cache_dir()?;
+
+        let total = downloads.len();
+        let mut downloaded = 0;
+        let (state, log_cb) = (&self.state, &mut self.callbacks.log_cb);
+
+        download_all(&*self.fs, &downloads, &cache_dir, &mut |event| {
+            let line = match event {
+                DownloadEvent::Started(name) => format!("downloading {}", name),
+                DownloadEvent::Done(name, _) => {
+                    downloaded += 1;
+                    state.set_percent(downloaded as f32 * 100.0 / total as f32);
+                    format!("downloaded {} ({}/{})", name, downloaded, total)
+                }
+            };
+
+            state.log_line(&line);
+            run_callback!(*log_cb, Info, &line);
+        })
+    }
+
+    /// Start downloading the packs on another
+    /// thread, as they are not needed until
+    /// after the port is compiled.
+    fn start_background_downloads(&mut self) -> BuilderResult<()> {
+        // the downloads of an earlier build that
+        // failed are not left to race these.
+        let _ = self.finish_background_downloads();
+
+        let downloads = pending_downloads(&*self.fs, &self.spec);
+        if downloads.is_empty() {
+            return Ok(());
+        }
+
+        self.enter_setup_stage(DownloadResources);
+        let cache_dir = self.download_cache_dir()?;
+
+        log!(
+            self,
+            Info,
+            &format!(
+                "downloading {} packs while the port compiles",
+                downloads.len()
+            )
+        );
+
+        let (state, fs) = (self.state.clone(), self.fs.clone());
+        self.background_downloads = Some(thread::spawn(move || {
+            download_all(&*fs, &downloads, &cache_dir, &mut |event| {
+                if let DownloadEvent::Done(name, _) = event {
+                    state.log_line(&format!("downloaded {}", name));
+                }
+            })
+        }));
+
+        Ok(())
+    }
+
+    /// Wait for the packs that are being
+    /// downloaded in the background, if any.
+    fn finish_background_downloads(&mut self) -> BuilderResult<()> {
+        let Some(handle) = self.background_downloads.take() else {
+            return Ok(());
+        };
+
+        if !handle.is_finished() {
+            log!(self, Info, "waiting for the packs to finish downloading");
+        }
+
+        match handle.join() {
+            Ok(result) => result,
+            Err(_) => {
+                let e = std::io::Error::other("the download thread panicked");
+                Err(err!(c_other!(e), "whilst downloading the packs"))
+            }
+        }
+    }
+
+    fn copy_rom<P: AsRef<Path>>(&mut self, repo_dir: P) -> BuilderResult<()> {
+        self.enter_setup_stage(CopyRom);
+
+        let roms: Vec<Rom> = self.spec.roms().cloned().collect();
+
+        for rom in &roms {
+            let target_rom_path = repo_dir.as_ref().join(rom.region.baserom_name());
+
+            // only the roms that are missing
+            // (or bad) are copied again.
+            if !baserom_is_good_on(&*self.fs, &target_rom_path, &rom.region) {
+                self.copy_one_rom(rom, &target_rom_path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn copy_one_rom(&mut self, rom: &Rom, target_rom_path: &Path) -> BuilderResult<()> {
+        use RomType::*;
+
+        let rom_type = rom.format;
+
+        // the base ROM in the repo was meant to
+        // be reused, but it is missing or bad.
+        if rom.path.as_os_str().is_empty() {
+            let no_rom_error = io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "`rom.path` is not set, and {} is missing or bad",
+                    target_rom_path.display()
+                ),
+            );
+            return Err(err!(c_fs!(no_rom_error), "no ROM was given"));
+        }
+
+        log!(
+            self,
+            Info,
+            &format!("copying the ROM to {}", rom.region.baserom_name())
+        );
+
+        let result = if rom_type == BigEndian {
+            match self.fs.copy(&rom.path, target_rom_path) {
+                Ok(_) => Ok(()),
+                Err(e) => {
+                    let msg = format!(
+                        "failed to copy the ROM from {} to {}!",
+                        &rom.path.display(),
+                        target_rom_path.display()
+                    );
+                    Err(err!(c_fs!(e, msg), "whilst copying the ROM file"))
+                }
+            }
+        } else {
+            log!(self, Warn, "the ROM is not a z64 format ROM!");
+            log!(self, Warn, &format!("converting from a {:?} ROM", rom_type));
+
+            let state = &self.state;
+            let result = convert_rom_with_fs(
+                &*self.fs,
+                &rom.path,
+                target_rom_path,
+                rom_type,
+                &mut |done, total| state.set_percent(done as f32 * 100.0 / total.max(1) as f32),
+            );
+
+            match result {
+                Ok(_) => Ok(()),
+                Err(e) => {
+                    let msg = format!(
+                        "failed to convert the ROM from {} to {}!",
+                        &rom.path.display(),
+                        target_rom_path.display()
+                    );
+                    Err(err!(c_fs!(e, msg), "whilst converting the ROM file"))
+                }
+            }
+        };
+        result?;
+
+        self.verify_baserom(rom, target_rom_path)
+    }
+
+    /// Check the checksum of the copied base
+    /// ROM, so that a bad dump fails here rather
+    /// than partway through extracting assets.
+    fn verify_baserom(&mut self, rom: &Rom, path: &Path) -> BuilderResult<()> {
+        let expected = rom.region.baserom_sha1();
+
+        let actual = match self.fs.open(path).and_then(util::sha1_read_hex) {
+            Ok(h) => h,
+            Err(e) => {
+                let msg = format!("failed to read the base ROM at {}", path.display());
+                return Err(err!(c_fs!(e, msg), "whilst verifying the ROM"));
+            }
+        };
+
+        if actual == expected {
+            return Ok(());
+        }
+
+        // do not leave the bad ROM behind, or
+        // the next build would skip copying it.
+        let _ = self.fs.remove_file(path);
+
+        Err(err!(
+            c_checksum!(rom.path.display().to_string(), expected.to_owned(), actual),
+            format!(
+                "the ROM is not a good {} ROM (is the region or format wrong?)",
+                rom.region
+            )
+        ))
+    }
+
+    /// Apply the patches of the spec to the repo,
+    /// skipping the ones that are applied already
+    /// (like those that `Build::diff_to_patch`
+    /// made from the changes in the repo).
+    fn apply_patches(&mut self, repo_dir: &Path) -> BuilderResult<()> {
+        let patches = self.spec.patches.clone().unwrap_or_default();
+        if patches.is_empty() {
+            return Ok(());
+        }
+
+        self.enter_setup_stage(ApplyPatches);
+
+        for patch in &patches {
+            if self.should_stop() {
+                return Err(err!(c_cancelled!()));
+            }
+
+            let action = format!("whilst applying the patch {}", patch.name);
+
+            // git runs in the repo, where a
+            // relative path would not be found.
+            let path = std::path::absolute(&patch.path).unwrap_or_else(|_| patch.path.clone());
+            if !self.fs.exists(&path) {
+                return Err(err!(
+                    c_invalid_spec!("patches", format!("{} does not exist", path.display())),
+                    action
+                ));
+            }
+
+            let apply = self.git_command().dir(repo_dir).arg("apply");
+            let applied = apply.clone().arg("--reverse").arg("--check").arg(&path);
+            if self.git_output(&applied).is_some() {
+                log!(
+                    self,
+                    Info,
+                    &format!("the patch {} is already applied", patch.name)
+                );
+                continue;
+            }
+
+            log!(self, Info, &format!("applying the patch {}", patch.name));
+            let git_cmd = apply.arg(&path);
+            log!(self, Debug, &format!("running {}", git_cmd));
+
+            let limits = Limits {
+                timeout: self.options.timeouts.clone,
+                inactivity: self.options.timeouts.inactivity,
+            };
+            let state = self.state.clone();
+            let should_stop = || state.is_cancelled();
+            let mut output = Vec::new();
+            let outcome = self.runner.run(&git_cmd, limits, &should_stop, &mut |ln| {
+                output.push(util::strip_ansi(ln).into_owned());
+            });
+
+            match outcome {
+                Ok(Outcome::Exited(true)) => {}
+                Ok(Outcome::Stopped) => return Err(err!(c_cancelled!())),
+                Ok(_) => {
+                    for line in &output {
+                        log!(self, Warn, line);
+                    }
+                    return Err(err!(
+                        c_invalid_spec!(
+                            "patches",
+                            format!("{} does not apply to the repo", path.display())
+                        ),
+                        action
+                    ));
+                }
+                Err(e) => {
+                    return Err(err!(
+                        c_spawn_cmd!(git_cmd.to_string(), "failed to start git", e),
+                        action
+                    ))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn create_build_script<P: AsRef<Path>>(&mut self, repo_dir: P) -> BuilderResult<()> {
+        let file_path = self.base_dir.join(self.spec.build_script_name());
+
+        let build_script_contents = self.spec.build_script(repo_dir.as_ref())?;
+
+        // rewriting an identical script would bump
+        // its mtime, which makes the executable
+        // look stale and recompiles the port.
+        let unchanged = self.fs.read(&file_path).is_ok_and(|existing| {
+            util::sha256_hex(existing) == util::sha256_hex(&build_script_contents)
+        });
+        if unchanged {
+            log!(self, Info, "the build script is already up to date");
+            return self.fs.make_executable(&file_path);
+        }
+
+        self.enter_setup_stage(CreateBuildScript);
+
+        if let Err(e) = self.fs.write(&file_path, build_script_contents.as_bytes()) {
+            let msg = format!(
+                "failed to write to the build script at {}!",
+                &file_path.display()
+            );
+            return Err(err!(c_fs!(e, msg), "whilst writing the build script"));
+        }
+
+        self.fs.make_executable(&file_path)?;
+        Ok(())
+    }
+
+    fn create_scripts_dir<P: AsRef<Path>>(&mut self, base_dir: P) -> BuilderResult<PathBuf> {
+        self.enter_setup_stage(CreateScriptsDir);
+
+        let scripts_dir = base_dir.as_ref().join("scripts");
+
+        if !self.fs.exists(&scripts_dir) {
+            match self.fs.create_dir_all(&scripts_dir) {
+                Ok(_) => (),
+                Err(e) => {
+                    let msg = format!("failed to create the build scripts dir: {}", e);
+                    return Err(err!(
+                        c_fs!(e, msg),
+                        "whilst trying to create the build script directory"
+                    ));
+                }
+            }
+        }
+
+        Ok(scripts_dir)
+    }
+
+    fn write_scripts<P: AsRef<Path>>(&mut self, scripts_dir: P) -> BuilderResult<()> {
+        self.enter_setup_stage(WritePostBuildScripts);
+
+        let hooks = self.spec.hooks.iter_mut().flat_map(Hooks::scripts_mut);
+        for script in self.spec.scripts.iter_mut().flatten().chain(hooks) {
+            let script_path = script.save(&scripts_dir)?;
+
+            self.fs.make_executable(&script_path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove whatever a failed setup stage left
+    /// behind, so that the next run does not
+    /// mistake a half-finished stage for a
+    /// finished one.
+    fn rollback_stage(&mut self, stage: &SetupStage, artifacts: &[PathBuf]) {
+        log!(
+            self,
+            Warn,
+            &format!("rolling back the failed stage: {}", stage)
+        );
+
+        for artifact in artifacts {
+            let result = if self.fs.is_dir(artifact) {
+                self.fs.remove_dir_all(artifact)
+            } else if self.fs.exists(artifact) {
+                self.fs.remove_file(artifact)
+            } else {
+                continue;
+            };
+
+            if let Err(e) = result {
+                log!(
+                    self,
+                    Warn,
+                    &format!(
+                        "failed to remove {}, please remove it manually: {}",
+                        artifact.display(),
+                        e
+                    )
+                );
+            }
+        }
+    }
+
+    /// Run the setup stages that are needed.
+    ///
+    /// With `overlap`, the stages that the compile
+    /// does not need (the pack downloads) are
+    /// left running in the background.
+    fn setup_build(&mut self, overlap: bool) -> BuilderResult<()> {
+        use SetupStage::*;
+
+        if self.spec.jobs.is_none() {
+            self.spec.jobs = self.config.jobs;
+        }
+
+        let host = self
+            .host
+            .get_or_insert_with(|| HostCapabilities::detect_with(&*self.runner))
+            .clone();
+        self.spec.set_host(&host);
+
+        for warning in host.warnings(&self.base_dir) {
+            log!(self, Warn, &warning);
+        }
+
+        let needed_targets =
+            needed_setup_tasks(&*self.fs, &self.spec, &self.base_dir, &mut self.callbacks);
+
+        let repo_dir = self.base_dir.join(&self.spec.repo.name);
+        let scripts_dir = self.base_dir.join("scripts");
+
+        // a clone checks the repo itself, before it
+        // is updated; a rebuild checks it here, before
+        // the stages that change its files.
+        if !self.spec.is_prebuilt() && !needed_targets.contains(&CloneRepo) {
+            if let Some(action) = self.handle_dirty_repo(&repo_dir, DirtyRepoAction::Keep)? {
+                let mut lockfile = self.load_lockfile()?;
+                if let Some(repo) = &mut lockfile.repo {
+                    repo.dirty_action = Some(action);
+                }
+                self.save_lockfile(&lockfile)?;
+            }
+        }
+
+        for target in needed_targets {
+            if self.should_stop() {
+                return Err(err!(c_cancelled!()));
+            }
+
+            if !self.stage_enabled(target) {
+                log!(self, Info, &format!("skipping the stage to {}", target));
+                continue;
+            }
+
+            // only the artifacts that this stage is
+            // about to create may be rolled back.
+            let new_artifacts = get_setup_stage_artifacts(&target, &self.spec, &self.base_dir)
+                .into_iter()
+                .filter(|artifact| !self.fs.exists(artifact))
+                .collect::<Vec<PathBuf>>();
+
+            let result = match target {
+                CloneRepo => self.clone_repo().map(|_| ()),
+                DownloadPrebuilt => self.download_prebuilt(),
+                FetchLfsObjects => self.fetch_lfs_objects(),
+                DownloadDiscordSdk => self.download_discord_sdk(),
+                DownloadResources if overlap && !target.needed_by_compile(&self.spec) => {
+                    self.start_background_downloads()
+                }
+                DownloadResources => self.download_resources(),
+                CopyRom => self.copy_rom(&repo_dir),
+                ApplyPatches => self.apply_patches(&repo_dir),
+                CreateBuildScript => self.create_build_script(&repo_dir),
+                CreateScriptsDir => self.create_scripts_dir(self.base_dir.clone()).map(|_| ()),
+                WritePostBuildScripts => self.write_scripts(&scripts_dir),
+            };
+
+            if let Err(e) = result {
+                // an interrupted stage is kept around
+                // so that it can be resumed, as is a
+                // clone that timed out or hung.
+                let resumable = match e.cause {
+                    ErrorCause::Cancelled => true,
+                    ErrorCause::StageTimedOut { .. } => target == CloneRepo,
+                    _ => false,
+                };
+                if !resumable {
+                    self.rollback_stage(&target, &new_artifacts);
+                }
+                return Err(e);
+            }
+
+            // a failing hook leaves the clone be.
+            if target == CloneRepo {
+                self.run_hooks(HookPoint::AfterClone)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run the custom stages at `point`
+    /// that are needed.
+    fn run_custom_stages(&mut self, point: StagePoint) -> BuilderResult<()> {
+        // taken out, so that the stages can
+        // look at the rest of the builder.
+        let mut stages = std::mem::take(&mut self.stages);
+        let result = self.run_stages_at(point, &mut stages);
+        self.stages = stages;
+        result
+    }
+
+    fn run_stages_at(
+        &mut self,
+        point: StagePoint,
+        stages: &mut [(StagePoint, Box<dyn Stage + 'a>)],
+    ) -> BuilderResult<()> {
+        for (_, stage) in stages.iter_mut().filter(|(p, _)| *p == point) {
+            if self.should_stop() {
+                return Err(err!(c_cancelled!()));
+            }
+
+            let (state, log_cb) = (&self.state, &mut self.callbacks.log_cb);
+            let mut log = |log_type: LogType, text: &str| {
+                state.log_line(text);
+                run_callback!(*log_cb, log_type, text);
+            };
+            let mut ctx = StageContext::new(&self.spec, &self.base_dir, state, &mut log);
+
+            if !stage.needed(&ctx) {
+                continue;
+            }
+
+            state.set_phase(BuildPhase::CustomStage);
+            run_callback!(self.callbacks.new_custom_stage_cb, stage.id());
+
+            stage.run(&mut ctx)?;
+        }
+
+        Ok(())
+    }
+
+    fn compile(&mut self) -> BuilderResult<()> {
+        self.run_hooks(HookPoint::BeforeCompile)?;
+
+        self.state.set_phase(BuildPhase::Compiling);
+        self.run_build_script(&[])
+    }
+
+    /// Check that Python, and the modules that the
+    /// scripts of the repo import, are installed.
+    fn check_python_deps(&mut self) -> BuilderResult<()> {
+        let repo_dir = self.base_dir.join(&self.spec.repo.name);
+        let Some(modules) = python_modules_used(&*self.fs, &repo_dir) else {
+            return Ok(());
+        };
+
+        let Some(python) = ["python3", "python"]
+            .into_iter()
+            .find(|python| self.has_program(python))
+        else {
+            return Err(err!(
+                c_missing_dep!("python3".to_owned(), deps::install_hint("python3")),
+                "Python 3 is needed to extract the assets of the port"
+            ));
+        };
+
+        let missing = self.missing_python_modules(python, &modules);
+        let Some(module) = missing.first() else {
+            return Ok(());
+        };
+
+        let package = deps::PYTHON_MODULES
+            .iter()
+            .find(|(known, _)| known == module)
+            .and_then(|(_, package)| *package);
+        let hint = match package {
+            Some(package) => format!("try `{} -m pip install {}`", python, package),
+            None => format!("reinstall {} with the {} module", python, module),
+        };
+
+        Err(err!(
+            c_missing_dep!(module.clone(), hint),
+            format!(
+                "the scripts that extract the assets of the port need the Python module(s) {}",
+                missing.join(", ")
+            )
+        ))
+    }
+
+    /// Get the native libraries that the port
+    /// links to, with the makeopt that asks for
+    /// each, going by the API makeopts that it
+    /// is built with.
+    fn native_libs_needed(&self) -> Vec<(&'static str, NativeLib)> {
+        // cross-compiled windows builds link to
+        // the libraries of the toolchain instead.
+        if cfg!(windows) || self.spec.has_makeopt("WINDOWS_BUILD", "1") {
+            return Vec::new();
+        }
+
+        // the makeopts of the spec come after
+        // the defaults, so they win.
+        let makeopts = Makeopt::default_makeopts()
+            .into_iter()
+            .chain(self.spec.build_makeopts())
+            .collect::<Vec<Makeopt>>();
+        let value = |key: &str| {
+            makeopts
+                .iter()
+                .rev()
+                .find(|m| m.key == key)
+                .map(|m| m.value.clone())
+                .unwrap_or_default()
+        };
+
+        let mut needed: Vec<(&'static str, NativeLib)> = Vec::new();
+        let mut need = |key: &'static str, lib: NativeLib| {
+            if !needed.iter().any(|(_, l)| *l == lib) {
+                needed.push((key, lib));
+            }
+        };
+
+        for key in ["WINDOW_API", "AUDIO_API", "CONTROLLER_API"] {
+            match value(key).as_str() {
+                "SDL2" => need(key, NativeLib::Sdl2),
+                "SDL1" => need(key, NativeLib::Sdl1),
+                _ => (),
+            }
+        }
+
+        match value("RENDER_API").as_str() {
+            "GL" if cfg!(target_os = "macos") => need("RENDER_API", NativeLib::Glew),
+            "GL" | "GL_LEGACY" if !cfg!(target_os = "macos") => need("RENDER_API", NativeLib::Gl),
+            _ => (),
+        }
+
+        needed
+    }
+
+    /// Check that the native libraries that
+    /// the port links to are installed.
+    fn check_native_libs(&mut self) -> BuilderResult<()> {
+        for (key, lib) in self.native_libs_needed() {
+            let pkg_config = Command::new("pkg-config")
+                .arg("--exists")
+                .arg(lib.pkg_config_name());
+
+            match self.probe(pkg_config) {
+                Some(false) => {
+                    return Err(err!(
+                        c_missing_dep!(lib.pkg_config_name().to_owned(), lib.install_hint()),
+                        format!(
+                            "the {} makeopt needs the {} library, with its headers",
+                            key,
+                            lib.pkg_config_name()
+                        )
+                    ))
+                }
+                Some(true) => (),
+                None => {
+                    log!(
+                        self,
+                        Debug,
+                        &format!(
+                            "pkg-config is not installed, so {} was not checked for",
+                            lib.pkg_config_name()
+                        )
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Log the shared libraries that the
+    /// executable needs, but are missing,
+    /// with how to install them.
+    fn log_missing_libs(&mut self, log_type: LogType, missing_libs: &[String]) {
+        for lib in missing_libs {
+            let text = match NativeLib::from_soname(lib) {
+                Some(native) => format!(
+                    "missing shared library: {} ({})",
+                    lib,
+                    native.install_hint()
+                ),
+                None => format!("missing shared library: {}", lib),
+            };
+            log!(self, log_type, &text);
+        }
+    }
+
+    /// Run the build script, passing `args`
+    /// through to make, and log its output.
+    fn run_build_script(&mut self, args: &[&str]) -> BuilderResult<()> {
+        // a custom make command is
+        // trusted to exist.
+        if self.spec.make_command().is_none() && self.make_command().is_none() {
+            let make = if deps::IS_BSD { "gmake" } else { "make" };
+            return Err(err!(
+                c_missing_dep!(make.to_owned(), deps::install_hint(make)),
+                "GNU make is needed to compile the port"
+            ));
+        }
+
+        // the assets are extracted by scripts that
+        // make only runs after compiling the C code,
+        // so what they need is checked up front.
+        if args.first() != Some(&"clean") {
+            self.check_python_deps()?;
+        }
+
+        // the libraries are only linked to when
+        // compiling, long after make started.
+        if args.is_empty() {
+            self.check_native_libs()?;
+        }
+
+        // make runs in its own process group, out of
+        // reach of the terminal's control-c, so the
+        // handler is needed to stop it.
+        install_interrupt_handler(&self.state);
+
+        let build_script_path = self.base_dir.join(self.spec.build_script_name());
+        let makeopts = util::get_makeopts_string(&self.spec.build_makeopts());
+
+        // for custom build scripts, which
+        // cannot rely on what smbuilder
+        // would have generated.
+        let mut build_cmd = Command::new(&build_script_path)
+            .dir(&self.base_dir)
+            .env(
+                "SMBUILDER_REPO_DIR",
+                self.base_dir.join(&self.spec.repo.name),
+            )
+            .env("SMBUILDER_JOBS", self.spec.jobs.unwrap_or(2).to_string())
+            .env("SMBUILDER_MAKEOPTS", makeopts)
+            .stdin(self.script_stdin(true));
+        for arg in args {
+            build_cmd = build_cmd.arg(*arg);
+        }
+        let limits = Limits {
+            timeout: self.options.timeouts.compile,
+            inactivity: self.options.timeouts.inactivity,
+        };
+
+        let compdb_path = self
+            .base_dir
+            .join(&self.spec.repo.name)
+            .join(COMPILE_COMMANDS_NAME);
+        let mut compdb = None;
+
+        if args.is_empty() && self.options.compile_commands {
+            if self.has_program("bear") {
+                build_cmd = compdb::wrap_with_bear(build_cmd, PathBuf::from("bear"), &compdb_path);
+            } else {
+                // the ports only print the commands
+                // that they run when asked to.
+                build_cmd = build_cmd.arg("V=1").arg("VERBOSE=1");
+                let repo_dir = self.base_dir.join(&self.spec.repo.name);
+                let repo_dir = std::path::absolute(&repo_dir).unwrap_or(repo_dir);
+                compdb = Some(CompileDatabase::new(repo_dir));
+            }
+        }
+
+        log!(self, Debug, &format!("running {}", build_cmd));
+
+        // only compiling writes
+        // object files.
+        let mut progress = args
+            .is_empty()
+            .then(|| CompileProgress::new(&*self.fs, &self.base_dir.join(&self.spec.repo.name)));
+
+        let state = self.state.clone();
+        let should_stop = || state.is_cancelled();
+        let ansi = self.options.ansi;
+        let log_cb = &mut self.callbacks.log_cb;
+        let compile_cb = &mut self.callbacks.compile_progress_cb;
+        let outcome = self
+            .runner
+            .run(&build_cmd, limits, &should_stop, &mut |ln| {
+                // classified without the colors, so that
+                // they cannot split up "error:" and such.
+                let plain = util::strip_ansi(ln);
+                let severity = OutputSeverity::classify(&plain);
+                if let Some(compdb) = &mut compdb {
+                    compdb.feed(&plain);
+                }
+                if let Some(percent) = progress.as_mut().and_then(|p| p.feed(&plain)) {
+                    state.set_percent(percent);
+                    run_callback!(*compile_cb, percent);
+                }
+                state.log_line(&plain);
+                if severity == OutputSeverity::Warning {
+                    state.count_warning();
+                }
+                run_callback!(*log_cb, BuildOutput { severity }, &ansi.apply(ln));
+            });
+
+        let stage = match args.first() {
+            None => "compile",
+            Some(&"clean") => "clean the build",
+            Some(_) => "extract the assets",
+        };
+
+        let result = match outcome {
+            Ok(Outcome::Exited(true)) => Ok(()),
+            Ok(Outcome::Exited(false)) => Err(err!(c_comp_failed!(
+                "the build script exited with an error"
+            ))),
+            Ok(Outcome::TimedOut(after)) => Err(err!(c_timed_out!(stage, after))),
+            Ok(Outcome::Inactive(after)) => Err(err!(
+                c_timed_out!(stage, after, true),
+                "make may be waiting on a prompt, or a tool may have hung"
+            )),
+            Ok(Outcome::Prompted(after, prompt)) => Err(err!(
+                c_timed_out!(stage, after, true),
+                format!("the build is waiting on the prompt \"{}\"", prompt)
+            )),
+            Ok(Outcome::Stopped) => Err(err!(c_cancelled!())),
+            Err(e) => Err(err!(
+                c_spawn_cmd!(
+                    build_script_path.to_string_lossy().to_string(),
+                    "failed to start the build script",
+                    e
+                ),
+                "whilst trying to compile"
+            )),
+        };
+
+        // the commands of a failed build are
+        // still worth having in the editor.
+        if let Some(compdb) = compdb {
+            if compdb.is_empty() {
+                log!(
+                    self,
+                    Warn,
+                    "no compiler commands were found in the output of make, so no compile_commands.json was written"
+                );
+            } else if let Err(e) = compdb.save(&compdb_path) {
+                log!(self, Warn, &e.to_string());
+            }
+        }
+
+        result
+    }
+
+    fn install_packs(&mut self, stage: PostBuildStage) -> BuilderResult<()> {
+        let packs = self.spec.packs();
+        let staged: Vec<&dyn Pack> = packs
+            .iter()
+            .map(|pack| pack.as_ref())
+            .filter(|pack| pack.stage() == stage)
+            .collect();
+
+        self.install_stage_packs(stage, &staged)?;
+
+        if stage == TexturePack {
+            self.check_texture_pack();
+        }
+        Ok(())
+    }
+
+    /// Warn about the textures of the pack that
+    /// the game will not load, as they do not
+    /// line up with the dumped ones.
+    ///
+    /// A pack that cannot be checked is only
+    /// mentioned, as it is still installed.
+    fn check_texture_pack(&mut self) {
+        let Some(pack) = &self.spec.texture_pack else {
+            return;
+        };
+
+        let repo_dir = self.base_dir.join(&self.spec.repo.name);
+        let report = match texturepack::validate_on(&*self.fs, &pack.path, &repo_dir) {
+            Ok(report) => report,
+            Err(e) => {
+                let msg = format!("could not check the textures of {}: {}", pack.name, e);
+                log!(self, Debug, &msg);
+                return;
+            }
+        };
+
+        let msg = format!(
+            "{} replaces {} of the {} dumped textures",
+            pack.name, report.matched, report.dumped
+        );
+        log!(self, Info, &msg);
+
+        if report.is_ok() {
+            return;
+        }
+
+        for m in &report.misplaced {
+            let msg = format!(
+                "{} is not loaded, as the game looks for it at {}",
+                m.from.display(),
+                m.to.display()
+            );
+            log!(self, Warn, &msg);
+        }
+
+        if !report.orphaned.is_empty() {
+            let msg = format!(
+                "{} textures of {} are never loaded, as the game has no textures named like them",
+                report.orphaned.len(),
+                pack.name
+            );
+            log!(self, Warn, &msg);
+
+            for path in &report.orphaned {
+                let msg = format!("not loaded: {}", path.display());
+                log!(self, Debug, &msg);
+            }
+        }
+    }
+
+    fn install_controller_mappings(&mut self) -> BuilderResult<()> {
+        let Some(controllers) = self.spec.controllers.clone() else {
+            return Ok(());
+        };
+
+        self.enter_postbuild_stage(ControllerMappings);
+
+        let executable = self.executable_path();
+        let dest_dir = executable.parent().unwrap_or(&self.base_dir);
+
+        for (source, name) in controllers.files() {
+            let dest = dest_dir.join(name);
+
+            if let Err(e) = self.fs.copy(&source, &dest) {
+                let msg = format!("failed to copy {} to {}", source.display(), dest.display());
+                return Err(err!(
+                    c_fs!(e, msg),
+                    "whilst installing the controller mappings"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn install_save_files(&mut self) -> BuilderResult<()> {
+        self.enter_postbuild_stage(SaveFiles);
+
+        let Some(saves) = self.spec.save_files.clone() else {
+            return Ok(());
+        };
+
+        let fs_err = |e: std::io::Error, path: &Path| {
+            let msg = format!("failed to copy {}", path.display());
+            err!(c_fs!(e, msg), "whilst installing the save files")
+        };
+
+        let hash = saves.hash().map_err(|e| fs_err(e, &saves.path))?;
+        let mut lockfile = self.load_lockfile()?;
+
+        if lockfile.save_files.as_deref() == Some(hash.as_str()) {
+            log!(self, Info, "the save files are already installed");
+            return Ok(());
+        }
+
+        let Some(save_dir) = self.spec.save_dir() else {
+            let e = std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "could not find the save directory of the game; set save_files.dir in the spec",
+            );
+            return Err(err!(c_other!(e), "failed to install the save files"));
+        };
+
+        // every install backs up into a folder
+        // of its own, so no backup is overwritten.
+        let backup_dir = self.base_dir.join("save_backups").join(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+                .to_string(),
+        );
+
+        for file in saves.files().map_err(|e| fs_err(e, &saves.path))? {
+            let dest = save_dir.join(&file);
+
+            if self.fs.exists(&dest) && !self.fs.is_dir(&dest) {
+                let backup = backup_dir.join(&file);
+                if let Some(parent) = backup.parent() {
+                    self.fs
+                        .create_dir_all(parent)
+                        .map_err(|e| fs_err(e, &dest))?;
+                }
+                self.fs.copy(&dest, &backup).map_err(|e| fs_err(e, &dest))?;
+                log!(
+                    self,
+                    Info,
+                    &format!("backed {} up to {}", dest.display(), backup.display())
+                );
+            }
+
+            if let Some(parent) = dest.parent() {
+                self.fs
+                    .create_dir_all(parent)
+                    .map_err(|e| fs_err(e, parent))?;
+            }
+
+            let source = saves.source(&file);
+            self.fs
+                .copy(&source, &dest)
+                .map_err(|e| fs_err(e, &source))?;
+        }
+
+        lockfile.save_files = Some(hash);
+        self.save_lockfile(&lockfile)
+    }
+
+    fn install_stage_packs(
+        &mut self,
+        stage: PostBuildStage,
+        packs: &[&dyn Pack],
+    ) -> BuilderResult<()> {
+        self.enter_postbuild_stage(stage);
+
+        let mut supported = Vec::new();
+        for &pack in packs {
+            if pack.is_supported(&self.spec) {
+                pack.validate()?;
+                supported.push(pack);
+            } else {
+                let msg = format!("this build does not support {}. skipping it.", pack.name());
+                log!(self, Warn, &msg);
+            }
+        }
+
+        if supported.is_empty() {
+            return Ok(());
+        }
+
+        let packs = &supported;
+        let repo_dir = self.base_dir.join(&self.spec.repo.name);
+
+        // model packs can be thousands of small
+        // files, so they are copied side by side.
+        let total = packs.len();
+        let workers = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(2)
+            .min(MAX_PACK_INSTALL_THREADS)
+            .min(total);
+        let next = AtomicUsize::new(0);
+        let (tx, rx) = mpsc::channel();
+
+        let mut first_err = None;
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                let tx = tx.clone();
+                let (next, repo_dir, spec) = (&next, &repo_dir, &self.spec);
+                let mode = self.options.install_mode;
+
+                scope.spawn(move || loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let pack = match packs.get(i) {
+                        Some(pack) => pack,
+                        None => break,
+                    };
+
+                    let result = pack.install(spec, repo_dir, mode, &mut |done, files, bytes| {
+                        let _ = tx.send(PackEvent::Progress(pack.name(), done, files, bytes));
+                    });
+
+                    if tx.send(PackEvent::Done(pack.name(), result)).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(tx);
+
+            // the callbacks are only run from
+            // this thread, as the packs progress.
+            let mut installed = 0;
+            for event in rx {
+                match event {
+                    PackEvent::Progress(name, done, files, bytes) => {
+                        // a lone pack is the only
+                        // thing that is progressing.
+                        if total == 1 {
+                            self.state.set_percent(done as f32 * 100.0 / files as f32);
+                        }
+
+                        run_callback!(
+                            self.callbacks.pack_install_progress_cb,
+                            name,
+                            done,
+                            files,
+                            bytes
+                        );
+                    }
+                    PackEvent::Done(name, Ok(_)) => {
+                        installed += 1;
+                        self.state
+                            .set_percent(installed as f32 * 100.0 / total as f32);
+
+                        let line = format!("installed {} ({}/{})", name, installed, total);
+                        self.state.log_line(&line);
+                        run_callback!(self.callbacks.log_cb, Info, &line);
+                    }
+                    PackEvent::Done(_, Err(e)) => {
+                        // the other packs are still
+                        // allowed to finish.
+                        next.store(total, Ordering::SeqCst);
+                        first_err.get_or_insert(e);
+                    }
+                }
+            }
+        });
+
+        match first_err {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// What the build script (or a post-build
+    /// script) reads from stdin: the answers
+    /// in the spec, the stdin of this process
+    /// if it is forwarded, or else nothing.
+    fn script_stdin(&self, build_script: bool) -> Stdin {
+        match &self.spec.answers {
+            Some(answers) if build_script => {
+                let mut input = answers.join("\n");
+                input.push('\n');
+                Stdin::Bytes(input.into_bytes())
+            }
+            _ if self.options.forward_stdin => Stdin::Inherit,
+            _ => Stdin::Null,
+        }
+    }
+
+    fn run_postbuild_scripts(&mut self) -> BuilderResult<()> {
+        self.enter_postbuild_stage(PostBuildScripts);
+        self.run_hooks(HookPoint::AfterPostBuild)
+    }
+
+    /// Run the scripts hooked into `point`,
+    /// in order, writing them out first.
+    fn run_hooks(&mut self, point: HookPoint) -> BuilderResult<()> {
+        let scripts: Vec<PostBuildScript> =
+            self.spec.hook_scripts(point).into_iter().cloned().collect();
+
+        if scripts.is_empty() {
+            return Ok(());
+        }
+
+        // the hooks before the post-build stages
+        // run before the scripts are written.
+        let scripts_dir = self.base_dir.join("scripts");
+        self.fs.create_dir_all(&scripts_dir).map_err(|e| {
+            let msg = format!("failed to create {}", scripts_dir.display());
+            err!(c_fs!(e, msg), "whilst writing the hooks")
+        })?;
+
+        log!(self, Debug, &format!("running the {} hooks", point));
+
+        for mut script in scripts {
+            if self.should_stop() {
+                return Err(err!(c_cancelled!()));
+            }
+
+            let script_path = script.save(&scripts_dir)?;
+            self.fs.make_executable(&script_path)?;
+
+            self.run_script(&script)?;
+        }
+
+        Ok(())
+    }
+
+    fn run_script(&mut self, script: &PostBuildScript) -> BuilderResult<()> {
+        if let Some(approve) = &mut self.callbacks.approve_script_cb {
+            if !approve(&redact(&script.name), &redact(&script.contents)) {
+                log!(
+                    self,
+                    Warn,
+                    &format!("skipping the unapproved script {}", script.name)
+                );
+                return Ok(());
+            }
+        }
+
+        run_callback!(
+            self.callbacks.new_postbuild_script_cb,
+            &script.name,
+            &script.description
+        );
+
+        // the path is filled in when the
+        // scripts are written out.
+        let Some(script_path) = script.path.as_ref() else {
+            return Err(err!(
+                c_invalid_spec!(
+                    "scripts",
+                    format!("the script {} has not been written out", script.name)
+                ),
+                "whilst running the post-build scripts"
+            ));
+        };
+
+        let limits = Limits {
+            timeout: self.options.timeouts.scripts,
+            inactivity: self.options.timeouts.inactivity,
+        };
+
+        let script_cmd = Command::new(script_path).stdin(self.script_stdin(false));
+
+        log!(self, Debug, &format!("running {}", script_cmd));
+
+        let state = self.state.clone();
+        let should_stop = || state.is_cancelled();
+        let ansi = self.options.ansi;
+        let log_cb = &mut self.callbacks.log_cb;
+        let outcome = self
+            .runner
+            .run(&script_cmd, limits, &should_stop, &mut |ln| {
+                state.log_line(&util::strip_ansi(ln));
+                run_callback!(*log_cb, Info, &ansi.apply(ln));
+            });
+
+        let stage = format!("run the script {}", script.name);
+        match outcome {
+            Ok(Outcome::Exited(true)) => Ok(()),
+            Ok(Outcome::Exited(false)) => Err(err!(
+                c_spawn_cmd!(script_path.to_string_lossy().to_string()),
+                format!("the script {} exited with an error", script.name)
+            )),
+            Ok(Outcome::TimedOut(after)) => Err(err!(c_timed_out!(stage, after))),
+            Ok(Outcome::Inactive(after)) => Err(err!(
+                c_timed_out!(stage, after, true),
+                "the script may be waiting on a prompt"
+            )),
+            Ok(Outcome::Prompted(after, prompt)) => Err(err!(
+                c_timed_out!(stage, after, true),
+                format!("the script is waiting on the prompt \"{}\"", prompt)
+            )),
+            Ok(Outcome::Stopped) => Err(err!(c_cancelled!())),
+            Err(e) => Err(err!(
+                c_spawn_cmd!(
+                    script_path.to_string_lossy().to_string(),
+                    "failed to run the script",
+                    e
+                ),
+                format!("whilst trying to run script {}", script.name)
+            )),
+        }
+    }
+
+    fn link_executable(&mut self) -> BuilderResult<()> {
+        self.enter_postbuild_stage(LinkExecutable);
+
+        let executable_path = self.executable_path();
+        let link_path = self.game_executable_path();
+
+        match self.fs.link_or_copy(&executable_path, &link_path) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let msg = format!(
+                    "failed to link {} to {}",
+                    &link_path.display(),
+                    &executable_path.display()
+                );
+                Err(err!(c_fs!(e, msg), "whilst linking the game executable"))
+            }
+        }
+    }
+
+    fn smoke_test(&mut self) -> BuilderResult<()> {
+        self.enter_postbuild_stage(SmokeTest);
+
+        let executable_path = self.executable_path();
+        let check = check_executable_on(&*self.fs, &*self.runner, &executable_path)?;
+
+        if check.ok() {
+            log!(
+                self,
+                Info,
+                &format!("the executable looks good ({:?})", check.format)
+            );
             return Ok(());
+        }
+
+        self.log_missing_libs(LogType::Error, &check.missing_libs);
+
+        Err(err!(
+            c_missing_libs!(executable_path, check.missing_libs),
+            "the game was built, but will not start"
+        ))
+    }
+
+    /// Strip the executable, and compress it with
+    /// UPX if the spec asks for it, returning its
+    /// size from before it was stripped.
+    fn strip_executable(&mut self) -> BuilderResult<Option<u64>> {
+        let config = match &self.spec.strip {
+            Some(config) => config.clone(),
+            None => return Ok(None),
+        };
+
+        self.enter_postbuild_stage(StripExecutable);
+
+        if self.spec.debug {
+            log!(
+                self,
+                Warn,
+                "not stripping the executable, as the spec is a debug build"
+            );
+            return Ok(None);
+        }
+
+        let executable_path = self.executable_path();
+        let original_size = self.fs.size(&executable_path).unwrap_or(0);
+
+        let exe = executable_path.as_os_str();
+        self.run_tool(StripExecutable, "strip", "binutils", &[exe])?;
+
+        if config.compress {
+            let quiet = OsStr::new("-q");
+
+            // upx refuses to compress an executable
+            // twice, such as one that was reused.
+            if self.run_tool(
+                StripExecutable,
+                "upx",
+                "upx",
+                &[OsStr::new("-t"), quiet, exe],
+            )? {
+                log!(self, Info, "the executable is already compressed");
+            } else if !self.run_tool(StripExecutable, "upx", "upx", &[quiet, exe])? {
+                return Err(err!(
+                    c_spawn_cmd!(format!("upx -q {}", executable_path.display())),
+                    "failed to compress the executable"
+                ));
+            }
+        }
+
+        log!(
+            self,
+            Info,
+            &format!(
+                "the executable went from {} KiB to {} KiB",
+                original_size / 1024,
+                self.fs.size(&executable_path).unwrap_or(0) / 1024
+            )
+        );
+
+        Ok(Some(original_size))
+    }
+
+    /// Run a tool for a post-build stage,
+    /// returning whether it succeeded.
+    ///
+    /// `package` is what to install
+    /// when the tool is missing.
+    fn run_tool(
+        &mut self,
+        stage: PostBuildStage,
+        program: &str,
+        package: &str,
+        args: &[&OsStr],
+    ) -> BuilderResult<bool> {
+        if !self.has_program(program) {
+            return Err(err!(
+                c_missing_dep!(program.to_owned(), deps::install_hint(package)),
+                format!("`{}` is needed to {}", program, stage)
+            ));
+        }
+
+        let mut tool_cmd = Command::new(program);
+        for arg in args {
+            tool_cmd = tool_cmd.arg(*arg);
+        }
+
+        let limits = Limits {
+            timeout: self.options.timeouts.scripts,
+            inactivity: self.options.timeouts.inactivity,
+        };
+
+        log!(self, Debug, &format!("running {}", tool_cmd));
+
+        let state = self.state.clone();
+        let should_stop = || state.is_cancelled();
+        let log_cb = &mut self.callbacks.log_cb;
+        let outcome = self.runner.run(&tool_cmd, limits, &should_stop, &mut |ln| {
+            state.log_line(ln);
+            run_callback!(*log_cb, Info, ln);
+        });
+
+        match outcome {
+            Ok(Outcome::Exited(success)) => Ok(success),
+            Ok(Outcome::TimedOut(after)) => Err(err!(c_timed_out!(stage, after))),
+            Ok(Outcome::Inactive(after)) | Ok(Outcome::Prompted(after, _)) => {
+                Err(err!(c_timed_out!(stage, after, true)))
+            }
+            Ok(Outcome::Stopped) => Err(err!(c_cancelled!())),
+            Err(e) => Err(err!(
+                c_spawn_cmd!(program.to_owned(), format!("failed to run {}", program), e),
+                format!("whilst trying to {}", stage)
+            )),
+        }
+    }
+
+    /// Give the built game the icon of the
+    /// spec, in the way of its platform.
+    fn embed_icon(&mut self) -> BuilderResult<()> {
+        let icon = match &self.spec.icon {
+            Some(icon) if self.options.embed_icon => self.base_dir.join(icon),
+            _ => return Ok(()),
+        };
+
+        self.enter_postbuild_stage(EmbedIcon);
+
+        if !self.fs.exists(&icon) {
+            let e = io::Error::new(io::ErrorKind::NotFound, "the icon does not exist");
+            let msg = format!("failed to find {}", icon.display());
+            return Err(err!(
+                c_fs!(e, msg),
+                format!("whilst trying to {}", EmbedIcon)
+            ));
+        }
+
+        let executable_path = self.executable_path();
+
+        match detect_executable_format_on(&*self.fs, &executable_path)? {
+            ExecutableFormat::Pe => self.embed_windows_icon(&executable_path, &icon),
+            ExecutableFormat::Elf => self.write_desktop_entry(&icon),
+            ExecutableFormat::MachO => self.write_app_bundle(&executable_path, &icon),
+        }
+    }
+
+    /// Set the icon resource of a Windows
+    /// executable, with `rcedit`.
+    fn embed_windows_icon(&mut self, executable_path: &Path, icon: &Path) -> BuilderResult<()> {
+        if !util::has_extension(icon, "ico") {
+            let e = io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Windows executables need an .ico icon",
+            );
+            return Err(err!(c_other!(e), format!("failed to {}", EmbedIcon)));
+        }
+
+        let args = [
+            executable_path.as_os_str(),
+            OsStr::new("--set-icon"),
+            icon.as_os_str(),
+        ];
+
+        if self.run_tool(EmbedIcon, "rcedit", "rcedit", &args)? {
+            Ok(())
+        } else {
+            Err(err!(
+                c_spawn_cmd!(format!("rcedit {}", executable_path.display())),
+                format!("failed to {}", EmbedIcon)
+            ))
+        }
+    }
+
+    /// Write a `.desktop` file next to the
+    /// game executable, along with a copy of
+    /// the icon for it to point to.
+    fn write_desktop_entry(&mut self, icon: &Path) -> BuilderResult<()> {
+        let game_executable = self.game_executable_path();
+        let icon_copy = match icon.extension() {
+            Some(ext) => game_executable.with_extension(ext),
+            None => game_executable.with_extension("png"),
+        };
+        let desktop_path = game_executable.with_extension("desktop");
+
+        let entry = icon::desktop_entry(
+            self.spec.name.as_deref().unwrap_or(&self.spec.repo.name),
+            &game_executable,
+            &icon_copy,
+            self.executable_path().parent().unwrap_or(&self.base_dir),
+        );
+
+        let result = self
+            .fs
+            .copy(icon, &icon_copy)
+            .and_then(|_| self.fs.write(&desktop_path, entry.as_bytes()));
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let msg = format!("failed to write {}", desktop_path.display());
+                Err(err!(
+                    c_fs!(e, msg),
+                    format!("whilst trying to {}", EmbedIcon)
+                ))
+            }
+        }
+    }
+
+    /// Wrap the executable in a macOS app
+    /// bundle, with the icon (converted to
+    /// `.icns` with `sips` if needed) in
+    /// its resources.
+    fn write_app_bundle(&mut self, executable_path: &Path, icon: &Path) -> BuilderResult<()> {
+        let name = self
+            .spec
+            .name
+            .clone()
+            .unwrap_or_else(|| self.spec.repo.name.clone());
+        let contents = self
+            .game_executable_path()
+            .with_extension("app")
+            .join("Contents");
+        let macos_dir = contents.join("MacOS");
+        let resources_dir = contents.join("Resources");
+        let bundle_icon = resources_dir.join("icon.icns");
+        let launcher = macos_dir.join("launch");
+
+        let fs_err = |e: io::Error, path: &Path| {
+            let msg = format!("failed to write {}", path.display());
+            err!(c_fs!(e, msg), format!("whilst trying to {}", EmbedIcon))
         };
 
-        for script in scripts {
-            run_callback!(
-                self.callbacks.new_postbuild_script_cb,
-                &script.name,
-                &script.description
-            );
+        for dir in [&macos_dir, &resources_dir] {
+            self.fs.create_dir_all(dir).map_err(|e| fs_err(e, dir))?;
+        }
+
+        if util::has_extension(icon, "icns") {
+            self.fs
+                .copy(icon, &bundle_icon)
+                .map_err(|e| fs_err(e, &bundle_icon))?;
+        } else {
+            let args = [
+                OsStr::new("-s"),
+                OsStr::new("format"),
+                OsStr::new("icns"),
+                icon.as_os_str(),
+                OsStr::new("--out"),
+                bundle_icon.as_os_str(),
+            ];
+
+            if !self.run_tool(EmbedIcon, "sips", "sips", &args)? {
+                return Err(err!(
+                    c_spawn_cmd!(format!("sips {}", icon.display())),
+                    "failed to convert the icon to .icns"
+                ));
+            }
+        }
+
+        let plist_path = contents.join("Info.plist");
+        self.fs
+            .write(
+                &plist_path,
+                icon::info_plist(&name, "launch", "icon").as_bytes(),
+            )
+            .map_err(|e| fs_err(e, &plist_path))?;
+
+        self.fs
+            .write(&launcher, icon::launcher_script(executable_path).as_bytes())
+            .map_err(|e| fs_err(e, &launcher))?;
+        self.fs.make_executable(&launcher)
+    }
+
+    /// Run the post-build stages, but for the
+    /// `unchanged` ones, whose part of the spec
+    /// has not changed since the last build.
+    fn post_build(&mut self, unchanged: &[PostBuildStage]) -> BuilderResult<Option<u64>> {
+        self.finish_background_downloads()?;
+        self.run_custom_stages(StagePoint::BeforePostBuild)?;
+
+        let mut stages = Vec::new();
+        for stage in PostBuildStage::ALL {
+            if unchanged.contains(&stage) {
+                log!(
+                    self,
+                    Info,
+                    &format!("not running the stage to {}: it is unchanged", stage)
+                );
+            } else {
+                stages.push(stage);
+            }
+        }
+
+        let original_size = self.run_postbuild_stages(&stages)?;
+        self.run_custom_stages(StagePoint::AfterPostBuild)?;
+
+        Ok(original_size)
+    }
+
+    /// Run `stages`, after the stages of them
+    /// that they depend on.
+    ///
+    /// Returns the size of the executable before
+    /// it was stripped, if it was stripped.
+    fn run_postbuild_stages(&mut self, stages: &[PostBuildStage]) -> BuilderResult<Option<u64>> {
+        let order =
+            graph::topological_order(stages, &PostBuildStage::ALL, PostBuildStage::depends_on);
+        let mut original_size = None;
+
+        for stage in order {
+            if !self.stage_enabled(stage) {
+                log!(self, Info, &format!("skipping the stage to {}", stage));
+                continue;
+            }
+
+            match stage {
+                EmbedIcon => self.embed_icon()?,
+                LinkExecutable => self.link_executable()?,
+                StripExecutable => original_size = self.strip_executable()?,
+                SmokeTest if self.options.smoke_test => self.smoke_test()?,
+                SmokeTest => (),
+                TexturePack | DynOSPacks => self.install_packs(stage)?,
+                SaveFiles => self.install_save_files()?,
+                ControllerMappings => self.install_controller_mappings()?,
+                PostBuildScripts => self.run_postbuild_scripts()?,
+            }
+        }
+
+        Ok(original_size)
+    }
+
+    /// Extract the assets from the base ROM,
+    /// without compiling the port.
+    ///
+    /// Runs the setup stages that are needed,
+    /// then the port's `res` make target. Useful
+    /// to regenerate the assets after swapping
+    /// the base ROM or a texture pack.
+    pub fn extract_assets(&mut self) -> BuilderResult<()> {
+        self.state.start();
+
+        let result = self.setup_build(false).and_then(|_| {
+            self.state.set_phase(BuildPhase::ExtractingAssets);
+            log!(self, Info, "extracting the assets");
+            self.run_build_script(&["res"])
+        });
+
+        self.state.set_phase(match result {
+            Ok(_) => BuildPhase::Finished,
+            Err(_) => BuildPhase::Failed,
+        });
+
+        result
+    }
 
-            let script_path = script.path.as_ref().unwrap_or_else(|| {
-                panic!("failed to unwrap the script path (please report this bug!)")
-            });
+    /// Re-run the texture pack, DynOS pack and
+    /// post-build script stages against an
+    /// existing build, without setting up or
+    /// compiling the port.
+    ///
+    /// Useful to reinstall a pack after editing
+    /// it. Fails if the port was never built.
+    pub fn run_post_build_only(&mut self) -> BuilderResult<()> {
+        self.state.start();
 
-            let cmd = cmd!(script_path);
-            match cmd.run() {
-                Ok(_) => (),
-                Err(e) => {
-                    return Err(err!(
-                        c_spawn_cmd!(
-                            script_path.to_string_lossy().to_string(),
-                            "failed to run the script",
-                            e
-                        ),
-                        format!("whilst trying to run script {}", script.name)
-                    ))
+        let result = self.run_post_build_stages();
+
+        self.state.set_phase(match result {
+            Ok(_) => BuildPhase::Finished,
+            Err(_) => BuildPhase::Failed,
+        });
+
+        result
+    }
+
+    fn run_post_build_stages(&mut self) -> BuilderResult<()> {
+        let executable_path = self.executable_path();
+
+        if !self.fs.exists(&executable_path) {
+            return Err(err!(
+                c_comp_failed!("the port has not been built yet"),
+                format!(
+                    "cannot run the post-build stages: {} does not exist",
+                    executable_path.display()
+                )
+            ));
+        }
+
+        // the executable is left as it is.
+        let stages: Vec<PostBuildStage> = PostBuildStage::ALL
+            .into_iter()
+            .filter(|stage| !stage.touches_executable())
+            .collect();
+        self.run_postbuild_stages(&stages)?;
+
+        self.save_manifest()
+    }
+
+    /// Hash the files that the build
+    /// installed, for `verify`.
+    fn create_manifest(&self, executable_path: &Path) -> BuilderResult<Manifest> {
+        match Manifest::create_with_fs(&*self.fs, &self.spec, &self.base_dir, executable_path) {
+            Ok(manifest) => Ok(manifest),
+            Err(e) => Err(err!(
+                c_fs!(e, "failed to hash the installed files"),
+                "whilst recording the files of the build"
+            )),
+        }
+    }
+
+    /// Record the manifest of the build in
+    /// the lockfile, keeping the rest of what
+    /// was recorded about the build.
+    fn save_manifest(&self) -> BuilderResult<()> {
+        let manifest = self.create_manifest(&self.executable_path())?;
+        let mut lockfile = self.load_lockfile()?;
+
+        let mut build = lockfile.locked_build(self.spec.config());
+        build.manifest = Some(manifest);
+        lockfile.set_locked_build(self.spec.config(), build);
+
+        self.save_lockfile(&lockfile)
+    }
+
+    /// Check that the files that the last
+    /// build installed (the executable and
+    /// the packs) have not been modified
+    /// since, such as by mods.
+    ///
+    /// Returns `None` if the build has no
+    /// manifest, having never been built (or
+    /// been built by an older smbuilder).
+    pub fn verify(&self) -> BuilderResult<Option<IntegrityReport>> {
+        let lockfile = self.load_lockfile()?;
+
+        Ok(lockfile
+            .locked_build(self.spec.config())
+            .manifest
+            .map(|manifest| manifest.verify_with_fs(&*self.fs, &self.base_dir)))
+    }
+
+    /// Pretend to build the spec, running the
+    /// callbacks and updating the build state
+    /// like a real build would, without running
+    /// git or make.
+    ///
+    /// Nothing is written to the base directory
+    /// (not even the progress, or the log), but
+    /// it is locked by the builder, like for a
+    /// real build.
+    ///
+    /// Meant for developing the progress UIs
+    /// of frontends.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use smbuilder::prelude::*;
+    /// # use smbuilder::prelude::builder_types::SimulationOptions;
+    /// # let mut builder = Builder::new(Spec::default(), "base", Callbacks::new()).unwrap();
+    /// // a quick run that fails halfway through
+    /// let options = SimulationOptions::new().compile_lines(50).fail(true);
+    /// assert!(builder.simulate(options).is_err());
+    /// ```
+    pub fn simulate(&mut self, options: SimulationOptions) -> BuilderResult<()> {
+        // the progress of a real build, which
+        // it may be resumed from, is kept.
+        let files = self.state.pause_writing();
+        self.state.start();
+
+        let result = self.run_simulation(options);
+
+        self.state.set_phase(match result {
+            Ok(_) => BuildPhase::Finished,
+            Err(_) => BuildPhase::Failed,
+        });
+        self.state.resume_writing(files);
+
+        result
+    }
+
+    fn run_simulation(&mut self, options: SimulationOptions) -> BuilderResult<()> {
+        let setup_stages = [
+            CloneRepo,
+            CopyRom,
+            ApplyPatches,
+            CreateBuildScript,
+            CreateScriptsDir,
+            WritePostBuildScripts,
+        ];
+
+        for stage in setup_stages {
+            if self.should_stop() {
+                return Err(err!(c_cancelled!()));
+            }
+
+            self.enter_setup_stage(stage);
+
+            if stage == CloneRepo {
+                log!(self, Info, "cloning the repository");
+
+                let total = options.clone_objects.max(1);
+                let step = (total / 50).max(1);
+                for received in (0..=total).step_by(step) {
+                    self.state
+                        .set_percent(received as f32 * 100.0 / total as f32);
+                    run_callback!(
+                        self.callbacks.repo_clone_progress_cb,
+                        received,
+                        total,
+                        received * 4096
+                    );
+                    thread::sleep(options.line_interval);
                 }
+            }
+        }
+
+        self.state.set_phase(BuildPhase::Compiling);
+
+        let lines = options.compile_lines.max(1);
+        for i in 0..lines {
+            if self.should_stop() {
+                return Err(err!(c_cancelled!()));
+            }
+
+            if options.fail && i == lines / 2 {
+                let line = format!("src/game/simulated_{}.c:42:1: error: expected ';'", i);
+                log!(
+                    self,
+                    BuildOutput {
+                        severity: OutputSeverity::Error
+                    },
+                    &line
+                );
+                return Err(err!(c_comp_failed!(
+                    "the build script exited with an error"
+                )));
+            }
+
+            let (line, severity) = if i % 37 == 36 {
+                (
+                    format!(
+                        "src/game/simulated_{}.c:10:5: warning: unused variable 'x'",
+                        i
+                    ),
+                    OutputSeverity::Warning,
+                )
+            } else {
+                (
+                    format!(
+                        "cc -c -O2 -o build/us_pc/src/game/simulated_{0}.o src/game/simulated_{0}.c",
+                        i
+                    ),
+                    OutputSeverity::Normal,
+                )
             };
+
+            self.state.set_percent(i as f32 * 100.0 / lines as f32);
+            log!(self, BuildOutput { severity }, &line);
+            thread::sleep(options.line_interval);
+        }
+
+        for stage in [LinkExecutable, TexturePack, DynOSPacks, PostBuildScripts] {
+            self.enter_postbuild_stage(stage);
+
+            if stage == PostBuildScripts {
+                for script in self.spec.hook_scripts(HookPoint::AfterPostBuild) {
+                    run_callback!(
+                        self.callbacks.new_postbuild_script_cb,
+                        &script.name,
+                        &script.description
+                    );
+                }
+            }
+
+            thread::sleep(options.line_interval);
         }
 
+        log!(self, Info, "the simulated build finished");
+
         Ok(())
     }
 
-    fn post_build(&mut self) -> BuilderResult<()> {
-        self.install_texture_pack()?;
-        self.install_dynos_packs()?;
-        self.run_postbuild_scripts()?;
+    /// Gets the path of the executable
+    /// that the port builds into.
+    ///
+    /// Forks name their executables a little
+    /// differently, so if the usual name does
+    /// not exist, the build directory is searched
+    /// for an `sm64.<region>` executable.
+    pub fn executable_path(&self) -> PathBuf {
+        if let SourceKind::Prebuilt { executable, .. } = &self.spec.source {
+            return self.prebuilt_executable_path(executable.as_deref());
+        }
 
-        Ok(())
+        let region = &self.spec.rom.region;
+        let mut executable_name = format!("sm64.{}.f3dex2e", region);
+
+        // cross-compiled windows builds (e.g. from WSL)
+        let windows = self.spec.has_makeopt("WINDOWS_BUILD", "1");
+        if windows {
+            executable_name.push_str(".exe");
+        }
+
+        let build_dir = self
+            .spec
+            .build_dir(self.base_dir.join(&self.spec.repo.name));
+
+        // some ports name the executable after
+        // themselves, which is all there is to it.
+        if let Some(name) = self.spec.repo.port().executable_name(windows) {
+            return build_dir.join(name);
+        }
+
+        let expected = build_dir.join(executable_name);
+
+        if self.fs.exists(&expected) {
+            return expected;
+        }
+
+        let prefix = format!("sm64.{}", region);
+        let found = self.fs.read_dir(&build_dir).ok().and_then(|entries| {
+            entries
+                .into_iter()
+                .filter(|p| !self.fs.is_dir(p))
+                .find(|p| {
+                    let name = p.file_name().unwrap_or_default().to_string_lossy();
+                    name.starts_with(&prefix)
+                        && (name.ends_with(".exe") == windows)
+                        && !name.ends_with(".map")
+                })
+        });
+
+        found.unwrap_or(expected)
+    }
+
+    /// Gets the path of the executable of a
+    /// prebuilt port: `executable` if the spec
+    /// names it, or else the first program in
+    /// the root of the release.
+    fn prebuilt_executable_path(&self, executable: Option<&Path>) -> PathBuf {
+        let build_dir = self
+            .spec
+            .build_dir(self.base_dir.join(&self.spec.repo.name));
+
+        if let Some(executable) = executable {
+            return build_dir.join(executable);
+        }
+
+        let mut programs: Vec<PathBuf> = self
+            .fs
+            .read_dir(&build_dir)
+            .map(|entries| {
+                entries
+                    .into_iter()
+                    .filter(|p| util::is_program(p))
+                    .collect()
+            })
+            .unwrap_or_default();
+        programs.sort();
+
+        programs
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| build_dir.join(&self.spec.repo.name))
+    }
+
+    /// Whether the executable is older than
+    /// the build script, meaning that the
+    /// build configuration changed since it
+    /// was compiled.
+    fn executable_is_stale(&self) -> bool {
+        let modified = |path: PathBuf| self.fs.modified(&path).ok();
+
+        match (
+            modified(self.executable_path()),
+            modified(self.base_dir.join(self.spec.build_script_name())),
+        ) {
+            (Some(executable), Some(script)) => script > executable,
+            _ => false,
+        }
+    }
+
+    /// Gets the stable path of the game
+    /// executable, in the root of the base
+    /// directory, for launchers to run.
+    ///
+    /// It is a symlink to the real executable
+    /// on unix, and a copy of it elsewhere.
+    ///
+    /// Every named configuration gets
+    /// one of its own.
+    pub fn game_executable_path(&self) -> PathBuf {
+        match self.spec.config() {
+            Some(name) => self.base_dir.join(format!("game_executable-{}", name)),
+            None => self.base_dir.join("game_executable"),
+        }
+    }
+
+    /// Gets the report of the last successful
+    /// `build`, if there has been one.
+    pub fn report(&self) -> Option<&BuildReport> {
+        self.report.as_ref()
     }
 
-    /// Build the spec.
+    /// Build the spec, returning a handle to
+    /// what was built.
     ///
     /// # Example
     ///
     /// ```no_run
-    /// let mut builder = Builder::new(my_spec, my_base_dir, my_callbacks);
+    /// # use smbuilder::prelude::*;
+    /// # let (my_spec, my_base_dir, my_callbacks) = (Spec::default(), "base", Callbacks::new());
+    /// let mut builder = Builder::new(my_spec, my_base_dir, my_callbacks).unwrap();
     /// // you must have your spec, base dir and callbacks set up beforehand!
     ///
     /// // builds the spec, takes a mutable reference
     /// // to itself for the callbacks.
-    /// builder.build();
+    /// let build = builder.build().unwrap();
+    /// println!("built {}", build.executable_path().display());
     /// ```
-    pub fn build(&mut self) -> BuilderResult<()> {
-        self.setup_build()?;
+    pub fn build(&mut self) -> BuilderResult<Build> {
+        self.state.start();
+        let result = self.run_build();
 
-        let executable_name = format!("sm64.{}.f3dex2e", self.spec.rom.region.to_string());
+        self.state.set_phase(match result {
+            Ok(_) => BuildPhase::Finished,
+            Err(_) => BuildPhase::Failed,
+        });
 
-        let executable_path = self
-            .base_dir
-            .join(&self.spec.repo.name)
-            .join("build")
-            .join(format!("{}_pc", self.spec.rom.region.to_string()))
-            .join(executable_name);
+        let report = result?;
+        self.report = Some(report.clone());
+
+        // like the log, the history is only
+        // informational, so failing to record
+        // the build does not fail it.
+        if let Err(e) = history::record(&*self.fs, &self.base_dir, &report) {
+            log!(
+                self,
+                Warn,
+                &format!("failed to record the build in the history: {}", e)
+            );
+        }
+        Ok(Build::new(
+            self.spec.clone(),
+            self.base_dir.clone(),
+            report,
+            self.fs.clone(),
+        ))
+    }
+
+    /// Build one of the named configurations of
+    /// the spec, like `build`.
+    ///
+    /// Every configuration shares the clone and
+    /// the base ROM, but is built into (and has
+    /// its packs installed into) a build directory
+    /// of its own, next to the default build.
+    pub fn build_configuration(&mut self, name: &str) -> BuilderResult<Build> {
+        let config_spec = self.spec.with_config(name)?;
+        let spec = std::mem::replace(&mut self.spec, config_spec);
+
+        let result = self.build();
+
+        self.spec = spec;
+        result
+    }
+
+    fn run_build(&mut self) -> BuilderResult<BuildReport> {
+        if self.spec.is_prebuilt() {
+            return self.run_prebuilt_build();
+        }
+
+        self.setup_build(true)?;
+        self.run_custom_stages(StagePoint::AfterSetup)?;
+
+        let started = Instant::now();
+        let executable_path = self.executable_path();
+        let lockfile = self.load_lockfile()?;
+        let spec_hashes = self.spec.hashes();
+        let makeopts = self.spec.build_makeopts();
+        let locked = lockfile.locked_build(self.spec.config());
+        let spec_changed = locked
+            .spec_hashes
+            .as_ref()
+            .map(|h| h.needs_recompile(&spec_hashes))
+            .unwrap_or(false);
+
+        let mut compiled = true;
+
+        if !self.stage_enabled(BuildStage::Compile) {
+            if !self.fs.exists(&executable_path) {
+                return Err(err!(
+                    c_comp_failed!("the port has not been built yet"),
+                    format!(
+                        "cannot skip the compile: {} does not exist",
+                        executable_path.display()
+                    )
+                ));
+            }
+
+            log!(self, Info, "skipping the compile");
+            compiled = false;
+        } else if self.options.force {
+            log!(self, Info, "forcing a rebuild");
+            self.compile()?;
+        } else if !self.fs.exists(&executable_path) {
+            self.compile()?;
+        } else if spec_changed {
+            log!(
+                self,
+                Info,
+                "the spec changed since the last build, rebuilding"
+            );
+
+            // the lockfile has every makeopt the port was
+            // built with, including the debug ones.
+            if let Some(old_makeopts) = &locked.makeopts {
+                let kind = makeopts_rebuild_kind(&self.spec.repo, old_makeopts, &makeopts);
 
-        if !executable_path.exists() {
-            self.compile();
+                if let RebuildKind::Clean { keys } = kind {
+                    log!(
+                        self,
+                        Info,
+                        &format!("{} changed, cleaning the build first", keys.join(", "))
+                    );
+                    self.state.set_phase(BuildPhase::Compiling);
+                    self.run_build_script(&["clean"])?;
+                }
+            }
+
+            self.compile()?;
+        } else if self.executable_is_stale() {
+            log!(
+                self,
+                Info,
+                "the build script changed since the last build, rebuilding"
+            );
+            self.compile()?;
         } else {
-            run_callback!(
-                self.callbacks.log_cb,
+            log!(
+                self,
                 LogType::Warn,
                 &format!(
-                    "not building the spec: the executable at {} already exists!",
+                    "not building the spec: the executable at {} is up to date!",
                     executable_path.display()
                 )
             );
+            compiled = false;
+
+            let compdb_path = self
+                .base_dir
+                .join(&self.spec.repo.name)
+                .join(COMPILE_COMMANDS_NAME);
+            if self.options.compile_commands && !self.fs.exists(&compdb_path) {
+                log!(
+                    self,
+                    Info,
+                    "compile_commands.json is only written when the port is compiled; force a rebuild to write it"
+                );
+            }
         }
 
-        self.post_build()?;
-        Ok(())
+        // the smoke test fails on these, but
+        // without it, they are worth a warning.
+        if compiled && !self.options.smoke_test {
+            let missing_libs = find_missing_libs_with(&*self.runner, &executable_path);
+            self.log_missing_libs(LogType::Warn, &missing_libs);
+        }
+
+        // checked before the post-build stages,
+        // which change the executable.
+        let reproducible = if !self.spec.reproducible {
+            None
+        } else if compiled {
+            Some(self.check_reproducible(locked.reproducible.as_ref(), &executable_path)?)
+        } else {
+            locked.reproducible.clone()
+        };
+
+        // what the post-build stages install into
+        // a build that was not recompiled is still
+        // there, unless the spec changed it.
+        let unchanged = match &locked.spec_hashes {
+            Some(hashes) if !compiled => hashes.unchanged_stages(&spec_hashes),
+            _ => Vec::new(),
+        };
+
+        let original_size = self.post_build(&unchanged)?;
+        let manifest = self.create_manifest(&executable_path)?;
+
+        // the post-build stages record what they
+        // installed (like the save files) in the
+        // lockfile, so it is loaded again.
+        let mut lockfile = self.load_lockfile()?;
+
+        // only a successful build is recorded, so
+        // that a failed one is retried next time.
+        lockfile.set_locked_build(
+            self.spec.config(),
+            LockedBuild {
+                spec_hashes: Some(spec_hashes),
+                makeopts: Some(makeopts),
+                reproducible,
+                manifest: Some(manifest),
+            },
+        );
+        self.save_lockfile(&lockfile)?;
+
+        Ok(BuildReport {
+            executable: executable_path.clone(),
+            debug: self.spec.debug,
+            size: self.fs.size(&executable_path).unwrap_or(0),
+            original_size,
+            unstripped_executable: self.spec.debug.then_some(executable_path),
+            compiled,
+            duration: started.elapsed(),
+            warnings: self.state.warnings(),
+            finished_at: history::now(),
+        })
+    }
+
+    /// Record what a reproducible build was
+    /// built from, checking the executable
+    /// against the previous build if that was
+    /// built from the same things.
+    fn check_reproducible(
+        &mut self,
+        previous: Option<&ReproducibleBuild>,
+        executable_path: &Path,
+    ) -> BuilderResult<ReproducibleBuild> {
+        let repo_dir = self.base_dir.join(&self.spec.repo.name);
+        let script_path = self.base_dir.join(self.spec.build_script_name());
+
+        let hash = |path: &Path| match self.sha256_hex(path) {
+            Ok(hash) => Ok(hash),
+            Err(e) => {
+                let msg = format!("failed to read {}", path.display());
+                Err(err!(
+                    c_fs!(e, msg),
+                    "whilst checking that the build is reproducible"
+                ))
+            }
+        };
+
+        let make = self.make_command().unwrap_or("make");
+        let head_cmd = self
+            .git_command()
+            .arg("-C")
+            .arg(&repo_dir)
+            .arg("rev-parse")
+            .arg("HEAD")
+            .stdin(Stdin::Null);
+        let current = ReproducibleBuild {
+            commit: self
+                .git_output(&head_cmd)
+                .and_then(|lines| lines.into_iter().next()),
+            source_date_epoch: self.spec.source_date_epoch(&repo_dir),
+            build_script: hash(&script_path)?,
+            toolchain: [make, "cc"]
+                .into_iter()
+                .filter_map(|program| self.program_version(program))
+                .collect(),
+            executable: hash(executable_path)?,
+        };
+
+        match previous {
+            Some(previous) if previous.same_inputs(&current) => {
+                if previous.executable != current.executable {
+                    return Err(err!(
+                        c_checksum!(
+                            executable_path.display().to_string(),
+                            previous.executable.clone(),
+                            current.executable
+                        ),
+                        "the build is not reproducible"
+                    ));
+                }
+
+                log!(
+                    self,
+                    Info,
+                    &format!(
+                        "the executable matches the previous build ({})",
+                        current.executable
+                    )
+                );
+            }
+            Some(_) => log!(
+                self,
+                Info,
+                "the inputs changed since the previous build, so it is not compared against it"
+            ),
+            None => log!(
+                self,
+                Info,
+                &format!(
+                    "recorded the hash of the executable ({})",
+                    current.executable
+                )
+            ),
+        }
+
+        Ok(current)
+    }
+
+    /// Download (if needed) a prebuilt port,
+    /// and run the post-build stages on it.
+    fn run_prebuilt_build(&mut self) -> BuilderResult<BuildReport> {
+        let started = Instant::now();
+
+        if self.options.force {
+            log!(self, Info, "forcing the release to be downloaded again");
+            let mut lockfile = self.load_lockfile()?;
+            lockfile.prebuilt = None;
+            self.save_lockfile(&lockfile)?;
+        }
+
+        self.setup_build(false)?;
+        self.run_custom_stages(StagePoint::AfterSetup)?;
+
+        let executable_path = self.executable_path();
+        if !self.fs.exists(&executable_path) {
+            let e = std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} does not exist", executable_path.display()),
+            );
+            return Err(err!(
+                c_fs!(e),
+                "the release has no executable where it was expected; set `source.executable`"
+            ));
+        }
+        self.fs.make_executable(&executable_path)?;
+
+        let original_size = self.post_build(&[])?;
+        self.save_manifest()?;
+
+        Ok(BuildReport {
+            executable: executable_path.clone(),
+            debug: false,
+            size: self.fs.size(&executable_path).unwrap_or(0),
+            original_size,
+            unstripped_executable: None,
+            compiled: false,
+            duration: started.elapsed(),
+            warnings: self.state.warnings(),
+            finished_at: history::now(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::backend::mock::{MemoryFs, RecordingRunner};
+    use crate::builder::state::ProgressFile;
+    use crate::builder::types::LogFileOptions;
+    use crate::types::Patch;
+
+    const SPEC: &str = r#"
+rom:
+  region: us
+  path: /roms/baserom.us.z64
+  format: rom_BigEndian
+repo:
+  name: sm64ex
+  url: https://github.com/sm64pc/sm64ex
+  branch: nightly
+  about: ""
+  supports_dynos: false
+name: sm64ex
+"#;
+
+    /// The test spec, skipping the ROM: there is no
+    /// good dump to copy, so the checksum fails (see
+    /// `copies_the_rom_through_the_fs`).
+    fn spec() -> Spec {
+        let mut spec: Spec = serde_yaml::from_str(SPEC).unwrap();
+        spec.skip_stages = Some(vec![BuildStage::CopyRom]);
+        spec
+    }
+
+    /// A base dir on `fs`, with the repo
+    /// cloned and the port built already.
+    fn base_dir(fs: &MemoryFs) -> PathBuf {
+        let base_dir = std::env::temp_dir().join("smbuilder-memory-fs");
+        let repo_dir = base_dir.join("sm64ex");
+
+        fs.add_file(repo_dir.join(".git"), b"gitdir: elsewhere");
+        fs.add_file(
+            repo_dir.join("build/us_pc/sm64.us.f3dex2e"),
+            b"not really a game",
+        );
+
+        let lockfile = Lockfile {
+            repo: Some(LockedRepo {
+                complete: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        lockfile.save_to(fs, &base_dir).unwrap();
+
+        base_dir
+    }
+
+    #[test]
+    fn builds_through_the_fs_and_runner() {
+        let fs = MemoryFs::default();
+        let runner = RecordingRunner::default();
+        let base_dir = base_dir(&fs);
+        let spec = spec();
+
+        let mut builder = Builder::new_with_fs(spec, &base_dir, Callbacks::new(), fs.clone())
+            .unwrap()
+            .with_runner(runner.clone())
+            .with_options(
+                BuildOptions::new()
+                    .force(true)
+                    .progress_file(true)
+                    .log_file(LogFileOptions::default()),
+            );
+        let build = builder.build().unwrap();
+        assert!(build.report().compiled);
+
+        // the host is probed for its APIs, the repo
+        // is checked for changes, and the host for
+        // make and the libraries of the port, then
+        // the build script is run, and the
+        // executable checked for missing libraries.
+        let commands = runner.commands();
+        let build_script = commands
+            .iter()
+            .position(|cmd| cmd.program == base_dir.join("build.sh"))
+            .unwrap();
+        assert!(commands
+            .iter()
+            .any(|cmd| cmd.args.iter().any(|arg| arg == "status")));
+
+        let probes: Vec<&Path> = commands[..build_script]
+            .iter()
+            .map(|cmd| cmd.program.as_path())
+            .collect();
+        assert!(probes.contains(&Path::new("glxinfo")));
+        assert!(probes.contains(&Path::new("gmake")));
+        assert!(probes.contains(&Path::new("pkg-config")));
+        assert!(commands[build_script..]
+            .iter()
+            .any(|cmd| cmd.program == Path::new("ldd")));
+
+        assert!(fs.exists(&base_dir.join("build.sh")));
+        assert!(fs.is_dir(&base_dir.join("scripts")));
+
+        let lockfile = Lockfile::load_from(&fs, &base_dir).unwrap();
+        let locked = lockfile.locked_build(None);
+        assert!(locked.spec_hashes.is_some());
+        assert!(locked.manifest.is_some());
+
+        // the progress, log and history are kept
+        // there too, and nothing is written to
+        // the real disk.
+        let progress = builder.previous_progress().unwrap();
+        assert_eq!(progress.phase, BuildPhase::Finished);
+        assert!(ProgressFile::load_from(&fs, &base_dir).is_some());
+        assert!(fs.exists(&base_dir.join("logs/build.log")));
+        assert_eq!(build.history().unwrap().len(), 1);
+        assert!(!base_dir.exists());
+    }
+
+    #[test]
+    fn does_not_rebuild_an_up_to_date_port() {
+        let fs = MemoryFs::default();
+        let runner = RecordingRunner::default();
+        let base_dir = base_dir(&fs);
+        let spec = spec();
+
+        let mut builder = Builder::new_with_fs(spec, &base_dir, Callbacks::new(), fs.clone())
+            .unwrap()
+            .with_runner(runner.clone());
+
+        // the first build has nothing in the
+        // lockfile to compare the spec to.
+        assert!(builder.build().unwrap().report().compiled);
+
+        // which make would have written.
+        fs.add_file(
+            base_dir.join("sm64ex/build/us_pc/sm64.us.f3dex2e"),
+            b"not really a game",
+        );
+
+        let build = builder.build().unwrap();
+        assert!(!build.report().compiled);
+
+        let ran_script = runner
+            .commands()
+            .iter()
+            .filter(|cmd| cmd.program == base_dir.join("build.sh"))
+            .count();
+        assert_eq!(ran_script, 1);
+    }
+
+    #[test]
+    fn cleans_the_build_when_debug_is_switched_on() {
+        let fs = MemoryFs::default();
+        let runner = RecordingRunner::default();
+        let base_dir = base_dir(&fs);
+        let spec = spec();
+
+        let mut builder = Builder::new_with_fs(spec, &base_dir, Callbacks::new(), fs.clone())
+            .unwrap()
+            .with_runner(runner.clone());
+        builder.build().unwrap();
+
+        builder.spec.debug = true;
+        assert!(builder.build().unwrap().report().compiled);
+
+        let cleaned = runner
+            .commands()
+            .iter()
+            .any(|cmd| cmd.args.iter().any(|arg| arg == "clean"));
+        assert!(cleaned);
+    }
+
+    #[test]
+    fn copies_the_rom_through_the_fs() {
+        let fs = MemoryFs::default();
+        let base_dir = base_dir(&fs);
+        let spec: Spec = serde_yaml::from_str(SPEC).unwrap();
+        fs.add_file(&spec.rom.path, b"not really a ROM");
+
+        let mut builder = Builder::new_with_fs(spec, &base_dir, Callbacks::new(), fs.clone())
+            .unwrap()
+            .with_runner(RecordingRunner::default());
+        let e = builder.build().unwrap_err();
+        assert!(matches!(e.cause, ErrorCause::ChecksumMismatch { .. }));
+
+        // the bad ROM is not left for
+        // the next build to skip over.
+        assert!(!fs.exists(&base_dir.join("sm64ex/baserom.us.z64")));
+    }
+
+    #[test]
+    fn keeps_a_clone_that_timed_out() {
+        /// Leaves part of the fetch
+        /// behind before timing out.
+        struct PartialFetch {
+            fs: MemoryFs,
+            shallow: PathBuf,
+            runner: RecordingRunner,
+        }
+
+        impl CommandRunner for PartialFetch {
+            fn run(
+                &self,
+                command: &Command,
+                limits: Limits,
+                should_stop: &dyn Fn() -> bool,
+                on_line: &mut dyn FnMut(&str),
+            ) -> io::Result<Outcome> {
+                if command.args.iter().any(|arg| arg == "fetch") {
+                    self.fs.add_file(&self.shallow, b"0123456789abcdef");
+                }
+                self.runner.run(command, limits, should_stop, on_line)
+            }
+        }
+
+        // libgit2 sets up the repo on the
+        // real disk, out of the way of the
+        // other tests.
+        let base_dir =
+            std::env::temp_dir().join(format!("smbuilder-clone-timeout-{}", std::process::id()));
+        let repo_dir = base_dir.join("sm64ex");
+        let shallow = repo_dir.join(".git/shallow");
+
+        let fs = MemoryFs::default();
+        fs.create_dir_all(&base_dir).unwrap();
+
+        let mut spec = spec();
+        spec.repo.depth = Some(1);
+
+        let runner = RecordingRunner::default();
+        runner.time_out_with_arg("fetch");
+        let mut builder = Builder::new_with_fs(spec, &base_dir, Callbacks::new(), fs.clone())
+            .unwrap()
+            .with_runner(PartialFetch {
+                fs: fs.clone(),
+                shallow: shallow.clone(),
+                runner: runner.clone(),
+            });
+        let result = builder.build();
+        let _ = std::fs::remove_dir_all(&base_dir);
+
+        let e = result.unwrap_err();
+        assert!(matches!(e.cause, ErrorCause::StageTimedOut { .. }));
+        assert!(fs.exists(&shallow));
+
+        // and is resumed on the next run.
+        let lockfile = Lockfile::load_from(&fs, &base_dir).unwrap();
+        assert!(lockfile.repo_incomplete());
+    }
+
+    #[test]
+    fn applies_the_patches_before_building() {
+        let fs = MemoryFs::default();
+        let base_dir = base_dir(&fs);
+        let patch_path = PathBuf::from("/patches/60fps.patch");
+        fs.add_file(&patch_path, b"diff --git a/src/game.c b/src/game.c");
+
+        let mut spec = spec();
+        spec.patches = Some(vec![Patch {
+            name: "60fps".to_owned(),
+            path: patch_path.clone(),
+            remote: None,
+        }]);
+
+        let is_apply = |cmd: &Command| {
+            cmd.args.iter().any(|arg| arg == "apply")
+                && !cmd.args.iter().any(|arg| arg == "--check")
+        };
+
+        // the patch does not reverse, so it
+        // has yet to be applied.
+        let runner = RecordingRunner::default();
+        runner.fail_with_arg("--reverse");
+        let mut builder =
+            Builder::new_with_fs(spec.clone(), &base_dir, Callbacks::new(), fs.clone())
+                .unwrap()
+                .with_runner(runner.clone())
+                .with_options(BuildOptions::new().force(true));
+        builder.build().unwrap();
+
+        let commands = runner.commands();
+        let apply = commands.iter().position(is_apply).unwrap();
+        let script = commands
+            .iter()
+            .position(|cmd| cmd.program == base_dir.join("build.sh"))
+            .unwrap();
+        assert!(apply < script);
+        assert_eq!(commands[apply].dir.as_ref(), Some(&base_dir.join("sm64ex")));
+        assert!(commands[apply].args.contains(&patch_path.clone().into()));
+
+        // it reverses now, so it is not
+        // applied a second time.
+        let runner = RecordingRunner::default();
+        let mut builder = Builder::new_with_fs(spec, &base_dir, Callbacks::new(), fs.clone())
+            .unwrap()
+            .with_runner(runner.clone())
+            .with_options(BuildOptions::new().force(true));
+        builder.build().unwrap();
+        assert!(!runner.commands().iter().any(is_apply));
+    }
+
+    #[test]
+    fn reapplies_the_patches_when_they_change() {
+        let fs = MemoryFs::default();
+        let base_dir = base_dir(&fs);
+        let patch = |name: &str| {
+            let path = PathBuf::from(format!("/patches/{}.patch", name));
+            fs.add_file(&path, b"diff --git a/src/game.c b/src/game.c");
+            Patch {
+                name: name.to_owned(),
+                path,
+                remote: None,
+            }
+        };
+
+        let mut spec = spec();
+        spec.patches = Some(vec![patch("60fps")]);
+
+        let runner = RecordingRunner::default();
+        runner.fail_with_arg("--reverse");
+        let applies = || {
+            runner
+                .commands()
+                .iter()
+                .filter(|cmd| cmd.args.iter().any(|arg| arg == "apply"))
+                .count()
+        };
+
+        let mut builder =
+            Builder::new_with_fs(spec.clone(), &base_dir, Callbacks::new(), fs.clone())
+                .unwrap()
+                .with_runner(runner.clone());
+        builder.build().unwrap();
+        let applied = applies();
+        assert!(applied > 0);
+
+        // nothing changed, so the patches
+        // are not looked at again.
+        fs.add_file(
+            base_dir.join("sm64ex/build/us_pc/sm64.us.f3dex2e"),
+            b"not really a game",
+        );
+        let build = builder.build().unwrap();
+        assert!(!build.report().compiled);
+        assert_eq!(applies(), applied);
+
+        // a new patch changes the hash, so they
+        // are applied, and the port recompiled.
+        spec.patches.as_mut().unwrap().push(patch("widescreen"));
+        let mut builder = Builder::new_with_fs(spec, &base_dir, Callbacks::new(), fs.clone())
+            .unwrap()
+            .with_runner(runner.clone());
+        let build = builder.build().unwrap();
+        assert!(build.report().compiled);
+        assert!(applies() > applied);
+    }
+
+    #[test]
+    fn records_the_apis_of_the_host() {
+        let fs = MemoryFs::default();
+        let runner = RecordingRunner::default();
+        let base_dir = base_dir(&fs);
+
+        // picking the APIs runs nothing.
+        assert!(!Makeopt::default_makeopts()
+            .iter()
+            .any(|m| m.key == "RENDER_API"));
+
+        let mut builder = Builder::new_with_fs(spec(), &base_dir, Callbacks::new(), fs.clone())
+            .unwrap()
+            .with_runner(runner.clone())
+            .with_options(BuildOptions::new().force(true));
+        builder.build().unwrap();
+        builder.build().unwrap();
+
+        let script = String::from_utf8(fs.read(&base_dir.join("build.sh")).unwrap()).unwrap();
+        assert!(script.contains("RENDER_API=GL"));
+
+        // so that a change of API cleans the build.
+        let lockfile = Lockfile::load_from(&fs, &base_dir).unwrap();
+        let makeopts = lockfile.locked_build(None).makeopts.unwrap();
+        assert!(makeopts
+            .iter()
+            .any(|m| m.key == "RENDER_API" && m.value == "GL"));
+
+        // the host is only probed once.
+        let probes = runner
+            .commands()
+            .iter()
+            .filter(|cmd| cmd.program == Path::new("glxinfo"))
+            .count();
+        assert_eq!(probes, 1);
     }
 }
@@ -0,0 +1,163 @@
+use super::backend::Command;
+use super::types::BuilderResult;
+use crate::error::ErrorCause;
+use crate::prelude::error_macros::*;
+use crate::prelude::Error;
+use crate::util;
+
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The name of the compile database, in
+/// the root of the repo, where clangd
+/// and other tools look for it.
+pub const COMPILE_COMMANDS_NAME: &str = "compile_commands.json";
+
+/// The extensions of the source files
+/// that the ports compile.
+const SOURCE_EXTENSIONS: &[&str] = &["c", "cc", "cpp", "cxx", "m", "mm"];
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// An entry of a compile database, in the
+/// format of clang's JSON compilation
+/// database.
+pub struct CompileCommand {
+    /// The directory that the
+    /// compiler was run in.
+    pub directory: PathBuf,
+    /// The source file that
+    /// was compiled.
+    pub file: PathBuf,
+    /// The compiler, and
+    /// its arguments.
+    pub arguments: Vec<String>,
+}
+
+/// Run `cmd` under `bear`, which writes the
+/// compiler commands that it runs to `dest`,
+/// keeping the entries that are already
+/// there (an incremental build only
+/// compiles some files).
+pub(crate) fn wrap_with_bear(cmd: Command, bear: PathBuf, dest: &Path) -> Command {
+    let mut args: Vec<OsString> = vec![
+        "--output".into(),
+        dest.into(),
+        "--append".into(),
+        "--".into(),
+        cmd.program.into(),
+    ];
+    args.extend(cmd.args);
+
+    Command {
+        program: bear,
+        args,
+        ..cmd
+    }
+}
+
+/// A compile database, pieced together from
+/// the commands that make prints as it runs
+/// them, for when `bear` is not installed.
+pub(crate) struct CompileDatabase {
+    directory: PathBuf,
+    commands: Vec<CompileCommand>,
+}
+
+impl CompileDatabase {
+    /// Creates an empty database of a build
+    /// that make runs in `directory`.
+    pub(crate) fn new(directory: PathBuf) -> Self {
+        CompileDatabase {
+            directory,
+            commands: Vec::new(),
+        }
+    }
+
+    /// Whether no commands
+    /// have been found.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Add the line of output, if it
+    /// is a command that compiles a
+    /// source file.
+    pub(crate) fn feed(&mut self, line: &str) {
+        let mut arguments: Vec<String> = line.split_whitespace().map(str::to_owned).collect();
+
+        // the compiler may be run through a cache.
+        if arguments
+            .first()
+            .is_some_and(|arg| arg.ends_with("ccache") || arg.ends_with("sccache"))
+        {
+            arguments.remove(0);
+        }
+
+        let is_compiler = arguments.first().is_some_and(|program| {
+            let name = Path::new(program)
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy();
+
+            ["gcc", "g++", "cc", "c++", "clang", "clang++"]
+                .iter()
+                .any(|compiler| name == *compiler || name.ends_with(&format!("-{}", compiler)))
+        });
+
+        if !is_compiler || !arguments.iter().any(|arg| arg == "-c") {
+            return;
+        }
+
+        let file = arguments
+            .iter()
+            .enumerate()
+            .skip(1)
+            // the output of `-o` is not the source.
+            .filter(|(i, _)| arguments[i - 1] != "-o")
+            .map(|(_, arg)| Path::new(arg))
+            .find(|path| {
+                path.extension()
+                    .is_some_and(|ext| SOURCE_EXTENSIONS.iter().any(|e| ext == *e))
+            })
+            .map(Path::to_path_buf);
+
+        if let Some(file) = file {
+            self.commands.push(CompileCommand {
+                directory: self.directory.clone(),
+                file,
+                arguments,
+            });
+        }
+    }
+
+    /// Write the database to `dest`, merged
+    /// with the one that is there already, as
+    /// an incremental build only compiles some
+    /// of the files.
+    pub(crate) fn save(self, dest: &Path) -> BuilderResult<()> {
+        let mut commands: Vec<CompileCommand> = fs::read_to_string(dest)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+
+        commands.retain(|old| !self.commands.iter().any(|new| new.file == old.file));
+        commands.extend(self.commands);
+
+        let contents = match serde_json::to_string_pretty(&commands) {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(err!(
+                    c_other!(e),
+                    "failed to serialize the compile database"
+                ))
+            }
+        };
+
+        util::write_atomically(dest, contents).map_err(|e| {
+            let msg = format!("failed to write {}", dest.display());
+            err!(c_fs!(e, msg), "whilst writing the compile database")
+        })
+    }
+}
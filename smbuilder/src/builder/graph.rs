@@ -0,0 +1,224 @@
+use super::remote_resources;
+use super::types::{PostBuildStage, SetupStage};
+use crate::prelude::{SourceKind, Spec};
+
+impl SetupStage {
+    /// Every setup stage, in the order that
+    /// they run in when nothing else decides.
+    pub const ALL: [SetupStage; 10] = [
+        SetupStage::DownloadResources,
+        SetupStage::DownloadPrebuilt,
+        SetupStage::CloneRepo,
+        SetupStage::FetchLfsObjects,
+        SetupStage::DownloadDiscordSdk,
+        SetupStage::CopyRom,
+        SetupStage::ApplyPatches,
+        SetupStage::CreateBuildScript,
+        SetupStage::CreateScriptsDir,
+        SetupStage::WritePostBuildScripts,
+    ];
+
+    /// Gets the stages whose products this
+    /// stage works on, which run before it,
+    /// and which it has to run again after.
+    pub fn depends_on(&self) -> &'static [SetupStage] {
+        use SetupStage::*;
+
+        match self {
+            FetchLfsObjects | DownloadDiscordSdk | CopyRom => &[CloneRepo],
+            // a fresh clone (or a patch that was
+            // just downloaded) has them to apply.
+            ApplyPatches => &[CloneRepo, DownloadResources],
+            WritePostBuildScripts => &[CreateScriptsDir],
+            _ => &[],
+        }
+    }
+
+    /// Whether the stage has anything to
+    /// do for `spec` at all.
+    pub fn applies_to(&self, spec: &Spec) -> bool {
+        use SetupStage::*;
+
+        let prebuilt = matches!(spec.source, SourceKind::Prebuilt { .. });
+
+        match self {
+            DownloadPrebuilt => prebuilt,
+            CloneRepo | FetchLfsObjects | CopyRom | CreateBuildScript => !prebuilt,
+            DownloadDiscordSdk => !prebuilt && spec.discord_sdk.is_some(),
+            ApplyPatches => !prebuilt && spec.patches.iter().flatten().next().is_some(),
+            DownloadResources => !remote_resources(spec).is_empty(),
+            CreateScriptsDir => true,
+            WritePostBuildScripts => spec.all_scripts().next().is_some(),
+        }
+    }
+
+    /// Whether the port cannot be compiled
+    /// until the stage has run.
+    ///
+    /// The stages that are not can run
+    /// alongside the compile.
+    pub fn needed_by_compile(&self, spec: &Spec) -> bool {
+        use SetupStage::*;
+
+        match self {
+            // the packs are only needed by the
+            // post-build stages, unlike patches.
+            DownloadResources => remote_resources(spec).iter().any(|d| !d.unpack),
+            CreateScriptsDir | WritePostBuildScripts => false,
+            _ => true,
+        }
+    }
+}
+
+impl PostBuildStage {
+    /// Every post-build stage, in the order that
+    /// they run in when nothing else decides.
+    pub const ALL: [PostBuildStage; 9] = [
+        PostBuildStage::EmbedIcon,
+        PostBuildStage::LinkExecutable,
+        PostBuildStage::StripExecutable,
+        PostBuildStage::SmokeTest,
+        PostBuildStage::TexturePack,
+        PostBuildStage::DynOSPacks,
+        PostBuildStage::SaveFiles,
+        PostBuildStage::ControllerMappings,
+        PostBuildStage::PostBuildScripts,
+    ];
+
+    /// Gets the stages that this
+    /// stage has to run after.
+    pub fn depends_on(&self) -> &'static [PostBuildStage] {
+        use PostBuildStage::*;
+
+        match self {
+            // the link is a copy of the executable
+            // on windows, so it is made after the
+            // icon is embedded, and stripped too.
+            LinkExecutable => &[EmbedIcon],
+            StripExecutable => &[LinkExecutable],
+            SmokeTest => &[StripExecutable],
+            // scripts may build on anything
+            // that was installed.
+            PostBuildScripts => &[TexturePack, DynOSPacks, SaveFiles, ControllerMappings],
+            _ => &[],
+        }
+    }
+
+    /// Whether the stage changes the
+    /// executable, rather than installing
+    /// things next to it.
+    pub fn touches_executable(&self) -> bool {
+        use PostBuildStage::*;
+
+        matches!(
+            self,
+            EmbedIcon | LinkExecutable | StripExecutable | SmokeTest
+        )
+    }
+}
+
+/// Add the stages of `all` that depend (directly
+/// or not) on a stage in `needed`, as they have
+/// to run again on the new products.
+pub fn with_dependents<S, D>(needed: &[S], all: &[S], depends_on: D) -> Vec<S>
+where
+    S: Copy + PartialEq + 'static,
+    D: Fn(&S) -> &'static [S],
+{
+    let mut result: Vec<S> = Vec::new();
+    for stage in needed {
+        if !result.contains(stage) {
+            result.push(*stage);
+        }
+    }
+
+    loop {
+        let dependent = all.iter().find(|stage| {
+            !result.contains(stage) && depends_on(stage).iter().any(|d| result.contains(d))
+        });
+
+        match dependent {
+            Some(stage) => result.push(*stage),
+            None => return result,
+        }
+    }
+}
+
+/// Order `stages` so that every stage runs after
+/// the stages in `stages` that it depends on,
+/// keeping to the order of `all` otherwise.
+///
+/// Each stage is only kept once.
+pub fn topological_order<S, D>(stages: &[S], all: &[S], depends_on: D) -> Vec<S>
+where
+    S: Copy + PartialEq + 'static,
+    D: Fn(&S) -> &'static [S],
+{
+    let mut remaining: Vec<S> = all.iter().copied().filter(|s| stages.contains(s)).collect();
+    let mut ordered = Vec::with_capacity(remaining.len());
+
+    while !remaining.is_empty() {
+        let ready = remaining
+            .iter()
+            .position(|stage| !depends_on(stage).iter().any(|d| remaining.contains(d)))
+            // the graph is fixed, and has no cycles,
+            // but a cycle should not hang the build.
+            .unwrap_or(0);
+
+        ordered.push(remaining.remove(ready));
+    }
+
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PostBuildStage::*;
+
+    #[test]
+    fn adds_the_dependents_of_a_stage() {
+        let stages = with_dependents(
+            &[EmbedIcon],
+            &PostBuildStage::ALL,
+            PostBuildStage::depends_on,
+        );
+        assert_eq!(
+            stages,
+            [EmbedIcon, LinkExecutable, StripExecutable, SmokeTest]
+        );
+
+        let stages = with_dependents(
+            &[SaveFiles],
+            &PostBuildStage::ALL,
+            PostBuildStage::depends_on,
+        );
+        assert_eq!(stages, [SaveFiles, PostBuildScripts]);
+    }
+
+    #[test]
+    fn orders_stages_after_their_dependencies() {
+        let stages = topological_order(
+            &[
+                PostBuildScripts,
+                SmokeTest,
+                TexturePack,
+                EmbedIcon,
+                SmokeTest,
+            ],
+            &PostBuildStage::ALL,
+            PostBuildStage::depends_on,
+        );
+        assert_eq!(
+            stages,
+            [EmbedIcon, SmokeTest, TexturePack, PostBuildScripts]
+        );
+
+        let stages = topological_order(
+            &[SetupStage::CopyRom, SetupStage::CloneRepo],
+            &SetupStage::ALL,
+            SetupStage::depends_on,
+        );
+        assert_eq!(stages, [SetupStage::CloneRepo, SetupStage::CopyRom]);
+    }
+}
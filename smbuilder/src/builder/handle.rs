@@ -0,0 +1,242 @@
+use super::backend::FsProvider;
+use super::history;
+use super::types::{BuildReport, BuilderResult};
+use crate::error::ErrorCause;
+use crate::manage::SPEC_FILE_NAME;
+use crate::prelude::error_macros::*;
+use crate::prelude::{Error, Patch, Spec};
+use crate::types::pack::{self, PackManifest};
+use crate::util;
+
+use git2::{DiffFormat, Repository};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::Arc;
+
+/// The directories of the build directory
+/// that the game needs at runtime, next
+/// to the executable.
+const RUNTIME_DIRS: &[&str] = &["res", "dynos"];
+
+#[derive(Clone, Debug)]
+/// A finished build, as returned
+/// by `Builder::build`.
+///
+/// Gives the paths of what was built, so that
+/// they do not have to be pieced together
+/// again from the spec.
+pub struct Build {
+    spec: Spec,
+    base_dir: PathBuf,
+    report: BuildReport,
+    fs: Arc<dyn FsProvider>,
+}
+
+impl Build {
+    pub(crate) fn new(
+        spec: Spec,
+        base_dir: PathBuf,
+        report: BuildReport,
+        fs: Arc<dyn FsProvider>,
+    ) -> Self {
+        Build {
+            spec,
+            base_dir,
+            report,
+            fs,
+        }
+    }
+
+    /// Gets the path of the built executable.
+    pub fn executable_path(&self) -> &Path {
+        &self.report.executable
+    }
+
+    /// Gets the directory that the
+    /// repo was cloned into.
+    pub fn repo_dir(&self) -> PathBuf {
+        self.base_dir.join(&self.spec.repo.name)
+    }
+
+    /// Gets the directory that the port
+    /// was built into (of its named
+    /// configuration, if it has one).
+    pub fn build_dir(&self) -> PathBuf {
+        self.spec.build_dir(self.repo_dir())
+    }
+
+    /// Uninstall the pack of the spec named
+    /// `name` from the build, without
+    /// rebuilding it.
+    ///
+    /// The pack stays in the spec, so a later
+    /// build installs it again, unless it is
+    /// taken out of the spec too.
+    pub fn uninstall_pack(&self, name: &str) -> BuilderResult<PackManifest> {
+        pack::uninstall_from_build(&self.spec, &self.base_dir, name)
+    }
+
+    /// Gets the report of the build.
+    pub fn report(&self) -> &BuildReport {
+        &self.report
+    }
+
+    /// Gets the reports of the builds of the
+    /// base dir, this one included, from the
+    /// oldest to the newest.
+    pub fn history(&self) -> BuilderResult<Vec<BuildReport>> {
+        history::load_from(&*self.fs, &self.base_dir)
+    }
+
+    /// Save the changes to the files of the repo
+    /// (like `git diff HEAD`) as a patch named
+    /// `name`, in the `patches` folder of the
+    /// base dir, and add it to the spec.
+    ///
+    /// The spec file of the base dir is saved
+    /// with the patch added, if there is one. A
+    /// patch of the same name is replaced. New
+    /// files that git does not track yet are
+    /// left out.
+    pub fn diff_to_patch(&mut self, name: &str) -> BuilderResult<Patch> {
+        let repo_dir = self.repo_dir();
+        let git_err = |e: git2::Error| {
+            err!(
+                c_other!(e),
+                format!("failed to diff the repo at {}", repo_dir.display())
+            )
+        };
+
+        let repo = Repository::open(&repo_dir).map_err(git_err)?;
+        let head = repo
+            .head()
+            .and_then(|head| head.peel_to_tree())
+            .map_err(git_err)?;
+        let diff = repo
+            .diff_tree_to_workdir_with_index(Some(&head), None)
+            .map_err(git_err)?;
+
+        if diff.deltas().len() == 0 {
+            let e = std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} has no changes to make a patch of", repo_dir.display()),
+            );
+            return Err(err!(c_other!(e), "whilst creating the patch"));
+        }
+
+        let mut contents: Vec<u8> = Vec::new();
+        diff.print(DiffFormat::Patch, |_, _, line| {
+            // the content of a line does
+            // not include its prefix.
+            if matches!(line.origin(), '+' | '-' | ' ') {
+                contents.push(line.origin() as u8);
+            }
+            contents.extend_from_slice(line.content());
+            true
+        })
+        .map_err(git_err)?;
+
+        let file_name: String = name
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+                _ => '_',
+            })
+            .collect();
+        let path = self
+            .base_dir
+            .join("patches")
+            .join(format!("{}.patch", file_name));
+
+        let write_result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| util::write_atomically(&path, &contents));
+        if let Err(e) = write_result {
+            let msg = format!("failed to write {}", path.display());
+            return Err(err!(c_fs!(e, msg), "whilst creating the patch"));
+        }
+
+        let patch = Patch {
+            name: name.to_owned(),
+            path,
+            remote: None,
+        };
+
+        let add_patch = |spec: &mut Spec| {
+            let patches = spec.patches.get_or_insert_with(Vec::new);
+            patches.retain(|p| p.name != patch.name);
+            patches.push(patch.clone());
+        };
+
+        // the spec of the build may be narrowed
+        // down to a configuration, so the file is
+        // loaded again rather than overwritten.
+        let spec_path = self.base_dir.join(SPEC_FILE_NAME);
+        if spec_path.is_file() {
+            let mut spec = Spec::from_file(&spec_path)?;
+            add_patch(&mut spec);
+            spec.to_file(&spec_path)?;
+        }
+        add_patch(&mut self.spec);
+
+        Ok(patch)
+    }
+
+    /// Start the game, from the directory of
+    /// the executable (where it looks for its
+    /// assets), without waiting for it to exit.
+    pub fn launch(&self) -> BuilderResult<Child> {
+        let executable = self.executable_path();
+        let dir = executable.parent().unwrap_or(&self.base_dir);
+
+        match Command::new(executable).current_dir(dir).spawn() {
+            Ok(child) => Ok(child),
+            Err(e) => Err(err!(
+                c_spawn_cmd!(
+                    executable.display().to_string(),
+                    "failed to start the game",
+                    e
+                ),
+                "whilst launching the build"
+            )),
+        }
+    }
+
+    /// Copy the game (the executable and
+    /// the assets and packs it loads) into
+    /// `dest`, to be run or shared without
+    /// the rest of the build.
+    ///
+    /// Returns the path of the exported
+    /// executable.
+    pub fn export<P: AsRef<Path>>(&self, dest: P) -> BuilderResult<PathBuf> {
+        let dest = dest.as_ref();
+        let executable = self.executable_path();
+        let exported = dest.join(executable.file_name().unwrap_or_default());
+
+        let fs_err = |e, path: &Path| {
+            let msg = format!("failed to copy {}", path.display());
+            err!(c_fs!(e, msg), "whilst exporting the build")
+        };
+
+        if let Err(e) = fs::create_dir_all(dest) {
+            return Err(fs_err(e, dest));
+        }
+
+        fs::copy(executable, &exported).map_err(|e| fs_err(e, executable))?;
+
+        let build_dir = executable.parent().unwrap_or(&self.base_dir);
+        for dir in RUNTIME_DIRS.iter().map(|name| build_dir.join(name)) {
+            if !dir.is_dir() {
+                continue;
+            }
+
+            util::copy_dir_into(&dir, dest, Default::default(), &mut |_, _, _| ())
+                .map_err(|e| fs_err(e, &dir))?;
+        }
+
+        Ok(exported)
+    }
+}
@@ -0,0 +1,121 @@
+use super::backend::{FsProvider, RealFs};
+use super::types::{BuildReport, BuilderResult};
+use crate::error::ErrorCause;
+use crate::prelude::error_macros::*;
+use crate::prelude::Error;
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The name of the directory, in the root of
+/// a base dir, that the reports of successful
+/// builds are kept in, one JSON file each.
+pub const HISTORY_DIR_NAME: &str = "history";
+
+/// The most reports that are kept; the
+/// oldest ones are removed past this.
+const MAX_REPORTS: usize = 100;
+
+/// Gets the time, in seconds
+/// since the unix epoch.
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Record the report of a build in the
+/// history of `base_dir` on `fs`, removing
+/// the oldest reports if there are too many.
+pub(crate) fn record(
+    fs: &dyn FsProvider,
+    base_dir: &Path,
+    report: &BuildReport,
+) -> BuilderResult<()> {
+    let dir = base_dir.join(HISTORY_DIR_NAME);
+    fs.create_dir_all(&dir).map_err(|e| history_err(e, &dir))?;
+
+    // builds of the same second (like the
+    // configurations of one spec) are kept
+    // apart by a counter.
+    let mut path = dir.join(format!("{}.json", report.finished_at));
+    let mut n = 1;
+    while fs.exists(&path) {
+        path = dir.join(format!("{}-{}.json", report.finished_at, n));
+        n += 1;
+    }
+
+    let contents = match serde_json::to_string_pretty(report) {
+        Ok(c) => c,
+        Err(e) => return Err(err!(c_other!(e), "failed to serialize the build report")),
+    };
+    fs.write(&path, contents.as_bytes())
+        .map_err(|e| history_err(e, &path))?;
+
+    let files = report_files(fs, &dir)?;
+    for (_, old) in files.iter().take(files.len().saturating_sub(MAX_REPORTS)) {
+        let _ = fs.remove_file(old);
+    }
+
+    Ok(())
+}
+
+/// Load the reports in the history of
+/// `base_dir`, from the oldest to the
+/// newest.
+///
+/// Reports that cannot be read are
+/// skipped, and a base dir that has
+/// never been built has no history.
+pub fn load<P: AsRef<Path>>(base_dir: P) -> BuilderResult<Vec<BuildReport>> {
+    load_from(&RealFs, base_dir)
+}
+
+/// Load the reports in the history of
+/// `base_dir` on `fs`, like `load`.
+pub fn load_from<P: AsRef<Path>>(
+    fs: &dyn FsProvider,
+    base_dir: P,
+) -> BuilderResult<Vec<BuildReport>> {
+    let dir = base_dir.as_ref().join(HISTORY_DIR_NAME);
+
+    if !fs.is_dir(&dir) {
+        return Ok(Vec::new());
+    }
+
+    let mut reports: Vec<BuildReport> = report_files(fs, &dir)?
+        .into_iter()
+        .filter_map(|(_, path)| fs.read(&path).ok())
+        .filter_map(|contents| serde_json::from_slice(&contents).ok())
+        .collect();
+    reports.sort_by_key(|report| report.finished_at);
+
+    Ok(reports)
+}
+
+/// Gets the report files in `dir`, with the
+/// time and counter in their names, oldest
+/// first.
+fn report_files(fs: &dyn FsProvider, dir: &Path) -> BuilderResult<Vec<((u64, u32), PathBuf)>> {
+    let entries = fs.read_dir(dir).map_err(|e| history_err(e, dir))?;
+
+    let mut files: Vec<((u64, u32), PathBuf)> = entries
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| {
+            let stem = path.file_stem()?.to_str()?;
+            let (time, n) = stem.split_once('-').unwrap_or((stem, "0"));
+            Some(((time.parse().ok()?, n.parse().ok()?), path))
+        })
+        .collect();
+    files.sort();
+
+    Ok(files)
+}
+
+fn history_err(e: io::Error, path: &Path) -> Error {
+    let msg = format!("failed to access {}", path.display());
+    err!(c_fs!(e, msg), "whilst recording the build history")
+}
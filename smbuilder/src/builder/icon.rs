@@ -0,0 +1,83 @@
+use std::path::Path;
+
+/// Gets the contents of a freedesktop
+/// `.desktop` file that starts the game
+/// with an icon.
+///
+/// `dir` is the directory that the game
+/// is started in.
+pub fn desktop_entry(name: &str, exec: &Path, icon: &Path, dir: &Path) -> String {
+    format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name={}\n\
+         Exec=\"{}\"\n\
+         Path={}\n\
+         Icon={}\n\
+         Terminal=false\n\
+         Categories=Game;\n",
+        name,
+        escape_exec(&exec.display().to_string()),
+        dir.display(),
+        icon.display()
+    )
+}
+
+/// Gets the contents of the `Info.plist`
+/// of a macOS app bundle.
+///
+/// `executable` is the name of the file in
+/// `Contents/MacOS`, and `icon` the name of
+/// the `.icns` file in `Contents/Resources`.
+pub fn info_plist(name: &str, executable: &str, icon: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n\
+         <dict>\n\
+         \t<key>CFBundleName</key>\n\
+         \t<string>{}</string>\n\
+         \t<key>CFBundleExecutable</key>\n\
+         \t<string>{}</string>\n\
+         \t<key>CFBundleIconFile</key>\n\
+         \t<string>{}</string>\n\
+         \t<key>CFBundlePackageType</key>\n\
+         \t<string>APPL</string>\n\
+         </dict>\n\
+         </plist>\n",
+        escape_xml(name),
+        escape_xml(executable),
+        escape_xml(icon)
+    )
+}
+
+/// Gets a shell script that starts
+/// `executable` from its own directory,
+/// for the inside of an app bundle.
+pub fn launcher_script(executable: &Path) -> String {
+    let quoted = format!(
+        "'{}'",
+        executable.display().to_string().replace('\'', "'\\''")
+    );
+
+    format!(
+        "#!/bin/sh\ncd \"$(dirname {})\" && exec {} \"$@\"\n",
+        quoted, quoted
+    )
+}
+
+/// Escape a quoted argument of the
+/// `Exec` key of a `.desktop` file.
+fn escape_exec(arg: &str) -> String {
+    arg.replace('\\', "\\\\\\\\")
+        .replace('"', "\\\\\"")
+        .replace('`', "\\\\`")
+        .replace('$', "\\\\$")
+        .replace('%', "%%")
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
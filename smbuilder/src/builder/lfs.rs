@@ -0,0 +1,92 @@
+use super::backend::{self, Command, CommandRunner, FsProvider, RealFs, Stdin, SystemRunner};
+use super::process::{Limits, Outcome};
+use super::types::BuilderResult;
+use crate::deps;
+use crate::error::ErrorCause;
+use crate::prelude::error_macros::*;
+use crate::prelude::Error;
+
+use std::io;
+use std::path::Path;
+
+/// Whether a repository stores files with
+/// Git LFS, according to its `.gitattributes`.
+pub fn repo_uses_lfs<P: AsRef<Path>>(repo_dir: P) -> bool {
+    repo_uses_lfs_on(&RealFs, repo_dir)
+}
+
+/// Like `repo_uses_lfs`, but
+/// looking on `fs`.
+pub fn repo_uses_lfs_on<P: AsRef<Path>>(fs: &dyn FsProvider, repo_dir: P) -> bool {
+    match fs.read(&repo_dir.as_ref().join(".gitattributes")) {
+        Ok(attributes) => String::from_utf8_lossy(&attributes)
+            .lines()
+            .any(|line| !line.trim_start().starts_with('#') && line.contains("filter=lfs")),
+        Err(_) => false,
+    }
+}
+
+/// Replace the LFS pointer files in a
+/// repository with the real objects, using
+/// the `git lfs` command.
+///
+/// Returns the output of `git lfs pull`.
+pub fn fetch_lfs_objects<P: AsRef<Path>>(repo_dir: P) -> BuilderResult<String> {
+    fetch_lfs_objects_with(&SystemRunner, repo_dir)
+}
+
+/// Like `fetch_lfs_objects`, but
+/// running `git` with `runner`.
+pub fn fetch_lfs_objects_with<P: AsRef<Path>>(
+    runner: &dyn CommandRunner,
+    repo_dir: P,
+) -> BuilderResult<String> {
+    let repo_dir = repo_dir.as_ref();
+    let git = |args: &[&str]| {
+        args.iter()
+            .fold(Command::new("git").dir(repo_dir), |cmd, arg| cmd.arg(*arg))
+            .stdin(Stdin::Null)
+    };
+
+    // without git-lfs, git has no `lfs`
+    // command, and exits with an error.
+    if !backend::probe(runner, git(&["lfs", "version"])).is_some_and(|(ok, _)| ok) {
+        return Err(err!(
+            c_missing_dep!(
+                "git-lfs".to_owned(),
+                format!(
+                    "this repository stores assets with Git LFS; {} and build again",
+                    deps::install_hint("git-lfs")
+                )
+            ),
+            "cannot fetch the Git LFS objects"
+        ));
+    }
+
+    let run = |args: &[&str]| {
+        let git_cmd = git(args);
+        let mut output = String::new();
+        let outcome = runner.run(&git_cmd, Limits::default(), &|| false, &mut |ln| {
+            output.push_str(ln);
+            output.push('\n');
+        });
+
+        let e = match outcome {
+            Ok(Outcome::Exited(true)) => return Ok(output),
+            Ok(_) => io::Error::other(format!("git exited with an error:\n{}", output)),
+            Err(e) => e,
+        };
+
+        Err(err!(
+            c_spawn_cmd!(
+                git_cmd.to_string(),
+                "failed to fetch the Git LFS objects",
+                e
+            ),
+            "whilst fetching the Git LFS objects"
+        ))
+    };
+
+    run(&["lfs", "install", "--local"])?;
+    run(&["lfs", "pull"])
+}
@@ -0,0 +1,121 @@
+use super::types::BuilderResult;
+use crate::error::ErrorCause;
+use crate::prelude::error_macros::*;
+use crate::prelude::Error;
+
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// The name of the lock file that
+/// lives in the root of a base dir.
+pub const LOCK_FILE_NAME: &str = ".smbuilder.lock";
+
+/// An advisory lock over a base directory.
+///
+/// Only one `BaseDirLock` can be held per
+/// base directory at a time, across processes.
+/// The lock is released when it is dropped
+/// (or when the owning process exits).
+#[derive(Debug)]
+pub struct BaseDirLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl BaseDirLock {
+    /// Try to acquire the lock for a base
+    /// directory, without blocking.
+    ///
+    /// Returns an error with the `BaseDirLocked`
+    /// cause if another build holds the lock.
+    pub fn acquire<P: AsRef<Path>>(base_dir: P) -> BuilderResult<Self> {
+        let base_dir = base_dir.as_ref();
+        let path = base_dir.join(LOCK_FILE_NAME);
+
+        let mut file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+        {
+            Ok(f) => f,
+            Err(e) => {
+                let msg = format!("failed to open the lock file at {}", path.display());
+                return Err(err!(c_fs!(e, msg), "whilst locking the base directory"));
+            }
+        };
+
+        match file.try_lock() {
+            Ok(_) => (),
+            Err(TryLockError::WouldBlock) => {
+                let pid = fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|s| s.trim().parse::<u32>().ok());
+
+                return Err(err!(c_dir_locked!(base_dir.to_owned(), pid)));
+            }
+            Err(TryLockError::Error(e)) => {
+                let msg = format!("failed to lock {}", path.display());
+                return Err(err!(c_fs!(e, msg), "whilst locking the base directory"));
+            }
+        };
+
+        // record who owns the lock, purely
+        // for nicer error messages.
+        let _ = file.set_len(0);
+        let _ = file.rewind();
+        let _ = write!(file, "{}", std::process::id());
+
+        Ok(BaseDirLock { file, path })
+    }
+
+    /// The path of the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for BaseDirLock {
+    fn drop(&mut self) {
+        // the file is intentionally left on
+        // disk; removing it would race with
+        // another process that already opened it.
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_one_lock_per_base_dir() {
+        let base_dir = std::env::temp_dir().join(format!("smbuilder-lock-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base_dir);
+        fs::create_dir_all(&base_dir).unwrap();
+
+        let lock = BaseDirLock::acquire(&base_dir).unwrap();
+        assert_eq!(lock.path(), base_dir.join(LOCK_FILE_NAME));
+
+        // the lock says who holds it.
+        let e = BaseDirLock::acquire(&base_dir).unwrap_err();
+        match e.cause {
+            ErrorCause::BaseDirLocked { dir, pid } => {
+                assert_eq!(dir, base_dir);
+                assert_eq!(pid, Some(std::process::id()));
+            }
+            _ => panic!("expected the base dir to be locked, got {}", e),
+        }
+
+        // the file stays behind, but
+        // the lock can be taken again.
+        drop(lock);
+        assert!(base_dir.join(LOCK_FILE_NAME).exists());
+        assert!(BaseDirLock::acquire(&base_dir).is_ok());
+
+        let _ = fs::remove_dir_all(&base_dir);
+    }
+}
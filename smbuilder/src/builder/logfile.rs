@@ -0,0 +1,147 @@
+use super::backend::FsProvider;
+use super::types::LogFileOptions;
+
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The name of the directory, in the root
+/// of a base dir, that the log files of
+/// builds are written to.
+pub const LOG_DIR_NAME: &str = "logs";
+
+/// The name of the log file of the
+/// latest build, in `LOG_DIR_NAME`.
+///
+/// Older ones are rotated to `build.1.log`,
+/// `build.2.log` and so on.
+pub const LOG_FILE_NAME: &str = "build.log";
+
+/// The log file of a build, which is capped
+/// in size and rotated, so that a runaway
+/// build cannot fill the disk with its log.
+pub(crate) struct LogFile {
+    fs: Arc<dyn FsProvider>,
+    path: PathBuf,
+    options: LogFileOptions,
+    file: Option<Box<dyn Write + Send>>,
+    written: u64,
+}
+
+impl fmt::Debug for LogFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogFile")
+            .field("path", &self.path)
+            .field("options", &self.options)
+            .field("written", &self.written)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LogFile {
+    /// A log file in the `logs` directory
+    /// of a base dir on `fs`.
+    ///
+    /// Nothing is written until `start`.
+    pub(crate) fn in_base_dir(
+        fs: Arc<dyn FsProvider>,
+        base_dir: &Path,
+        options: LogFileOptions,
+    ) -> LogFile {
+        LogFile {
+            fs,
+            path: base_dir.join(LOG_DIR_NAME).join(LOG_FILE_NAME),
+            options,
+            file: None,
+            written: 0,
+        }
+    }
+
+    /// Start the log of a new build,
+    /// rotating the last one away.
+    pub(crate) fn start(&mut self) -> io::Result<()> {
+        self.file = None;
+
+        if let Some(dir) = self.path.parent() {
+            self.fs.create_dir_all(dir)?;
+        }
+
+        self.rotate()
+    }
+
+    /// Write a line to the log, cut short if it
+    /// is too long, starting a new file first if
+    /// the current one is full.
+    pub(crate) fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.file.is_none() {
+            return Ok(());
+        }
+
+        let line = truncate_line(line, self.options.max_line_len);
+
+        if self.written + line.len() as u64 + 1 > self.options.max_size && self.written > 0 {
+            self.rotate()?;
+        }
+
+        // flushed line by line, so that the
+        // log can be followed as it is written.
+        if let Some(file) = &mut self.file {
+            writeln!(file, "{}", line)?;
+            file.flush()?;
+            self.written += line.len() as u64 + 1;
+        }
+
+        Ok(())
+    }
+
+    /// Move `build.log` to `build.1.log` (and so
+    /// on, dropping the oldest), and open a new,
+    /// empty `build.log`.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+
+        if self.fs.exists(&self.path) {
+            let keep = self.options.keep;
+
+            if keep == 0 {
+                self.fs.remove_file(&self.path)?;
+            } else {
+                let _ = self.fs.remove_file(&self.rotated_path(keep));
+
+                for n in (1..keep).rev() {
+                    let from = self.rotated_path(n);
+                    if self.fs.exists(&from) {
+                        self.fs.rename(&from, &self.rotated_path(n + 1))?;
+                    }
+                }
+
+                self.fs.rename(&self.path, &self.rotated_path(1))?;
+            }
+        }
+
+        self.file = Some(self.fs.create(&self.path)?);
+        self.written = 0;
+        Ok(())
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        self.path.with_extension(format!("{}.log", n))
+    }
+}
+
+/// Cut a line down to at most `max_len` bytes
+/// (on a character boundary), saying how much
+/// of it was cut.
+fn truncate_line(line: &str, max_len: usize) -> std::borrow::Cow<'_, str> {
+    if line.len() <= max_len {
+        return line.into();
+    }
+
+    let mut end = max_len;
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    format!("{}... ({} bytes cut)", &line[..end], line.len() - end).into()
+}
@@ -3,49 +3,168 @@ pub mod types;
 
 /// Houses the builder struct that
 /// takes care of all the building.
+#[allow(clippy::module_inception)]
 pub mod builder;
 
+/// A pollable snapshot of a build's state.
+pub mod state;
+
+/// Sanity checks for built executables.
+pub mod verify;
+
+/// Git LFS support.
+pub mod lfs;
+
+/// Advisory locking of base directories,
+/// so that concurrent builds do not step
+/// on each other.
+pub mod lock;
+
+/// Running commands under a watchdog.
+pub mod process;
+
+/// Working out how much of a port has
+/// to be rebuilt when its makeopts change.
+pub mod rebuild;
+
+/// Resolving `latest-release` to
+/// the tag of the newest release.
+pub mod release;
+
+/// The handle to a finished build.
+pub mod handle;
+
+/// The files that give the built
+/// game an icon on each platform.
+pub mod icon;
+
+/// Size-capped, rotated log files.
+pub mod logfile;
+
+/// The order that the stages of a build
+/// run in, from what they depend on.
+pub mod graph;
+
+/// Custom build stages, for consumers
+/// of the crate to add their own steps.
+pub mod stage;
+
+/// The reports of past builds,
+/// kept in the base dir.
+pub mod history;
+
+/// Compile databases (`compile_commands.json`)
+/// for the editors of developers.
+pub mod compdb;
+
+/// Estimating how far along
+/// the compiler is.
+pub(crate) mod progress;
+
+/// The filesystem and command runner
+/// that the builder works through.
+pub mod backend;
+
 use crate::callback_types::LogType;
-use crate::prelude::{run_callback, Callbacks, Region, Spec};
+use crate::prelude::{
+    run_callback, Callbacks, DiscordSdk, Lockfile, Region, RemoteResource, SourceKind, Spec,
+};
+use crate::{deps, util};
 
+use backend::{FsProvider, RealFs};
 use types::SetupStage;
 use LogType::*;
 
-use std::path::Path;
+use std::fmt;
+use std::path::{Path, PathBuf};
 
 /// Get the core setup tasks that are needed.
 ///
-/// Returns a list of `SmbuilderSetupStage`.
-// TODO: example
+/// Returns a list of `SetupStage`s, in the
+/// order that they have to run in.
+///
+/// # Example
+///
+/// ```
+/// # use smbuilder::prelude::*;
+/// # use smbuilder::prelude::builder_types::SetupStage;
+/// # use smbuilder::builder::get_needed_setup_tasks;
+/// let spec = Spec::default();
+/// let stages = get_needed_setup_tasks(&spec, "no-such-base-dir", &mut Callbacks::new());
+///
+/// // nothing was cloned into the base dir yet.
+/// assert!(stages.contains(&SetupStage::CloneRepo));
+/// ```
 pub fn get_needed_setup_tasks<P: AsRef<Path>>(
     spec: &Spec,
     base_dir: P,
     callbacks: &mut Callbacks,
+) -> Vec<SetupStage> {
+    needed_setup_tasks(&RealFs, spec, base_dir.as_ref(), callbacks)
+}
+
+/// Get the core setup tasks that are needed,
+/// looking at the base dir on `fs`.
+pub(crate) fn needed_setup_tasks(
+    fs: &dyn FsProvider,
+    spec: &Spec,
+    base_dir: &Path,
+    callbacks: &mut Callbacks,
 ) -> Vec<SetupStage> {
     use SetupStage::*;
 
-    let base_dir = base_dir.as_ref();
     let mut needed_stages: Vec<SetupStage> = Vec::new();
 
-    // check if the repo is cloned
-    if !base_dir.join(&spec.repo.name).exists() {
-        needed_stages.push(CloneRepo)
+    // check if the repo is cloned, or if
+    // a previous clone was interrupted
+    let lockfile = Lockfile::load_from(fs, base_dir).unwrap_or_else(|e| {
+        run_callback!(
+            callbacks.log_cb,
+            Warn,
+            &format!("ignoring the unreadable lockfile: {}", e)
+        );
+        Lockfile::default()
+    });
+
+    let repo_dir = base_dir.join(&spec.repo.name);
+
+    if !pending_downloads(fs, spec).is_empty() {
+        needed_stages.push(DownloadResources);
     }
 
-    // check if the rom exists
-    if !base_dir
-        .join(&spec.repo.name)
-        .join(format!("baserom.{}.z64", spec.rom.region.to_string()))
-        .exists()
-    {
-        needed_stages.push(CopyRom)
+    // prebuilt ports have nothing to clone,
+    // and no assets to extract.
+    if let SourceKind::Prebuilt { checksum, .. } = &spec.source {
+        if !fs.exists(&spec.build_dir(&repo_dir)) || lockfile.prebuilt.as_ref() != Some(checksum) {
+            needed_stages.push(DownloadPrebuilt);
+        }
+    } else {
+        needed_stages.extend(get_needed_source_tasks(
+            fs, spec, base_dir, &lockfile, callbacks,
+        ));
     }
 
-    // check if the build script exists
-    if !base_dir.join("build.sh").exists() {
-        needed_stages.push(CreateBuildScript)
+    // post-build script stuff
+    if !fs.exists(&base_dir.join("scripts")) {
+        needed_stages.push(CreateScriptsDir)
     }
 
+    if let Some(scripts) = &spec.scripts {
+        if scripts.iter().any(|script| script.path.is_none()) {
+            needed_stages.push(WritePostBuildScripts);
+        }
+    }
+
+    // whatever works on the products of a
+    // stage that runs has to run again too.
+    let applicable: Vec<SetupStage> = SetupStage::ALL
+        .into_iter()
+        .filter(|stage| stage.applies_to(spec))
+        .collect();
+    let needed_stages = graph::with_dependents(&needed_stages, &applicable, SetupStage::depends_on);
+    let needed_stages =
+        graph::topological_order(&needed_stages, &SetupStage::ALL, SetupStage::depends_on);
+
     // log
     let needed_stages_string = needed_stages
         .iter()
@@ -59,26 +178,280 @@ pub fn get_needed_setup_tasks<P: AsRef<Path>>(
         &format!("needed tasks: {}", needed_stages_string)
     );
 
-    // post-build script stuff
-    if !base_dir.join("scripts").exists() {
-        needed_stages.push(CreateScriptsDir)
+    needed_stages
+}
+
+/// Get the setup tasks that are needed
+/// to compile the port from source.
+fn get_needed_source_tasks(
+    fs: &dyn FsProvider,
+    spec: &Spec,
+    base_dir: &Path,
+    lockfile: &Lockfile,
+    callbacks: &mut Callbacks,
+) -> Vec<SetupStage> {
+    use SetupStage::*;
+
+    let mut needed_stages: Vec<SetupStage> = Vec::new();
+    let repo_dir = base_dir.join(&spec.repo.name);
+
+    if !fs.exists(&repo_dir) || lockfile.repo_incomplete() {
+        needed_stages.push(CloneRepo);
+        // whether the repo uses LFS is only
+        // known after it has been cloned.
+        needed_stages.push(FetchLfsObjects);
+    } else if lfs::repo_uses_lfs_on(fs, &repo_dir) && !lockfile.lfs_fetched() {
+        needed_stages.push(FetchLfsObjects);
     }
 
-    if let Some(scripts) = &spec.scripts {
-        for script in scripts {
-            if script.path.is_none() {
-                needed_stages.push(WritePostBuildScripts);
+    if spec.discord_sdk.is_some() {
+        if !DiscordSdk::is_installed_on(fs, &repo_dir) {
+            needed_stages.push(DownloadDiscordSdk);
+        }
+    } else if spec.has_makeopt("DISCORD_SDK", "1")
+        && fs.exists(&repo_dir)
+        && !DiscordSdk::is_installed_on(fs, &repo_dir)
+    {
+        run_callback!(
+            callbacks.log_cb,
+            Warn,
+            "DISCORD_SDK=1 needs the Discord game SDK, which is not in the repo; \
+             set `discord_sdk` in the spec to have it downloaded"
+        );
+    }
+
+    // check if the roms exist, and that they are
+    // not left over from a previous, bad spec
+    for rom in spec.roms() {
+        let baserom_path = repo_dir.join(rom.region.baserom_name());
+
+        let good = baserom_is_good_on(fs, &baserom_path, &rom.region);
+
+        if !fs.exists(&baserom_path) {
+            needed_stages.push(CopyRom);
+            break;
+        } else if !good {
+            run_callback!(
+                callbacks.log_cb,
+                Warn,
+                &format!(
+                    "{} does not match the expected checksum, copying the ROM again",
+                    baserom_path.display()
+                )
+            );
+            needed_stages.push(CopyRom);
+            break;
+        }
+    }
+
+    // a fresh clone has the patches applied
+    // again too, as it depends on the clone.
+    let hashes = spec.hashes();
+    if lockfile.patches_changed(spec.config(), &hashes) {
+        let built = lockfile.locked_build(spec.config()).spec_hashes.is_some();
+
+        if spec.patches.iter().flatten().next().is_some() {
+            needed_stages.push(ApplyPatches);
+        } else if built && fs.exists(&repo_dir) {
+            run_callback!(
+                callbacks.log_cb,
+                Warn,
+                "the patches were taken out of the spec, but stay applied \
+                 to the repo until it is cloned again"
+            );
+        }
+    }
+
+    // check if the build script exists, or if
+    // the spec changed since it was written
+    if !fs.exists(&base_dir.join(spec.build_script_name()))
+        || lockfile.build_script_changed(spec.config(), &hashes)
+    {
+        needed_stages.push(CreateBuildScript)
+    }
+
+    needed_stages
+}
+
+/// A pack or patch that is
+/// downloaded before building.
+pub(crate) struct PendingDownload {
+    /// The name of the pack or patch.
+    pub name: String,
+    /// Where it comes from.
+    pub resource: RemoteResource,
+    /// Where it goes.
+    pub dest: PathBuf,
+    /// Whether it is a zip, to be
+    /// unpacked into `dest`.
+    pub unpack: bool,
+}
+
+/// Get the packs and patches of the spec that
+/// are downloaded, and are not on `fs` yet.
+pub(crate) fn pending_downloads(fs: &dyn FsProvider, spec: &Spec) -> Vec<PendingDownload> {
+    remote_resources(spec)
+        .into_iter()
+        .filter(|download| !fs.exists(&download.dest))
+        .collect()
+}
+
+/// Get the packs and patches of the spec that
+/// are downloaded, whether they are on disk
+/// already or not.
+pub(crate) fn remote_resources(spec: &Spec) -> Vec<PendingDownload> {
+    let packs = spec
+        .texture_pack
+        .iter()
+        .map(|p| (&p.name, &p.path, &p.remote))
+        .chain(
+            spec.dynos_packs
+                .iter()
+                .flatten()
+                .map(|p| (&p.name, &p.path, &p.remote)),
+        )
+        .map(|(name, path, remote)| (name, path, remote, true));
+
+    let patches = spec
+        .patches
+        .iter()
+        .flatten()
+        .map(|p| (&p.name, &p.path, &p.remote, false));
+
+    packs
+        .chain(patches)
+        .filter_map(|(name, path, remote, unpack)| {
+            Some(PendingDownload {
+                name: name.clone(),
+                resource: remote.clone()?,
+                dest: path.clone(),
+                unpack,
+            })
+        })
+        .collect()
+}
+
+/// Get the modules of `deps::PYTHON_MODULES`
+/// that the Python scripts of the repo (in its
+/// root and `tools`, like `extract_assets.py`)
+/// import, or `None` if it has no scripts.
+pub(crate) fn python_modules_used(
+    fs: &dyn FsProvider,
+    repo_dir: &Path,
+) -> Option<Vec<&'static str>> {
+    let scripts: Vec<PathBuf> = [repo_dir.to_owned(), repo_dir.join("tools")]
+        .iter()
+        .filter_map(|dir| fs.read_dir(dir).ok())
+        .flatten()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "py"))
+        .collect();
+
+    if scripts.is_empty() {
+        return None;
+    }
+
+    let mut used = Vec::new();
+
+    for script in scripts {
+        let Ok(bytes) = fs.read(&script) else {
+            continue;
+        };
+        let contents = String::from_utf8_lossy(&bytes);
+
+        for line in contents.lines().map(str::trim) {
+            // `import a.b as c, d` and `from a.b import c`
+            let names = if let Some(rest) = line.strip_prefix("import ") {
+                rest
+            } else if let Some(rest) = line.strip_prefix("from ") {
+                rest.split_whitespace().next().unwrap_or_default()
+            } else {
                 continue;
+            };
+
+            for name in names.split(',') {
+                let module = name.split_whitespace().next().unwrap_or_default();
+                let module = module.split('.').next().unwrap_or_default();
+
+                let known = deps::PYTHON_MODULES
+                    .iter()
+                    .find(|(known, _)| *known == module);
+                if let Some((known, _)) = known {
+                    if !used.contains(known) {
+                        used.push(*known);
+                    }
+                }
             }
         }
     }
 
-    // return
-    needed_stages
+    Some(used)
+}
+
+/// Check that the base ROM at `path` is
+/// a good dump of the ROM for `region`.
+pub fn baserom_is_good<P: AsRef<Path>>(path: P, region: &Region) -> bool {
+    baserom_is_good_on(&RealFs, path.as_ref(), region)
+}
+
+/// Check that the base ROM at `path` on `fs`
+/// is a good dump, like `baserom_is_good`.
+pub(crate) fn baserom_is_good_on(fs: &dyn FsProvider, path: &Path, region: &Region) -> bool {
+    fs.open(path)
+        .and_then(util::sha1_read_hex)
+        .is_ok_and(|actual| actual == region.baserom_sha1())
+}
+
+/// Get the files and directories that a setup
+/// stage creates inside of the base directory.
+///
+/// Used to roll a stage back if it fails
+/// halfway through.
+pub fn get_setup_stage_artifacts<P: AsRef<Path>>(
+    stage: &SetupStage,
+    spec: &Spec,
+    base_dir: P,
+) -> Vec<PathBuf> {
+    use SetupStage::*;
+
+    let base_dir = base_dir.as_ref();
+    let repo_dir = base_dir.join(&spec.repo.name);
+
+    match stage {
+        CloneRepo => vec![repo_dir],
+        DownloadPrebuilt => vec![spec.build_dir(&repo_dir)],
+        FetchLfsObjects => Vec::new(),
+        DownloadDiscordSdk => {
+            let install_dir = DiscordSdk::install_dir(&repo_dir);
+            DiscordSdk::host_files()
+                .into_iter()
+                .map(|(_, name)| install_dir.join(name))
+                .collect()
+        }
+        // the ones that are on disk already
+        // are left out by the builder.
+        DownloadResources => remote_resources(spec)
+            .into_iter()
+            .map(|download| download.dest)
+            .collect(),
+        CopyRom => spec
+            .roms()
+            .map(|rom| repo_dir.join(rom.region.baserom_name()))
+            .collect(),
+        // the patched files belong to the clone.
+        ApplyPatches => Vec::new(),
+        CreateBuildScript => vec![base_dir.join(spec.build_script_name())],
+        CreateScriptsDir => vec![base_dir.join("scripts")],
+        WritePostBuildScripts => spec
+            .scripts
+            .iter()
+            .flatten()
+            .map(|script| base_dir.join("scripts").join(format!("{}.sh", script.name)))
+            .collect(),
+    }
 }
 
-impl ToString for Region {
-    fn to_string(&self) -> String {
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use Region::*;
 
         let retval = match self {
@@ -86,8 +459,9 @@ impl ToString for Region {
             Eu => "eu",
             Jp => "jp",
             Sh => "sh",
+            Cn => "cn",
         };
 
-        retval.to_owned()
+        write!(f, "{}", retval)
     }
 }
@@ -0,0 +1,431 @@
+use duct::Expression;
+use std::io::{self, BufRead, BufReader};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a running command is checked
+/// for timeouts and cancellation.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long a process group is given to
+/// exit after being asked to, before it
+/// is killed outright.
+#[cfg(unix)]
+const KILL_GRACE_PERIOD: Duration = Duration::from_secs(3);
+
+/// The limits that a command is run under.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    /// How long the command may run for.
+    pub timeout: Option<Duration>,
+    /// How long the command may go
+    /// without printing anything.
+    pub inactivity: Option<Duration>,
+}
+
+/// How a watched command ended.
+#[derive(Debug)]
+pub enum Outcome {
+    /// The command exited by itself, and
+    /// whether it exited successfully.
+    Exited(bool),
+    /// The command ran for longer than its
+    /// timeout, and was killed.
+    TimedOut(Duration),
+    /// The command printed nothing for longer
+    /// than the inactivity limit, and was killed.
+    Inactive(Duration),
+    /// The command printed part of a line (like
+    /// a prompt) and then nothing for longer than
+    /// the inactivity limit, and was killed.
+    ///
+    /// Only commands run under a pseudo-terminal
+    /// can tell this apart from `Inactive`.
+    Prompted(Duration, String),
+    /// The command was stopped on request.
+    Stopped,
+}
+
+/// Kills a process group when dropped, unless
+/// it has already exited, so that a panicking
+/// callback does not leave compilers behind.
+struct GroupGuard {
+    pids: Vec<u32>,
+    exited: bool,
+}
+
+impl Drop for GroupGuard {
+    fn drop(&mut self) {
+        if !self.exited {
+            kill_process_group(&self.pids);
+        }
+    }
+}
+
+enum Event {
+    Line(String),
+    /// The unfinished last line, so far.
+    #[cfg_attr(not(all(unix, feature = "pty")), allow(dead_code))]
+    Partial(String),
+    Exit(io::Result<()>),
+}
+
+/// Run a command in its own process group,
+/// calling `on_line` with every line it prints
+/// (to either stdout or stderr).
+///
+/// The whole process group is killed if the
+/// command breaks one of its limits, or once
+/// `should_stop` returns true.
+pub(crate) fn run_watched<S, F>(
+    expr: Expression,
+    limits: Limits,
+    should_stop: S,
+    on_line: F,
+) -> io::Result<Outcome>
+where
+    S: Fn() -> bool,
+    F: FnMut(&str),
+{
+    let handle = in_own_process_group(expr).stderr_to_stdout().reader()?;
+    let mut group = GroupGuard {
+        pids: handle.pids(),
+        exited: false,
+    };
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(&handle);
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+            match reader.read_line(&mut line) {
+                // duct waits for the command at the end of
+                // its output, and errors if it failed.
+                Ok(0) => {
+                    let _ = tx.send(Event::Exit(Ok(())));
+                    return;
+                }
+                Ok(_) => {
+                    let text = line.trim_end_matches(['\r', '\n']).to_owned();
+                    if tx.send(Event::Line(text)).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    let _ = tx.send(Event::Exit(Err(e)));
+                    return;
+                }
+            }
+        }
+    });
+
+    watch(&rx, &mut group, limits, should_stop, on_line)
+}
+
+/// How long an unfinished line has to sit
+/// there before it is passed on by itself,
+/// as it is probably a prompt.
+const PROMPT_DELAY: Duration = Duration::from_secs(2);
+
+/// Watch the events from a running command
+/// until it exits or breaks one of its limits.
+fn watch<S, F>(
+    rx: &mpsc::Receiver<Event>,
+    group: &mut GroupGuard,
+    limits: Limits,
+    should_stop: S,
+    mut on_line: F,
+) -> io::Result<Outcome>
+where
+    S: Fn() -> bool,
+    F: FnMut(&str),
+{
+    let started = Instant::now();
+    let mut last_output = Instant::now();
+    // the unfinished line, and
+    // whether it was passed on.
+    let mut partial: Option<(String, bool)> = None;
+
+    loop {
+        if should_stop() {
+            kill_process_group(&group.pids);
+            return Ok(Outcome::Stopped);
+        }
+
+        if let Some(timeout) = limits.timeout {
+            if started.elapsed() > timeout {
+                kill_process_group(&group.pids);
+                return Ok(Outcome::TimedOut(timeout));
+            }
+        }
+
+        if let Some((line, shown)) = &mut partial {
+            if !*shown && last_output.elapsed() > PROMPT_DELAY {
+                on_line(line);
+                *shown = true;
+            }
+        }
+
+        if let Some(inactivity) = limits.inactivity {
+            if last_output.elapsed() > inactivity {
+                kill_process_group(&group.pids);
+                return Ok(match partial.take() {
+                    Some((prompt, _)) => Outcome::Prompted(inactivity, prompt),
+                    None => Outcome::Inactive(inactivity),
+                });
+            }
+        }
+
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(Event::Line(line)) => {
+                last_output = Instant::now();
+                partial = None;
+                on_line(&line);
+            }
+            Ok(Event::Partial(line)) => {
+                last_output = Instant::now();
+                partial = Some((line, false));
+            }
+            Ok(Event::Exit(status)) => {
+                group.exited = true;
+                return Ok(Outcome::Exited(status.is_ok()));
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return Ok(Outcome::Exited(false)),
+        }
+    }
+}
+
+/// The size (columns, rows) of the
+/// pseudo-terminal from `run_in_pty`.
+#[cfg(all(unix, feature = "pty"))]
+const PTY_SIZE: (u16, u16) = (120, 40);
+
+/// Like `run_watched`, but run the command in its
+/// own session, with a pseudo-terminal as its
+/// controlling terminal (and stdin, stdout and
+/// stderr), so that it behaves like it would
+/// in a terminal. `input` is typed into it.
+///
+/// Unfinished lines that sit there for a while
+/// are passed to `on_line` as well, and the
+/// command ends up `Prompted` if it never goes
+/// on from one.
+#[cfg(all(unix, feature = "pty"))]
+pub(crate) fn run_in_pty<S, F>(
+    mut command: std::process::Command,
+    input: Vec<u8>,
+    limits: Limits,
+    should_stop: S,
+    on_line: F,
+) -> io::Result<Outcome>
+where
+    S: Fn() -> bool,
+    F: FnMut(&str),
+{
+    use std::io::Read;
+    use std::os::unix::process::CommandExt;
+    use std::process::Stdio;
+
+    let (mut master, slave) = open_pty()?;
+    command
+        .stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave));
+
+    // SAFETY: only async-signal-safe functions
+    // are called between the fork and the exec.
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    let mut child = command.spawn()?;
+    // the command still holds our copies of the
+    // terminal, which would keep it open (and the
+    // reader from ever seeing the end of it).
+    drop(command);
+
+    // the child leads a new session, and
+    // so its own process group as well.
+    let mut group = GroupGuard {
+        pids: vec![child.id()],
+        exited: false,
+    };
+
+    if !input.is_empty() {
+        let mut writer = master.try_clone()?;
+        thread::spawn(move || {
+            use std::io::Write;
+            let _ = writer.write_all(&input);
+        });
+    }
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        let mut pending: Vec<u8> = Vec::new();
+
+        loop {
+            match master.read(&mut buf) {
+                // once every process has let go of the
+                // terminal, reading it fails with EIO.
+                Ok(0) => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+                Ok(n) => pending.extend_from_slice(&buf[..n]),
+            }
+
+            while let Some(end) = pending.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = pending.drain(..=end).collect();
+                if tx.send(Event::Line(terminal_line(&line))).is_err() {
+                    return;
+                }
+            }
+
+            if !pending.is_empty() && tx.send(Event::Partial(terminal_line(&pending))).is_err() {
+                return;
+            }
+        }
+
+        if !pending.is_empty() {
+            let _ = tx.send(Event::Line(terminal_line(&pending)));
+        }
+
+        let status = match child.wait() {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(io::Error::other(status.to_string())),
+            Err(e) => Err(e),
+        };
+        let _ = tx.send(Event::Exit(status));
+    });
+
+    watch(&rx, &mut group, limits, should_stop, on_line)
+}
+
+/// Open a pseudo-terminal, returning
+/// its master and slave ends.
+#[cfg(all(unix, feature = "pty"))]
+fn open_pty() -> io::Result<(std::fs::File, std::fs::File)> {
+    use std::os::unix::io::FromRawFd;
+
+    let mut master: libc::c_int = -1;
+    let mut slave: libc::c_int = -1;
+    let size = libc::winsize {
+        ws_col: PTY_SIZE.0,
+        ws_row: PTY_SIZE.1,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+
+    // SAFETY: openpty only writes the two fds,
+    // and is not asked for the slave's name.
+    let ret = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            &size as *const _ as *mut _,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: both fds were just opened, and
+    // nothing else owns them. The master is kept
+    // out of the command (the slave is dup'd
+    // onto its stdio, so closing it is fine).
+    unsafe {
+        libc::fcntl(master, libc::F_SETFD, libc::FD_CLOEXEC);
+        libc::fcntl(slave, libc::F_SETFD, libc::FD_CLOEXEC);
+        Ok((
+            std::fs::File::from_raw_fd(master),
+            std::fs::File::from_raw_fd(slave),
+        ))
+    }
+}
+
+/// Turn the bytes of a line from a terminal into
+/// what it would show: progress meters redraw
+/// the line after a `\r`, so only the text after
+/// the last one is kept.
+#[cfg(all(unix, feature = "pty"))]
+fn terminal_line(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    let text = text.trim_end_matches(['\r', '\n']);
+
+    match text.rfind('\r') {
+        Some(i) => text[i + 1..].to_owned(),
+        None => text.to_owned(),
+    }
+}
+
+#[cfg(unix)]
+fn in_own_process_group(expr: Expression) -> Expression {
+    use std::os::unix::process::CommandExt;
+
+    expr.before_spawn(|command| {
+        command.process_group(0);
+        Ok(())
+    })
+}
+
+#[cfg(not(unix))]
+fn in_own_process_group(expr: Expression) -> Expression {
+    expr
+}
+
+/// Kill the process groups led by `pids`,
+/// which take in every process that they
+/// started (make, its shells and compilers).
+///
+/// They are sent `SIGTERM` first, so that
+/// make can clean up half-written files, and
+/// `SIGKILL` if they have not exited after
+/// a grace period. This works the same way
+/// on Linux, macOS and the BSDs.
+#[cfg(unix)]
+pub(crate) fn kill_process_group(pids: &[u32]) {
+    // SAFETY: killpg has no memory safety
+    // requirements; a stale pid only fails.
+    let signal = |pid: u32, sig: libc::c_int| unsafe { libc::killpg(pid as libc::pid_t, sig) };
+
+    for pid in pids {
+        signal(*pid, libc::SIGTERM);
+    }
+
+    // signal 0 only checks whether
+    // anything in the group is alive.
+    let started = Instant::now();
+    while started.elapsed() < KILL_GRACE_PERIOD {
+        if pids.iter().all(|pid| signal(*pid, 0) != 0) {
+            return;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    for pid in pids {
+        signal(*pid, libc::SIGKILL);
+    }
+}
+
+/// Kill the process trees rooted at `pids`.
+#[cfg(not(unix))]
+pub(crate) fn kill_process_group(pids: &[u32]) {
+    for pid in pids {
+        let _ = duct::cmd!("taskkill", "/F", "/T", "/PID", pid.to_string())
+            .stdout_null()
+            .stderr_null()
+            .unchecked()
+            .run();
+    }
+}
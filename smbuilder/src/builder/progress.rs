@@ -0,0 +1,143 @@
+use super::backend::FsProvider;
+
+use std::path::Path;
+
+/// The extensions of the sources that
+/// are compiled to an object file each.
+const SOURCE_EXTENSIONS: &[&str] = &["c", "s"];
+
+/// The directories of a repo that hold no
+/// sources of the port itself (the tools are
+/// built by their own makefile, before it).
+const SKIPPED_DIRS: &[&str] = &[".git", "build", "tools"];
+
+/// How deep into the repo the sources are
+/// counted. The fs does not tell symlinks
+/// apart, so one that loops back up is
+/// cut off here.
+const MAX_DEPTH: usize = 16;
+
+/// An estimate of how far along the compiler
+/// is, from the object files that make has
+/// written out of the sources in the repo.
+pub(crate) struct CompileProgress {
+    total: usize,
+    done: usize,
+}
+
+impl CompileProgress {
+    /// Count the sources in `repo_dir`
+    /// (on `fs`) to compile.
+    pub(crate) fn new(fs: &dyn FsProvider, repo_dir: &Path) -> Self {
+        CompileProgress {
+            total: count_sources(fs, repo_dir, 0),
+            done: 0,
+        }
+    }
+
+    /// Add the line of output, returning the
+    /// new percentage if it is a command that
+    /// writes an object file.
+    ///
+    /// Stays under 100%, as there is still
+    /// the linking (and the sources that
+    /// were not counted) after.
+    pub(crate) fn feed(&mut self, line: &str) -> Option<f32> {
+        if self.total == 0 || !writes_object(line) {
+            return None;
+        }
+
+        self.done += 1;
+        Some((self.done as f32 * 100.0 / self.total as f32).min(99.0))
+    }
+}
+
+/// Whether the line is a command (of the
+/// compiler or assembler) that outputs an
+/// object file, or the short form that some
+/// ports print instead, like
+/// `Compiling: src/foo.c -> build/foo.o`.
+fn writes_object(line: &str) -> bool {
+    let mut args = line.split_whitespace();
+
+    while let Some(arg) = args.next() {
+        let output = match arg.strip_prefix("-o") {
+            Some("") => args.next().unwrap_or_default(),
+            Some(output) => output,
+            None if arg == "->" => args.next().unwrap_or_default(),
+            None => continue,
+        };
+
+        if output.ends_with(".o") {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Count the C and assembly
+/// sources under `dir`.
+fn count_sources(fs: &dyn FsProvider, dir: &Path, depth: usize) -> usize {
+    if depth > MAX_DEPTH {
+        return 0;
+    }
+
+    let Ok(entries) = fs.read_dir(dir) else {
+        return 0;
+    };
+
+    entries
+        .into_iter()
+        .map(|path| {
+            let name = path.file_name().unwrap_or_default();
+
+            if fs.is_dir(&path) {
+                if SKIPPED_DIRS.iter().any(|skipped| name == *skipped) {
+                    0
+                } else {
+                    count_sources(fs, &path, depth + 1)
+                }
+            } else {
+                let is_source = path
+                    .extension()
+                    .is_some_and(|ext| SOURCE_EXTENSIONS.iter().any(|e| ext == *e));
+                usize::from(is_source)
+            }
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_the_commands_that_write_objects() {
+        assert!(writes_object(
+            "cc -c -O2 -o build/us_pc/src/game/area.o src/game/area.c"
+        ));
+        assert!(writes_object(
+            "as -march=vr4300 -obuild/us_pc/lib/asm/foo.o lib/asm/foo.s"
+        ));
+        assert!(writes_object(
+            "Compiling: src/game/area.c -> build/us_pc/src/game/area.o"
+        ));
+        assert!(!writes_object(
+            "cc -o build/us_pc/sm64.us.f3dex2e build/us_pc/src/game/area.o"
+        ));
+        assert!(!writes_object(
+            "src/game/area.c:12: warning: unused variable"
+        ));
+    }
+
+    #[test]
+    fn stays_under_a_hundred_percent() {
+        let mut progress = CompileProgress { total: 2, done: 0 };
+
+        assert_eq!(progress.feed("cc -c -o a.o a.c"), Some(50.0));
+        assert_eq!(progress.feed("make: Nothing to be done"), None);
+        assert_eq!(progress.feed("cc -c -o b.o b.c"), Some(99.0));
+        assert_eq!(progress.feed("cc -c -o c.o c.c"), Some(99.0));
+    }
+}
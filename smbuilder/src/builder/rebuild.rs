@@ -0,0 +1,152 @@
+use crate::prelude::{Makeopt, Repo};
+
+/// The makeopts that change how every file is
+/// compiled, in every port. make only tracks the
+/// files themselves, so changing one of these
+/// leaves stale objects behind.
+///
+/// `VERSION` is not one of them, as every
+/// version is built into its own directory.
+const COMMON_CLEAN_KEYS: &[&str] = &[
+    "RENDER_API",
+    "WINDOW_API",
+    "AUDIO_API",
+    "CONTROLLER_API",
+    "TARGET_ARCH",
+    "TARGET_BITS",
+    "TARGET_RPI",
+    "TARGET_WEB",
+    "COMPILER",
+    "DEBUG",
+    "NON_MATCHING",
+    "BETTERCAMERA",
+    "NODRAWINGDISTANCE",
+    "TEXTURE_FIX",
+    "EXT_OPTIONS_MENU",
+    "EXTERNAL_DATA",
+    "DISCORDRPC",
+];
+
+/// Makeopts that only some ports have, keyed by
+/// a part of the port's repo name or URL.
+const PORT_CLEAN_KEYS: &[(&str, &[&str])] = &[
+    (
+        "ex-alo",
+        &["QOL_FEATURES", "QOL_FIXES", "QOL_REDONE", "PORT_MOP_OBJS"],
+    ),
+    (
+        "coop",
+        &["DEVELOPMENT", "HEADLESS", "COOPNET", "DISCORD_SDK"],
+    ),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// How a port has to be rebuilt after
+/// its makeopts changed.
+pub enum RebuildKind {
+    /// A plain `make` picks up the change.
+    Incremental,
+    /// The port has to be cleaned first,
+    /// because of the changes to `keys`.
+    Clean {
+        /// The makeopts that need a clean build.
+        keys: Vec<String>,
+    },
+}
+
+/// Work out how a port has to be rebuilt
+/// when its makeopts change from `old` to `new`.
+pub fn makeopts_rebuild_kind(repo: &Repo, old: &[Makeopt], new: &[Makeopt]) -> RebuildKind {
+    let value = |makeopts: &[Makeopt], key: &str| {
+        makeopts
+            .iter()
+            .rev()
+            .find(|m| m.key == key)
+            .map(|m| m.value.clone())
+    };
+
+    let port = format!("{} {}", repo.name, repo.url).to_lowercase();
+    let port_keys = PORT_CLEAN_KEYS
+        .iter()
+        .filter(|(port_name, _)| port.contains(port_name))
+        .flat_map(|(_, keys)| keys.iter());
+
+    let mut keys: Vec<String> = COMMON_CLEAN_KEYS
+        .iter()
+        .chain(port_keys)
+        .filter(|key| value(old, key) != value(new, key))
+        .map(|key| key.to_string())
+        .collect();
+    keys.sort();
+    keys.dedup();
+
+    if keys.is_empty() {
+        RebuildKind::Incremental
+    } else {
+        RebuildKind::Clean { keys }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn makeopts(pairs: &[(&str, &str)]) -> Vec<Makeopt> {
+        pairs
+            .iter()
+            .map(|(key, value)| Makeopt {
+                key: key.to_string(),
+                value: value.to_string(),
+            })
+            .collect()
+    }
+
+    fn repo(name: &str) -> Repo {
+        Repo {
+            name: name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rebuilds_incrementally_without_compile_flags() {
+        let old = makeopts(&[("VERSION", "us"), ("BETTERCAMERA", "1")]);
+        let new = makeopts(&[("VERSION", "eu"), ("BETTERCAMERA", "1")]);
+
+        assert_eq!(
+            makeopts_rebuild_kind(&repo("sm64ex"), &old, &new),
+            RebuildKind::Incremental
+        );
+    }
+
+    #[test]
+    fn cleans_when_a_compile_flag_changes() {
+        let old = makeopts(&[("RENDER_API", "GL"), ("BETTERCAMERA", "0")]);
+        // the last value of a key is the one that make uses.
+        let new = makeopts(&[("BETTERCAMERA", "0"), ("BETTERCAMERA", "1")]);
+
+        assert_eq!(
+            makeopts_rebuild_kind(&repo("sm64ex"), &old, &new),
+            RebuildKind::Clean {
+                keys: vec!["BETTERCAMERA".to_owned(), "RENDER_API".to_owned()],
+            }
+        );
+    }
+
+    #[test]
+    fn only_checks_the_flags_of_the_port() {
+        let old = makeopts(&[]);
+        let new = makeopts(&[("QOL_FIXES", "1")]);
+
+        assert_eq!(
+            makeopts_rebuild_kind(&repo("sm64ex"), &old, &new),
+            RebuildKind::Incremental
+        );
+        assert_eq!(
+            makeopts_rebuild_kind(&repo("sm64ex-alo"), &old, &new),
+            RebuildKind::Clean {
+                keys: vec!["QOL_FIXES".to_owned()],
+            }
+        );
+    }
+}
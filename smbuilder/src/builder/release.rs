@@ -0,0 +1,110 @@
+use std::cmp::Ordering;
+
+/// The `repo.branch` that tracks the newest
+/// release of the repo, rather than a branch.
+pub const LATEST_RELEASE: &str = "latest-release";
+
+/// Pick the newest release out of the tags
+/// of a repo.
+///
+/// That is the tag with the highest version
+/// (`v1.2.10` beats `v1.2.9`), ignoring
+/// pre-releases (`v2.0.0-rc1`) unless there is
+/// nothing else. Anything before the first
+/// digit (`v`, `release-`) is ignored too.
+///
+/// The refs of a remote do not say when they
+/// were made, so repos without a single version
+/// tag fall back to the last tag by name.
+pub fn latest_release_tag<S: AsRef<str>>(tags: &[S]) -> Option<String> {
+    let tags: Vec<&str> = tags.iter().map(|t| t.as_ref()).collect();
+
+    let newest = tags
+        .iter()
+        .filter_map(|tag| Some((*tag, parse_version(tag)?)))
+        .max_by(|(a_tag, a), (b_tag, b)| {
+            // stable releases win over pre-releases,
+            // then the higher version wins.
+            (!a.prerelease)
+                .cmp(&!b.prerelease)
+                .then_with(|| compare_numbers(&a.numbers, &b.numbers))
+                .then_with(|| a_tag.cmp(b_tag))
+        })
+        .map(|(tag, _)| tag);
+
+    newest
+        .or_else(|| tags.iter().max().copied())
+        .map(str::to_owned)
+}
+
+struct Version {
+    numbers: Vec<u64>,
+    prerelease: bool,
+}
+
+fn parse_version(tag: &str) -> Option<Version> {
+    let start = tag.find(|c: char| c.is_ascii_digit())?;
+    let version = &tag[start..];
+
+    let (core, prerelease) = match version.find(['-', '+']) {
+        Some(i) => (&version[..i], version[i..].starts_with('-')),
+        None => (version, false),
+    };
+
+    let numbers = core
+        .split('.')
+        .map(|n| n.parse::<u64>().ok())
+        .collect::<Option<Vec<u64>>>()?;
+
+    Some(Version {
+        numbers,
+        prerelease,
+    })
+}
+
+/// Compare versions, with missing
+/// numbers counting as 0 (`1.2` is
+/// the same as `1.2.0`).
+fn compare_numbers(a: &[u64], b: &[u64]) -> Ordering {
+    let len = a.len().max(b.len());
+
+    (0..len)
+        .map(|i| {
+            let a = a.get(i).copied().unwrap_or(0);
+            let b = b.get(i).copied().unwrap_or(0);
+            a.cmp(&b)
+        })
+        .find(|o| o.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_the_highest_version() {
+        let tags = ["v1.2.9", "v1.2.10", "v1.1", "release-1.2.3"];
+        assert_eq!(latest_release_tag(&tags).as_deref(), Some("v1.2.10"));
+
+        // missing numbers count as 0.
+        let tags = ["v1.2", "v1.2.0.1"];
+        assert_eq!(latest_release_tag(&tags).as_deref(), Some("v1.2.0.1"));
+    }
+
+    #[test]
+    fn skips_prereleases_unless_there_is_nothing_else() {
+        let tags = ["v1.9.0", "v2.0.0-rc1", "v1.8.0+build5"];
+        assert_eq!(latest_release_tag(&tags).as_deref(), Some("v1.9.0"));
+
+        let tags = ["v2.0.0-rc1", "v2.0.0-rc2"];
+        assert_eq!(latest_release_tag(&tags).as_deref(), Some("v2.0.0-rc2"));
+    }
+
+    #[test]
+    fn falls_back_to_the_last_tag_by_name() {
+        let tags = ["alpha", "nightly", "beta"];
+        assert_eq!(latest_release_tag(&tags).as_deref(), Some("nightly"));
+        assert_eq!(latest_release_tag::<&str>(&[]), None);
+    }
+}
@@ -0,0 +1,108 @@
+use super::state::BuildStateHandle;
+use super::types::BuilderResult;
+use crate::callback_types::LogType;
+use crate::prelude::Spec;
+
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Where in a build a custom stage is run.
+pub enum StagePoint {
+    /// After the setup stages, before
+    /// the port is compiled (or, for a
+    /// prebuilt port, checked).
+    AfterSetup,
+    /// After the port is compiled, before
+    /// the post-build stages.
+    BeforePostBuild,
+    /// After the post-build stages, before
+    /// the build is recorded in the lockfile.
+    AfterPostBuild,
+}
+
+/// A build stage of a consumer of the crate,
+/// like a launcher, added with
+/// `Builder::with_stage`.
+///
+/// Custom stages run after the built-in
+/// stages at their `StagePoint`, in the order
+/// that they were added. A stage that fails
+/// fails the build.
+pub trait Stage: Send {
+    /// The id of the stage, passed to the
+    /// custom stage callback when it starts.
+    ///
+    /// Prefixing it with the name of the
+    /// launcher (like `mylauncher.shortcut`)
+    /// keeps it apart from other stages.
+    fn id(&self) -> &str;
+
+    /// Whether the stage needs to run at all.
+    ///
+    /// Runs every time by default.
+    fn needed(&self, _ctx: &StageContext) -> bool {
+        true
+    }
+
+    /// Run the stage.
+    fn run(&mut self, ctx: &mut StageContext) -> BuilderResult<()>;
+}
+
+/// What a custom stage gets to see
+/// of the build that it is part of.
+pub struct StageContext<'c> {
+    spec: &'c Spec,
+    base_dir: &'c Path,
+    state: &'c BuildStateHandle,
+    log: &'c mut dyn FnMut(LogType, &str),
+}
+
+impl<'c> StageContext<'c> {
+    pub(crate) fn new(
+        spec: &'c Spec,
+        base_dir: &'c Path,
+        state: &'c BuildStateHandle,
+        log: &'c mut dyn FnMut(LogType, &str),
+    ) -> Self {
+        StageContext {
+            spec,
+            base_dir,
+            state,
+            log,
+        }
+    }
+
+    /// Gets the spec that is being built.
+    pub fn spec(&self) -> &Spec {
+        self.spec
+    }
+
+    /// Gets the base directory of the build.
+    pub fn base_dir(&self) -> &Path {
+        self.base_dir
+    }
+
+    /// Gets the directory that the
+    /// repo was cloned into.
+    pub fn repo_dir(&self) -> PathBuf {
+        self.base_dir.join(&self.spec.repo.name)
+    }
+
+    /// Gets the directory that the
+    /// port is built into.
+    pub fn build_dir(&self) -> PathBuf {
+        self.spec.build_dir(self.repo_dir())
+    }
+
+    /// Log a line, like the
+    /// built-in stages do.
+    pub fn log(&mut self, log_type: LogType, text: &str) {
+        (self.log)(log_type, text);
+    }
+
+    /// Report how far along the stage
+    /// is, from 0 to 100.
+    pub fn set_percent(&self, percent: f32) {
+        self.state.set_percent(percent);
+    }
+}
@@ -0,0 +1,468 @@
+use super::backend::{FsProvider, RealFs};
+use super::logfile::LogFile;
+use super::types::{PostBuildStage, SetupStage};
+use crate::redact::redact;
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The name of the file, in the root of a
+/// base dir, that the progress of the
+/// running build is saved to.
+pub const PROGRESS_FILE_NAME: &str = "progress.yaml";
+
+/// The name of the JSON file, in the root of
+/// a base dir, that the progress of the running
+/// build is written to for external watchers,
+/// when `BuildOptions::progress_file` is set.
+pub const PROGRESS_JSON_FILE_NAME: &str = "progress.json";
+
+/// How often the progress is saved
+/// whilst a stage is running.
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// The phase that a build is in.
+pub enum BuildPhase {
+    #[default]
+    /// The build has not started.
+    Idle,
+    /// A setup stage is running.
+    Setup(SetupStage),
+    /// The assets are being extracted
+    /// from the base ROM.
+    ExtractingAssets,
+    /// The port is being compiled.
+    Compiling,
+    /// A post-build stage is running.
+    PostBuild(PostBuildStage),
+    /// A custom stage (see
+    /// `Builder::with_stage`) is running.
+    CustomStage,
+    /// The build finished successfully.
+    Finished,
+    /// The build failed, or was cancelled.
+    Failed,
+}
+
+#[derive(Clone, Debug, Default)]
+/// A snapshot of the state of a build,
+/// cheap enough to take every frame.
+pub struct BuildState {
+    /// The current phase of the build.
+    pub phase: BuildPhase,
+    /// How far along the current stage
+    /// is, from 0 to 100, if known.
+    pub percent: Option<f32>,
+    /// The number of bytes of the
+    /// repo that have been cloned.
+    pub bytes_cloned: Option<u64>,
+    /// The last line that was logged.
+    pub last_log_line: Option<String>,
+    /// The time since the build started.
+    pub elapsed: Duration,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// The progress of a build, as it was last
+/// saved to the base dir.
+///
+/// A launcher that was restarted halfway
+/// through a build can load this to show
+/// where the build left off, and build
+/// again to resume it.
+pub struct SavedProgress {
+    /// The phase that the build was in.
+    pub phase: BuildPhase,
+    /// How far along the stage was.
+    pub percent: Option<f32>,
+    /// The number of bytes of the
+    /// repo that had been cloned.
+    pub bytes_cloned: Option<u64>,
+    /// How long the build had been
+    /// running for, in seconds.
+    pub elapsed_secs: u64,
+    /// When the progress was saved, in
+    /// seconds since the unix epoch.
+    pub saved_at: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// The progress of a build, as it is written
+/// to `progress.json`, so that shell scripts,
+/// status bars and the like can watch a build
+/// without linking against smbuilder.
+///
+/// ```json
+/// {
+///   "phase": "Compiling",
+///   "stage": "compile the port",
+///   "percent": 42.5,
+///   "eta_secs": 73,
+///   "elapsed_secs": 190,
+///   "updated_at": 1700000000
+/// }
+/// ```
+pub struct ProgressFile {
+    /// The phase that the build is in.
+    pub phase: BuildPhase,
+    /// What the build is doing, in words,
+    /// or `null` if it is not running.
+    pub stage: Option<String>,
+    /// How far along the stage is.
+    pub percent: Option<f32>,
+    /// Roughly how long the stage has left,
+    /// in seconds, going by how fast it has
+    /// gone so far.
+    pub eta_secs: Option<u64>,
+    /// How long the build has been
+    /// running for, in seconds.
+    pub elapsed_secs: u64,
+    /// When the file was written, in
+    /// seconds since the unix epoch.
+    pub updated_at: u64,
+}
+
+impl ProgressFile {
+    /// Load the progress file in
+    /// a base dir, if there is one.
+    pub fn load<P: AsRef<Path>>(base_dir: P) -> Option<ProgressFile> {
+        ProgressFile::load_from(&RealFs, base_dir)
+    }
+
+    /// Load the progress file in a base
+    /// dir on `fs`, like `load`.
+    pub fn load_from<P: AsRef<Path>>(fs: &dyn FsProvider, base_dir: P) -> Option<ProgressFile> {
+        let contents = fs
+            .read(&base_dir.as_ref().join(PROGRESS_JSON_FILE_NAME))
+            .ok()?;
+        serde_json::from_slice(&contents).ok()
+    }
+}
+
+impl SavedProgress {
+    /// Load the progress saved in a base
+    /// dir, if there is any.
+    pub fn load<P: AsRef<Path>>(base_dir: P) -> Option<SavedProgress> {
+        SavedProgress::load_from(&RealFs, base_dir)
+    }
+
+    /// Load the progress saved in a base
+    /// dir on `fs`, like `load`.
+    pub fn load_from<P: AsRef<Path>>(fs: &dyn FsProvider, base_dir: P) -> Option<SavedProgress> {
+        let contents = fs.read(&base_dir.as_ref().join(PROGRESS_FILE_NAME)).ok()?;
+        serde_yaml::from_slice(&contents).ok()
+    }
+
+    /// Whether the build stopped partway
+    /// through, without finishing or failing,
+    /// such as when the launcher crashed.
+    pub fn interrupted(&self) -> bool {
+        !matches!(
+            self.phase,
+            BuildPhase::Idle | BuildPhase::Finished | BuildPhase::Failed
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct StateInner {
+    phase: BuildPhase,
+    percent: Option<f32>,
+    bytes_cloned: Option<u64>,
+    last_log_line: Option<String>,
+    started: Option<Instant>,
+    finished: Option<Instant>,
+    fs: Option<Arc<dyn FsProvider>>,
+    progress_path: Option<PathBuf>,
+    json_path: Option<PathBuf>,
+    last_saved: Option<Instant>,
+    phase_started: Option<Instant>,
+    log_file: Option<LogFile>,
+    warnings: usize,
+}
+
+/// The files that the state of
+/// a build is written to.
+pub(crate) struct StateFiles {
+    progress_path: Option<PathBuf>,
+    json_path: Option<PathBuf>,
+    log_file: Option<LogFile>,
+}
+
+impl StateInner {
+    fn elapsed(&self) -> Duration {
+        match (self.started, self.finished) {
+            (Some(started), Some(finished)) => finished - started,
+            (Some(started), None) => started.elapsed(),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Roughly how long the current phase has
+    /// left, going by how fast it has gone.
+    fn eta(&self) -> Option<Duration> {
+        let percent = self.percent.filter(|p| *p > 0.0)?;
+        let so_far = self.phase_started?.elapsed();
+
+        Some(so_far.mul_f32((100.0 - percent) / percent))
+    }
+
+    /// Save the progress, if a filesystem and
+    /// a path to save it to were set. With `force`
+    /// unset, it is only saved once every
+    /// `SAVE_INTERVAL`.
+    fn save(&mut self, force: bool) {
+        let Some(fs) = &self.fs else {
+            return;
+        };
+        if self.progress_path.is_none() && self.json_path.is_none() {
+            return;
+        }
+
+        if !force && self.last_saved.is_some_and(|t| t.elapsed() < SAVE_INTERVAL) {
+            return;
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        // the progress is only informational, so
+        // failing to save it does not fail the build.
+        if let Some(path) = &self.progress_path {
+            let progress = SavedProgress {
+                phase: self.phase,
+                percent: self.percent,
+                bytes_cloned: self.bytes_cloned,
+                elapsed_secs: self.elapsed().as_secs(),
+                saved_at: now,
+            };
+
+            if let Ok(contents) = serde_yaml::to_string(&progress) {
+                let _ = fs.write(path, contents.as_bytes());
+            }
+        }
+
+        if let Some(path) = &self.json_path {
+            let progress = ProgressFile {
+                phase: self.phase,
+                stage: stage_name(self.phase),
+                percent: self.percent,
+                eta_secs: self.eta().map(|eta| eta.as_secs()),
+                elapsed_secs: self.elapsed().as_secs(),
+                updated_at: now,
+            };
+
+            if let Ok(contents) = serde_json::to_string_pretty(&progress) {
+                let _ = fs.write(path, contents.as_bytes());
+            }
+        }
+
+        self.last_saved = Some(Instant::now());
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// A shared handle to the state of a build.
+///
+/// It can be cloned and sent to another
+/// thread (such as a GUI's main loop) to
+/// poll the build whilst it runs, or
+/// to cancel it.
+pub struct BuildStateHandle {
+    inner: Arc<Mutex<StateInner>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl BuildStateHandle {
+    /// Take a snapshot of the state.
+    pub fn snapshot(&self) -> BuildState {
+        let inner = self.lock();
+
+        BuildState {
+            phase: inner.phase,
+            percent: inner.percent,
+            bytes_cloned: inner.bytes_cloned,
+            last_log_line: inner.last_log_line.clone(),
+            elapsed: inner.elapsed(),
+        }
+    }
+
+    /// Ask the build to stop.
+    ///
+    /// The build stops at the next safe point,
+    /// killing make (and every compiler that it
+    /// started) if it is running, and fails
+    /// with a `Cancelled` error.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the build has been asked to stop.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Whether the build has started,
+    /// and has not finished yet.
+    pub(crate) fn is_running(&self) -> bool {
+        !matches!(
+            self.lock().phase,
+            BuildPhase::Idle | BuildPhase::Finished | BuildPhase::Failed
+        )
+    }
+
+    /// Whether `other` is a handle
+    /// to the same build.
+    pub(crate) fn same_as(&self, other: &BuildStateHandle) -> bool {
+        Arc::ptr_eq(&self.cancelled, &other.cancelled)
+    }
+
+    /// Whether this is the last handle to
+    /// the build, of a builder that has
+    /// been dropped.
+    pub(crate) fn is_only_handle(&self) -> bool {
+        Arc::strong_count(&self.cancelled) == 1
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, StateInner> {
+        // a panic whilst holding the lock does not
+        // leave the state in an unusable shape.
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Write the progress files through `fs`.
+    pub(crate) fn write_through(&self, fs: Arc<dyn FsProvider>) {
+        self.lock().fs = Some(fs);
+    }
+
+    /// Save the progress to `path` whenever
+    /// it changes, at most once a second.
+    pub(crate) fn persist_to(&self, path: PathBuf) {
+        self.lock().progress_path = Some(path);
+    }
+
+    /// Also write the progress to `path` as
+    /// a `ProgressFile`, or stop writing it.
+    pub(crate) fn write_json_to(&self, path: Option<PathBuf>) {
+        self.lock().json_path = path;
+    }
+
+    /// Also write every line of the log
+    /// to `log_file`, or stop writing it.
+    pub(crate) fn log_to(&self, log_file: Option<LogFile>) {
+        self.lock().log_file = log_file;
+    }
+
+    /// Stop writing the state to any file,
+    /// returning where it was written to, for
+    /// `resume_writing`.
+    pub(crate) fn pause_writing(&self) -> StateFiles {
+        let mut inner = self.lock();
+
+        StateFiles {
+            progress_path: inner.progress_path.take(),
+            json_path: inner.json_path.take(),
+            log_file: inner.log_file.take(),
+        }
+    }
+
+    /// Write the state to `files`
+    /// again, after `pause_writing`.
+    pub(crate) fn resume_writing(&self, files: StateFiles) {
+        let mut inner = self.lock();
+
+        inner.progress_path = files.progress_path;
+        inner.json_path = files.json_path;
+        inner.log_file = files.log_file;
+    }
+
+    pub(crate) fn start(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+
+        let mut inner = self.lock();
+        *inner = StateInner {
+            started: Some(Instant::now()),
+            phase_started: Some(Instant::now()),
+            fs: inner.fs.take(),
+            progress_path: inner.progress_path.take(),
+            json_path: inner.json_path.take(),
+            log_file: inner.log_file.take(),
+            ..Default::default()
+        };
+        inner.save(true);
+
+        // like the progress, the log is only
+        // informational, so failing to write
+        // it does not fail the build.
+        if let Some(log_file) = &mut inner.log_file {
+            let _ = log_file.start();
+        }
+    }
+
+    pub(crate) fn set_phase(&self, phase: BuildPhase) {
+        let mut inner = self.lock();
+        inner.phase = phase;
+        inner.percent = None;
+        inner.phase_started = Some(Instant::now());
+
+        if matches!(phase, BuildPhase::Finished | BuildPhase::Failed) {
+            inner.finished = Some(Instant::now());
+        }
+
+        inner.save(true);
+    }
+
+    pub(crate) fn set_percent(&self, percent: f32) {
+        let mut inner = self.lock();
+        inner.percent = Some(percent.clamp(0.0, 100.0));
+        inner.save(false);
+    }
+
+    pub(crate) fn set_bytes_cloned(&self, bytes: u64) {
+        let mut inner = self.lock();
+        inner.bytes_cloned = Some(bytes);
+        inner.save(false);
+    }
+
+    pub(crate) fn log_line(&self, line: &str) {
+        let mut inner = self.lock();
+        let line = redact(line).into_owned();
+
+        if let Some(log_file) = &mut inner.log_file {
+            let _ = log_file.write_line(&line);
+        }
+
+        inner.last_log_line = Some(line);
+    }
+
+    /// Count a warning, logged by smbuilder
+    /// or by the compiler.
+    pub(crate) fn count_warning(&self) {
+        self.lock().warnings += 1;
+    }
+
+    /// Gets the number of warnings
+    /// since the build started.
+    pub(crate) fn warnings(&self) -> usize {
+        self.lock().warnings
+    }
+}
+
+/// Describe what a build in
+/// `phase` is doing, in words.
+pub(crate) fn stage_name(phase: BuildPhase) -> Option<String> {
+    match phase {
+        BuildPhase::Setup(stage) => Some(stage.to_string()),
+        BuildPhase::ExtractingAssets => Some("extract the assets".to_owned()),
+        BuildPhase::Compiling => Some("compile the port".to_owned()),
+        BuildPhase::PostBuild(stage) => Some(stage.to_string()),
+        BuildPhase::CustomStage => Some("run a custom stage".to_owned()),
+        BuildPhase::Idle | BuildPhase::Finished | BuildPhase::Failed => None,
+    }
+}
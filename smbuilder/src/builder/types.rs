@@ -1,4 +1,12 @@
+use crate::callback_types::{LogType, OutputSeverity};
+use crate::config::ColorChoice;
 use crate::prelude::Error;
+use crate::util::strip_ansi;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
 
 /// Error type.
 ///
@@ -6,22 +14,512 @@ use crate::prelude::Error;
 /// something more sensible.
 pub type BuilderResult<T> = Result<T, Error>;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Default)]
+/// Options that change how a build is run,
+/// as opposed to what is being built.
+pub struct BuildOptions {
+    /// Check the built executable (and the
+    /// shared libraries it needs) after
+    /// building.
+    pub smoke_test: bool,
+    /// Compile the port even if the
+    /// executable already exists and
+    /// looks up to date.
+    pub force: bool,
+    /// How long the stages that run
+    /// commands may take.
+    pub timeouts: Timeouts,
+    /// How texture and DynOS packs are
+    /// installed into the build.
+    pub install_mode: InstallMode,
+    /// A directory of repositories that are
+    /// shared between builds.
+    ///
+    /// When set, the objects of the repo are
+    /// fetched into a bare store in here, and
+    /// the build gets a worktree of it, so that
+    /// builds of the same fork (even on other
+    /// branches) do not each hold a full clone.
+    pub shared_repos: Option<PathBuf>,
+    /// What to do with the ANSI escape
+    /// sequences (colors) in the output
+    /// of the build.
+    pub ansi: AnsiMode,
+    /// Let the build script and the post-build
+    /// scripts read the stdin of this process,
+    /// so that the user can answer their prompts
+    /// from the terminal.
+    ///
+    /// Otherwise they get no input (or the
+    /// answers in the spec), so that they
+    /// cannot hang waiting on it.
+    pub forward_stdin: bool,
+    /// Write the progress of the build to
+    /// `progress.json` in the base dir, for
+    /// watchers that do not link against
+    /// smbuilder (see `ProgressFile`).
+    pub progress_file: bool,
+    /// Give the built game the `icon` of the
+    /// spec: embedded into Windows executables,
+    /// as a `.desktop` file on Linux and the
+    /// BSDs, and in an app bundle on macOS.
+    pub embed_icon: bool,
+    /// How much of the log is passed
+    /// on to the log callback.
+    pub verbosity: Verbosity,
+    /// When to color the output, overriding
+    /// the `color` of the config.
+    ///
+    /// With `Never`, the ANSI escape sequences
+    /// are also stripped from the output of the
+    /// build, like with `AnsiMode::Strip`.
+    pub color: Option<ColorChoice>,
+    /// Write the log of the build to
+    /// `logs/build.log` in the base dir,
+    /// keeping the logs of earlier builds.
+    pub log_file: Option<LogFileOptions>,
+    /// Only run these stages, skipping
+    /// every other one (and the ones in
+    /// the `skip_stages` of the spec).
+    ///
+    /// Nothing checks that the stages that
+    /// are skipped were run before.
+    pub only_stages: Option<Vec<BuildStage>>,
+    /// Write a `compile_commands.json` into the
+    /// repo when compiling, for editors that use
+    /// clangd.
+    ///
+    /// It is written with `bear` if it is
+    /// installed, or else pieced together from
+    /// the commands that make prints.
+    pub compile_commands: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Limits on the log files of builds.
+pub struct LogFileOptions {
+    /// The most bytes that one log file
+    /// holds before a new one is started.
+    pub max_size: u64,
+    /// How many old log files to keep.
+    pub keep: usize,
+    /// The longest that a line may be, in
+    /// bytes; longer ones are cut short.
+    pub max_line_len: usize,
+}
+
+impl Default for LogFileOptions {
+    fn default() -> Self {
+        LogFileOptions {
+            max_size: 16 * 1024 * 1024,
+            keep: 5,
+            max_line_len: 4096,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+/// How much of the log is passed
+/// on to the log callback.
+pub enum Verbosity {
+    /// Only errors and warnings (and
+    /// the errors in the build output).
+    Quiet,
+    /// Everything but the `Debug`
+    /// messages.
+    #[default]
+    Normal,
+    /// Everything, including the
+    /// commands that are run.
+    Verbose,
+}
+
+impl Verbosity {
+    /// The inverse of `as u8`.
+    pub(crate) fn from_u8(n: u8) -> Verbosity {
+        match n {
+            0 => Verbosity::Quiet,
+            1 => Verbosity::Normal,
+            _ => Verbosity::Verbose,
+        }
+    }
+
+    /// Whether a message of the given type
+    /// is passed on at this verbosity.
+    pub fn allows(&self, log_type: &LogType) -> bool {
+        match log_type {
+            LogType::Error | LogType::Warn => true,
+            LogType::BuildOutput { severity } => {
+                *self > Verbosity::Quiet || *severity == OutputSeverity::Error
+            }
+            LogType::Info => *self > Verbosity::Quiet,
+            LogType::Debug => *self == Verbosity::Verbose,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// What to do with the ANSI escape
+/// sequences in the output of commands,
+/// before it is passed to the log callback.
+pub enum AnsiMode {
+    /// Pass the output on as-is, for
+    /// frontends that print to a terminal.
+    #[default]
+    Keep,
+    /// Strip the escape sequences, for
+    /// frontends that show the output
+    /// as plain text (like GUIs).
+    Strip,
+}
+
+impl AnsiMode {
+    /// Apply the mode to a line of output.
+    pub fn apply<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        match self {
+            AnsiMode::Keep => Cow::Borrowed(line),
+            AnsiMode::Strip => strip_ansi(line),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A summary of a finished build.
+pub struct BuildReport {
+    /// The path of the built executable.
+    pub executable: PathBuf,
+    /// Whether the port was built
+    /// with the debug profile.
+    pub debug: bool,
+    /// The path of an executable that still
+    /// has its debug symbols, for symbolicating
+    /// crash reports, if there is one.
+    pub unstripped_executable: Option<PathBuf>,
+    /// The size of the executable.
+    pub size: u64,
+    /// The size of the executable before it
+    /// was stripped, if it was stripped.
+    pub original_size: Option<u64>,
+    /// Whether the port was compiled, rather
+    /// than the previous build being reused.
+    pub compiled: bool,
+    /// How long the build took.
+    pub duration: Duration,
+    /// The number of warnings, of smbuilder
+    /// and of the compiler, in the build.
+    #[serde(default)]
+    pub warnings: usize,
+    /// When the build finished, in
+    /// seconds since the unix epoch.
+    #[serde(default)]
+    pub finished_at: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// How the files of a pack are
+/// installed into a build.
+///
+/// Modes that share storage only work when
+/// the pack is on the same filesystem as the
+/// build, and fall back to copying otherwise.
+pub enum InstallMode {
+    /// Copy every file.
+    Copy,
+    /// Reflink (clone) every file on
+    /// filesystems that support it (Btrfs,
+    /// XFS, APFS...), so that installs share
+    /// storage until either side is changed.
+    #[default]
+    Reflink,
+    /// Like `Reflink`, but hard link the
+    /// files where reflinks are unsupported.
+    ///
+    /// Saves the most space, but editing an
+    /// installed file in place edits the
+    /// original pack as well.
+    HardLink,
+}
+
+#[derive(Clone, Copy, Debug)]
+/// Limits on how long the stages
+/// of a build may take.
+///
+/// `None` means no limit.
+pub struct Timeouts {
+    /// How long cloning the repository
+    /// may take.
+    pub clone: Option<Duration>,
+    /// How long compiling (or extracting
+    /// the assets) may take.
+    pub compile: Option<Duration>,
+    /// How long each post-build script
+    /// may take.
+    pub scripts: Option<Duration>,
+    /// How long make (or a script) may go
+    /// without printing anything before it
+    /// is considered hung, say on a prompt.
+    pub inactivity: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            clone: None,
+            compile: None,
+            scripts: None,
+            inactivity: Some(Duration::from_secs(30 * 60)),
+        }
+    }
+}
+
+impl BuildOptions {
+    /// Creates the default set of options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether to check the built
+    /// executable after building.
+    pub fn smoke_test(mut self, smoke_test: bool) -> Self {
+        self.smoke_test = smoke_test;
+        self
+    }
+
+    /// Set whether to always compile,
+    /// even if the executable exists.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Share the repositories between builds,
+    /// storing them in `dir`.
+    pub fn shared_repos<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.shared_repos = Some(dir.into());
+        self
+    }
+
+    /// Set how packs are installed.
+    pub fn install_mode(mut self, mode: InstallMode) -> Self {
+        self.install_mode = mode;
+        self
+    }
+
+    /// Set the limits on how long
+    /// the stages may take.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Set what to do with the ANSI
+    /// escape sequences in the output.
+    pub fn ansi(mut self, mode: AnsiMode) -> Self {
+        self.ansi = mode;
+        self
+    }
+
+    /// Set whether to forward the
+    /// stdin of this process.
+    pub fn forward_stdin(mut self, forward_stdin: bool) -> Self {
+        self.forward_stdin = forward_stdin;
+        self
+    }
+
+    /// Set whether to write the
+    /// progress to `progress.json`.
+    pub fn progress_file(mut self, progress_file: bool) -> Self {
+        self.progress_file = progress_file;
+        self
+    }
+
+    /// Set whether to give the built
+    /// game the icon of the spec.
+    pub fn embed_icon(mut self, embed_icon: bool) -> Self {
+        self.embed_icon = embed_icon;
+        self
+    }
+
+    /// Set how much of the log is
+    /// passed on to the log callback.
+    pub fn verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    /// Set when to color the output.
+    pub fn color(mut self, color: ColorChoice) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    /// Write the log to a file,
+    /// within the given limits.
+    pub fn log_file(mut self, options: LogFileOptions) -> Self {
+        self.log_file = Some(options);
+        self
+    }
+
+    /// Only run `stages`.
+    pub fn only_stages(mut self, stages: Vec<BuildStage>) -> Self {
+        self.only_stages = Some(stages);
+        self
+    }
+
+    /// Set whether to write a
+    /// `compile_commands.json`.
+    pub fn compile_commands(mut self, compile_commands: bool) -> Self {
+        self.compile_commands = compile_commands;
+        self
+    }
+}
+
+impl Timeouts {
+    /// No limits at all.
+    pub fn none() -> Self {
+        Timeouts {
+            clone: None,
+            compile: None,
+            scripts: None,
+            inactivity: None,
+        }
+    }
+
+    /// Set the limit on cloning.
+    pub fn clone_timeout(mut self, timeout: Duration) -> Self {
+        self.clone = Some(timeout);
+        self
+    }
+
+    /// Set the limit on compiling.
+    pub fn compile(mut self, timeout: Duration) -> Self {
+        self.compile = Some(timeout);
+        self
+    }
+
+    /// Set the limit on each script.
+    pub fn scripts(mut self, timeout: Duration) -> Self {
+        self.scripts = Some(timeout);
+        self
+    }
+
+    /// Set how long a command may go
+    /// without printing anything.
+    pub fn inactivity(mut self, timeout: Option<Duration>) -> Self {
+        self.inactivity = timeout;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+/// How a simulated build (see
+/// `Builder::simulate`) behaves.
+pub struct SimulationOptions {
+    /// How many lines the fake
+    /// compile prints.
+    pub compile_lines: usize,
+    /// The delay between each
+    /// line of the fake compile.
+    pub line_interval: Duration,
+    /// How many objects the fake
+    /// clone receives.
+    pub clone_objects: usize,
+    /// Fail the fake compile partway
+    /// through, to exercise error paths.
+    pub fail: bool,
+}
+
+impl Default for SimulationOptions {
+    fn default() -> Self {
+        SimulationOptions {
+            compile_lines: 400,
+            line_interval: Duration::from_millis(15),
+            clone_objects: 2000,
+            fail: false,
+        }
+    }
+}
+
+impl SimulationOptions {
+    /// Creates the default simulation options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how many lines the
+    /// fake compile prints.
+    pub fn compile_lines(mut self, lines: usize) -> Self {
+        self.compile_lines = lines;
+        self
+    }
+
+    /// Set the delay between each line
+    /// of the fake compile.
+    pub fn line_interval(mut self, interval: Duration) -> Self {
+        self.line_interval = interval;
+        self
+    }
+
+    /// Set how many objects the
+    /// fake clone receives.
+    pub fn clone_objects(mut self, objects: usize) -> Self {
+        self.clone_objects = objects;
+        self
+    }
+
+    /// Set whether the fake
+    /// compile fails.
+    pub fn fail(mut self, fail: bool) -> Self {
+        self.fail = fail;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
 /// An enum to represent the different "setup stages"
 /// involved in building a port.
 ///
 /// It includes critical steps to be able to
 /// build a basic, vanilla port.
+///
+/// It is written as (and parsed from) the name
+/// of the variant, like `CloneRepo`. Its
+/// `Display` is a lowercase phrase that fits
+/// after "failed to" (`clone the repository`),
+/// and `label` is a label for UIs; neither
+/// changes between versions.
 pub enum SetupStage {
     /// Clone the repository from
     /// the spec.
     CloneRepo,
 
+    /// Download and unpack the release
+    /// of a prebuilt port.
+    DownloadPrebuilt,
+
+    /// Fetch the Git LFS objects of
+    /// the repository, if it uses LFS.
+    FetchLfsObjects,
+
+    /// Download the Discord game SDK into
+    /// the repo, if the spec asks for it.
+    DownloadDiscordSdk,
+
+    /// Download the packs and patches of
+    /// the spec that are not on disk yet,
+    /// side by side.
+    DownloadResources,
+
     /// Copy the base ROM (and converts
     /// its format, if necessary) into
     /// the repo's root for asset extraction.
     CopyRom,
 
+    /// Apply the patches of the spec to the
+    /// repo, skipping the ones that are
+    /// applied already.
+    ApplyPatches,
+
     /// Create the build script.
     CreateBuildScript,
 
@@ -32,47 +530,204 @@ pub enum SetupStage {
     WritePostBuildScripts,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
 /// An enum to represent the different post-build
 /// stages involved in building a port.
 ///
 /// Represents actions such as installing model
 /// packs and texture packs, running scripts, etc.
+///
+/// Written, parsed and displayed like
+/// `SetupStage`.
 pub enum PostBuildStage {
+    /// Link the built executable to
+    /// a stable path in the base dir.
+    LinkExecutable,
+    /// Strip the executable, and
+    /// compress it if asked to.
+    StripExecutable,
+    /// Give the executable the icon
+    /// of the spec, if asked to.
+    EmbedIcon,
+    /// Check that the built executable
+    /// will actually start.
+    SmokeTest,
     /// Install the texture pack
     TexturePack,
     /// Install the DynOS packs(s)
     DynOSPacks,
+    /// Install the save files into
+    /// the game's save directory.
+    SaveFiles,
+    /// Install the controller mappings
+    /// next to the executable.
+    ControllerMappings,
     /// Run the Post-Build scripts
     PostBuildScripts,
 }
 
-impl ToString for SetupStage {
-    fn to_string(&self) -> String {
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// Any stage of a build, to pick stages
+/// out with: the setup stages, the compile
+/// and the post-build stages.
+///
+/// Written (and parsed) in snake case,
+/// like `copy_rom` or `texture_pack`.
+pub enum BuildStage {
+    /// See `SetupStage::CloneRepo`.
+    CloneRepo,
+    /// See `SetupStage::DownloadPrebuilt`.
+    DownloadPrebuilt,
+    /// See `SetupStage::FetchLfsObjects`.
+    FetchLfsObjects,
+    /// See `SetupStage::DownloadDiscordSdk`.
+    DownloadDiscordSdk,
+    /// See `SetupStage::DownloadResources`.
+    DownloadResources,
+    /// See `SetupStage::CopyRom`.
+    CopyRom,
+    /// See `SetupStage::ApplyPatches`.
+    ApplyPatches,
+    /// See `SetupStage::CreateBuildScript`.
+    CreateBuildScript,
+    /// See `SetupStage::CreateScriptsDir`.
+    CreateScriptsDir,
+    /// See `SetupStage::WritePostBuildScripts`.
+    WritePostBuildScripts,
+    /// Compile the port.
+    Compile,
+    /// See `PostBuildStage::LinkExecutable`.
+    LinkExecutable,
+    /// See `PostBuildStage::StripExecutable`.
+    StripExecutable,
+    /// See `PostBuildStage::EmbedIcon`.
+    EmbedIcon,
+    /// See `PostBuildStage::SmokeTest`.
+    SmokeTest,
+    /// See `PostBuildStage::TexturePack`.
+    TexturePack,
+    /// See `PostBuildStage::DynOSPacks`.
+    DynosPacks,
+    /// See `PostBuildStage::SaveFiles`.
+    SaveFiles,
+    /// See `PostBuildStage::ControllerMappings`.
+    ControllerMappings,
+    /// See `PostBuildStage::PostBuildScripts`.
+    PostBuildScripts,
+}
+
+impl From<SetupStage> for BuildStage {
+    fn from(stage: SetupStage) -> Self {
+        match stage {
+            SetupStage::CloneRepo => BuildStage::CloneRepo,
+            SetupStage::DownloadPrebuilt => BuildStage::DownloadPrebuilt,
+            SetupStage::FetchLfsObjects => BuildStage::FetchLfsObjects,
+            SetupStage::DownloadDiscordSdk => BuildStage::DownloadDiscordSdk,
+            SetupStage::DownloadResources => BuildStage::DownloadResources,
+            SetupStage::CopyRom => BuildStage::CopyRom,
+            SetupStage::ApplyPatches => BuildStage::ApplyPatches,
+            SetupStage::CreateBuildScript => BuildStage::CreateBuildScript,
+            SetupStage::CreateScriptsDir => BuildStage::CreateScriptsDir,
+            SetupStage::WritePostBuildScripts => BuildStage::WritePostBuildScripts,
+        }
+    }
+}
+
+impl From<PostBuildStage> for BuildStage {
+    fn from(stage: PostBuildStage) -> Self {
+        match stage {
+            PostBuildStage::LinkExecutable => BuildStage::LinkExecutable,
+            PostBuildStage::StripExecutable => BuildStage::StripExecutable,
+            PostBuildStage::EmbedIcon => BuildStage::EmbedIcon,
+            PostBuildStage::SmokeTest => BuildStage::SmokeTest,
+            PostBuildStage::TexturePack => BuildStage::TexturePack,
+            PostBuildStage::DynOSPacks => BuildStage::DynosPacks,
+            PostBuildStage::SaveFiles => BuildStage::SaveFiles,
+            PostBuildStage::ControllerMappings => BuildStage::ControllerMappings,
+            PostBuildStage::PostBuildScripts => BuildStage::PostBuildScripts,
+        }
+    }
+}
+
+impl SetupStage {
+    /// Gets a label for the stage,
+    /// for UIs to show while it runs.
+    pub fn label(&self) -> &'static str {
+        use SetupStage::*;
+
+        match self {
+            CloneRepo => "Cloning the repository",
+            DownloadPrebuilt => "Downloading the release",
+            FetchLfsObjects => "Fetching the Git LFS objects",
+            DownloadDiscordSdk => "Downloading the Discord SDK",
+            DownloadResources => "Downloading the packs and patches",
+            CopyRom => "Copying the base ROM",
+            ApplyPatches => "Applying the patches",
+            CreateBuildScript => "Creating the build script",
+            CreateScriptsDir => "Creating the scripts folder",
+            WritePostBuildScripts => "Writing the post-build scripts",
+        }
+    }
+}
+
+impl PostBuildStage {
+    /// Gets a label for the stage,
+    /// for UIs to show while it runs.
+    pub fn label(&self) -> &'static str {
+        use PostBuildStage::*;
+
+        match self {
+            LinkExecutable => "Linking the game executable",
+            StripExecutable => "Stripping the executable",
+            EmbedIcon => "Embedding the icon",
+            SmokeTest => "Checking the executable",
+            TexturePack => "Installing the texture pack",
+            DynOSPacks => "Installing the DynOS packs",
+            SaveFiles => "Installing the save files",
+            ControllerMappings => "Installing the controller mappings",
+            PostBuildScripts => "Running the post-build scripts",
+        }
+    }
+}
+
+impl fmt::Display for SetupStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use SetupStage::*;
 
         let result = match self {
             CloneRepo => "clone the repository",
+            DownloadPrebuilt => "download the prebuilt release",
+            FetchLfsObjects => "fetch the Git LFS objects",
+            DownloadDiscordSdk => "download the Discord game SDK",
+            DownloadResources => "download the packs and patches",
             CopyRom => "copy the base ROM",
+            ApplyPatches => "apply the patches",
             CreateBuildScript => "create the build script",
             CreateScriptsDir => "create the post-build script folder",
             WritePostBuildScripts => "write the post-build scripts",
         };
 
-        result.to_owned()
+        write!(f, "{}", result)
     }
 }
 
-impl ToString for PostBuildStage {
-    fn to_string(&self) -> String {
+impl fmt::Display for PostBuildStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use PostBuildStage::*;
 
         let result = match self {
+            LinkExecutable => "link the game executable",
+            SmokeTest => "check the built executable",
+            StripExecutable => "strip the executable",
+            EmbedIcon => "embed the icon",
             TexturePack => "install the texture pack",
             DynOSPacks => "install the DynOS pack(s)",
+            SaveFiles => "install the save files",
+            ControllerMappings => "install the controller mappings",
             PostBuildScripts => "run the post-build script(s)",
         };
 
-        result.to_owned()
+        write!(f, "{}", result)
     }
 }
@@ -0,0 +1,128 @@
+use super::backend::{self, Command, CommandRunner, FsProvider, RealFs, SystemRunner};
+use super::types::BuilderResult;
+use crate::error::ErrorCause;
+use crate::prelude::error_macros::*;
+use crate::prelude::Error;
+
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The binary format of an executable.
+pub enum ExecutableFormat {
+    /// An ELF binary (Linux, the BSDs).
+    Elf,
+    /// A Mach-O binary (macOS).
+    MachO,
+    /// A PE binary (Windows).
+    Pe,
+}
+
+#[derive(Clone, Debug)]
+/// The result of checking a built executable.
+pub struct ExecutableCheck {
+    /// The format of the executable.
+    pub format: ExecutableFormat,
+    /// Shared libraries that the executable
+    /// links to, but that could not be found.
+    pub missing_libs: Vec<String>,
+}
+
+impl ExecutableCheck {
+    /// Whether the executable looks
+    /// like it will start.
+    pub fn ok(&self) -> bool {
+        self.missing_libs.is_empty()
+    }
+}
+
+/// Work out the format of an executable
+/// from its magic bytes.
+pub fn detect_executable_format<P: AsRef<Path>>(path: P) -> BuilderResult<ExecutableFormat> {
+    detect_executable_format_on(&RealFs, path)
+}
+
+/// Like `detect_executable_format`,
+/// but reading from `fs`.
+pub fn detect_executable_format_on<P: AsRef<Path>>(
+    fs: &dyn FsProvider,
+    path: P,
+) -> BuilderResult<ExecutableFormat> {
+    let path = path.as_ref();
+    let mut magic = [0u8; 4];
+
+    let read_result = fs.open(path).and_then(|mut f| f.read_exact(&mut magic));
+    if let Err(e) = read_result {
+        let msg = format!("failed to read the header of {}", path.display());
+        return Err(err!(c_fs!(e, msg), "whilst checking the executable"));
+    }
+
+    match magic {
+        [0x7f, b'E', b'L', b'F'] => Ok(ExecutableFormat::Elf),
+        [0xcf, 0xfa, 0xed, 0xfe] | [0xfe, 0xed, 0xfa, 0xcf] | [0xca, 0xfe, 0xba, 0xbe] => {
+            Ok(ExecutableFormat::MachO)
+        }
+        [b'M', b'Z', _, _] => Ok(ExecutableFormat::Pe),
+        _ => {
+            let inner_err = std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} is not a recognized executable", path.display()),
+            );
+            Err(err!(c_fs!(inner_err), "the built executable is invalid"))
+        }
+    }
+}
+
+/// Get the shared libraries that an ELF
+/// executable needs, but are missing, with `ldd`.
+///
+/// Returns an empty list if `ldd` is not
+/// available.
+pub fn find_missing_libs<P: AsRef<Path>>(path: P) -> Vec<String> {
+    find_missing_libs_with(&SystemRunner, path)
+}
+
+/// Like `find_missing_libs`, but
+/// running `ldd` with `runner`.
+pub fn find_missing_libs_with<P: AsRef<Path>>(runner: &dyn CommandRunner, path: P) -> Vec<String> {
+    let Some((_, output)) = backend::probe(runner, Command::new("ldd").arg(path.as_ref())) else {
+        return Vec::new();
+    };
+
+    // lines look like `libfoo.so.1 => not found`
+    output
+        .iter()
+        .filter(|line| line.contains("not found"))
+        .filter_map(|line| line.split("=>").next())
+        .map(|lib| lib.trim().to_owned())
+        .collect()
+}
+
+/// Check that a built executable is a valid
+/// binary, and that all the shared libraries
+/// it needs are present.
+pub fn check_executable<P: AsRef<Path>>(path: P) -> BuilderResult<ExecutableCheck> {
+    check_executable_on(&RealFs, &SystemRunner, path)
+}
+
+/// Like `check_executable`, but reading from
+/// `fs`, and running `ldd` with `runner`.
+pub fn check_executable_on<P: AsRef<Path>>(
+    fs: &dyn FsProvider,
+    runner: &dyn CommandRunner,
+    path: P,
+) -> BuilderResult<ExecutableCheck> {
+    let path = path.as_ref();
+    let format = detect_executable_format_on(fs, path)?;
+
+    let missing_libs = if format == ExecutableFormat::Elf {
+        find_missing_libs_with(runner, path)
+    } else {
+        Vec::new()
+    };
+
+    Ok(ExecutableCheck {
+        format,
+        missing_libs,
+    })
+}
@@ -0,0 +1,308 @@
+use crate::callback_types::LogType;
+use crate::error::ErrorCause;
+use crate::prelude::builder_types::BuilderResult;
+use crate::prelude::error_macros::*;
+use crate::prelude::{run_callback, Callbacks, Error, Hooks, Spec};
+use crate::util;
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// The file extension of spec bundles.
+pub const BUNDLE_EXTENSION: &str = "smb";
+
+/// The name of the spec within a bundle.
+pub const BUNDLED_SPEC_NAME: &str = "spec.yaml";
+
+/// Packs larger than this are left out of
+/// bundles, and keep pointing at their
+/// original location.
+pub const MAX_BUNDLED_PACK_SIZE: u64 = 64 * 1024 * 1024;
+
+fn zip_err(e: zip::result::ZipError, desc: &str) -> Error {
+    err!(c_other!(e), desc)
+}
+
+fn add_file<W: Write + io::Seek>(
+    zip: &mut ZipWriter<W>,
+    src: &Path,
+    name: &str,
+) -> BuilderResult<()> {
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let mut contents = Vec::new();
+    if let Err(e) = File::open(src).and_then(|mut f| f.read_to_end(&mut contents)) {
+        let msg = format!("failed to read {}", src.display());
+        return Err(err!(c_fs!(e, msg), "whilst bundling the spec"));
+    }
+
+    zip.start_file(name, options)
+        .map_err(|e| zip_err(e, "failed to add a file to the bundle"))?;
+
+    match zip.write_all(&contents) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let msg = format!("failed to write {} into the bundle", name);
+            Err(err!(c_fs!(e, msg), "whilst bundling the spec"))
+        }
+    }
+}
+
+fn add_dir<W: Write + io::Seek>(
+    zip: &mut ZipWriter<W>,
+    src: &Path,
+    prefix: &str,
+) -> BuilderResult<()> {
+    let entries = match fs::read_dir(src) {
+        Ok(e) => e,
+        Err(e) => {
+            let msg = format!("failed to read the directory {}", src.display());
+            return Err(err!(c_fs!(e, msg), "whilst bundling the spec"));
+        }
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+
+        if path.is_dir() {
+            add_dir(zip, &path, &name)?;
+        } else {
+            add_file(zip, &path, &name)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+impl Spec {
+    /// Packs the spec, along with its patches,
+    /// scripts and small packs, into a single
+    /// bundle file.
+    ///
+    /// The ROMs are **never** bundled; they are
+    /// expected to be placed next to the
+    /// extracted bundle as `baserom.<region>.z64`.
+    pub fn to_bundle<P: AsRef<Path>>(
+        &self,
+        bundle_path: P,
+        callbacks: &mut Callbacks,
+    ) -> BuilderResult<()> {
+        let bundle_path = bundle_path.as_ref();
+
+        let file = match File::create(bundle_path) {
+            Ok(f) => f,
+            Err(e) => {
+                let msg = format!("failed to create {}", bundle_path.display());
+                return Err(err!(c_fs!(e, msg), "whilst bundling the spec"));
+            }
+        };
+
+        let mut zip = ZipWriter::new(file);
+        let mut spec = self.clone();
+
+        spec.rom.path = PathBuf::from(spec.rom.region.baserom_name());
+        for rom in spec.extra_roms.iter_mut().flatten() {
+            rom.path = PathBuf::from(rom.region.baserom_name());
+        }
+
+        for patch in spec.patches.iter_mut().flatten() {
+            let name = format!("patches/{}", file_name(&patch.path));
+            add_file(&mut zip, &patch.path, &name)?;
+            patch.path = PathBuf::from(name);
+        }
+
+        // scripts are stored inline in the spec
+        let hooks = spec.hooks.iter_mut().flat_map(Hooks::scripts_mut);
+        for script in spec.scripts.iter_mut().flatten().chain(hooks) {
+            script.path = None;
+        }
+
+        let mut bundle_pack = |path: &mut PathBuf, prefix: &str| -> BuilderResult<()> {
+            let size = util::dir_size(&*path);
+
+            if size > MAX_BUNDLED_PACK_SIZE {
+                run_callback!(
+                    callbacks.log_cb,
+                    LogType::Warn,
+                    &format!(
+                        "not bundling the pack at {}, as it is too large ({} bytes)",
+                        path.display(),
+                        size
+                    )
+                );
+                return Ok(());
+            }
+
+            let name = format!("{}/{}", prefix, file_name(path));
+            add_dir(&mut zip, path, &name)?;
+            *path = PathBuf::from(name);
+            Ok(())
+        };
+
+        if let Some(pack) = &mut spec.texture_pack {
+            bundle_pack(&mut pack.path, "packs/texture")?;
+        }
+
+        for pack in spec.dynos_packs.iter_mut().flatten() {
+            bundle_pack(&mut pack.path, "packs/dynos")?;
+        }
+
+        let spec_string = match serde_yaml::to_string(&spec) {
+            Ok(s) => s,
+            Err(e) => return Err(err!(c_other!(e), "failed to serialize the spec")),
+        };
+
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        zip.start_file(BUNDLED_SPEC_NAME, options)
+            .map_err(|e| zip_err(e, "failed to add the spec to the bundle"))?;
+
+        if let Err(e) = zip.write_all(spec_string.as_bytes()) {
+            return Err(err!(c_fs!(e), "whilst writing the spec into the bundle"));
+        }
+
+        zip.finish()
+            .map_err(|e| zip_err(e, "failed to finish writing the bundle"))?;
+
+        Ok(())
+    }
+
+    /// Unpacks a bundle into a directory, and
+    /// loads the spec inside of it, but **doesn't
+    /// check it**, like `from_file`.
+    ///
+    /// The paths in the returned spec point into
+    /// the extracted directory.
+    pub fn from_bundle<P, Q>(bundle_path: P, extract_dir: Q) -> BuilderResult<Spec>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let bundle_path = bundle_path.as_ref();
+        let extract_dir = extract_dir.as_ref();
+
+        let file = match File::open(bundle_path) {
+            Ok(f) => f,
+            Err(e) => {
+                let msg = format!("failed to open {}", bundle_path.display());
+                return Err(err!(c_fs!(e, msg), "whilst unpacking the bundle"));
+            }
+        };
+
+        let mut archive =
+            ZipArchive::new(file).map_err(|e| zip_err(e, "failed to read the bundle"))?;
+
+        // `extract` refuses entries that would
+        // escape the target directory.
+        archive
+            .extract(extract_dir)
+            .map_err(|e| zip_err(e, "failed to unpack the bundle"))?;
+
+        let mut spec = Spec::from_file(extract_dir.join(BUNDLED_SPEC_NAME))?;
+
+        let resolve = |path: &mut PathBuf| {
+            if path.is_relative() {
+                *path = extract_dir.join(&*path);
+            }
+        };
+
+        resolve(&mut spec.rom.path);
+
+        for rom in spec.extra_roms.iter_mut().flatten() {
+            resolve(&mut rom.path);
+        }
+
+        for patch in spec.patches.iter_mut().flatten() {
+            resolve(&mut patch.path);
+        }
+
+        if let Some(pack) = &mut spec.texture_pack {
+            resolve(&mut pack.path);
+        }
+
+        for pack in spec.dynos_packs.iter_mut().flatten() {
+            resolve(&mut pack.path);
+        }
+
+        Ok(spec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Patch, TexturePack};
+
+    const SPEC: &str = r#"
+rom:
+  region: us
+  path: /roms/baserom.us.z64
+  format: rom_BigEndian
+repo:
+  name: sm64ex
+  url: https://github.com/sm64pc/sm64ex
+  branch: nightly
+  about: ""
+  supports_dynos: false
+name: sm64ex
+"#;
+
+    #[test]
+    fn unpacks_what_was_bundled() {
+        let dir = std::env::temp_dir().join(format!("smbuilder-bundle-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("render96/gfx")).unwrap();
+
+        fs::write(
+            dir.join("60fps.patch"),
+            "diff --git a/src/game.c b/src/game.c\n",
+        )
+        .unwrap();
+        fs::write(dir.join("render96/gfx/mario.png"), "not really a texture").unwrap();
+
+        let mut spec: Spec = serde_yaml::from_str(SPEC).unwrap();
+        spec.patches = Some(vec![Patch {
+            name: "60fps".to_owned(),
+            path: dir.join("60fps.patch"),
+            remote: None,
+        }]);
+        spec.texture_pack = Some(TexturePack {
+            name: "Render96".to_owned(),
+            path: dir.join("render96"),
+            ..Default::default()
+        });
+
+        let bundle_path = dir.join(format!("sm64ex.{}", BUNDLE_EXTENSION));
+        spec.to_bundle(&bundle_path, &mut Callbacks::new()).unwrap();
+
+        let extract_dir = dir.join("extracted");
+        let unpacked = Spec::from_bundle(&bundle_path, &extract_dir).unwrap();
+
+        // the paths point into the extracted
+        // bundle, but the ROM is never in it.
+        assert_eq!(unpacked.rom.path, extract_dir.join("baserom.us.z64"));
+        assert!(!unpacked.rom.path.exists());
+
+        let patch = &unpacked.patches.as_ref().unwrap()[0];
+        assert_eq!(patch.path, extract_dir.join("patches/60fps.patch"));
+        assert_eq!(
+            fs::read_to_string(&patch.path).unwrap(),
+            "diff --git a/src/game.c b/src/game.c\n"
+        );
+
+        let pack = unpacked.texture_pack.as_ref().unwrap();
+        assert_eq!(pack.path, extract_dir.join("packs/texture/render96"));
+        assert!(pack.path.join("gfx/mario.png").is_file());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
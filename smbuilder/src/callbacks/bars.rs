@@ -0,0 +1,236 @@
+use super::types::{LogType, OutputSeverity};
+use super::{print_log, Callbacks};
+use crate::builder_types::{PostBuildStage, SetupStage};
+
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::time::Duration;
+
+/// Where the compile is in the order of
+/// the stages, between the setup and the
+/// post-build ones.
+const COMPILE: u64 = SetupStage::ALL.len() as u64;
+
+/// How many stages a build can have
+/// at most, counting the compile.
+const STAGES: u64 = COMPILE + 1 + PostBuildStage::ALL.len() as u64;
+
+const OVERALL_TEMPLATE: &str = "[{elapsed_precise}] [{bar:30.green/white}] {wide_msg:.bold}";
+const SPINNER_TEMPLATE: &str = "  {spinner:.cyan} {wide_msg}";
+const STAGE_TEMPLATE: &str = "  [{bar:30.cyan/blue}] {percent:>3}% {wide_msg}";
+
+#[derive(Clone)]
+/// Progress bars for a build in the terminal:
+/// one for the whole build, and one for the
+/// stage that is running.
+///
+/// The logged lines are printed above the bars,
+/// apart from the regular build output, which is
+/// shown on the stage's bar instead. The bars
+/// are hidden when stderr is not a terminal.
+///
+/// # Example
+///
+/// ```no_run
+/// # use smbuilder::prelude::*;
+/// # use smbuilder::callbacks::bars::ProgressBars;
+/// let bars = ProgressBars::new();
+/// let mut builder = Builder::new(Spec::default(), "base", bars.callbacks()).unwrap();
+/// let result = builder.build();
+/// bars.finish();
+/// ```
+pub struct ProgressBars {
+    multi: MultiProgress,
+    overall: ProgressBar,
+    stage: ProgressBar,
+}
+
+impl Default for ProgressBars {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressBars {
+    /// Create the bars, drawn on stderr.
+    pub fn new() -> Self {
+        Self::with_target(ProgressDrawTarget::stderr())
+    }
+
+    fn with_target(target: ProgressDrawTarget) -> Self {
+        let multi = MultiProgress::with_draw_target(target);
+
+        let overall = multi.add(ProgressBar::new(STAGES));
+        overall.set_style(style(OVERALL_TEMPLATE));
+
+        let stage = multi.add(ProgressBar::new_spinner());
+        stage.set_style(style(SPINNER_TEMPLATE));
+        stage.enable_steady_tick(Duration::from_millis(100));
+
+        ProgressBars {
+            multi,
+            overall,
+            stage,
+        }
+    }
+
+    /// Get callbacks that draw the build on
+    /// the bars, and print what it logs.
+    ///
+    /// Script approval and the dirty repo
+    /// callback are left unset, like with
+    /// `channel_bridge`.
+    pub fn callbacks(&self) -> Callbacks<'static> {
+        let (log, setup, postbuild, custom, script, clone, compile, pack) = (
+            self.clone(),
+            self.clone(),
+            self.clone(),
+            self.clone(),
+            self.clone(),
+            self.clone(),
+            self.clone(),
+            self.clone(),
+        );
+
+        Callbacks::new()
+            .log(move |log_type, text| log.log(log_type, text))
+            .new_setup_stage(move |stage| {
+                let position = SetupStage::ALL.iter().position(|s| *s == stage);
+                setup.start_stage(position.unwrap_or_default() as u64, stage.label());
+            })
+            .new_postbuild_stage(move |stage| {
+                let position = PostBuildStage::ALL.iter().position(|s| *s == stage);
+                postbuild.start_stage(
+                    COMPILE + 1 + position.unwrap_or_default() as u64,
+                    stage.label(),
+                );
+            })
+            .new_custom_stage(move |id| {
+                custom.start_stage(custom.overall.position(), id);
+            })
+            .new_postbuild_script(move |name, _| {
+                script.stage.set_message(format!("Running {}", name));
+            })
+            .repo_clone_progress(move |received, total, bytes| {
+                clone.set_progress(
+                    received as u64,
+                    total as u64,
+                    Some(format!(
+                        "{}/{} objects ({})",
+                        received,
+                        total,
+                        HumanBytes(bytes as u64)
+                    )),
+                );
+            })
+            .compile_progress(move |percent| {
+                if compile.overall.position() < COMPILE {
+                    compile.start_stage(COMPILE, "Compiling the port");
+                }
+                // the message is the last line
+                // of output, which is kept.
+                compile.set_progress(percent.round() as u64, 100, None);
+            })
+            .pack_install_progress(move |pack_name, installed, total, bytes| {
+                pack.set_progress(
+                    installed as u64,
+                    total as u64,
+                    Some(format!(
+                        "{} ({}/{} files, {})",
+                        pack_name,
+                        installed,
+                        total,
+                        HumanBytes(bytes)
+                    )),
+                );
+            })
+    }
+
+    /// Clear the bars, once the
+    /// build is done (or failed).
+    pub fn finish(&self) {
+        self.stage.finish_and_clear();
+        self.overall.finish_and_clear();
+    }
+
+    fn log(&self, log_type: LogType, text: &str) {
+        match log_type {
+            LogType::BuildOutput {
+                severity: OutputSeverity::Normal,
+            } => self.stage.set_message(text.trim().to_owned()),
+            _ => self.multi.suspend(|| print_log(log_type, text)),
+        }
+    }
+
+    /// Move the overall bar on to the stage
+    /// at `position`, and reset the stage bar
+    /// to a spinner until there is progress.
+    fn start_stage(&self, position: u64, label: &str) {
+        // the stages mostly run in order, but custom
+        // stages and retries should not go back.
+        self.overall
+            .set_position(position.max(self.overall.position()));
+        self.overall.set_message(label.to_owned());
+
+        self.stage.set_style(style(SPINNER_TEMPLATE));
+        self.stage.set_length(0);
+        self.stage.set_position(0);
+        self.stage.set_message(label.to_owned());
+    }
+
+    /// Show `done` out of `total` on the stage
+    /// bar, replacing the message if there is one.
+    fn set_progress(&self, done: u64, total: u64, message: Option<String>) {
+        if self.stage.length() != Some(total) {
+            self.stage.set_style(style(STAGE_TEMPLATE));
+            self.stage.set_length(total);
+        }
+
+        self.stage.set_position(done);
+        if let Some(message) = message {
+            self.stage.set_message(message);
+        }
+    }
+}
+
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template)
+        .expect("the templates are valid")
+        .progress_chars("=> ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callbacks::run_callback;
+
+    #[test]
+    fn follows_the_stages_of_a_build() {
+        let bars = ProgressBars::with_target(ProgressDrawTarget::hidden());
+        let mut callbacks = bars.callbacks();
+
+        run_callback!(callbacks.new_setup_stage_cb, SetupStage::CloneRepo);
+        run_callback!(callbacks.repo_clone_progress_cb, 5, 10, 2048);
+        assert_eq!(bars.overall.position(), 2);
+        assert_eq!(bars.stage.length(), Some(10));
+        assert_eq!(bars.stage.message(), "5/10 objects (2.00 KiB)");
+
+        run_callback!(
+            callbacks.log_cb,
+            LogType::BuildOutput {
+                severity: OutputSeverity::Normal
+            },
+            "cc -c -o a.o a.c",
+        );
+        run_callback!(callbacks.compile_progress_cb, 40.0);
+        assert_eq!(bars.overall.position(), COMPILE);
+        assert_eq!(bars.stage.position(), 40);
+
+        run_callback!(
+            callbacks.new_postbuild_stage_cb,
+            PostBuildStage::TexturePack
+        );
+        run_callback!(callbacks.new_setup_stage_cb, SetupStage::CreateScriptsDir);
+        assert_eq!(bars.overall.position(), COMPILE + 5);
+        assert_eq!(bars.stage.length(), Some(0));
+    }
+}
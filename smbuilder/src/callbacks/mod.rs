@@ -1,7 +1,14 @@
 /// Houses callback types.
 pub mod types;
 
+/// Progress bars for the terminal,
+/// drawn from the callbacks.
+#[cfg(feature = "indicatif")]
+pub mod bars;
+
 use crate::builder_types::{PostBuildStage, SetupStage};
+use colored::Colorize;
+use std::sync::mpsc::{self, Receiver, Sender};
 use types::*;
 
 /// Run a `Callbacks`
@@ -13,16 +20,38 @@ use types::*;
 /// reference to the callback,
 /// as there are `FnMut`s.
 ///
+/// Secrets (such as tokens in URLs) are
+/// redacted from the text arguments.
+///
 // TODO: example
 macro_rules! run_callback {
-    ($callback:expr, $($cb_arg:tt)*) => {
+    ($callback:expr, $($cb_arg:expr),+ $(,)?) => {
         if let Some(callback) = &mut $callback {
-            callback($($cb_arg)*);
+            $crate::callbacks::call_redacted!(callback, [], $($cb_arg),+);
         };
     };
 }
 
-pub(crate) use run_callback;
+/// Call `callback` with the arguments, the
+/// text ones redacted, one at a time.
+macro_rules! call_redacted {
+    ($callback:ident, [$($arg:ident)*], $next:expr $(, $rest:expr)*) => {{
+        #[allow(unused_imports)]
+        use $crate::redact::{EmitOther as _, EmitText as _};
+
+        let value = $next;
+        let arg = (&$crate::redact::Emit(value)).emit();
+        $crate::callbacks::call_redacted!($callback, [$($arg)* arg], $($rest),*)
+    }};
+
+    ($callback:ident, [$($arg:ident)*], ) => {{
+        use $crate::redact::Emitted as _;
+
+        $callback($($arg.arg()),*)
+    }};
+}
+
+pub(crate) use {call_redacted, run_callback};
 
 /// A struct to store callbacks
 /// to hook various events during
@@ -39,6 +68,9 @@ pub struct Callbacks<'cb> {
     /// A callback that is invoked
     /// on a new post-build stage.
     pub new_postbuild_stage_cb: Option<Box<NewPostBuildStageCb<'cb>>>,
+    /// A callback that is invoked
+    /// on a new custom stage.
+    pub new_custom_stage_cb: Option<Box<NewCustomStageCb<'cb>>>,
     /// A callback that is invoked when
     /// a new post-build script is being
     /// run.
@@ -47,6 +79,24 @@ pub struct Callbacks<'cb> {
     /// from git2's `RemoteCallbacks` to
     /// provide info on clone progress.
     pub repo_clone_progress_cb: Option<Box<RepoCloneProgressCb<'cb>>>,
+    /// A callback that is asked to approve
+    /// each post-build script before it is run.
+    pub approve_script_cb: Option<Box<ApproveScriptCb<'cb>>>,
+    /// A callback that reports how far
+    /// along the compile is.
+    pub compile_progress_cb: Option<Box<CompileProgressCb<'cb>>>,
+    /// A callback that reports the progress
+    /// of installing texture and DynOS packs.
+    pub pack_install_progress_cb: Option<Box<PackInstallProgressCb<'cb>>>,
+    /// A callback that is asked what to do
+    /// with changes to the files of the repo.
+    pub dirty_repo_cb: Option<Box<DirtyRepoCb<'cb>>>,
+}
+
+impl<'cb> Default for Callbacks<'cb> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'cb> Callbacks<'cb> {
@@ -56,8 +106,13 @@ impl<'cb> Callbacks<'cb> {
             log_cb: None,
             new_setup_stage_cb: None,
             new_postbuild_stage_cb: None,
+            new_custom_stage_cb: None,
             new_postbuild_script_cb: None,
             repo_clone_progress_cb: None,
+            approve_script_cb: None,
+            compile_progress_cb: None,
+            pack_install_progress_cb: None,
+            dirty_repo_cb: None,
         }
     }
 
@@ -66,6 +121,10 @@ impl<'cb> Callbacks<'cb> {
     /// See the docs on `[LogCb]`
     /// for more information on arguments.
     ///
+    /// Secrets (such as tokens in URLs)
+    /// are redacted from the text before
+    /// it reaches the callback.
+    ///
     // TODO: example
     pub fn log<F>(mut self, callback: F) -> Self
     where
@@ -75,6 +134,17 @@ impl<'cb> Callbacks<'cb> {
         self
     }
 
+    /// Set the log callback to one that
+    /// prints to the terminal, with a colored
+    /// prefix for every type of message.
+    ///
+    /// Errors and warnings go to stderr, and
+    /// the rest to stdout. The colors follow
+    /// the color choice of the builder.
+    pub fn terminal_log(self) -> Self {
+        self.log(print_log)
+    }
+
     /// Set the new setup stage
     /// callback.
     ///
@@ -103,6 +173,19 @@ impl<'cb> Callbacks<'cb> {
         self
     }
 
+    /// Set the new custom stage
+    /// callback.
+    ///
+    /// See the docs on `[NewCustomStageCb]`
+    /// for more information on arguments.
+    pub fn new_custom_stage<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&str) + Send + Sync + 'cb,
+    {
+        self.new_custom_stage_cb = Some(Box::new(callback) as Box<NewCustomStageCb<'cb>>);
+        self
+    }
+
     /// Set the repo clone progress
     /// callback.
     ///
@@ -132,4 +215,180 @@ impl<'cb> Callbacks<'cb> {
         self.new_postbuild_script_cb = Some(Box::new(callback) as Box<NewPostBuildScriptCb<'cb>>);
         self
     }
+
+    /// Set the script approval callback.
+    ///
+    /// See the docs on `[ApproveScriptCb]`
+    /// for more information on arguments.
+    pub fn approve_script<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&str, &str) -> bool + Send + Sync + 'cb,
+    {
+        self.approve_script_cb = Some(Box::new(callback) as Box<ApproveScriptCb<'cb>>);
+        self
+    }
+
+    /// Set the compile progress
+    /// callback.
+    ///
+    /// See the docs on `[CompileProgressCb]`
+    /// for more information on arguments.
+    pub fn compile_progress<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(f32) + Send + Sync + 'cb,
+    {
+        self.compile_progress_cb = Some(Box::new(callback) as Box<CompileProgressCb<'cb>>);
+        self
+    }
+
+    /// Set the pack install progress
+    /// callback.
+    ///
+    /// See the docs on `[PackInstallProgressCb]`
+    /// for more information on arguments.
+    pub fn pack_install_progress<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&str, usize, usize, u64) + Send + Sync + 'cb,
+    {
+        self.pack_install_progress_cb = Some(Box::new(callback) as Box<PackInstallProgressCb<'cb>>);
+        self
+    }
+
+    /// Set the dirty repo callback.
+    ///
+    /// See the docs on `[DirtyRepoCb]`
+    /// for more information on arguments.
+    ///
+    /// Without it, the changes are kept on a
+    /// rebuild, and stashed before the repo
+    /// is updated.
+    pub fn dirty_repo<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&[String]) -> DirtyRepoAction + Send + Sync + 'cb,
+    {
+        self.dirty_repo_cb = Some(Box::new(callback) as Box<DirtyRepoCb<'cb>>);
+        self
+    }
+}
+
+/// Print a logged line to the terminal, like
+/// the callback that `terminal_log` sets.
+pub(crate) fn print_log(log_type: LogType, text: &str) {
+    use LogType as L;
+    use OutputSeverity as S;
+
+    match log_type {
+        L::Error => eprintln!("{}{}", "error: ".bold().red(), text),
+        L::Warn => eprintln!("{}{}", "warn: ".bold().magenta(), text),
+        L::BuildOutput { severity } => match severity {
+            S::Normal => println!("{}{}", "make: ".bold().cyan(), text),
+            S::Warning => println!("{}{}", "make: ".bold().cyan(), text.yellow()),
+            S::Error => println!("{}{}", "make: ".bold().cyan(), text.red()),
+        },
+        L::Info => println!("{}{}", "info: ".bold().blue(), text),
+        L::Debug => println!("{}{}", "debug: ".bold().dimmed(), text.dimmed()),
+    }
+}
+
+/// Get callbacks that send every event of
+/// a build down a channel, and the receiving
+/// end of it.
+///
+/// The builder can then run on another thread,
+/// whilst a GUI's main loop drains the receiver
+/// with `try_recv`, without its own callbacks
+/// having to be `Send`.
+///
+/// Script approval and the dirty repo callback
+/// need an answer, so they are not bridged; set
+/// them on the returned callbacks with
+/// `approve_script` and `dirty_repo` if they
+/// are needed.
+///
+/// # Example
+///
+/// ```no_run
+/// # use smbuilder::prelude::*;
+/// # use smbuilder::prelude::callback_types::BuildEvent;
+/// let (callbacks, events) = channel_bridge();
+/// let mut builder = Builder::new(Spec::default(), "base", callbacks).unwrap();
+/// std::thread::spawn(move || builder.build());
+///
+/// // in the GUI's update loop:
+/// while let Ok(event) = events.try_recv() {
+///     if let BuildEvent::SetupStage(stage) = event {
+///         println!("{}", stage.label());
+///     }
+/// }
+/// ```
+pub fn channel_bridge() -> (Callbacks<'static>, Receiver<BuildEvent>) {
+    let (sender, receiver) = mpsc::channel();
+
+    (channel_bridge_to(sender), receiver)
+}
+
+/// Like `channel_bridge`, but sends the events to
+/// `sender`, as any type that they convert into.
+///
+/// Useful for sending other messages (like the
+/// result of the build) on the same channel, so
+/// that they arrive in order with the events.
+pub fn channel_bridge_to<T>(sender: Sender<T>) -> Callbacks<'static>
+where
+    T: From<BuildEvent> + Send + 'static,
+{
+    let (log, setup, postbuild, custom, script, clone, compile, pack) = (
+        sender.clone(),
+        sender.clone(),
+        sender.clone(),
+        sender.clone(),
+        sender.clone(),
+        sender.clone(),
+        sender.clone(),
+        sender,
+    );
+
+    // a closed receiver only means that no one is
+    // listening anymore, which is not the build's
+    // problem, so send errors are ignored.
+    Callbacks::new()
+        .log(move |log_type, text| {
+            let _ = log.send(T::from(BuildEvent::Log {
+                log_type,
+                text: text.to_owned(),
+            }));
+        })
+        .new_setup_stage(move |stage| {
+            let _ = setup.send(T::from(BuildEvent::SetupStage(stage)));
+        })
+        .new_postbuild_stage(move |stage| {
+            let _ = postbuild.send(T::from(BuildEvent::PostBuildStage(stage)));
+        })
+        .new_custom_stage(move |id| {
+            let _ = custom.send(T::from(BuildEvent::CustomStage(id.to_owned())));
+        })
+        .new_postbuild_script(move |name, description| {
+            let _ = script.send(T::from(BuildEvent::PostBuildScript {
+                name: name.to_owned(),
+                description: description.to_owned(),
+            }));
+        })
+        .repo_clone_progress(move |received, total, bytes| {
+            let _ = clone.send(T::from(BuildEvent::RepoCloneProgress {
+                received,
+                total,
+                bytes,
+            }));
+        })
+        .compile_progress(move |percent| {
+            let _ = compile.send(T::from(BuildEvent::CompileProgress { percent }));
+        })
+        .pack_install_progress(move |pack_name, installed, total, bytes| {
+            let _ = pack.send(T::from(BuildEvent::PackInstallProgress {
+                pack: pack_name.to_owned(),
+                installed,
+                total,
+                bytes,
+            }));
+        })
 }
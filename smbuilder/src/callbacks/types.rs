@@ -1,4 +1,5 @@
 use crate::prelude::builder_types::{PostBuildStage, SetupStage};
+use serde::{Deserialize, Serialize};
 
 /// Callback for a log event.
 ///
@@ -19,6 +20,13 @@ pub type NewSetupStageCb<'cb> = dyn FnMut(SetupStage) + Send + Sync + 'cb;
 ///  * post-build stage
 pub type NewPostBuildStageCb<'cb> = dyn FnMut(PostBuildStage) + Send + Sync + 'cb;
 
+/// Callback for a new custom stage (see
+/// `Builder::with_stage`).
+///
+/// Args:
+///  * id of the stage
+pub type NewCustomStageCb<'cb> = dyn FnMut(&str) + Send + Sync + 'cb;
+
 /// Callback for when a new Post-Build script is run.
 ///
 /// Args:
@@ -26,6 +34,44 @@ pub type NewPostBuildStageCb<'cb> = dyn FnMut(PostBuildStage) + Send + Sync + 'c
 ///  * description of the script
 pub type NewPostBuildScriptCb<'cb> = dyn FnMut(&str, &str) + Send + Sync + 'cb;
 
+/// Callback to approve a post-build script
+/// before it is run. The script is skipped
+/// if this returns `false`.
+///
+/// Args:
+///  * name of the script
+///  * contents of the script
+pub type ApproveScriptCb<'cb> = dyn FnMut(&str, &str) -> bool + Send + Sync + 'cb;
+
+/// Callback for when the files of the repo
+/// were changed by hand, found before the
+/// repo is updated or built.
+///
+/// Args:
+///  * the changed files, relative to the repo
+///
+/// Returns what to do with the changes.
+pub type DirtyRepoCb<'cb> = dyn FnMut(&[String]) -> DirtyRepoAction + Send + Sync + 'cb;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// What to do with the changes to
+/// the files of a repo, before it is
+/// updated or built.
+pub enum DirtyRepoAction {
+    /// Keep the changes, and build with
+    /// them, leaving the repo at the
+    /// commit that it is at.
+    Keep,
+    /// Stash the changes (like `git stash`),
+    /// so that they can be got back.
+    Stash,
+    /// Throw the changes away.
+    Discard,
+    /// Stop the build, leaving
+    /// the repo as it is.
+    Abort,
+}
+
 /// Callback for repository clone progress.
 ///
 /// Args:
@@ -34,6 +80,25 @@ pub type NewPostBuildScriptCb<'cb> = dyn FnMut(&str, &str) + Send + Sync + 'cb;
 ///  * recieved bytes
 pub type RepoCloneProgressCb<'cb> = dyn FnMut(usize, usize, usize) + Send + Sync + 'cb;
 
+/// Callback for the progress of compiling
+/// the port, estimated from the object files
+/// that make writes.
+///
+/// Args:
+///  * percent, from 0 to 100
+pub type CompileProgressCb<'cb> = dyn FnMut(f32) + Send + Sync + 'cb;
+
+/// Callback for pack installation progress,
+/// run after every file that is installed.
+///
+/// Args:
+///  * name of the pack
+///  * files installed so far
+///  * total files in the pack
+///  * bytes installed so far
+pub type PackInstallProgressCb<'cb> = dyn FnMut(&str, usize, usize, u64) + Send + Sync + 'cb;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 /// An enum to represent
 /// a log type, for the
 /// log callback.
@@ -44,8 +109,149 @@ pub enum LogType {
     Warn,
     /// Indicates some
     /// build output.
-    BuildOutput,
+    BuildOutput {
+        /// How severe the line
+        /// of output is.
+        severity: OutputSeverity,
+    },
     /// Indicates some
     /// info.
     Info,
+    /// Indicates details of what the
+    /// builder is doing, such as the
+    /// commands it runs.
+    ///
+    /// Only passed on with
+    /// `Verbosity::Verbose`.
+    Debug,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+/// The severity of a line of
+/// build output, as guessed
+/// from the compiler's and
+/// make's output.
+pub enum OutputSeverity {
+    #[default]
+    /// Regular output.
+    Normal,
+    /// A compiler warning.
+    Warning,
+    /// A compiler, linker
+    /// or make error.
+    Error,
+}
+
+impl OutputSeverity {
+    /// Classify a line of gcc/clang/make output.
+    pub fn classify(line: &str) -> Self {
+        let lower = line.to_lowercase();
+
+        let is_error = lower.contains("error:")
+            || lower.contains("undefined reference")
+            || lower.contains("fatal error")
+            || (lower.contains("***") && lower.contains("error"));
+
+        if is_error {
+            OutputSeverity::Error
+        } else if lower.contains("warning:") {
+            OutputSeverity::Warning
+        } else {
+            OutputSeverity::Normal
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// An event of a build, as sent
+/// by the callbacks that
+/// `channel_bridge` returns.
+///
+/// Each variant carries the arguments
+/// of the callback of the same name.
+pub enum BuildEvent {
+    /// Something was logged.
+    Log {
+        /// The type of the message.
+        log_type: LogType,
+        /// The text of the message.
+        text: String,
+    },
+    /// A new setup stage started.
+    SetupStage(SetupStage),
+    /// A new post-build stage started.
+    PostBuildStage(PostBuildStage),
+    /// A new custom stage started.
+    CustomStage(String),
+    /// A post-build script is being run.
+    PostBuildScript {
+        /// The filename of the script.
+        name: String,
+        /// The description of the script.
+        description: String,
+    },
+    /// The repo is being cloned.
+    RepoCloneProgress {
+        /// The objects received so far.
+        received: usize,
+        /// The total number of objects.
+        total: usize,
+        /// The bytes received so far.
+        bytes: usize,
+    },
+    /// The port is being compiled.
+    CompileProgress {
+        /// How far along the compile
+        /// is, from 0 to 100.
+        percent: f32,
+    },
+    /// A pack is being installed.
+    PackInstallProgress {
+        /// The name of the pack.
+        pack: String,
+        /// The files installed so far.
+        installed: usize,
+        /// The total files in the pack.
+        total: usize,
+        /// The bytes installed so far.
+        bytes: u64,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_compiler_output() {
+        use OutputSeverity::*;
+
+        let lines = [
+            (
+                "src/game/area.c:12:5: warning: unused variable 'x'",
+                Warning,
+            ),
+            ("src/game/area.c:12:5: error: expected ';'", Error),
+            (
+                "src/game/area.c:1:10: fatal error: foo.h: No such file",
+                Error,
+            ),
+            ("area.c:(.text+0x1c): undefined reference to `foo'", Error),
+            ("make: *** [Makefile:12: build/area.o] Error 1", Error),
+            ("gcc -c -o build/area.o src/game/area.c", Normal),
+            ("", Normal),
+        ];
+
+        for (line, severity) in lines {
+            assert_eq!(OutputSeverity::classify(line), severity, "{}", line);
+        }
+    }
+
+    #[test]
+    fn errors_win_over_warnings() {
+        assert_eq!(
+            OutputSeverity::classify("WARNING: treating warnings as errors: error: unused"),
+            OutputSeverity::Error
+        );
+    }
 }
@@ -0,0 +1,171 @@
+use crate::error::ErrorCause;
+use crate::paths;
+use crate::prelude::builder_types::BuilderResult;
+use crate::prelude::error_macros::*;
+use crate::prelude::{Error, Spec};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use toml_edit::{Document, Item};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// When to color the output.
+pub enum ColorChoice {
+    #[default]
+    /// Color the output if it
+    /// goes to a terminal.
+    Auto,
+    /// Always color the output.
+    Always,
+    /// Never color the output.
+    Never,
+}
+
+impl ColorChoice {
+    /// Make the `colored` crate (which
+    /// errors are colored with) follow
+    /// the choice.
+    pub fn apply(&self) {
+        match self {
+            ColorChoice::Auto => colored::control::unset_override(),
+            ColorChoice::Always => colored::control::set_override(true),
+            ColorChoice::Never => colored::control::set_override(false),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// The global configuration of smbuilder,
+/// from `config.toml` in the config directory
+/// (`~/.config/smbuilder` on Linux).
+///
+/// The builder falls back to it for whatever
+/// the spec (or the `BuildOptions`) leaves
+/// unset.
+///
+/// ```toml
+/// cache_dir = "/mnt/games/smbuilder-cache"
+/// jobs = 8
+/// base_dir = "/mnt/games/smbuilder"
+/// proxy = "http://proxy.local:3128"
+/// color = "auto" # or "always", "never"
+/// ```
+pub struct Config {
+    /// Where smbuilder caches things, such
+    /// as the repos shared between builds.
+    pub cache_dir: Option<PathBuf>,
+    /// The number of compile jobs, for
+    /// specs that do not set it.
+    pub jobs: Option<u8>,
+    /// The directory that launchers
+    /// put new builds in.
+    pub base_dir: Option<PathBuf>,
+    /// A proxy to clone repos through.
+    pub proxy: Option<String>,
+    /// When to color the output.
+    pub color: ColorChoice,
+}
+
+impl Config {
+    /// Gets the path of the config file,
+    /// in `paths::config_dir`.
+    ///
+    /// Returns `None` if the home
+    /// directory cannot be found.
+    pub fn default_path() -> Option<PathBuf> {
+        paths::config_dir().map(|dir| dir.join("config.toml"))
+    }
+
+    /// Gets the cache directory, falling
+    /// back to `paths::cache_dir`.
+    pub fn effective_cache_dir(&self) -> Option<PathBuf> {
+        self.cache_dir.clone().or_else(paths::cache_dir)
+    }
+
+    /// Gets the directory that new builds
+    /// go in, falling back to `paths::builds_dir`.
+    pub fn effective_base_dir(&self) -> Option<PathBuf> {
+        self.base_dir.clone().or_else(paths::builds_dir)
+    }
+
+    /// Gets the base dir for a new build
+    /// of `spec`, named after the spec.
+    pub fn base_dir_for(&self, spec: &Spec) -> Option<PathBuf> {
+        let name = spec.name.as_deref().unwrap_or(&spec.repo.name);
+        self.effective_base_dir().map(|dir| dir.join(name))
+    }
+
+    /// Load the config file, returning the
+    /// default config if there is none.
+    pub fn load_default() -> BuilderResult<Config> {
+        match Config::default_path() {
+            Some(path) if path.exists() => Config::load(path),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    /// Load a config file.
+    pub fn load<P: AsRef<Path>>(path: P) -> BuilderResult<Config> {
+        let path = path.as_ref();
+
+        let file_string = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) => {
+                let msg = format!("failed to read {}", path.display());
+                return Err(err!(c_fs!(e, msg), "whilst loading the config"));
+            }
+        };
+
+        Config::parse(&file_string)
+    }
+
+    /// Parse the contents of a config file.
+    pub fn parse(config: &str) -> BuilderResult<Config> {
+        let doc = match config.parse::<Document>() {
+            Ok(d) => d,
+            Err(e) => return Err(err!(c_other!(e), "failed to parse the config")),
+        };
+
+        let jobs = match doc.get("jobs") {
+            None => None,
+            Some(item) => match item.as_integer().map(u8::try_from) {
+                Some(Ok(jobs)) if jobs > 0 => Some(jobs),
+                _ => return Err(invalid("jobs", "a number from 1 to 255")),
+            },
+        };
+
+        let color = match get_str(&doc, "color")? {
+            None | Some("auto") => ColorChoice::Auto,
+            Some("always") => ColorChoice::Always,
+            Some("never") => ColorChoice::Never,
+            Some(_) => return Err(invalid("color", "one of auto, always or never")),
+        };
+
+        Ok(Config {
+            cache_dir: get_str(&doc, "cache_dir")?.map(PathBuf::from),
+            jobs,
+            base_dir: get_str(&doc, "base_dir")?.map(PathBuf::from),
+            proxy: get_str(&doc, "proxy")?.map(str::to_owned),
+            color,
+        })
+    }
+}
+
+fn get_str<'a>(doc: &'a Document, key: &str) -> BuilderResult<Option<&'a str>> {
+    match doc.get(key) {
+        None | Some(Item::None) => Ok(None),
+        Some(item) => match item.as_str() {
+            Some(s) => Ok(Some(s)),
+            None => Err(invalid(key, "a string")),
+        },
+    }
+}
+
+fn invalid(key: &str, expected: &str) -> Error {
+    let e = io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("`{}` should be {}", key, expected),
+    );
+    err!(c_other!(e), "invalid config")
+}
@@ -0,0 +1,392 @@
+use super::{Daemon, Request, Response};
+use crate::error::ErrorCause;
+use crate::prelude::builder_types::BuilderResult;
+use crate::prelude::error_macros::*;
+use crate::prelude::Error;
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+/// The largest spec that can be
+/// submitted, in bytes.
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// The most headers that a request
+/// may have.
+const MAX_HEADERS: usize = 64;
+
+/// The longest that the request line
+/// or a header may be, in bytes.
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+
+/// How long a client may take to
+/// send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug)]
+/// A tiny HTTP server in front of a `Daemon`,
+/// for building on a headless machine (like a
+/// NAS) and watching the builds from another.
+///
+/// Every answer is a `Response` as JSON.
+///
+/// | Route                         | Request                                |
+/// |-------------------------------|----------------------------------------|
+/// | `GET /builds`                 | `List`                                 |
+/// | `POST /builds`                | submit the spec in the body, as yaml   |
+/// | `GET /builds/{id}`            | `Status`                               |
+/// | `GET /builds/{id}/log?since=` | `Log`                                  |
+/// | `POST /builds/{id}/cancel`    | `Cancel`                               |
+///
+/// `POST /builds` takes `config` and `force`
+/// as query parameters.
+///
+/// Submitted specs can run post-build scripts
+/// on the server, so without a `token`, it only
+/// serves on loopback addresses (like
+/// `127.0.0.1`), to the machine itself.
+pub struct HttpServer {
+    daemon: Daemon,
+    root: PathBuf,
+    token: Option<String>,
+}
+
+impl HttpServer {
+    /// Creates a server for `daemon`, which
+    /// builds submitted specs under `root`.
+    pub fn new<P: Into<PathBuf>>(daemon: Daemon, root: P) -> Self {
+        HttpServer {
+            daemon,
+            root: root.into(),
+            token: None,
+        }
+    }
+
+    /// Only answer requests with an
+    /// `Authorization: Bearer <token>` header.
+    pub fn token<S: ToString>(mut self, token: S) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Listen on `addr`, serving each
+    /// client on a thread of its own.
+    ///
+    /// Fails if `addr` is not a loopback address
+    /// and no token was set. Never returns
+    /// otherwise, unless the socket fails.
+    pub fn serve<A: ToSocketAddrs>(&self, addr: A) -> BuilderResult<()> {
+        let addrs: Vec<SocketAddr> = match addr.to_socket_addrs() {
+            Ok(addrs) => addrs.collect(),
+            Err(e) => return Err(err!(c_other!(e), "failed to start the HTTP server")),
+        };
+
+        if self.token.is_none() && addrs.iter().any(|addr| !addr.ip().is_loopback()) {
+            let e = io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "a token is needed to serve on an address other than loopback",
+            );
+            return Err(err!(c_other!(e), "refusing to start the HTTP server"));
+        }
+
+        let listener = match TcpListener::bind(&addrs[..]) {
+            Ok(l) => l,
+            Err(e) => return Err(err!(c_other!(e), "failed to start the HTTP server")),
+        };
+
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(s) => s,
+                Err(e) => return Err(err!(c_other!(e), "the HTTP server's socket failed")),
+            };
+
+            let server = self.clone();
+            thread::spawn(move || {
+                // a client that goes away is
+                // not the server's problem.
+                let _ = server.serve_client(stream);
+            });
+        }
+
+        Ok(())
+    }
+
+    fn serve_client(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+
+        let (status, response) = match read_request(&mut reader)? {
+            Some(request) => self.route(request),
+            None => (400, error("the request is malformed")),
+        };
+
+        let body = serde_json::to_string(&response).map_err(io::Error::other)?;
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            reason(status),
+            body.len(),
+            body
+        )?;
+        writer.flush()
+    }
+
+    fn route(&self, request: HttpRequest) -> (u16, Response) {
+        if let Some(token) = &self.token {
+            let expected = format!("Bearer {}", token);
+            let authorization = request.authorization.as_deref().unwrap_or_default();
+            if !constant_time_eq(authorization.as_bytes(), expected.as_bytes()) {
+                return (401, error("a valid token is needed"));
+            }
+        }
+
+        let (path, query) = match request.target.split_once('?') {
+            Some((path, query)) => (path, query),
+            None => (request.target.as_str(), ""),
+        };
+        let param = |name: &str| {
+            query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_owned())
+        };
+
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let method = request.method.as_str();
+
+        let id = match segments.get(1).map(|id| id.parse::<u64>()) {
+            Some(Ok(id)) => Some(id),
+            Some(Err(_)) => return (404, error("there is no such build")),
+            None => None,
+        };
+
+        let daemon_request = match (method, segments.as_slice(), id) {
+            ("GET", ["builds"], _) => Request::List,
+            ("POST", ["builds"], _) => {
+                let Ok(spec) = String::from_utf8(request.body) else {
+                    return (400, error("the spec is not valid UTF-8"));
+                };
+                let force = param("force").is_some_and(|f| f == "true" || f == "1");
+
+                return match self
+                    .daemon
+                    .submit(&self.root, &spec, param("config"), force)
+                {
+                    Ok(id) => (202, Response::Started { id }),
+                    Err(e) => (
+                        422,
+                        Response::Error {
+                            message: e.to_string(),
+                            code: Some(e.kind().code()),
+                        },
+                    ),
+                };
+            }
+            ("GET", ["builds", _], Some(id)) => Request::Status { id },
+            ("GET", ["builds", _, "log"], Some(id)) => Request::Log {
+                id,
+                since: param("since").and_then(|s| s.parse().ok()).unwrap_or(0),
+            },
+            ("POST", ["builds", _, "cancel"], Some(id)) => Request::Cancel { id },
+            (_, ["builds"] | ["builds", _] | ["builds", _, "log" | "cancel"], _) => {
+                return (405, error("the method is not allowed here"))
+            }
+            _ => return (404, error("there is nothing here")),
+        };
+
+        match self.daemon.handle(daemon_request) {
+            // the only errors without a code
+            // are for builds that do not exist.
+            response @ Response::Error { code: None, .. } => (404, response),
+            response => (200, response),
+        }
+    }
+}
+
+struct HttpRequest {
+    method: String,
+    target: String,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Read a request, returning `None`
+/// if it is malformed or too big.
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Option<HttpRequest>> {
+    let mut line = String::new();
+    if !read_line(reader, &mut line)? {
+        return Ok(None);
+    }
+
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let (method, target) = (method.to_owned(), target.to_owned());
+
+    let mut content_length = 0;
+    let mut authorization = None;
+
+    for _ in 0..=MAX_HEADERS {
+        line.clear();
+        if !read_line(reader, &mut line)? {
+            return Ok(None);
+        }
+
+        let header = line.trim_end();
+        if header.is_empty() {
+            if content_length > MAX_BODY_SIZE {
+                return Ok(None);
+            }
+
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body)?;
+
+            return Ok(Some(HttpRequest {
+                method,
+                target,
+                authorization,
+                body,
+            }));
+        }
+
+        let Some((name, value)) = header.split_once(':') else {
+            return Ok(None);
+        };
+        let value = value.trim();
+
+        if name.eq_ignore_ascii_case("content-length") {
+            match value.parse() {
+                Ok(len) => content_length = len,
+                Err(_) => return Ok(None),
+            }
+        } else if name.eq_ignore_ascii_case("authorization") {
+            authorization = Some(value.to_owned());
+        }
+    }
+
+    Ok(None)
+}
+
+/// Read a line into `line`, returning
+/// `false` if it is longer than
+/// `MAX_LINE_LENGTH`.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<bool> {
+    let limit = MAX_LINE_LENGTH as u64 + 1;
+    let read = reader.by_ref().take(limit).read_line(line)?;
+
+    Ok(read as u64 != limit)
+}
+
+/// Compare two byte strings in a time that does
+/// not depend on where they first differ, so that
+/// the token cannot be guessed byte by byte.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn error(message: &str) -> Response {
+    Response::Error {
+        message: message.to_owned(),
+        code: None,
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        422 => "Unprocessable Entity",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn parse(request: &str) -> Option<HttpRequest> {
+        read_request(&mut Cursor::new(request.as_bytes())).unwrap()
+    }
+
+    fn list_request(authorization: Option<&str>) -> HttpRequest {
+        HttpRequest {
+            method: "GET".to_owned(),
+            target: "/builds".to_owned(),
+            authorization: authorization.map(str::to_owned),
+            body: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn reads_a_request() {
+        let request = parse(
+            "POST /builds?force=1 HTTP/1.1\r\nAuthorization: Bearer abc\r\nContent-Length: 5\r\n\r\nname:",
+        )
+        .unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.target, "/builds?force=1");
+        assert_eq!(request.authorization.as_deref(), Some("Bearer abc"));
+        assert_eq!(request.body, b"name:");
+    }
+
+    #[test]
+    fn rejects_requests_that_are_too_big() {
+        let long_target = "a".repeat(MAX_LINE_LENGTH);
+        assert!(parse(&format!("GET /{} HTTP/1.1\r\n\r\n", long_target)).is_none());
+
+        let long_header = format!("GET / HTTP/1.1\r\nX-Filler: {}\r\n\r\n", long_target);
+        assert!(parse(&long_header).is_none());
+
+        let headers = "X-Filler: 1\r\n".repeat(MAX_HEADERS + 1);
+        assert!(parse(&format!("GET / HTTP/1.1\r\n{}\r\n", headers)).is_none());
+
+        let body = format!(
+            "POST /builds HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_SIZE + 1
+        );
+        assert!(parse(&body).is_none());
+    }
+
+    #[test]
+    fn checks_the_token() {
+        let server = HttpServer::new(Daemon::new(), "builds").token("hunter2");
+
+        let (status, _) = server.route(list_request(None));
+        assert_eq!(status, 401);
+        let (status, _) = server.route(list_request(Some("Bearer hunter3")));
+        assert_eq!(status, 401);
+        let (status, _) = server.route(list_request(Some("Bearer hunter2")));
+        assert_eq!(status, 200);
+    }
+
+    #[test]
+    fn only_serves_loopback_without_a_token() {
+        let server = HttpServer::new(Daemon::new(), "builds");
+
+        assert!(server.serve("0.0.0.0:0").is_err());
+    }
+
+    #[test]
+    fn compares_in_constant_time() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"tokens"));
+    }
+}
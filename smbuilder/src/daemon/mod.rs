@@ -0,0 +1,442 @@
+/// Serving the daemon over a Unix socket.
+#[cfg(unix)]
+pub mod socket;
+
+/// Serving the daemon over HTTP.
+#[cfg(feature = "http")]
+pub mod http;
+
+#[cfg(unix)]
+pub use socket::{default_socket_path, Client, SOCKET_NAME};
+
+use crate::builder::state::{stage_name, BuildPhase, BuildStateHandle};
+use crate::callback_types::LogType;
+use crate::error::ErrorCause;
+use crate::manage::SPEC_FILE_NAME;
+use crate::prelude::builder_types::{BuildOptions, BuilderResult};
+use crate::prelude::error_macros::*;
+use crate::prelude::{Builder, Callbacks, Error, Spec};
+use crate::util;
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// The most lines of the log of each build
+/// that the daemon keeps for clients that
+/// connect partway through it.
+const MAX_LOG_LINES: usize = 2000;
+
+/// The most builds that have finished that
+/// the daemon keeps the status and log of.
+const MAX_FINISHED_JOBS: usize = 100;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+/// A request to the daemon, sent as one
+/// line of JSON.
+///
+/// ```json
+/// {"op": "build", "spec_path": "/home/me/builds/sm64ex/build.yaml"}
+/// {"op": "status", "id": 1}
+/// {"op": "log", "id": 1, "since": 120}
+/// {"op": "cancel", "id": 1}
+/// {"op": "list"}
+/// ```
+pub enum Request {
+    /// Start building a spec, in
+    /// the directory of the spec.
+    Build {
+        /// The path of the spec file.
+        spec_path: PathBuf,
+        /// A named configuration of
+        /// the spec to build.
+        #[serde(default)]
+        config: Option<String>,
+        /// Compile the port even if it
+        /// looks up to date.
+        #[serde(default)]
+        force: bool,
+    },
+    /// Ask a build to stop.
+    Cancel {
+        /// The ID of the build.
+        id: u64,
+    },
+    /// Get the status of a build.
+    Status {
+        /// The ID of the build.
+        id: u64,
+    },
+    /// Get the lines that a build has
+    /// logged, from the `since`th on.
+    Log {
+        /// The ID of the build.
+        id: u64,
+        /// The number of the first
+        /// line to get.
+        #[serde(default)]
+        since: usize,
+    },
+    /// Get the status of every build
+    /// that the daemon has run.
+    List,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+/// The answer of the daemon to a
+/// `Request`, sent as one line of JSON.
+pub enum Response {
+    /// A build was started.
+    Started {
+        /// The ID of the build, for
+        /// the other requests.
+        id: u64,
+    },
+    /// The build was asked to stop.
+    Cancelling,
+    /// The status of a build.
+    Status(JobStatus),
+    /// Lines logged by a build.
+    Log {
+        /// The number of the first line.
+        since: usize,
+        /// The lines, from `since` on.
+        lines: Vec<String>,
+        /// The number to pass as `since`
+        /// to get the lines after these.
+        next: usize,
+    },
+    /// The status of every build.
+    List {
+        /// The builds, oldest first.
+        jobs: Vec<JobStatus>,
+    },
+    /// The request failed.
+    Error {
+        /// Why it failed.
+        message: String,
+        /// The code of the kind of the error
+        /// (see `ErrorKind::code`), if it came
+        /// from the build.
+        code: Option<i32>,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Where a build run by the daemon is at.
+pub struct JobStatus {
+    /// The ID of the build.
+    pub id: u64,
+    /// The path of the spec file.
+    pub spec_path: PathBuf,
+    /// The phase that the build is in.
+    pub phase: BuildPhase,
+    /// What the build is doing, in words,
+    /// or `null` if it is not running.
+    pub stage: Option<String>,
+    /// How far along the stage is.
+    pub percent: Option<f32>,
+    /// How long the build has been
+    /// running for, in seconds.
+    pub elapsed_secs: u64,
+    /// The path of the executable, once
+    /// the build has finished.
+    pub executable: Option<PathBuf>,
+    /// Why the build failed, if it did.
+    pub error: Option<String>,
+    /// The code of the kind of the
+    /// error, if the build failed.
+    pub code: Option<i32>,
+}
+
+#[derive(Debug, Default)]
+struct JobLog {
+    /// The number of lines that were
+    /// dropped from the front.
+    dropped: usize,
+    lines: VecDeque<String>,
+}
+
+/// How a build ended: the path of the
+/// executable, or the error and its code.
+type Outcome = Option<Result<PathBuf, (String, i32)>>;
+
+#[derive(Debug)]
+struct Job {
+    id: u64,
+    spec_path: PathBuf,
+    state: BuildStateHandle,
+    log: Arc<Mutex<JobLog>>,
+    outcome: Arc<Mutex<Outcome>>,
+}
+
+impl Job {
+    fn is_finished(&self) -> bool {
+        self.outcome
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_some()
+    }
+
+    fn status(&self) -> JobStatus {
+        let snapshot = self.state.snapshot();
+        let outcome = self.outcome.lock().unwrap_or_else(|e| e.into_inner());
+
+        let (executable, error, code) = match &*outcome {
+            Some(Ok(path)) => (Some(path.clone()), None, None),
+            Some(Err((message, code))) => (None, Some(message.clone()), Some(*code)),
+            None => (None, None, None),
+        };
+
+        JobStatus {
+            id: self.id,
+            spec_path: self.spec_path.clone(),
+            phase: snapshot.phase,
+            stage: stage_name(snapshot.phase),
+            percent: snapshot.percent,
+            elapsed_secs: snapshot.elapsed.as_secs(),
+            executable,
+            error,
+            code,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// A background service that runs builds for
+/// desktop launchers, so that the builds carry
+/// on when the launcher is closed, and it can
+/// pick them back up when it is opened again.
+///
+/// Clients send a `Request` and get a
+/// `Response`, over a Unix socket (see
+/// `serve`) or HTTP (see `http::serve`).
+pub struct Daemon {
+    jobs: Arc<Mutex<Vec<Job>>>,
+    /// The number of specs that were
+    /// submitted, to name their
+    /// staged copies apart.
+    submitted: Arc<AtomicU64>,
+}
+
+impl Daemon {
+    /// Creates a daemon with
+    /// no builds.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer a request.
+    pub fn handle(&self, request: Request) -> Response {
+        match request {
+            Request::Build {
+                spec_path,
+                config,
+                force,
+            } => match self.start(spec_path, config, force) {
+                Ok(id) => Response::Started { id },
+                Err(e) => Response::Error {
+                    message: e.to_string(),
+                    code: Some(e.kind().code()),
+                },
+            },
+            Request::Cancel { id } => self.with_job(id, |job| {
+                job.state.cancel();
+                Response::Cancelling
+            }),
+            Request::Status { id } => self.with_job(id, |job| Response::Status(job.status())),
+            Request::Log { id, since } => self.with_job(id, |job| {
+                let log = job.log.lock().unwrap_or_else(|e| e.into_inner());
+                let from = since.max(log.dropped);
+                let lines: Vec<String> =
+                    log.lines.iter().skip(from - log.dropped).cloned().collect();
+
+                Response::Log {
+                    since: from,
+                    next: from + lines.len(),
+                    lines,
+                }
+            }),
+            Request::List => {
+                let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+                Response::List {
+                    jobs: jobs.iter().map(Job::status).collect(),
+                }
+            }
+        }
+    }
+
+    /// Start building a spec that was sent as
+    /// yaml (rather than as a path), in a base
+    /// dir of its own under `root`, named after
+    /// the spec.
+    ///
+    /// A spec of the same name that was sent
+    /// before is replaced, and its build is
+    /// carried on from, unless it is still
+    /// being built.
+    pub fn submit(
+        &self,
+        root: &Path,
+        spec: &str,
+        config: Option<String>,
+        force: bool,
+    ) -> BuilderResult<u64> {
+        let parsed = match serde_yaml::from_str::<Spec>(spec) {
+            Ok(s) => s,
+            Err(e) => return Err(err!(c_other!(e), "failed to parse the submitted spec")),
+        };
+
+        // the name comes from whoever sent the
+        // spec, so it must not leave the root.
+        let name: String = parsed
+            .name
+            .as_deref()
+            .unwrap_or(&parsed.repo.name)
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+
+        if name.is_empty() || name.starts_with('.') {
+            return Err(err!(c_invalid_spec!(
+                "name",
+                "cannot be used as the name of a directory"
+            )));
+        }
+
+        let base_dir = root.join(&name);
+        let spec_path = base_dir.join(SPEC_FILE_NAME);
+
+        // the spec is staged next to the old one,
+        // which is only replaced once the base dir
+        // is locked, so that a running build
+        // keeps its own.
+        let number = self.submitted.fetch_add(1, Ordering::Relaxed);
+        let staged_path = base_dir.join(format!(".{}.{}", SPEC_FILE_NAME, number));
+
+        let written =
+            fs::create_dir_all(&base_dir).and_then(|_| util::write_atomically(&staged_path, spec));
+        if let Err(e) = written {
+            let msg = format!("failed to write {}", staged_path.display());
+            return Err(err!(c_fs!(e, msg), "whilst saving the submitted spec"));
+        }
+
+        let result = self.start_staged(&staged_path, spec_path, config, force);
+        if result.is_err() {
+            let _ = fs::remove_file(&staged_path);
+        }
+
+        result
+    }
+
+    fn with_job<F: FnOnce(&Job) -> Response>(&self, id: u64, f: F) -> Response {
+        let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+
+        match jobs.iter().find(|job| job.id == id) {
+            Some(job) => f(job),
+            None => Response::Error {
+                message: format!("there is no build with the ID {}", id),
+                code: None,
+            },
+        }
+    }
+
+    fn start(&self, spec_path: PathBuf, config: Option<String>, force: bool) -> BuilderResult<u64> {
+        let staged_path = spec_path.clone();
+        self.start_staged(&staged_path, spec_path, config, force)
+    }
+
+    /// Start building the spec at `staged_path`,
+    /// which is moved to `spec_path` once the
+    /// base dir is locked.
+    fn start_staged(
+        &self,
+        staged_path: &Path,
+        spec_path: PathBuf,
+        config: Option<String>,
+        force: bool,
+    ) -> BuilderResult<u64> {
+        let base_dir = spec_path.parent().map(Path::to_owned).unwrap_or_default();
+
+        let log = Arc::new(Mutex::new(JobLog::default()));
+        let sink = log.clone();
+
+        let mut callbacks = Callbacks::new().log(move |log_type, text| {
+            if log_type == LogType::Debug {
+                return;
+            }
+
+            let mut log = sink.lock().unwrap_or_else(|e| e.into_inner());
+            log.lines.push_back(text.to_owned());
+            if log.lines.len() > MAX_LOG_LINES {
+                log.lines.pop_front();
+                log.dropped += 1;
+            }
+        });
+
+        let spec = Spec::from_file_checked(staged_path, &mut callbacks)?;
+        // the base dir is locked here, so a
+        // second build of it fails right away.
+        let mut builder =
+            Builder::new(spec, base_dir, callbacks)?.with_options(BuildOptions::new().force(force));
+
+        if staged_path != spec_path {
+            if let Err(e) = fs::rename(staged_path, &spec_path) {
+                let msg = format!("failed to write {}", spec_path.display());
+                return Err(err!(c_fs!(e, msg), "whilst saving the submitted spec"));
+            }
+        }
+
+        let outcome = Arc::new(Mutex::new(None));
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let id = jobs.last().map(|job| job.id + 1).unwrap_or(1);
+
+        jobs.push(Job {
+            id,
+            spec_path,
+            state: builder.state_handle(),
+            log,
+            outcome: outcome.clone(),
+        });
+
+        // like the logs, only the latest
+        // finished builds are kept.
+        let finished = jobs.iter().filter(|job| job.is_finished()).count();
+        let mut dropped = finished.saturating_sub(MAX_FINISHED_JOBS);
+        jobs.retain(|job| {
+            if dropped > 0 && job.is_finished() {
+                dropped -= 1;
+                false
+            } else {
+                true
+            }
+        });
+
+        thread::spawn(move || {
+            let result = match &config {
+                Some(name) => builder.build_configuration(name),
+                None => builder.build(),
+            };
+
+            let result = result
+                .map(|build| build.executable_path().to_owned())
+                .map_err(|e| (e.to_string(), e.kind().code()));
+
+            *outcome.lock().unwrap_or_else(|e| e.into_inner()) = Some(result);
+        });
+
+        Ok(id)
+    }
+}
@@ -0,0 +1,161 @@
+use super::{Daemon, Request, Response};
+use crate::error::ErrorCause;
+use crate::paths;
+use crate::prelude::builder_types::BuilderResult;
+use crate::prelude::error_macros::*;
+use crate::prelude::Error;
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// The name of the socket that the
+/// daemon listens on by default.
+pub const SOCKET_NAME: &str = "smbuilder.sock";
+
+/// Gets the path of the socket that the
+/// daemon listens on by default:
+/// `$XDG_RUNTIME_DIR/smbuilder.sock`, or
+/// `smbuilder.sock` in the cache directory.
+pub fn default_socket_path() -> Option<PathBuf> {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join(SOCKET_NAME)),
+        _ => paths::cache_dir().map(|dir| dir.join(SOCKET_NAME)),
+    }
+}
+
+impl Daemon {
+    /// Listen on the Unix socket at `path`, serving
+    /// each client on a thread of its own.
+    ///
+    /// Clients send one line of JSON for each
+    /// request, and get one back for each answer,
+    /// and may send many on one connection.
+    ///
+    /// A stale socket left behind by a daemon
+    /// that is no longer running is replaced.
+    /// Never returns unless the socket fails.
+    pub fn serve<P: AsRef<Path>>(&self, path: P) -> BuilderResult<()> {
+        let path = path.as_ref();
+
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                let e = io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("a daemon is already listening on {}", path.display()),
+                );
+                return Err(err!(c_other!(e), "failed to start the daemon"));
+            }
+
+            let _ = fs::remove_file(path);
+        }
+
+        if let Some(dir) = path.parent() {
+            if let Err(e) = fs::create_dir_all(dir) {
+                let msg = format!("failed to create {}", dir.display());
+                return Err(err!(c_fs!(e, msg), "whilst starting the daemon"));
+            }
+        }
+
+        let listener = match UnixListener::bind(path) {
+            Ok(l) => l,
+            Err(e) => {
+                let msg = format!("failed to listen on {}", path.display());
+                return Err(err!(c_fs!(e, msg), "whilst starting the daemon"));
+            }
+        };
+
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(s) => s,
+                Err(e) => return Err(err!(c_other!(e), "the daemon's socket failed")),
+            };
+
+            let daemon = self.clone();
+            thread::spawn(move || {
+                // a client that goes away is
+                // not the daemon's problem.
+                let _ = daemon.serve_client(stream);
+            });
+        }
+
+        Ok(())
+    }
+
+    fn serve_client(&self, stream: UnixStream) -> io::Result<()> {
+        let mut writer = stream.try_clone()?;
+
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let response = match serde_json::from_str::<Request>(&line) {
+                Ok(request) => self.handle(request),
+                Err(e) => Response::Error {
+                    message: format!("invalid request: {}", e),
+                    code: None,
+                },
+            };
+
+            let mut out = serde_json::to_string(&response).map_err(io::Error::other)?;
+            out.push('\n');
+            writer.write_all(out.as_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A connection to a daemon.
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Client {
+    /// Connect to the daemon listening
+    /// on the socket at `path`.
+    pub fn connect<P: AsRef<Path>>(path: P) -> BuilderResult<Client> {
+        let path = path.as_ref();
+
+        let stream = match UnixStream::connect(path) {
+            Ok(s) => s,
+            Err(e) => {
+                let msg = format!("failed to connect to {}", path.display());
+                return Err(err!(c_fs!(e, msg), "is the daemon running?"));
+            }
+        };
+
+        let writer = match stream.try_clone() {
+            Ok(w) => w,
+            Err(e) => return Err(err!(c_other!(e), "failed to connect to the daemon")),
+        };
+
+        Ok(Client {
+            reader: BufReader::new(stream),
+            writer,
+        })
+    }
+
+    /// Send a request, and wait
+    /// for the answer to it.
+    pub fn request(&mut self, request: &Request) -> BuilderResult<Response> {
+        let failed = |e: io::Error| err!(c_other!(e), "failed to talk to the daemon");
+
+        let mut line = serde_json::to_string(request).map_err(|e| failed(io::Error::other(e)))?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).map_err(failed)?;
+
+        let mut answer = String::new();
+        if self.reader.read_line(&mut answer).map_err(failed)? == 0 {
+            let e = io::Error::new(io::ErrorKind::UnexpectedEof, "the daemon hung up");
+            return Err(failed(e));
+        }
+
+        serde_json::from_str(&answer).map_err(|e| failed(io::Error::other(e)))
+    }
+}
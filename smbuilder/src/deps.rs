@@ -0,0 +1,346 @@
+use duct::{cmd, Expression};
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Whether the host is one of the BSDs,
+/// where `make` is BSD make rather than
+/// GNU make.
+pub const IS_BSD: bool = cfg!(any(
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A system package manager.
+#[allow(missing_docs)]
+pub enum PackageManager {
+    Apt,
+    Dnf,
+    Pacman,
+    Zypper,
+    Apk,
+    Xbps,
+    /// FreeBSD's `pkg`.
+    Pkg,
+    /// OpenBSD's `pkg_add`.
+    PkgAdd,
+    /// NetBSD's `pkgin`.
+    Pkgin,
+    Brew,
+    MacPorts,
+}
+
+impl PackageManager {
+    /// Detect the package manager of the host.
+    pub fn detect() -> Option<Self> {
+        use PackageManager::*;
+
+        let candidates = [
+            ("apt-get", Apt),
+            ("dnf", Dnf),
+            ("pacman", Pacman),
+            ("zypper", Zypper),
+            ("apk", Apk),
+            ("xbps-install", Xbps),
+            ("pkg_add", PkgAdd),
+            ("pkgin", Pkgin),
+            ("pkg", Pkg),
+            ("brew", Brew),
+            ("port", MacPorts),
+        ];
+
+        candidates
+            .into_iter()
+            .find(|(program, _)| has_program(program))
+            .map(|(_, manager)| manager)
+    }
+
+    /// Get the command that installs a package.
+    pub fn install_command(&self, package: &str) -> String {
+        use PackageManager::*;
+
+        match self {
+            Apt => format!("sudo apt-get install {}", package),
+            Dnf => format!("sudo dnf install {}", package),
+            Pacman => format!("sudo pacman -S {}", package),
+            Zypper => format!("sudo zypper install {}", package),
+            Apk => format!("sudo apk add {}", package),
+            Xbps => format!("sudo xbps-install {}", package),
+            Pkg => format!("sudo pkg install {}", package),
+            PkgAdd => format!("doas pkg_add {}", package),
+            Pkgin => format!("sudo pkgin install {}", package),
+            Brew => format!("brew install {}", package),
+            MacPorts => format!("sudo port install {}", package),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A native library that the ports
+/// link to, depending on the APIs
+/// that they are built with.
+pub enum NativeLib {
+    /// SDL 2, for the `SDL2` window,
+    /// audio and controller APIs.
+    Sdl2,
+    /// SDL 1.2, for the `SDL1` APIs.
+    Sdl1,
+    /// OpenGL, for the `GL` and
+    /// `GL_LEGACY` renderers.
+    Gl,
+    /// GLEW, which the `GL` renderer
+    /// loads OpenGL with on macOS.
+    Glew,
+}
+
+impl NativeLib {
+    /// Gets the name of the library
+    /// for `pkg-config`.
+    pub fn pkg_config_name(&self) -> &'static str {
+        match self {
+            NativeLib::Sdl2 => "sdl2",
+            NativeLib::Sdl1 => "sdl",
+            NativeLib::Gl => "gl",
+            NativeLib::Glew => "glew",
+        }
+    }
+
+    /// Gets the library that a shared library
+    /// (like `libSDL2-2.0.so.0`) is part of,
+    /// if it is one of these.
+    pub fn from_soname(soname: &str) -> Option<NativeLib> {
+        let prefixes = [
+            ("libSDL2", NativeLib::Sdl2),
+            ("libSDL-1.2", NativeLib::Sdl1),
+            ("libGLEW", NativeLib::Glew),
+            ("libGL.", NativeLib::Gl),
+        ];
+
+        prefixes
+            .into_iter()
+            .find(|(prefix, _)| soname.starts_with(prefix))
+            .map(|(_, lib)| lib)
+    }
+
+    /// Gets the package (with the headers) that
+    /// provides the library with `manager`.
+    pub fn package(&self, manager: PackageManager) -> &'static str {
+        use NativeLib::*;
+        use PackageManager::*;
+
+        match (self, manager) {
+            (Sdl2, Apt) => "libsdl2-dev",
+            (Sdl2, Dnf | Xbps) => "SDL2-devel",
+            (Sdl2, Zypper) => "libSDL2-devel",
+            (Sdl2, Apk) => "sdl2-dev",
+            (Sdl2, Pkgin) => "SDL2",
+            (Sdl2, MacPorts) => "libsdl2",
+            (Sdl2, Pacman | Pkg | PkgAdd | Brew) => "sdl2",
+            (Sdl1, Apt) => "libsdl1.2-dev",
+            (Sdl1, Dnf | Xbps) => "SDL-devel",
+            (Sdl1, Zypper) => "libSDL-devel",
+            (Sdl1, Apk) => "sdl12-compat-dev",
+            (Sdl1, Pkg) => "sdl12",
+            (Sdl1, PkgAdd) => "sdl",
+            (Sdl1, Pkgin) => "SDL",
+            (Sdl1, MacPorts) => "libsdl12",
+            (Sdl1, Pacman | Brew) => "sdl12-compat",
+            (Gl, Apt) => "libgl1-mesa-dev",
+            (Gl, Dnf) => "mesa-libGL-devel",
+            (Gl, Zypper) => "Mesa-libGL-devel",
+            (Gl, Apk) => "mesa-dev",
+            (Gl, Xbps) => "MesaLib-devel",
+            (Gl, Pkg) => "libglvnd",
+            (Gl, Pkgin) => "MesaLib",
+            (Gl, Pacman | PkgAdd | Brew | MacPorts) => "mesa",
+            (Glew, Apt) => "libglew-dev",
+            (Glew, Dnf | Zypper | Xbps) => "glew-devel",
+            (Glew, Apk) => "glew-dev",
+            (Glew, Pacman | Pkg | PkgAdd | Pkgin | Brew | MacPorts) => "glew",
+        }
+    }
+
+    /// Get a hint on how to install
+    /// the library on this host.
+    pub fn install_hint(&self) -> String {
+        match PackageManager::detect() {
+            Some(manager) => format!("try `{}`", manager.install_command(self.package(manager))),
+            None => format!(
+                "install {} (with its headers) with your package manager",
+                self.pkg_config_name()
+            ),
+        }
+    }
+
+    /// Whether the library (and its headers)
+    /// is installed, going by `pkg-config`.
+    ///
+    /// `None` if `pkg-config` is not
+    /// installed, so it cannot be told.
+    pub fn is_installed(&self) -> Option<bool> {
+        if !has_program("pkg-config") {
+            return None;
+        }
+
+        let found = cmd!("pkg-config", "--exists", self.pkg_config_name())
+            .stdout_null()
+            .stderr_null()
+            .unchecked()
+            .run()
+            .map(|o| o.status.success())
+            .unwrap_or(false);
+
+        Some(found)
+    }
+}
+
+/// Get a hint on how to install a package
+/// on this host, for error messages.
+pub fn install_hint(package: &str) -> String {
+    match PackageManager::detect() {
+        Some(manager) => format!("try `{}`", manager.install_command(package)),
+        None => format!("install {} with your package manager", package),
+    }
+}
+
+/// Get the GNU make command of the host.
+///
+/// `gmake` is preferred wherever it exists,
+/// as `make` is BSD make on the BSDs. `None`
+/// is returned if no GNU make can be found.
+pub fn make_command() -> Option<&'static str> {
+    if has_program("gmake") {
+        Some("gmake")
+    } else if !IS_BSD && has_program("make") {
+        Some("make")
+    } else {
+        None
+    }
+}
+
+/// The modules that the asset extraction
+/// scripts of some ports import, which
+/// may be missing from an install of
+/// Python, with the pip package that
+/// provides them (`None` for the ones that
+/// are part of Python itself).
+pub const PYTHON_MODULES: &[(&str, Option<&str>)] = &[
+    ("PIL", Some("pillow")),
+    ("numpy", Some("numpy")),
+    ("zlib", None),
+];
+
+/// Get the Python 3 command of the host.
+///
+/// `python3` is preferred, as `python`
+/// may still be Python 2 on old hosts.
+pub fn python_command() -> Option<&'static str> {
+    if has_program("python3") {
+        Some("python3")
+    } else if has_program("python") {
+        Some("python")
+    } else {
+        None
+    }
+}
+
+/// A Python script that prints the modules
+/// of its arguments that cannot be imported.
+pub(crate) const FIND_MISSING_MODULES: &str = "import importlib.util, sys; \
+    print(*(m for m in sys.argv[1:] if importlib.util.find_spec(m) is None))";
+
+/// Get the modules of `modules` that
+/// `python` cannot import.
+///
+/// If Python cannot be run at all,
+/// nothing is reported as missing.
+pub fn missing_python_modules(python: &str, modules: &[&str]) -> Vec<String> {
+    let mut args = vec!["-c".to_owned(), FIND_MISSING_MODULES.to_owned()];
+    args.extend(modules.iter().map(|m| m.to_string()));
+
+    let output = match cmd(python, &args).stderr_null().unchecked().read() {
+        Ok(o) => o,
+        Err(_) => return Vec::new(),
+    };
+
+    output
+        .split_whitespace()
+        .filter(|module| modules.contains(module))
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Get a command that downloads `url` to
+/// `dest` with a tool that ships with the
+/// host, for when smbuilder is built without
+/// the `remote` feature.
+///
+/// Tries `curl`, `wget`, FreeBSD's `fetch`
+/// and OpenBSD's `ftp`, in that order.
+pub fn download_command(url: &str, dest: &Path) -> Option<Expression> {
+    if has_program("curl") {
+        Some(cmd!("curl", "-fsSL", "-o", dest, url))
+    } else if has_program("wget") {
+        Some(cmd!("wget", "-q", "-O", dest, url))
+    } else if has_program("fetch") {
+        Some(cmd!("fetch", "-q", "-o", dest, url))
+    } else if cfg!(target_os = "openbsd") && has_program("ftp") {
+        Some(cmd!("ftp", "-V", "-o", dest, url))
+    } else {
+        None
+    }
+}
+
+/// Get the first line that a program
+/// prints for `--version`, if it can
+/// be found.
+pub fn program_version(program: &str) -> Option<String> {
+    if !has_program(program) {
+        return None;
+    }
+
+    let output = cmd!(program, "--version")
+        .stderr_to_stdout()
+        .unchecked()
+        .read()
+        .ok()?;
+
+    output
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_owned)
+}
+
+/// Find a program in the `PATH`,
+/// like `which`.
+pub fn find_program<S: AsRef<str>>(name: S) -> Option<PathBuf> {
+    let name = name.as_ref();
+    let path_var = env::var_os("PATH")?;
+
+    env::split_paths(&path_var)
+        .map(|dir| dir.join(name))
+        .find(|candidate| is_executable(candidate))
+}
+
+/// Whether a program can be found
+/// in the `PATH`.
+pub fn has_program<S: AsRef<str>>(name: S) -> bool {
+    find_program(name).is_some()
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file() || path.with_extension("exe").is_file()
+}
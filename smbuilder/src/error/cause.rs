@@ -1,8 +1,11 @@
 use super::AnyError;
 use std::path::PathBuf;
+use std::time::Duration;
 
-#[derive(Debug)]
 /// Possible causes for an error
+///
+/// Its `Debug` form is the redacted
+/// message, like its `Display` form.
 pub enum ErrorCause {
     /// Indicates a failure in cloning a repo.
     RepoClone {
@@ -39,9 +42,169 @@ pub enum ErrorCause {
         /// The message that the program would like to give.
         msg: &'static str,
     },
+    /// Indicates that another build is already
+    /// running in the same base directory.
+    BaseDirLocked {
+        /// The base directory that is locked.
+        dir: PathBuf,
+        /// The PID of the process holding the
+        /// lock, if it could be read.
+        pid: Option<u32>,
+    },
+    /// Indicates that the base directory cannot
+    /// be written to, such as on an SD card that
+    /// is mounted read-only.
+    BaseDirNotWritable {
+        /// The base directory.
+        dir: PathBuf,
+        /// A directory that could be
+        /// used instead, if there is one.
+        fallback: Option<PathBuf>,
+        /// The error from trying to write to it.
+        ctx: std::io::Error,
+    },
+    /// Indicates that the built executable
+    /// will not start, due to missing shared
+    /// libraries.
+    MissingLibraries {
+        /// The path to the executable.
+        path: PathBuf,
+        /// The missing libraries.
+        libs: Vec<String>,
+    },
+    /// Indicates a failure in downloading
+    /// something over the network.
+    Download {
+        /// The URL being downloaded.
+        url: String,
+        /// Context (possible cause)
+        ctx: Option<AnyError>,
+    },
+    /// Indicates that a file or download
+    /// did not match its expected checksum.
+    ChecksumMismatch {
+        /// What was being checked (a path or URL).
+        target: String,
+        /// The expected checksum.
+        expected: String,
+        /// The actual checksum.
+        actual: String,
+    },
+    /// Indicates that a signature is not valid
+    /// for a file (or spec) and public key.
+    BadSignature {
+        /// What was being verified (a path,
+        /// or the name of a spec).
+        target: String,
+    },
+    /// Indicates that a program or library
+    /// that the build needs is missing.
+    MissingDependency {
+        /// The name of the dependency.
+        name: String,
+        /// How to get hold of it, if known.
+        msg: Option<String>,
+    },
+    /// Indicates that a stage ran for longer
+    /// than it was allowed to, or stopped
+    /// printing anything, and was killed.
+    StageTimedOut {
+        /// The stage that timed out.
+        stage: String,
+        /// The limit that was hit.
+        after: Duration,
+        /// Whether the stage stopped printing
+        /// anything, rather than running for
+        /// too long overall.
+        inactive: bool,
+    },
+    /// Indicates that a spec is missing
+    /// a field, or that a field is invalid.
+    InvalidSpec {
+        /// The field that is invalid.
+        field: String,
+        /// What is wrong with it.
+        reason: String,
+    },
+    /// Indicates that the build was cancelled,
+    /// either by the user or by a signal.
+    Cancelled,
     /// An error that doesnt apply to any of the variants
     Other {
         /// Context (cause, if any)
         ctx: Option<AnyError>,
     },
 }
+
+/// The exit code of a CLI run (and the
+/// code of an operation) that succeeded,
+/// next to the codes of `ErrorKind`.
+pub const SUCCESS_CODE: i32 = 0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// What sort of failure an error is, for
+/// wrapper scripts and launchers to branch on.
+///
+/// Each has a stable `code`, which is also
+/// the exit code of the CLI.
+pub enum ErrorKind {
+    /// Anything that none of the
+    /// other kinds fit.
+    Other,
+    /// The spec cannot be built as written.
+    SpecInvalid,
+    /// A program or library that the
+    /// build needs is missing.
+    DepsMissing,
+    /// The repo could not be cloned.
+    CloneFailed,
+    /// The port did not compile.
+    CompileFailed,
+    /// The build was cancelled.
+    Cancelled,
+}
+
+impl ErrorKind {
+    /// Gets the code of the kind, which
+    /// never changes between versions.
+    pub fn code(&self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::SpecInvalid => 2,
+            ErrorKind::DepsMissing => 3,
+            ErrorKind::CloneFailed => 4,
+            ErrorKind::CompileFailed => 5,
+            ErrorKind::Cancelled => 6,
+        }
+    }
+
+    /// The inverse of `code`.
+    pub fn from_code(code: i32) -> Option<ErrorKind> {
+        match code {
+            1 => Some(ErrorKind::Other),
+            2 => Some(ErrorKind::SpecInvalid),
+            3 => Some(ErrorKind::DepsMissing),
+            4 => Some(ErrorKind::CloneFailed),
+            5 => Some(ErrorKind::CompileFailed),
+            6 => Some(ErrorKind::Cancelled),
+            _ => None,
+        }
+    }
+}
+
+impl ErrorCause {
+    /// Gets the kind of failure
+    /// that the cause is.
+    pub fn kind(&self) -> ErrorKind {
+        use ErrorCause as C;
+
+        match self {
+            C::InvalidSpec { .. } => ErrorKind::SpecInvalid,
+            C::MissingDependency { .. } | C::MissingLibraries { .. } => ErrorKind::DepsMissing,
+            C::RepoClone { .. } => ErrorKind::CloneFailed,
+            C::CompilationFailed { .. } => ErrorKind::CompileFailed,
+            C::Cancelled => ErrorKind::Cancelled,
+            _ => ErrorKind::Other,
+        }
+    }
+}
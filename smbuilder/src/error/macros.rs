@@ -103,7 +103,185 @@ macro_rules! c_spawn_cmd {
     };
 }
 
-pub use {c_comp_failed, c_fs, c_other, c_repo_clone, c_spawn_cmd};
+#[macro_export]
+/// Locked base directory error cause.
+///
+/// Rules:
+///  * `dir: PathBuf`
+///  * `dir: PathBuf, pid: Option<u32>`
+macro_rules! c_dir_locked {
+    ($dir:expr) => {
+        ErrorCause::BaseDirLocked {
+            dir: $dir,
+            pid: None,
+        }
+    };
+
+    ($dir:expr, $pid:expr) => {
+        ErrorCause::BaseDirLocked {
+            dir: $dir,
+            pid: $pid,
+        }
+    };
+}
+
+#[macro_export]
+/// Cancelled build error cause.
+macro_rules! c_cancelled {
+    () => {
+        ErrorCause::Cancelled
+    };
+}
+
+#[macro_export]
+/// Missing shared libraries error cause.
+///
+/// `path: PathBuf, libs: Vec<String>`
+macro_rules! c_missing_libs {
+    ($path:expr, $libs:expr) => {
+        ErrorCause::MissingLibraries {
+            path: $path,
+            libs: $libs,
+        }
+    };
+}
+
+#[macro_export]
+/// Download error cause.
+///
+/// Rules:
+///  * `url: String`
+///  * `url: String, ctx: impl std::error::Error`
+macro_rules! c_download {
+    ($url:expr) => {
+        ErrorCause::Download {
+            url: $url,
+            ctx: None,
+        }
+    };
+
+    ($url:expr, $ctx:expr) => {
+        ErrorCause::Download {
+            url: $url,
+            ctx: Some(Box::new($ctx)),
+        }
+    };
+}
+
+#[macro_export]
+/// Checksum mismatch error cause.
+///
+/// `target: String, expected: String, actual: String`
+macro_rules! c_checksum {
+    ($target:expr, $expected:expr, $actual:expr) => {
+        ErrorCause::ChecksumMismatch {
+            target: $target,
+            expected: $expected,
+            actual: $actual,
+        }
+    };
+}
+
+#[macro_export]
+/// Unwritable base directory error cause.
+///
+/// Rules:
+///  * `dir: PathBuf, ctx: std::io::Error`
+///  * same as above, but with `fallback: Option<PathBuf>`
+macro_rules! c_dir_not_writable {
+    ($dir:expr, $ctx:expr) => {
+        ErrorCause::BaseDirNotWritable {
+            dir: $dir,
+            fallback: None,
+            ctx: $ctx,
+        }
+    };
+
+    ($dir:expr, $ctx:expr, $fallback:expr) => {
+        ErrorCause::BaseDirNotWritable {
+            dir: $dir,
+            fallback: $fallback,
+            ctx: $ctx,
+        }
+    };
+}
+
+#[macro_export]
+/// Bad signature error cause.
+///
+/// `target: impl ToString`
+macro_rules! c_bad_signature {
+    ($target:expr) => {
+        ErrorCause::BadSignature {
+            target: $target.to_string(),
+        }
+    };
+}
+
+#[macro_export]
+/// Missing dependency error cause.
+///
+/// Rules:
+///  * `name: String`
+///  * `name: String, msg: impl ToString`
+macro_rules! c_missing_dep {
+    ($name:expr) => {
+        ErrorCause::MissingDependency {
+            name: $name,
+            msg: None,
+        }
+    };
+
+    ($name:expr, $msg:expr) => {
+        ErrorCause::MissingDependency {
+            name: $name,
+            msg: Some($msg.to_string()),
+        }
+    };
+}
+
+#[macro_export]
+/// Stage timed out error cause.
+///
+/// Rules:
+///  * `stage: impl ToString, after: Duration`
+///  * same as above but with `inactive: bool`
+macro_rules! c_timed_out {
+    ($stage:expr, $after:expr) => {
+        ErrorCause::StageTimedOut {
+            stage: $stage.to_string(),
+            after: $after,
+            inactive: false,
+        }
+    };
+
+    ($stage:expr, $after:expr, $inactive:expr) => {
+        ErrorCause::StageTimedOut {
+            stage: $stage.to_string(),
+            after: $after,
+            inactive: $inactive,
+        }
+    };
+}
+
+#[macro_export]
+/// Invalid spec error cause.
+///
+/// `field: impl ToString, reason: impl ToString`
+macro_rules! c_invalid_spec {
+    ($field:expr, $reason:expr) => {
+        ErrorCause::InvalidSpec {
+            field: $field.to_string(),
+            reason: $reason.to_string(),
+        }
+    };
+}
+
+pub use {
+    c_bad_signature, c_cancelled, c_checksum, c_comp_failed, c_dir_locked, c_dir_not_writable,
+    c_download, c_fs, c_invalid_spec, c_missing_dep, c_missing_libs, c_other, c_repo_clone,
+    c_spawn_cmd, c_timed_out,
+};
 
 #[macro_export]
 /// Instantiate an Error struct.
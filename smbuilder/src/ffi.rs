@@ -0,0 +1,510 @@
+//! A minimal C API, for launchers that are
+//! not written in rust to embed smbuilder.
+//!
+//! Build the library with
+//! `cargo rustc -p smbuilder --features ffi --crate-type cdylib`
+//! (or `staticlib`), and generate a header with
+//! `cbindgen --config cbindgen.toml`.
+//!
+//! A build is started on a thread of its own,
+//! and what it does is polled as events:
+//!
+//! ```c
+//! SmbBuilder *b = smb_builder_new("builds/sm64ex/build.yaml");
+//! if (!b) { fprintf(stderr, "%s\n", smb_last_error()); return 1; }
+//!
+//! smb_build_start(b);
+//!
+//! SmbEvent ev;
+//! for (;;) {
+//!     while (smb_poll_event(b, &ev)) {
+//!         if (ev.kind == SMB_EVENT_KIND_LOG) puts(ev.message);
+//!         if (ev.kind == SMB_EVENT_KIND_FINISHED || ev.kind == SMB_EVENT_KIND_FAILED) goto done;
+//!     }
+//!     sleep_ms(50);
+//! }
+//! done:
+//! smb_builder_free(b);
+//! ```
+
+use crate::builder::state::BuildStateHandle;
+use crate::prelude::callback_types::{BuildEvent, LogType};
+use crate::prelude::{channel_bridge_to, Builder, Spec};
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// What an event is about.
+pub enum SmbEventKind {
+    /// A line was logged.
+    Log,
+    /// A new setup stage started. The
+    /// message is the label of the stage.
+    SetupStage,
+    /// A new post-build stage started. The
+    /// message is the label of the stage.
+    PostBuildStage,
+    /// A post-build script is being run. The
+    /// message is the name of the script.
+    PostBuildScript,
+    /// The repo is being cloned, `percent`
+    /// of the way through.
+    CloneProgress,
+    /// A pack is being installed, `percent`
+    /// of the way through. The message is
+    /// the name of the pack.
+    PackInstallProgress,
+    /// The build finished. The message is
+    /// the path of the executable.
+    Finished,
+    /// The build failed (or was cancelled).
+    /// The message is the error.
+    Failed,
+    /// A new custom stage started. The
+    /// message is the id of the stage.
+    CustomStage,
+    /// The port is being compiled,
+    /// `percent` of the way through.
+    CompileProgress,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The level of a logged line.
+pub enum SmbLogLevel {
+    /// Not a logged line.
+    None,
+    /// An error.
+    Error,
+    /// A warning.
+    Warn,
+    /// Some info.
+    Info,
+    /// Details, such as
+    /// the commands run.
+    Debug,
+    /// A line of output
+    /// from the build.
+    BuildOutput,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+/// An event of a build, filled
+/// in by `smb_poll_event`.
+pub struct SmbEvent {
+    /// What the event is about.
+    pub kind: SmbEventKind,
+    /// The level, for `Log` events.
+    pub level: SmbLogLevel,
+    /// How far along the stage is, from
+    /// 0 to 100, or -1 if it is not known.
+    pub percent: f32,
+    /// The text of the event, which stays valid
+    /// until the next call to `smb_poll_event`
+    /// or `smb_builder_free`. Never null.
+    pub message: *const c_char,
+}
+
+struct OwnedEvent {
+    kind: SmbEventKind,
+    level: SmbLogLevel,
+    percent: f32,
+    message: String,
+}
+
+/// A builder, and the build
+/// that it may be running.
+///
+/// Opaque to C.
+pub struct SmbBuilder {
+    builder: Option<Builder<'static>>,
+    state: BuildStateHandle,
+    events: Receiver<OwnedEvent>,
+    sender: Sender<OwnedEvent>,
+    thread: Option<JoinHandle<()>>,
+    message: CString,
+}
+
+fn set_last_error(msg: String) {
+    let msg = CString::new(msg.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+impl From<BuildEvent> for OwnedEvent {
+    fn from(event: BuildEvent) -> Self {
+        let percent = |done: usize, total: usize| {
+            if total == 0 {
+                -1.0
+            } else {
+                (done * 100) as f32 / total as f32
+            }
+        };
+
+        let (kind, level, percent, message) = match event {
+            BuildEvent::Log { log_type, text } => {
+                let level = match log_type {
+                    LogType::Error => SmbLogLevel::Error,
+                    LogType::Warn => SmbLogLevel::Warn,
+                    LogType::Info => SmbLogLevel::Info,
+                    LogType::Debug => SmbLogLevel::Debug,
+                    LogType::BuildOutput { .. } => SmbLogLevel::BuildOutput,
+                };
+                (SmbEventKind::Log, level, -1.0, text)
+            }
+            BuildEvent::SetupStage(stage) => (
+                SmbEventKind::SetupStage,
+                SmbLogLevel::None,
+                -1.0,
+                stage.label().to_owned(),
+            ),
+            BuildEvent::PostBuildStage(stage) => (
+                SmbEventKind::PostBuildStage,
+                SmbLogLevel::None,
+                -1.0,
+                stage.label().to_owned(),
+            ),
+            BuildEvent::CustomStage(id) => (SmbEventKind::CustomStage, SmbLogLevel::None, -1.0, id),
+            BuildEvent::PostBuildScript { name, .. } => {
+                (SmbEventKind::PostBuildScript, SmbLogLevel::None, -1.0, name)
+            }
+            BuildEvent::RepoCloneProgress {
+                received, total, ..
+            } => (
+                SmbEventKind::CloneProgress,
+                SmbLogLevel::None,
+                percent(received, total),
+                String::new(),
+            ),
+            BuildEvent::CompileProgress { percent } => (
+                SmbEventKind::CompileProgress,
+                SmbLogLevel::None,
+                percent,
+                String::new(),
+            ),
+            BuildEvent::PackInstallProgress {
+                pack,
+                installed,
+                total,
+                ..
+            } => (
+                SmbEventKind::PackInstallProgress,
+                SmbLogLevel::None,
+                percent(installed, total),
+                pack,
+            ),
+        };
+
+        OwnedEvent {
+            kind,
+            level,
+            percent,
+            message,
+        }
+    }
+}
+
+/// Gets the directory of a spec, resolving a
+/// relative path against the current directory,
+/// as a bare file name has an empty parent.
+fn base_dir_of(spec_path: &Path) -> io::Result<PathBuf> {
+    let spec_path = std::path::absolute(spec_path)?;
+
+    Ok(spec_path.parent().map(Path::to_owned).unwrap_or_default())
+}
+
+/// Gets the error of the last call on this
+/// thread that failed, or null if there
+/// was none.
+///
+/// The string stays valid until the next
+/// call that fails on this thread.
+#[no_mangle]
+pub extern "C" fn smb_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| match &*e.borrow() {
+        Some(msg) => msg.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Create a builder for the spec at `spec_path`,
+/// building in the directory of the spec. A
+/// relative path is relative to the current
+/// directory.
+///
+/// Returns null if the spec could not be
+/// loaded or the directory could not be
+/// locked; see `smb_last_error`.
+///
+/// # Safety
+///
+/// `spec_path` must be a valid,
+/// nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn smb_builder_new(spec_path: *const c_char) -> *mut SmbBuilder {
+    if spec_path.is_null() {
+        set_last_error("the spec path is null".to_owned());
+        return ptr::null_mut();
+    }
+
+    let spec_path = PathBuf::from(CStr::from_ptr(spec_path).to_string_lossy().into_owned());
+    let base_dir = match base_dir_of(&spec_path) {
+        Ok(dir) => dir,
+        Err(e) => {
+            set_last_error(format!("failed to resolve {}: {}", spec_path.display(), e));
+            return ptr::null_mut();
+        }
+    };
+
+    let (sender, events) = mpsc::channel();
+    let mut callbacks = channel_bridge_to(sender.clone());
+
+    let builder = Spec::from_file_checked(&spec_path, &mut callbacks)
+        .and_then(|spec| Builder::new(spec, base_dir, callbacks));
+
+    match builder {
+        Ok(builder) => Box::into_raw(Box::new(SmbBuilder {
+            state: builder.state_handle(),
+            builder: Some(builder),
+            events,
+            sender,
+            thread: None,
+            message: CString::default(),
+        })),
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Start building, on a thread of its own.
+///
+/// Returns false if the builder has
+/// already been started.
+///
+/// # Safety
+///
+/// `builder` must have come from
+/// `smb_builder_new`, and not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn smb_build_start(builder: *mut SmbBuilder) -> bool {
+    let Some(handle) = builder.as_mut() else {
+        return false;
+    };
+    let Some(mut builder) = handle.builder.take() else {
+        set_last_error("the build has already been started".to_owned());
+        return false;
+    };
+
+    let sender = handle.sender.clone();
+    handle.thread = Some(thread::spawn(move || {
+        let event = match builder.build() {
+            Ok(build) => OwnedEvent {
+                kind: SmbEventKind::Finished,
+                level: SmbLogLevel::None,
+                percent: 100.0,
+                message: build.executable_path().display().to_string(),
+            },
+            Err(e) => OwnedEvent {
+                kind: SmbEventKind::Failed,
+                level: SmbLogLevel::None,
+                percent: -1.0,
+                message: e.to_string(),
+            },
+        };
+        let _ = sender.send(event);
+    }));
+
+    true
+}
+
+/// Take the next event of the build into
+/// `event`, without waiting for one.
+///
+/// Returns false if there was none.
+///
+/// # Safety
+///
+/// `builder` must have come from
+/// `smb_builder_new`, and not been freed,
+/// and `event` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn smb_poll_event(builder: *mut SmbBuilder, event: *mut SmbEvent) -> bool {
+    let (Some(handle), false) = (builder.as_mut(), event.is_null()) else {
+        return false;
+    };
+    // the result is sent on the same channel, after
+    // every other event of the build, so it is
+    // always polled last.
+    let Ok(next) = handle.events.try_recv() else {
+        return false;
+    };
+
+    handle.message = CString::new(next.message.replace('\0', "")).unwrap_or_default();
+    event.write(SmbEvent {
+        kind: next.kind,
+        level: next.level,
+        percent: next.percent,
+        message: handle.message.as_ptr(),
+    });
+
+    true
+}
+
+/// Ask the build to stop. It fails with
+/// a `Failed` event once it has.
+///
+/// # Safety
+///
+/// `builder` must have come from
+/// `smb_builder_new`, and not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn smb_cancel(builder: *mut SmbBuilder) {
+    if let Some(handle) = builder.as_ref() {
+        handle.state.cancel();
+    }
+}
+
+/// Free a builder, cancelling its build
+/// and waiting for it to stop if it
+/// is still running.
+///
+/// # Safety
+///
+/// `builder` must have come from
+/// `smb_builder_new` (or be null),
+/// and not already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn smb_builder_free(builder: *mut SmbBuilder) {
+    if builder.is_null() {
+        return;
+    }
+
+    let mut handle = Box::from_raw(builder);
+    handle.state.cancel();
+
+    if let Some(thread) = handle.thread.take() {
+        let _ = thread.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::backend::mock::{MemoryFs, RecordingRunner};
+    use crate::lockfile::{LockedRepo, Lockfile};
+    use crate::prelude::builder_types::BuildOptions;
+
+    const SPEC: &str = r#"
+rom:
+  region: us
+  path: /roms/baserom.us.z64
+  format: rom_BigEndian
+repo:
+  name: sm64ex
+  url: https://github.com/sm64pc/sm64ex
+  branch: nightly
+  about: ""
+  supports_dynos: false
+name: sm64ex
+# there is no good dump of the ROM to copy.
+skip_stages:
+  - copy_rom
+"#;
+
+    /// A builder of a port that is already cloned
+    /// and built, on a `MemoryFs`, as C would get
+    /// it from `smb_builder_new`.
+    fn smb_builder() -> *mut SmbBuilder {
+        let fs = MemoryFs::default();
+        let base_dir = std::env::temp_dir().join("smbuilder-ffi");
+        let repo_dir = base_dir.join("sm64ex");
+
+        fs.add_file(repo_dir.join(".git"), b"gitdir: elsewhere");
+        fs.add_file(
+            repo_dir.join("build/us_pc/sm64.us.f3dex2e"),
+            b"not really a game",
+        );
+        let lockfile = Lockfile {
+            repo: Some(LockedRepo {
+                complete: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        lockfile.save_to(&fs, &base_dir).unwrap();
+
+        let (sender, events) = mpsc::channel();
+        let spec: Spec = serde_yaml::from_str(SPEC).unwrap();
+        let builder = Builder::new_with_fs(spec, &base_dir, channel_bridge_to(sender.clone()), fs)
+            .unwrap()
+            .with_runner(RecordingRunner::default())
+            .with_options(BuildOptions::new().force(true));
+
+        Box::into_raw(Box::new(SmbBuilder {
+            state: builder.state_handle(),
+            builder: Some(builder),
+            events,
+            sender,
+            thread: None,
+            message: CString::default(),
+        }))
+    }
+
+    #[test]
+    fn polls_the_result_after_the_events() {
+        let builder = smb_builder();
+
+        unsafe {
+            assert!(smb_build_start(builder));
+            assert!(!smb_build_start(builder));
+
+            if let Some(thread) = (*builder).thread.take() {
+                thread.join().unwrap();
+            }
+
+            let mut kinds = Vec::new();
+            let mut event = std::mem::MaybeUninit::<SmbEvent>::uninit();
+            while smb_poll_event(builder, event.as_mut_ptr()) {
+                let event = event.assume_init_ref();
+                assert!(!event.message.is_null());
+                kinds.push(event.kind);
+            }
+
+            assert!(kinds.contains(&SmbEventKind::SetupStage));
+            assert_eq!(kinds.last(), Some(&SmbEventKind::Finished));
+            assert_eq!(
+                kinds
+                    .iter()
+                    .filter(|k| **k == SmbEventKind::Finished)
+                    .count(),
+                1
+            );
+
+            smb_builder_free(builder);
+        }
+    }
+
+    #[test]
+    fn resolves_a_bare_file_name() {
+        let cwd = std::env::current_dir().unwrap();
+
+        assert_eq!(base_dir_of(Path::new("build.yaml")).unwrap(), cwd);
+        assert_eq!(
+            base_dir_of(Path::new("builds/sm64ex/build.yaml")).unwrap(),
+            cwd.join("builds/sm64ex")
+        );
+    }
+}
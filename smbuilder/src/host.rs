@@ -0,0 +1,198 @@
+use crate::builder::backend::{self, Command, CommandRunner, SystemRunner};
+use crate::prelude::Makeopt;
+
+use std::path::Path;
+use std::{env, fs};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The display server of the host.
+pub enum DisplayServer {
+    /// A Wayland compositor.
+    Wayland,
+    /// An X11 server.
+    X11,
+    /// Neither, or a platform without
+    /// a separate display server.
+    Other,
+}
+
+#[derive(Clone, Debug)]
+/// What the host that smbuilder is running
+/// on is capable of, used to pick the
+/// rendering, windowing and audio backends
+/// that a port is built with.
+pub struct HostCapabilities {
+    /// The display server in use.
+    pub display_server: DisplayServer,
+    /// The OpenGL version reported by the
+    /// driver, as `(major, minor)`, if it
+    /// could be detected.
+    pub gl_version: Option<(u32, u32)>,
+    /// Whether SDL2 is installed.
+    pub has_sdl2: bool,
+    /// Whether SDL 1.2 is installed.
+    pub has_sdl1: bool,
+    /// Whether Direct3D is available (Windows).
+    pub has_d3d: bool,
+    /// Whether smbuilder is running under the
+    /// Windows Subsystem for Linux.
+    pub wsl: bool,
+    /// Whether WSLg (the WSL GUI support)
+    /// is available, if running under WSL.
+    pub wslg: bool,
+}
+
+impl HostCapabilities {
+    /// Probe the host.
+    ///
+    /// Anything that cannot be detected is
+    /// assumed to be present, so that the
+    /// defaults stay the same as on a
+    /// regular desktop.
+    pub fn detect() -> Self {
+        Self::detect_with(&SystemRunner)
+    }
+
+    /// Probe the host, like `detect`, running
+    /// `pkg-config` and `glxinfo` with `runner`.
+    pub fn detect_with(runner: &dyn CommandRunner) -> Self {
+        let display_server = if env::var_os("WAYLAND_DISPLAY").is_some() {
+            DisplayServer::Wayland
+        } else if env::var_os("DISPLAY").is_some() {
+            DisplayServer::X11
+        } else {
+            DisplayServer::Other
+        };
+
+        let run = |program: &str, args: &[&str]| {
+            let command = args
+                .iter()
+                .fold(Command::new(program), |command, arg| command.arg(*arg));
+            backend::probe(runner, command)
+        };
+        let has_program = |program: &str| run(program, &["--version"]).is_some();
+        let pkg_config_has =
+            |lib: &str| run("pkg-config", &["--exists", lib]).is_some_and(|(ok, _)| ok);
+
+        let can_probe_sdl = has_program("pkg-config");
+        let has_sdl2 = !can_probe_sdl || pkg_config_has("sdl2") || has_program("sdl2-config");
+        let has_sdl1 = (can_probe_sdl && pkg_config_has("sdl")) || has_program("sdl-config");
+
+        let wsl = is_wsl();
+
+        HostCapabilities {
+            display_server,
+            gl_version: run("glxinfo", &["-B"]).and_then(|(_, lines)| parse_gl_version(&lines)),
+            has_sdl2,
+            has_sdl1,
+            has_d3d: cfg!(target_os = "windows"),
+            wsl,
+            wslg: wsl && Path::new("/mnt/wslg").exists(),
+        }
+    }
+
+    /// Whether the host can only run the
+    /// legacy (fixed-function) OpenGL renderer.
+    pub fn needs_legacy_gl(&self) -> bool {
+        matches!(self.gl_version, Some((major, minor)) if major < 2 || (major == 2 && minor < 1))
+    }
+
+    /// Get warnings about pitfalls of building
+    /// in `base_dir` on this host.
+    pub fn warnings<P: AsRef<Path>>(&self, base_dir: P) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if !self.wsl {
+            return warnings;
+        }
+
+        let base_dir = base_dir.as_ref();
+        let base_dir = base_dir.canonicalize().unwrap_or(base_dir.to_owned());
+
+        if is_windows_drive_mount(&base_dir) {
+            warnings.push(format!(
+                "{} is on a Windows drive; building there under WSL is very slow, consider a directory in the Linux filesystem (such as ~/smbuilder)",
+                base_dir.display()
+            ));
+        }
+
+        if !self.wslg {
+            warnings.push(
+                "WSLg was not found, so a native Linux build may not be able to open a window; consider upgrading WSL, or cross-compiling with the WINDOWS_BUILD=1 makeopt".to_owned(),
+            );
+        }
+
+        warnings
+    }
+
+    /// Get the `RENDER_API`, `WINDOW_API`,
+    /// `AUDIO_API` and `CONTROLLER_API` makeopts
+    /// that suit this host.
+    pub fn api_makeopts(&self) -> Vec<Makeopt> {
+        let (render, window, audio, controller) = if self.has_d3d {
+            ("D3D11", "DXGI", "SDL2", "SDL2")
+        } else if !self.has_sdl2 && self.has_sdl1 {
+            // the SDL1 window backend only
+            // works with the legacy renderer.
+            ("GL_LEGACY", "SDL1", "SDL1", "SDL1")
+        } else if self.needs_legacy_gl() {
+            ("GL_LEGACY", "SDL2", "SDL2", "SDL2")
+        } else {
+            ("GL", "SDL2", "SDL2", "SDL2")
+        };
+
+        vec![
+            Makeopt::new("RENDER_API", render),
+            Makeopt::new("WINDOW_API", window),
+            Makeopt::new("AUDIO_API", audio),
+            Makeopt::new("CONTROLLER_API", controller),
+        ]
+    }
+}
+
+/// Whether the process is running under WSL.
+pub fn is_wsl() -> bool {
+    if env::var_os("WSL_DISTRO_NAME").is_some() {
+        return true;
+    }
+
+    fs::read_to_string("/proc/sys/kernel/osrelease")
+        .map(|release| release.to_lowercase().contains("microsoft"))
+        .unwrap_or(false)
+}
+
+/// Whether a path is on a Windows drive
+/// mounted by WSL (like `/mnt/c`).
+fn is_windows_drive_mount(path: &Path) -> bool {
+    let mut components = path.components().skip(1);
+
+    let is_mnt = components
+        .next()
+        .map(|c| c.as_os_str() == "mnt")
+        .unwrap_or(false);
+    let is_drive = components
+        .next()
+        .and_then(|c| c.as_os_str().to_str().map(|s| s.to_owned()))
+        .map(|s| s.len() == 1 && s.chars().all(|c| c.is_ascii_alphabetic()))
+        .unwrap_or(false);
+
+    is_mnt && is_drive
+}
+
+/// Find the OpenGL version in
+/// the output of `glxinfo -B`.
+fn parse_gl_version(lines: &[String]) -> Option<(u32, u32)> {
+    // prefer the core profile version, as
+    // the compat one is capped on mesa
+    let line = lines
+        .iter()
+        .find(|l| l.contains("core profile version string"))
+        .or_else(|| lines.iter().find(|l| l.contains("OpenGL version string")))?;
+
+    let version = line.split(':').nth(1)?.split_whitespace().next()?;
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+
+    Some((major, minor))
+}
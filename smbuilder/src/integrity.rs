@@ -0,0 +1,130 @@
+use crate::builder::backend::{FsProvider, RealFs};
+use crate::prelude::{DynosPack, Spec};
+use crate::util;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// The SHA-256 hashes of the files that a
+/// build produced and installed: the executable
+/// and the files of its packs.
+///
+/// Kept in the lockfile, to tell when the
+/// installation has been modified since.
+pub struct Manifest {
+    /// The hashes, by the path of the
+    /// file relative to the base dir.
+    pub files: BTreeMap<PathBuf, String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// The files of a build that no longer
+/// match its manifest.
+pub struct IntegrityReport {
+    /// Files that have been changed.
+    pub modified: Vec<PathBuf>,
+    /// Files that have been removed.
+    pub missing: Vec<PathBuf>,
+}
+
+impl IntegrityReport {
+    /// Whether every file still
+    /// matches the manifest.
+    pub fn is_intact(&self) -> bool {
+        self.modified.is_empty() && self.missing.is_empty()
+    }
+}
+
+impl Manifest {
+    /// Hash the executable and the installed
+    /// packs of a build of `spec`.
+    ///
+    /// The files of the texture pack are found
+    /// from the pack itself, as the directory it
+    /// is installed into also has the files of
+    /// the port. Packs that are no longer on
+    /// disk are left out.
+    pub fn create(spec: &Spec, base_dir: &Path, executable: &Path) -> io::Result<Manifest> {
+        Manifest::create_with_fs(&RealFs, spec, base_dir, executable)
+    }
+
+    /// Hash the executable and the installed
+    /// packs of a build on `fs`, like `create`.
+    pub fn create_with_fs(
+        fs: &dyn FsProvider,
+        spec: &Spec,
+        base_dir: &Path,
+        executable: &Path,
+    ) -> io::Result<Manifest> {
+        let repo_dir = base_dir.join(&spec.repo.name);
+        let mut files = vec![executable.to_owned()];
+
+        if let Some(pack) = &spec.texture_pack {
+            let gfx = pack.path.join("gfx");
+            let installed = spec.build_dir(&repo_dir).join("res").join("gfx");
+            files.extend(installed_files(&gfx, &installed)?);
+        }
+
+        if spec.repo.has_dynos() {
+            let packs_dir = DynosPack::install_dir(spec, &repo_dir);
+
+            for pack in spec.dynos_packs.iter().flatten() {
+                let installed = packs_dir.join(pack.path.file_name().unwrap_or_default());
+                files.extend(installed_files(&installed, &installed)?);
+            }
+        }
+
+        let mut manifest = Manifest::default();
+
+        for path in files {
+            let relative = path.strip_prefix(base_dir).unwrap_or(&path).to_owned();
+            manifest
+                .files
+                .insert(relative, util::sha256_read_hex(fs.open(&path)?)?);
+        }
+
+        Ok(manifest)
+    }
+
+    /// Check the files of a build in
+    /// `base_dir` against the manifest.
+    pub fn verify(&self, base_dir: &Path) -> IntegrityReport {
+        self.verify_with_fs(&RealFs, base_dir)
+    }
+
+    /// Check the files of a build in `base_dir`
+    /// on `fs` against the manifest.
+    pub fn verify_with_fs(&self, fs: &dyn FsProvider, base_dir: &Path) -> IntegrityReport {
+        let mut report = IntegrityReport::default();
+
+        for (relative, hash) in &self.files {
+            let path = base_dir.join(relative);
+            match fs.open(&path).and_then(util::sha256_read_hex) {
+                Ok(actual) if &actual == hash => (),
+                Ok(_) => report.modified.push(relative.clone()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    report.missing.push(relative.clone())
+                }
+                Err(_) => report.modified.push(relative.clone()),
+            }
+        }
+
+        report
+    }
+}
+
+/// List the files of `source` as they
+/// are installed under `installed`.
+pub(crate) fn installed_files(source: &Path, installed: &Path) -> io::Result<Vec<PathBuf>> {
+    if !source.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut files = Vec::new();
+    util::list_files(source, Path::new(""), &mut files)?;
+
+    Ok(files.into_iter().map(|f| installed.join(f)).collect())
+}
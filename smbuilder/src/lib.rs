@@ -46,4 +46,76 @@ pub mod callbacks;
 /// Utility Functions.
 pub mod util;
 
-use prelude::{builder_types, callback_types, romconvert};
+/// Downloading resources over the network.
+#[cfg(feature = "remote")]
+pub mod net;
+
+/// Distributable spec bundles.
+#[cfg(feature = "bundle")]
+pub mod bundle;
+
+/// Signing specs and bundles, so that
+/// users can check that they have not
+/// been tampered with.
+#[cfg(feature = "signing")]
+pub mod signing;
+
+/// A C API, for embedding smbuilder
+/// in launchers written in other
+/// languages.
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+/// A background service that runs builds
+/// for launchers, over a Unix socket or HTTP.
+#[cfg(feature = "daemon")]
+pub mod daemon;
+
+/// A terminal UI for builds.
+#[cfg(feature = "tui")]
+pub mod tui;
+
+/// Detection of the programs and
+/// libraries that builds depend on.
+pub mod deps;
+
+/// Detection of what the host
+/// is capable of running.
+pub mod host;
+
+/// Hiding secrets (such as access tokens
+/// in URLs) from logs and errors.
+pub mod redact;
+
+/// ROM format conversion, re-exporting
+/// the `n64romconvert` crate.
+pub mod romconvert;
+
+/// The lockfile, which records the
+/// on-disk state of a build.
+pub mod lockfile;
+
+/// The global configuration file.
+pub mod config;
+
+/// The platform's directories for
+/// data, caches and config.
+pub mod paths;
+
+/// Checking that the installed files
+/// of a build have not been modified.
+pub mod integrity;
+
+/// Laying texture packs out the way
+/// that ports expect them.
+pub mod texturepack;
+
+/// Managing directories of builds,
+/// for launchers.
+pub mod manage;
+
+/// An index over a directory
+/// of many builds.
+pub mod workspace;
+
+use prelude::{builder_types, callback_types};
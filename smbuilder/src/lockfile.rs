@@ -0,0 +1,340 @@
+use crate::builder::backend::{FsProvider, RealFs};
+use crate::callback_types::DirtyRepoAction;
+use crate::error::ErrorCause;
+use crate::integrity::Manifest;
+use crate::prelude::builder_types::{BuilderResult, PostBuildStage};
+use crate::prelude::error_macros::*;
+use crate::prelude::{Error, Makeopt};
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The name of the lockfile that
+/// lives in the root of a base dir.
+pub const LOCKFILE_NAME: &str = "lockfile.yaml";
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// Records the state of a build on disk,
+/// so that later runs can pick up where
+/// the previous one left off.
+///
+/// This is written by the builder and
+/// should not be edited by hand.
+pub struct Lockfile {
+    /// The state of the cloned repository,
+    /// if it has been cloned at all.
+    pub repo: Option<LockedRepo>,
+    /// Hashes of the parts of the spec that
+    /// the last successful build was built
+    /// with.
+    pub spec_hashes: Option<SpecHashes>,
+    /// The makeopts that the last successful
+    /// build was built with.
+    #[serde(default)]
+    pub makeopts: Option<Vec<Makeopt>>,
+    /// What the named configurations of the
+    /// spec were last built with, by name.
+    #[serde(default)]
+    pub configs: BTreeMap<String, LockedBuild>,
+    /// The checksum of the prebuilt release
+    /// that is unpacked, for prebuilt ports.
+    #[serde(default)]
+    pub prebuilt: Option<String>,
+    /// What the last reproducible build
+    /// was built from, and what it built.
+    #[serde(default)]
+    pub reproducible: Option<ReproducibleBuild>,
+    /// The hashes of the files that the
+    /// last successful build installed.
+    #[serde(default)]
+    pub manifest: Option<Manifest>,
+    /// The hash of the save files that were
+    /// last installed (see `SaveFiles::hash`).
+    #[serde(default)]
+    pub save_files: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// What a build of a spec (or of one of
+/// its named configurations) was last
+/// successfully built with.
+pub struct LockedBuild {
+    /// The hashes of the spec.
+    pub spec_hashes: Option<SpecHashes>,
+    /// The makeopts.
+    pub makeopts: Option<Vec<Makeopt>>,
+    /// What it was built from, and what it
+    /// built, if it was a reproducible build.
+    #[serde(default)]
+    pub reproducible: Option<ReproducibleBuild>,
+    /// The hashes of the files it installed.
+    #[serde(default)]
+    pub manifest: Option<Manifest>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// Everything that a reproducible build was
+/// built from, along with the hash of the
+/// executable it built, for later builds to
+/// be checked against.
+pub struct ReproducibleBuild {
+    /// The commit that was built.
+    pub commit: Option<String>,
+    /// What `SOURCE_DATE_EPOCH` was set to.
+    pub source_date_epoch: Option<i64>,
+    /// The SHA-256 hash of the build script.
+    pub build_script: String,
+    /// The versions of the tools that
+    /// built it (make and the compiler).
+    pub toolchain: Vec<String>,
+    /// The SHA-256 hash of the executable,
+    /// before the post-build stages.
+    pub executable: String,
+}
+
+impl ReproducibleBuild {
+    /// Whether two builds were built from
+    /// the same things, and so should have
+    /// built the same executable.
+    pub fn same_inputs(&self, other: &ReproducibleBuild) -> bool {
+        self.commit == other.commit
+            && self.source_date_epoch == other.source_date_epoch
+            && self.build_script == other.build_script
+            && self.toolchain == other.toolchain
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// Hashes of the parts of a spec that
+/// affect different stages of the build,
+/// used to work out which stages need to be
+/// re-run when the spec changes.
+pub struct SpecHashes {
+    /// Everything that ends up in the
+    /// build script (makeopts, jobs).
+    pub build_script: String,
+    /// The patches.
+    #[serde(default)]
+    pub patches: String,
+    /// The texture pack and DynOS packs.
+    pub packs: String,
+    /// The scripts that run at the end
+    /// of the build.
+    pub scripts: String,
+}
+
+impl SpecHashes {
+    /// Whether the changes between two sets
+    /// of hashes mean that the port needs
+    /// to be recompiled.
+    pub fn needs_recompile(&self, other: &SpecHashes) -> bool {
+        self.build_script != other.build_script || self.patches != other.patches
+    }
+
+    /// Gets the post-build stages whose part
+    /// of the spec is the same in both sets
+    /// of hashes, which do not need to run
+    /// again if the port was not recompiled.
+    pub fn unchanged_stages(&self, other: &SpecHashes) -> Vec<PostBuildStage> {
+        let mut stages = Vec::new();
+
+        if self.packs == other.packs {
+            stages.extend([PostBuildStage::TexturePack, PostBuildStage::DynOSPacks]);
+        }
+        if self.scripts == other.scripts {
+            stages.push(PostBuildStage::PostBuildScripts);
+        }
+
+        stages
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// The state of a cloned repository.
+pub struct LockedRepo {
+    /// The URL the repo was cloned from.
+    pub url: String,
+    /// The branch that was cloned.
+    pub branch: String,
+    /// The commit that was checked out,
+    /// if the clone has completed.
+    pub commit: Option<String>,
+    /// Whether the clone has completed.
+    ///
+    /// An interrupted clone is kept on disk
+    /// with this set to `false`, so that the
+    /// next run resumes it.
+    pub complete: bool,
+    /// Whether the Git LFS objects have
+    /// been fetched, for repos that use it.
+    #[serde(default)]
+    pub lfs_fetched: bool,
+    /// The tag that `latest-release`
+    /// was resolved to, if it was used.
+    #[serde(default)]
+    pub resolved_tag: Option<String>,
+    /// What was done with the changes to the
+    /// files of the repo the last time that
+    /// they were found, before an update or
+    /// a rebuild.
+    #[serde(default)]
+    pub dirty_action: Option<DirtyRepoAction>,
+}
+
+impl Lockfile {
+    /// Gets the path of the lockfile
+    /// within a base directory.
+    pub fn path<P: AsRef<Path>>(base_dir: P) -> PathBuf {
+        base_dir.as_ref().join(LOCKFILE_NAME)
+    }
+
+    /// Loads the lockfile from a base directory,
+    /// returning an empty lockfile if there
+    /// is none yet.
+    pub fn load<P: AsRef<Path>>(base_dir: P) -> BuilderResult<Lockfile> {
+        Lockfile::load_from(&RealFs, base_dir)
+    }
+
+    /// Loads the lockfile from a base
+    /// directory on `fs`, like `load`.
+    pub fn load_from<P: AsRef<Path>>(fs: &dyn FsProvider, base_dir: P) -> BuilderResult<Lockfile> {
+        let path = Lockfile::path(base_dir);
+
+        if !fs.exists(&path) {
+            return Ok(Lockfile::default());
+        }
+
+        let file_bytes = match fs.read(&path) {
+            Ok(b) => b,
+            Err(e) => {
+                let msg = format!("failed to read {}", path.display());
+                return Err(err!(c_fs!(e, msg), "whilst loading the lockfile"));
+            }
+        };
+
+        match serde_yaml::from_slice::<Lockfile>(&file_bytes) {
+            Ok(l) => Ok(l),
+            Err(e) => Err(err!(c_other!(e), "failed to parse the lockfile")),
+        }
+    }
+
+    /// Writes the lockfile into a base directory.
+    pub fn save<P: AsRef<Path>>(&self, base_dir: P) -> BuilderResult<()> {
+        self.save_to(&RealFs, base_dir)
+    }
+
+    /// Writes the lockfile into a base
+    /// directory on `fs`, like `save`.
+    pub fn save_to<P: AsRef<Path>>(&self, fs: &dyn FsProvider, base_dir: P) -> BuilderResult<()> {
+        let path = Lockfile::path(base_dir);
+
+        let contents = match serde_yaml::to_string(self) {
+            Ok(s) => s,
+            Err(e) => return Err(err!(c_other!(e), "failed to serialize the lockfile")),
+        };
+
+        match fs.write(&path, contents.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let msg = format!("failed to write {}", path.display());
+                Err(err!(c_fs!(e, msg), "whilst saving the lockfile"))
+            }
+        }
+    }
+
+    /// Gets what the default build, or the named
+    /// configuration `config`, was last built with.
+    pub fn locked_build(&self, config: Option<&str>) -> LockedBuild {
+        match config {
+            Some(name) => self.configs.get(name).cloned().unwrap_or_default(),
+            None => LockedBuild {
+                spec_hashes: self.spec_hashes.clone(),
+                makeopts: self.makeopts.clone(),
+                reproducible: self.reproducible.clone(),
+                manifest: self.manifest.clone(),
+            },
+        }
+    }
+
+    /// Records what the default build, or the named
+    /// configuration `config`, was built with.
+    pub fn set_locked_build(&mut self, config: Option<&str>, build: LockedBuild) {
+        match config {
+            Some(name) => {
+                self.configs.insert(name.to_owned(), build);
+            }
+            None => {
+                self.spec_hashes = build.spec_hashes;
+                self.makeopts = build.makeopts;
+                self.reproducible = build.reproducible;
+                self.manifest = build.manifest;
+            }
+        }
+    }
+
+    /// Whether the build script hash differs
+    /// from the one recorded by the last
+    /// successful build (of the named
+    /// configuration `config`, if given).
+    ///
+    /// Returns `false` if nothing was recorded.
+    pub fn build_script_changed(&self, config: Option<&str>, current: &SpecHashes) -> bool {
+        self.locked_build(config)
+            .spec_hashes
+            .map(|h| h.build_script != current.build_script)
+            .unwrap_or(false)
+    }
+
+    /// Whether the patches differ from the ones
+    /// that the last successful build (of the
+    /// named configuration `config`, if given)
+    /// was made with.
+    ///
+    /// Returns `true` if nothing was recorded,
+    /// as they may not have been applied yet.
+    pub fn patches_changed(&self, config: Option<&str>, current: &SpecHashes) -> bool {
+        self.locked_build(config)
+            .spec_hashes
+            .map(|h| h.patches != current.patches)
+            .unwrap_or(true)
+    }
+
+    /// Gets the manifests of the default
+    /// build and every named configuration.
+    pub fn manifests(&self) -> impl Iterator<Item = &Manifest> {
+        self.manifest
+            .iter()
+            .chain(self.configs.values().filter_map(|b| b.manifest.as_ref()))
+    }
+
+    /// Forget `files` (relative to the base dir)
+    /// in the manifests of every build, once
+    /// they have been removed on purpose.
+    pub(crate) fn forget_files(&mut self, files: &[PathBuf]) {
+        let manifests = self.manifest.iter_mut().chain(
+            self.configs
+                .values_mut()
+                .filter_map(|b| b.manifest.as_mut()),
+        );
+
+        for manifest in manifests {
+            for file in files {
+                manifest.files.remove(file);
+            }
+        }
+    }
+
+    /// Whether the Git LFS objects of
+    /// the repo have been fetched.
+    pub fn lfs_fetched(&self) -> bool {
+        self.repo.as_ref().map(|r| r.lfs_fetched).unwrap_or(false)
+    }
+
+    /// Whether a previous run started
+    /// cloning the repo, but never
+    /// finished.
+    pub fn repo_incomplete(&self) -> bool {
+        self.repo.as_ref().map(|r| !r.complete).unwrap_or(false)
+    }
+}
@@ -0,0 +1,656 @@
+use crate::builder::lock::BaseDirLock;
+use crate::builder::lock::LOCK_FILE_NAME;
+use crate::builder::logfile::LOG_DIR_NAME;
+use crate::builder::state::{PROGRESS_FILE_NAME, PROGRESS_JSON_FILE_NAME};
+use crate::config::Config;
+use crate::error::ErrorCause;
+use crate::integrity::{self, IntegrityReport};
+use crate::lockfile::{Lockfile, LOCKFILE_NAME};
+use crate::prelude::builder_types::BuilderResult;
+use crate::prelude::error_macros::*;
+use crate::prelude::{DynosPack, Error, Spec};
+use crate::types::pack::{self, PackManifest};
+use crate::util;
+
+use git2::Repository;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// The name of the spec file that
+/// lives in the root of a base dir.
+pub const SPEC_FILE_NAME: &str = "build.yaml";
+
+#[derive(Clone, Debug)]
+/// A build that lives in a directory of
+/// builds, as listed by `list_builds`.
+pub struct ManagedBuild {
+    /// The base directory of the build.
+    pub base_dir: PathBuf,
+    /// The spec of the build.
+    pub spec: Spec,
+}
+
+impl ManagedBuild {
+    /// Load the build in a base directory.
+    pub fn load<P: Into<PathBuf>>(base_dir: P) -> BuilderResult<ManagedBuild> {
+        let base_dir = base_dir.into();
+        let spec = Spec::from_file(base_dir.join(SPEC_FILE_NAME))?;
+
+        Ok(ManagedBuild { base_dir, spec })
+    }
+
+    /// Gets the name of the build: the name
+    /// in the spec, or else the name of
+    /// the base directory.
+    pub fn name(&self) -> String {
+        match &self.spec.name {
+            Some(name) => name.clone(),
+            None => self
+                .base_dir
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+        }
+    }
+
+    /// Gets the path of the icon of
+    /// the spec, if it has one.
+    pub fn icon_path(&self) -> Option<PathBuf> {
+        self.spec.icon.as_ref().map(|icon| self.base_dir.join(icon))
+    }
+
+    /// Whether the spec has a tag.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.spec
+            .tags
+            .iter()
+            .flatten()
+            .any(|t| t.eq_ignore_ascii_case(tag))
+    }
+
+    /// Check that the files that the build
+    /// (and its named configurations) installed
+    /// have not been modified since, so that
+    /// launchers can offer to repair it.
+    ///
+    /// Returns `None` if nothing has
+    /// been recorded to check against.
+    pub fn verify(&self) -> BuilderResult<Option<IntegrityReport>> {
+        let lockfile = Lockfile::load(&self.base_dir)?;
+        let mut manifests = lockfile.manifests().peekable();
+
+        if manifests.peek().is_none() {
+            return Ok(None);
+        }
+
+        let mut report = IntegrityReport::default();
+        for manifest in manifests {
+            let checked = manifest.verify(&self.base_dir);
+            report.modified.extend(checked.modified);
+            report.missing.extend(checked.missing);
+        }
+
+        Ok(Some(report))
+    }
+
+    /// Uninstall the pack named `name` from the
+    /// build, without rebuilding it, like
+    /// `Build::uninstall_pack`.
+    ///
+    /// Fails with the `BaseDirLocked` cause
+    /// if the build is being built.
+    pub fn uninstall_pack(&self, name: &str) -> BuilderResult<PackManifest> {
+        let _lock = BaseDirLock::acquire(&self.base_dir)?;
+        pack::uninstall_from_build(&self.spec, &self.base_dir, name)
+    }
+
+    /// Work out what the space that the
+    /// build takes up is used by.
+    pub fn disk_usage(&self) -> DiskUsage {
+        let spec = &self.spec;
+        let repo_dir = self.base_dir.join(&spec.repo.name);
+        let build_dir = repo_dir.join("build");
+        let total = util::dir_size(&self.base_dir);
+
+        let files_size = |files: Vec<PathBuf>| -> u64 {
+            files
+                .iter()
+                .filter_map(|f| fs::symlink_metadata(f).ok())
+                .map(|m| m.len())
+                .sum()
+        };
+        // packs that are kept in the base dir
+        // count, along with their installed copies.
+        let source_size = |path: &Path| {
+            let path = self.base_dir.join(path);
+            if path.starts_with(&self.base_dir) && !path.starts_with(&repo_dir) {
+                util::dir_size(path)
+            } else {
+                0
+            }
+        };
+
+        let (installed_texture_pack, texture_pack_source) = match &spec.texture_pack {
+            Some(pack) => {
+                let installed = spec.build_dir(&repo_dir).join("res").join("gfx");
+                let files = integrity::installed_files(&pack.path.join("gfx"), &installed);
+                (
+                    files_size(files.unwrap_or_default()),
+                    source_size(&pack.path),
+                )
+            }
+            None => (0, 0),
+        };
+
+        let packs_dir = DynosPack::install_dir(spec, &repo_dir);
+        let installed_dynos = util::dir_size(&packs_dir);
+        let dynos_packs = installed_dynos
+            + spec
+                .dynos_packs
+                .iter()
+                .flatten()
+                .map(|pack| source_size(&pack.path))
+                .sum::<u64>();
+
+        let logs = files_size(
+            fs::read_dir(&self.base_dir)
+                .map(|entries| {
+                    entries
+                        .flatten()
+                        .map(|entry| entry.path())
+                        .filter(|path| path.is_file() && util::has_extension(path, "log"))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        );
+
+        let artifacts_total = util::dir_size(&build_dir);
+        let installed_packs = (installed_texture_pack + installed_dynos).min(artifacts_total);
+        let build_artifacts = artifacts_total - installed_packs;
+        let repo = util::dir_size(&repo_dir).saturating_sub(artifacts_total);
+
+        let texture_packs = installed_texture_pack + texture_pack_source;
+        let counted = repo + build_artifacts + texture_packs + dynos_packs + logs;
+
+        DiskUsage {
+            repo,
+            build_artifacts,
+            texture_packs,
+            dynos_packs,
+            logs,
+            other: total.saturating_sub(counted),
+        }
+    }
+
+    /// Whether the build (or one of its
+    /// named configurations) has been
+    /// built successfully before.
+    pub fn is_built(&self) -> bool {
+        Lockfile::load(&self.base_dir)
+            .map(|l| l.spec_hashes.is_some() || l.configs.values().any(|c| c.spec_hashes.is_some()))
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// What the space that a build takes
+/// up is used by, in bytes.
+pub struct DiskUsage {
+    /// The clone of the repo, without
+    /// what was built in it.
+    pub repo: u64,
+    /// What the port was built into
+    /// (objects, extracted assets and
+    /// the executable).
+    pub build_artifacts: u64,
+    /// The texture pack, as installed (and
+    /// the pack itself, if it is kept in
+    /// the base dir).
+    pub texture_packs: u64,
+    /// The DynOS packs, as installed (and
+    /// the packs themselves, if they are
+    /// kept in the base dir).
+    pub dynos_packs: u64,
+    /// The build logs.
+    pub logs: u64,
+    /// Everything else, such as the base
+    /// ROM and the post-build scripts.
+    pub other: u64,
+}
+
+impl DiskUsage {
+    /// The space that the
+    /// build takes up in all.
+    pub fn total(&self) -> u64 {
+        self.repo
+            + self.build_artifacts
+            + self.texture_packs
+            + self.dynos_packs
+            + self.logs
+            + self.other
+    }
+}
+
+/// List the builds in the directories
+/// directly under `root`, sorted by name.
+///
+/// Directories without a (readable)
+/// spec file are skipped.
+pub fn list_builds<P: AsRef<Path>>(root: P) -> BuilderResult<Vec<ManagedBuild>> {
+    let root = root.as_ref();
+
+    let entries = match fs::read_dir(root) {
+        Ok(e) => e,
+        Err(e) => {
+            let msg = format!("failed to read {}", root.display());
+            return Err(err!(c_fs!(e, msg), "whilst listing the builds"));
+        }
+    };
+
+    let mut builds: Vec<ManagedBuild> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.join(SPEC_FILE_NAME).is_file())
+        .filter_map(|path| ManagedBuild::load(path).ok())
+        .collect();
+
+    builds.sort_by_key(|build| build.name().to_lowercase());
+    Ok(builds)
+}
+
+/// How long build logs are kept
+/// by default, in days.
+pub const DEFAULT_LOG_MAX_AGE_DAYS: u64 = 30;
+
+#[derive(Clone, Debug)]
+/// Options for `gc_with`.
+pub struct GcOptions {
+    /// How old a log has to be
+    /// for it to be collected.
+    pub log_max_age: Duration,
+    /// The cache directory to look for
+    /// shared repos in (under `repos`), if
+    /// there is one.
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl Default for GcOptions {
+    fn default() -> Self {
+        GcOptions {
+            log_max_age: Duration::from_secs(DEFAULT_LOG_MAX_AGE_DAYS * 24 * 60 * 60),
+            cache_dir: Config::load_default()
+                .ok()
+                .and_then(|config| config.effective_cache_dir()),
+        }
+    }
+}
+
+impl GcOptions {
+    /// Creates the default options: logs older
+    /// than `DEFAULT_LOG_MAX_AGE_DAYS`, and the
+    /// cache directory of the config.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how many days old a log
+    /// has to be to be collected.
+    pub fn log_max_age_days(mut self, days: u64) -> Self {
+        self.log_max_age = Duration::from_secs(days * 24 * 60 * 60);
+        self
+    }
+
+    /// Set the cache directory.
+    pub fn cache_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Why something can be collected.
+pub enum GcKind {
+    /// A directory in the root of the builds
+    /// that has the files of a build but no
+    /// spec file, such as one left behind by
+    /// a deleted build.
+    OrphanedDir,
+    /// A build log that is older
+    /// than the maximum age.
+    StaleLog,
+    /// A shared repo in the cache that no
+    /// build has a worktree of anymore.
+    DeadCacheEntry,
+    /// A repo whose clone was
+    /// interrupted, and never resumed.
+    PartialClone,
+}
+
+#[derive(Clone, Debug)]
+/// Something that `gc` found.
+pub struct GcItem {
+    /// The file or directory.
+    pub path: PathBuf,
+    /// Why it can be collected.
+    pub kind: GcKind,
+    /// How many bytes it takes up.
+    pub size: u64,
+    /// The base dir that it is in (or is), if
+    /// any, which is locked whilst it is deleted.
+    pub base_dir: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Default)]
+/// What `gc` found, to be shown to the
+/// user before anything is deleted.
+pub struct GcReport {
+    /// The things that can be deleted.
+    pub items: Vec<GcItem>,
+}
+
+impl GcReport {
+    /// How many bytes deleting
+    /// everything would free.
+    pub fn reclaimable(&self) -> u64 {
+        self.items.iter().map(|item| item.size).sum()
+    }
+
+    /// Delete everything that was found, once
+    /// the user has confirmed it, returning how
+    /// many bytes were freed.
+    ///
+    /// Things that are already gone, or that are
+    /// in a base dir that a build has locked since
+    /// they were found, are skipped.
+    pub fn delete(self) -> BuilderResult<u64> {
+        let mut freed = 0;
+
+        for item in self.items {
+            let _lock = match item.base_dir.as_deref().map(BaseDirLock::acquire) {
+                Some(Ok(lock)) => Some(lock),
+                Some(Err(_)) => continue,
+                None => None,
+            };
+
+            let result = if item.path.is_dir() {
+                fs::remove_dir_all(&item.path)
+            } else {
+                fs::remove_file(&item.path)
+            };
+
+            match result {
+                Ok(_) => freed += item.size,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => {
+                    let msg = format!("failed to delete {}", item.path.display());
+                    return Err(err!(c_fs!(e, msg), "whilst collecting garbage"));
+                }
+            }
+        }
+
+        Ok(freed)
+    }
+}
+
+/// Find the data under a directory of builds
+/// (and in the cache) that can be deleted,
+/// with the default `GcOptions`.
+///
+/// Nothing is deleted until `GcReport::delete`
+/// is called.
+pub fn gc<P: AsRef<Path>>(root: P) -> BuilderResult<GcReport> {
+    gc_with(root, GcOptions::default())
+}
+
+/// Find the data that can be deleted,
+/// like `gc`, with the given options.
+pub fn gc_with<P: AsRef<Path>>(root: P, options: GcOptions) -> BuilderResult<GcReport> {
+    let root = root.as_ref();
+
+    let entries = match fs::read_dir(root) {
+        Ok(e) => e,
+        Err(e) => {
+            let msg = format!("failed to read {}", root.display());
+            return Err(err!(c_fs!(e, msg), "whilst collecting garbage"));
+        }
+    };
+
+    let mut report = GcReport::default();
+    let mut found = |path: PathBuf, kind: GcKind, base_dir: Option<&Path>| {
+        let size = util::dir_size(&path);
+        report.items.push(GcItem {
+            path,
+            kind,
+            size,
+            base_dir: base_dir.map(Path::to_owned),
+        });
+    };
+
+    let mut dirs: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+
+    for dir in dirs {
+        if !dir.join(SPEC_FILE_NAME).is_file() {
+            // anything else in the root is
+            // not ours to delete.
+            if is_base_dir(&dir) {
+                found(dir.clone(), GcKind::OrphanedDir, Some(&dir));
+            }
+            continue;
+        }
+
+        for log in stale_logs(&dir, options.log_max_age) {
+            found(log, GcKind::StaleLog, Some(&dir));
+        }
+
+        if let Some(repo_dir) = partial_clone(&dir) {
+            found(repo_dir, GcKind::PartialClone, Some(&dir));
+        }
+    }
+
+    if let Some(cache_dir) = &options.cache_dir {
+        for store in dead_shared_repos(&cache_dir.join("repos")) {
+            found(store, GcKind::DeadCacheEntry, None);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Whether a directory has any of the files
+/// that a build leaves in its base dir.
+fn is_base_dir(dir: &Path) -> bool {
+    [
+        LOCKFILE_NAME,
+        PROGRESS_FILE_NAME,
+        PROGRESS_JSON_FILE_NAME,
+        LOCK_FILE_NAME,
+    ]
+    .iter()
+    .any(|name| dir.join(name).is_file())
+}
+
+/// Find the `.log` files in a base dir (and
+/// its `logs` directory) that have not been
+/// written to for longer than `max_age`.
+fn stale_logs(base_dir: &Path, max_age: Duration) -> Vec<PathBuf> {
+    let now = SystemTime::now();
+    let is_stale = |path: &Path| {
+        fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .map(|age| age > max_age)
+            .unwrap_or(false)
+    };
+
+    let mut logs: Vec<PathBuf> = [base_dir.to_owned(), base_dir.join(LOG_DIR_NAME)]
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.flatten().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && util::has_extension(path, "log"))
+        .filter(|path| is_stale(path))
+        .collect();
+    logs.sort();
+    logs
+}
+
+/// Gets the repo dir of a build whose clone
+/// was interrupted, unless the build is
+/// running (and so may be resuming it).
+///
+/// The build may start after this, so
+/// `GcReport::delete` locks it again.
+fn partial_clone(base_dir: &Path) -> Option<PathBuf> {
+    let build = ManagedBuild::load(base_dir).ok()?;
+    let lockfile = Lockfile::load(base_dir).ok()?;
+    let repo_dir = base_dir.join(&build.spec.repo.name);
+
+    if !lockfile.repo_incomplete() || !repo_dir.exists() {
+        return None;
+    }
+
+    // the lock is let go of straight away.
+    BaseDirLock::acquire(base_dir).ok()?;
+    Some(repo_dir)
+}
+
+/// Find the shared repos (bare stores) that
+/// no build has a worktree of anymore.
+fn dead_shared_repos(repos_dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(repos_dir) {
+        Ok(e) => e,
+        Err(_) => return Vec::new(),
+    };
+
+    let is_dead = |path: &Path| {
+        let store = match Repository::open_bare(path) {
+            Ok(r) => r,
+            Err(_) => return false,
+        };
+
+        let names = match store.worktrees() {
+            Ok(names) => names,
+            Err(_) => return false,
+        };
+
+        names
+            .iter()
+            .flatten()
+            .filter_map(|name| store.find_worktree(name).ok())
+            .all(|worktree| worktree.validate().is_err())
+    };
+
+    let mut stores: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir() && util::has_extension(path, "git"))
+        .filter(|path| is_dead(path))
+        .collect();
+    stores.sort();
+    stores
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lockfile::LockedBuild;
+
+    /// An empty directory for the
+    /// files of the test `name`.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("smbuilder-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn counts_a_built_configuration_as_built() {
+        let base_dir = test_dir("managed-config");
+        let build = ManagedBuild {
+            base_dir: base_dir.clone(),
+            spec: Spec::default(),
+        };
+        assert!(!build.is_built());
+
+        let mut lockfile = Lockfile::default();
+        lockfile.configs.insert(
+            "debug".to_owned(),
+            LockedBuild {
+                spec_hashes: Some(build.spec.hashes()),
+                ..Default::default()
+            },
+        );
+        lockfile.save(&base_dir).unwrap();
+        assert!(build.is_built());
+
+        fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn collects_only_what_smbuilder_left_behind() {
+        let root = test_dir("gc");
+        let options = GcOptions {
+            log_max_age: Duration::from_secs(60 * 60),
+            cache_dir: None,
+        };
+
+        // a build that was deleted, but left its
+        // lockfile, and a directory of something else.
+        let orphaned = root.join("deleted");
+        fs::create_dir_all(&orphaned).unwrap();
+        Lockfile::default().save(&orphaned).unwrap();
+        let unrelated = root.join("photos");
+        fs::create_dir_all(&unrelated).unwrap();
+        fs::write(unrelated.join("holiday.jpg"), "not really a photo").unwrap();
+
+        // a build with an old log and a new one.
+        let live = root.join("sm64ex");
+        fs::create_dir_all(live.join(LOG_DIR_NAME)).unwrap();
+        fs::write(live.join(SPEC_FILE_NAME), "").unwrap();
+        let stale_log = live.join(LOG_DIR_NAME).join("build.1.log");
+        let new_log = live.join(LOG_DIR_NAME).join("build.log");
+        fs::write(&new_log, "compiling").unwrap();
+        fs::File::create(&stale_log)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(2 * 60 * 60))
+            .unwrap();
+
+        let report = gc_with(&root, options.clone()).unwrap();
+        let found: Vec<(&Path, GcKind)> = report
+            .items
+            .iter()
+            .map(|item| (item.path.as_path(), item.kind))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (orphaned.as_path(), GcKind::OrphanedDir),
+                (stale_log.as_path(), GcKind::StaleLog),
+            ]
+        );
+
+        // the build started since, so its log is kept.
+        let lock = BaseDirLock::acquire(&live).unwrap();
+        report.delete().unwrap();
+        assert!(!orphaned.exists());
+        assert!(stale_log.exists());
+        assert!(new_log.exists());
+        assert!(unrelated.join("holiday.jpg").exists());
+
+        drop(lock);
+        gc_with(&root, options).unwrap().delete().unwrap();
+        assert!(!stale_log.exists());
+        assert!(new_log.exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
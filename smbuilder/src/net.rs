@@ -0,0 +1,94 @@
+use crate::error::ErrorCause;
+use crate::prelude::builder_types::BuilderResult;
+use crate::prelude::error_macros::*;
+use crate::prelude::Error;
+use crate::util;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Download a (small) text file into memory.
+pub fn download_string(url: &str) -> BuilderResult<String> {
+    let mut response = match ureq::get(url).call() {
+        Ok(r) => r,
+        Err(e) => return Err(err!(c_download!(url.to_owned(), e))),
+    };
+
+    match response.body_mut().read_to_string() {
+        Ok(s) => Ok(s),
+        Err(e) => Err(err!(
+            c_download!(url.to_owned(), e),
+            "failed to read the response"
+        )),
+    }
+}
+
+/// Download a file to a path on disk, streaming
+/// it rather than loading it into memory.
+///
+/// Returns the amount of bytes written.
+pub fn download_file<P: AsRef<Path>>(url: &str, dest: P) -> BuilderResult<u64> {
+    let dest = dest.as_ref();
+
+    let response = match ureq::get(url).call() {
+        Ok(r) => r,
+        Err(e) => return Err(err!(c_download!(url.to_owned(), e))),
+    };
+
+    let file = match File::create(dest) {
+        Ok(f) => f,
+        Err(e) => {
+            let msg = format!("failed to create {}", dest.display());
+            return Err(err!(c_fs!(e, msg), "whilst downloading a file"));
+        }
+    };
+
+    let mut reader = response.into_body().into_reader();
+    let mut writer = BufWriter::new(file);
+
+    match io::copy(&mut reader, &mut writer) {
+        Ok(n) => Ok(n),
+        Err(e) => Err(err!(
+            c_download!(url.to_owned(), e),
+            format!("failed to write the download to {}", dest.display())
+        )),
+    }
+}
+
+/// Download a file into `writer`, streaming
+/// it rather than loading it into memory.
+///
+/// Returns the amount of bytes written.
+pub fn download_to(url: &str, writer: &mut dyn Write) -> BuilderResult<u64> {
+    let response = match ureq::get(url).call() {
+        Ok(r) => r,
+        Err(e) => return Err(err!(c_download!(url.to_owned(), e))),
+    };
+
+    let mut reader = response.into_body().into_reader();
+
+    match io::copy(&mut reader, writer).and_then(|n| writer.flush().map(|_| n)) {
+        Ok(n) => Ok(n),
+        Err(e) => Err(err!(
+            c_download!(url.to_owned(), e),
+            "failed to write the download"
+        )),
+    }
+}
+
+/// Check some downloaded bytes against
+/// an expected SHA-256 checksum.
+pub fn verify_sha256<B: AsRef<[u8]>>(target: &str, bytes: B, expected: &str) -> BuilderResult<()> {
+    let actual = util::sha256_hex(bytes);
+
+    if actual.eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        Err(err!(c_checksum!(
+            target.to_owned(),
+            expected.to_owned(),
+            actual
+        )))
+    }
+}
@@ -0,0 +1,127 @@
+use std::env;
+use std::path::PathBuf;
+
+/// The name of the directories that smbuilder
+/// keeps its files in, within the data, cache
+/// and config directories of the platform.
+pub const APP_DIR_NAME: &str = "smbuilder";
+
+/// Gets the home directory of the user.
+pub fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+}
+
+/// Gets the directory that smbuilder keeps
+/// its data (such as builds) in.
+///
+/// `$XDG_DATA_HOME/smbuilder` (or
+/// `~/.local/share/smbuilder`) on Linux
+/// and the BSDs, `~/Library/Application
+/// Support/smbuilder` on macOS and
+/// `%APPDATA%\smbuilder` on Windows.
+pub fn data_dir() -> Option<PathBuf> {
+    platform_dir(
+        "XDG_DATA_HOME",
+        &[".local", "share"],
+        &["Library", "Application Support"],
+        "APPDATA",
+        APP_DIR_NAME,
+    )
+}
+
+/// Gets the directory that smbuilder
+/// keeps its caches in.
+///
+/// `$XDG_CACHE_HOME/smbuilder` (or
+/// `~/.cache/smbuilder`) on Linux and
+/// the BSDs, `~/Library/Caches/smbuilder`
+/// on macOS and `%LOCALAPPDATA%\smbuilder`
+/// on Windows.
+pub fn cache_dir() -> Option<PathBuf> {
+    platform_dir(
+        "XDG_CACHE_HOME",
+        &[".cache"],
+        &["Library", "Caches"],
+        "LOCALAPPDATA",
+        APP_DIR_NAME,
+    )
+}
+
+/// Gets the directory that smbuilder
+/// keeps its config file in.
+///
+/// `$XDG_CONFIG_HOME/smbuilder` (or
+/// `~/.config/smbuilder`) on Linux and
+/// the BSDs, `~/Library/Application
+/// Support/smbuilder` on macOS and
+/// `%APPDATA%\smbuilder` on Windows.
+pub fn config_dir() -> Option<PathBuf> {
+    platform_dir(
+        "XDG_CONFIG_HOME",
+        &[".config"],
+        &["Library", "Application Support"],
+        "APPDATA",
+        APP_DIR_NAME,
+    )
+}
+
+/// Gets the directory that a game keeps its
+/// saves and settings in, where SDL puts it
+/// (`SDL_GetPrefPath`) for `app`: the same
+/// places as `data_dir`, named after the
+/// game rather than smbuilder.
+pub fn game_data_dir(app: &str) -> Option<PathBuf> {
+    platform_dir(
+        "XDG_DATA_HOME",
+        &[".local", "share"],
+        &["Library", "Application Support"],
+        "APPDATA",
+        app,
+    )
+}
+
+/// Gets the directory that new
+/// builds are put in by default.
+pub fn builds_dir() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("builds"))
+}
+
+/// Gets a directory of the platform,
+/// with `app` on the end.
+///
+/// The XDG variables are honoured on every
+/// unix, as long as they are absolute paths
+/// (relative ones are invalid, and ignored).
+fn platform_dir(
+    xdg_var: &str,
+    xdg_fallback: &[&str],
+    macos_fallback: &[&str],
+    windows_var: &str,
+    app: &str,
+) -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        env::var_os(windows_var)
+            .map(PathBuf::from)
+            .or_else(|| home_dir().map(|home| home.join("AppData").join("Roaming")))?
+    } else if let Some(dir) = env::var_os(xdg_var)
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+    {
+        dir
+    } else {
+        let fallback = if cfg!(target_os = "macos") {
+            macos_fallback
+        } else {
+            xdg_fallback
+        };
+
+        fallback
+            .iter()
+            .fold(home_dir()?, |dir, part| dir.join(part))
+    };
+
+    Some(dir.join(app))
+}
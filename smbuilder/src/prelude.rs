@@ -1,10 +1,10 @@
-/// Re-export functions from the `n64romconvert` crate.
-pub mod romconvert {
-    pub use n64romconvert::{byte_endian_swap, byte_swap, determine_format, endian_swap, RomType};
-}
+// rom conversion
+pub use crate::romconvert;
 
 // Builder stuff
 pub use crate::builder::builder::Builder;
+pub use crate::builder::handle::Build;
+pub use crate::builder::stage::{Stage, StageContext, StagePoint};
 pub use crate::builder::types as builder_types;
 
 // callbacks
@@ -17,6 +17,9 @@ pub use crate::spec::*;
 // core types
 pub use crate::types::*;
 
+// lockfile
+pub use crate::lockfile::Lockfile;
+
 // errors
 pub use crate::error::macros as error_macros;
 pub use crate::error::{Error, ErrorCause};
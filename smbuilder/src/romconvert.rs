@@ -0,0 +1,110 @@
+pub use n64romconvert::{byte_endian_swap, byte_swap, determine_format, endian_swap, RomType};
+
+use crate::builder::backend::{FsProvider, RealFs};
+
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// How much of the ROM is converted at
+/// a time. Must be a multiple of 4, the
+/// size of an N64 word.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Convert a ROM of any format into a big
+/// endian (`.z64`) ROM, which is what the
+/// ports extract their assets from.
+///
+/// The ROM is streamed through in small chunks,
+/// and written to a temporary file that replaces
+/// `dest` once it is complete, so that an
+/// interrupted conversion never leaves a
+/// truncated ROM behind.
+///
+/// `progress` is called after every chunk,
+/// with the bytes converted so far and the
+/// size of the ROM.
+pub fn convert_rom(
+    src: &Path,
+    dest: &Path,
+    format: RomType,
+    progress: &mut dyn FnMut(u64, u64),
+) -> io::Result<()> {
+    convert_rom_with_fs(&RealFs, src, dest, format, progress)
+}
+
+/// Convert a ROM on `fs` into a big
+/// endian ROM, like `convert_rom`.
+pub fn convert_rom_with_fs(
+    fs: &dyn FsProvider,
+    src: &Path,
+    dest: &Path,
+    format: RomType,
+    progress: &mut dyn FnMut(u64, u64),
+) -> io::Result<()> {
+    let mut input = fs.open(src)?;
+    let total = fs.size(src)?;
+
+    let temp_path = temp_path_for(dest);
+    let result = (|| {
+        let mut output = fs.create(&temp_path)?;
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut done = 0;
+
+        loop {
+            let len = read_chunk(&mut input, &mut buf)?;
+            if len == 0 {
+                break;
+            }
+
+            let chunk = &mut buf[..len];
+            swap_chunk(chunk, format);
+            output.write_all(chunk)?;
+
+            done += len as u64;
+            progress(done, total);
+        }
+
+        output.flush()
+    })();
+
+    match result {
+        Ok(_) => fs.rename(&temp_path, dest),
+        Err(e) => {
+            let _ = fs.remove_file(&temp_path);
+            Err(e)
+        }
+    }
+}
+
+/// Convert a chunk of a ROM in
+/// `format` to big endian, in place.
+fn swap_chunk(chunk: &mut [u8], format: RomType) {
+    match format {
+        RomType::BigEndian => (),
+        RomType::ByteSwapped => chunk.chunks_exact_mut(2).for_each(|half| half.swap(0, 1)),
+        RomType::LittleEndian => chunk.chunks_exact_mut(4).for_each(|word| word.reverse()),
+    }
+}
+
+/// Fill `buf` as far as possible, so that chunks
+/// never end partway through a word.
+fn read_chunk<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(len)
+}
+
+fn temp_path_for(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_owned();
+    name.push(".part");
+    dest.with_file_name(name)
+}
@@ -0,0 +1,281 @@
+use crate::error::ErrorCause;
+use crate::prelude::builder_types::BuilderResult;
+use crate::prelude::error_macros::*;
+use crate::prelude::Error;
+use crate::util;
+
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The extension of detached signature
+/// files, added onto the signed file's
+/// own name (`build.yaml.sig`).
+pub const SIGNATURE_EXTENSION: &str = "sig";
+
+/// An ed25519 key pair, for signing
+/// specs and bundles.
+pub struct SigningKey {
+    pair: Ed25519KeyPair,
+}
+
+impl SigningKey {
+    /// Generate a new key, returning it along
+    /// with its PKCS#8 document, which should be
+    /// stored somewhere safe to sign with later.
+    pub fn generate() -> BuilderResult<(SigningKey, Vec<u8>)> {
+        let pkcs8 = match Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()) {
+            Ok(d) => d,
+            Err(_) => return Err(err!(c_other!(key_err()), "failed to generate a key")),
+        };
+
+        let key = SigningKey::from_pkcs8(pkcs8.as_ref())?;
+        Ok((key, pkcs8.as_ref().to_vec()))
+    }
+
+    /// Load a key from a PKCS#8 document,
+    /// like the one from `generate`.
+    pub fn from_pkcs8(pkcs8: &[u8]) -> BuilderResult<SigningKey> {
+        match Ed25519KeyPair::from_pkcs8(pkcs8) {
+            Ok(pair) => Ok(SigningKey { pair }),
+            Err(_) => Err(err!(c_other!(key_err()), "failed to load the signing key")),
+        }
+    }
+
+    /// Gets the public key, to hand out
+    /// to the people verifying the specs.
+    pub fn public_key(&self) -> PublicKey {
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(self.pair.public_key().as_ref());
+        PublicKey(bytes)
+    }
+
+    /// Sign some bytes.
+    pub fn sign(&self, bytes: &[u8]) -> Signature {
+        let mut signature = [0; 64];
+        signature.copy_from_slice(self.pair.sign(bytes).as_ref());
+        Signature(signature)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// An ed25519 public key.
+///
+/// Written as (and parsed from) hex.
+pub struct PublicKey([u8; 32]);
+
+impl PublicKey {
+    /// Check that `signature` is a signature
+    /// of `bytes` made with this key.
+    ///
+    /// `target` names what is being
+    /// verified, for the error.
+    pub fn verify(&self, target: &str, bytes: &[u8], signature: &Signature) -> BuilderResult<()> {
+        match UnparsedPublicKey::new(&ED25519, &self.0).verify(bytes, &signature.0) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(err!(c_bad_signature!(target))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// An ed25519 signature.
+///
+/// Written as (and parsed from) hex.
+pub struct Signature([u8; 64]);
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", to_hex(&self.0))
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", to_hex(&self.0))
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bytes = [0; 32];
+        from_hex(s, &mut bytes, "public key")?;
+        Ok(PublicKey(bytes))
+    }
+}
+
+impl FromStr for Signature {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bytes = [0; 64];
+        from_hex(s, &mut bytes, "signature")?;
+        Ok(Signature(bytes))
+    }
+}
+
+/// Gets the path of the detached
+/// signature of a file.
+pub fn signature_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".");
+    name.push(SIGNATURE_EXTENSION);
+    path.with_file_name(name)
+}
+
+/// Sign a file (such as a spec file or a
+/// bundle), writing the signature next to
+/// it, and return the signature's path.
+pub fn sign_file<P: AsRef<Path>>(path: P, key: &SigningKey) -> BuilderResult<PathBuf> {
+    let path = path.as_ref();
+    let signature = key.sign(&read(path)?);
+    let signature_path = signature_path(path);
+
+    match util::write_atomically(&signature_path, format!("{}\n", signature)) {
+        Ok(_) => Ok(signature_path),
+        Err(e) => {
+            let msg = format!("failed to write {}", signature_path.display());
+            Err(err!(c_fs!(e, msg), "whilst signing a file"))
+        }
+    }
+}
+
+/// Verify a file against the signature
+/// next to it, from `sign_file`.
+pub fn verify_file<P: AsRef<Path>>(path: P, key: &PublicKey) -> BuilderResult<()> {
+    let path = path.as_ref();
+    let signature_path = signature_path(path);
+
+    let signature = match fs::read_to_string(&signature_path) {
+        Ok(s) => s.trim().parse::<Signature>()?,
+        Err(e) => {
+            let msg = format!("failed to read {}", signature_path.display());
+            return Err(err!(c_fs!(e, msg), "the file has no signature"));
+        }
+    };
+
+    key.verify(&path.display().to_string(), &read(path)?, &signature)
+}
+
+fn read(path: &Path) -> BuilderResult<Vec<u8>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(bytes),
+        Err(e) => {
+            let msg = format!("failed to read {}", path.display());
+            Err(err!(c_fs!(e, msg), "whilst signing or verifying a file"))
+        }
+    }
+}
+
+fn key_err() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid ed25519 key")
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str, bytes: &mut [u8], what: &str) -> BuilderResult<()> {
+    let s = s.trim();
+    let digits = bytes.len() * 2;
+    let invalid = || {
+        let e = io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("expected {} hex digits", digits),
+        );
+        Err(err!(c_other!(e), format!("invalid {}", what)))
+    };
+
+    if s.len() != digits || !s.is_ascii() {
+        return invalid();
+    }
+
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = match u8::from_str_radix(&s[i * 2..i * 2 + 2], 16) {
+            Ok(b) => b,
+            Err(_) => return invalid(),
+        };
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_what_was_signed() {
+        let (key, pkcs8) = SigningKey::generate().unwrap();
+        let public_key = key.public_key();
+        let signature = key.sign(b"name: sm64ex");
+
+        assert!(public_key
+            .verify("build.yaml", b"name: sm64ex", &signature)
+            .is_ok());
+
+        // the key is the same once loaded again.
+        let loaded = SigningKey::from_pkcs8(&pkcs8).unwrap();
+        assert_eq!(loaded.public_key(), public_key);
+
+        let e = public_key
+            .verify("build.yaml", b"name: sm64ex-coop", &signature)
+            .unwrap_err();
+        assert!(matches!(e.cause, ErrorCause::BadSignature { .. }));
+
+        let (other, _) = SigningKey::generate().unwrap();
+        let e = other
+            .public_key()
+            .verify("build.yaml", b"name: sm64ex", &signature)
+            .unwrap_err();
+        assert!(matches!(e.cause, ErrorCause::BadSignature { .. }));
+    }
+
+    #[test]
+    fn parses_keys_and_signatures_as_hex() {
+        let (key, _) = SigningKey::generate().unwrap();
+        let public_key = key.public_key();
+        let signature = key.sign(b"name: sm64ex");
+
+        assert_eq!(
+            public_key.to_string().parse::<PublicKey>().unwrap(),
+            public_key
+        );
+        assert_eq!(
+            signature.to_string().parse::<Signature>().unwrap(),
+            signature
+        );
+
+        assert!("abcd".parse::<PublicKey>().is_err());
+        assert!("zz".repeat(32).parse::<PublicKey>().is_err());
+        assert!("é".repeat(32).parse::<PublicKey>().is_err());
+    }
+
+    #[test]
+    fn signs_files_next_to_them() {
+        let dir = std::env::temp_dir().join(format!("smbuilder-signing-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let spec_path = dir.join("build.yaml");
+        fs::write(&spec_path, "name: sm64ex\n").unwrap();
+
+        let (key, _) = SigningKey::generate().unwrap();
+        let path = sign_file(&spec_path, &key).unwrap();
+        assert_eq!(path, dir.join("build.yaml.sig"));
+        assert!(verify_file(&spec_path, &key.public_key()).is_ok());
+
+        fs::write(&spec_path, "name: sm64ex-coop\n").unwrap();
+        let e = verify_file(&spec_path, &key.public_key()).unwrap_err();
+        assert!(matches!(e.cause, ErrorCause::BadSignature { .. }));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,45 +1,211 @@
 use crate::callback_types::LogType;
 use crate::error::ErrorCause;
+use crate::host::HostCapabilities;
+use crate::lockfile::SpecHashes;
 use crate::prelude::error_macros::*;
 use crate::prelude::{builder_types::BuilderResult, *};
 use crate::romconvert::determine_format;
-use crate::util;
+use crate::{deps, paths, util};
 
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Default, Builder, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Builder, Deserialize, Serialize)]
+#[builder(build_fn(validate = "Self::validate", error = "Error"))]
 /// Represents a build spec.
 ///
 /// All of its child structs implements
 /// `Deserialize` and `Serialize`, and a
 /// spec file is derived directly from this
 /// structure.
+///
+/// Specs made in code should be made with
+/// `SpecBuilder`, which checks the spec
+/// when it is built.
 pub struct Spec {
     /// The ROM to extract assets out of.
     pub rom: Rom,
+    /// More ROMs, of other regions, for the
+    /// ports that need several base ROMs (like
+    /// to compare the text of a translation).
+    ///
+    /// They are copied and checked along with
+    /// `rom`, which still picks the region
+    /// that the port is built for.
+    #[serde(default)]
+    #[builder(default)]
+    pub extra_roms: Option<Vec<Rom>>,
     /// The repository to build from.
     pub repo: Repo,
+    /// Where the port comes from: compiled
+    /// from `repo`, or a prebuilt release.
+    #[serde(default)]
+    #[builder(default)]
+    pub source: SourceKind,
     /// Amount of compile jobs that are
     /// allowed for the compiler. Will
     /// be used to set the `-j` flag
     /// during compile time.
     pub jobs: Option<u8>,
     /// A custom name.
+    #[builder(default)]
     pub name: Option<String>,
+    /// What the spec builds, for launchers.
+    #[builder(default)]
+    pub description: Option<String>,
+    /// Who made the spec.
+    #[builder(default)]
+    pub author: Option<String>,
+    /// The version of the spec
+    /// (not of the port).
+    #[builder(default)]
+    pub version: Option<String>,
+    /// An icon for launchers to show, relative
+    /// to the base dir if it is not absolute.
+    #[builder(default)]
+    pub icon: Option<PathBuf>,
+    /// Tags to sort and search specs
+    /// by, such as `coop` or `hd`.
+    #[builder(default)]
+    pub tags: Option<Vec<String>>,
+    /// Values that the rest of the spec (and
+    /// the fragments that it includes) can use
+    /// as `{{name}}`, which are filled in when
+    /// it is loaded.
+    ///
+    /// Saving the spec keeps the `{{name}}`s
+    /// of the values that were not changed.
+    #[serde(default)]
+    #[builder(default)]
+    pub vars: Option<BTreeMap<String, String>>,
+    /// Fragment files, relative to the spec,
+    /// whose makeopts, packs, patches and
+    /// scripts are merged into the spec when
+    /// it is loaded (see `SpecFragment`).
+    ///
+    /// The spec's own entries win over those
+    /// of a fragment with the same key or name.
+    #[serde(default)]
+    #[builder(default)]
+    pub include: Option<Vec<PathBuf>>,
     /// Make flags to be passed to the
     /// compiler.
+    #[builder(default)]
     pub makeopts: Option<Vec<Makeopt>>,
     /// DynOS packs, if supported.
+    #[builder(default)]
     pub dynos_packs: Option<Vec<DynosPack>>,
     /// Patrhes.
+    #[builder(default)]
     pub patches: Option<Vec<Patch>>,
     /// Post install scripts.
+    ///
+    /// These run like the `after_post_build`
+    /// hooks, which newer specs should use.
+    #[builder(default)]
     pub scripts: Option<Vec<PostBuildScript>>,
+    /// Scripts to run at points of the build,
+    /// like after the repo is cloned.
+    #[serde(default)]
+    #[builder(default)]
+    pub hooks: Option<Hooks>,
     /// A texture pack.
+    #[builder(default)]
     pub texture_pack: Option<TexturePack>,
+    /// Settings for how the port is built.
+    #[builder(default, setter(name = "build_config"))]
+    pub build: Option<BuildConfig>,
+    /// Build the port with debug symbols,
+    /// and without stripping it, so that
+    /// crash reports can be symbolicated.
+    #[serde(default)]
+    #[builder(default)]
+    pub debug: bool,
+    /// Strip (and optionally compress)
+    /// the executable after building it.
+    ///
+    /// Ignored for debug builds.
+    #[serde(default)]
+    #[builder(default)]
+    pub strip: Option<StripConfig>,
+    /// Name the build inside of the game,
+    /// with the spec and the commit it was
+    /// built from.
+    #[serde(default)]
+    #[builder(default)]
+    pub identity: Option<IdentityConfig>,
+    /// Download the Discord game SDK
+    /// before building, for ports (like
+    /// sm64ex-coop) that link against it.
+    #[serde(default)]
+    #[builder(default)]
+    pub discord_sdk: Option<DiscordSdk>,
+    /// Save files to install into the
+    /// game's save directory after
+    /// building it.
+    #[serde(default)]
+    #[builder(default)]
+    pub save_files: Option<SaveFiles>,
+    /// Controller mappings to install
+    /// next to the executable.
+    #[serde(default)]
+    #[builder(default)]
+    pub controllers: Option<ControllerConfig>,
+    /// Build the port the same way every time:
+    /// with `SOURCE_DATE_EPOCH` pinned to the
+    /// time of the commit, the makeopts in a set
+    /// order and no absolute paths in the build
+    /// script.
+    ///
+    /// The hash of the executable is recorded in
+    /// the lockfile, and later builds of the same
+    /// commit with the same toolchain are checked
+    /// against it.
+    #[serde(default)]
+    #[builder(default)]
+    pub reproducible: bool,
+    /// Answers to the prompts of the build
+    /// script (like accepting a license),
+    /// written to its stdin, one per line.
+    #[serde(default)]
+    #[builder(default)]
+    pub answers: Option<Vec<String>>,
+    /// Named configurations of the spec, that
+    /// are built side by side from one clone.
+    #[serde(default)]
+    #[builder(default)]
+    pub configs: Option<Vec<SpecConfig>>,
+    /// Stages that are never run for this
+    /// spec, like `copy_rom` when the
+    /// baserom is already in the repo.
+    #[serde(default)]
+    #[builder(default)]
+    pub skip_stages: Option<Vec<builder_types::BuildStage>>,
+    /// The configuration that the spec has been
+    /// narrowed down to with `with_config`.
+    #[serde(skip)]
+    #[builder(setter(skip))]
+    config: Option<String>,
+    /// The entries merged in from `include`,
+    /// which are left out when saving.
+    #[serde(skip)]
+    #[builder(setter(skip))]
+    included: Option<SpecFragment>,
+    /// The spec as it was written, before
+    /// its `vars` were filled in, which
+    /// saving puts them back from.
+    #[serde(skip)]
+    #[builder(setter(skip))]
+    unexpanded: Option<Value>,
+    /// The makeopts that pick the APIs
+    /// of the host, from `set_host`.
+    #[serde(skip)]
+    #[builder(setter(skip))]
+    host_makeopts: Vec<Makeopt>,
 }
 
 impl Spec {
@@ -57,9 +223,177 @@ impl Spec {
             }
         };
 
-        match serde_yaml::from_str::<Spec>(&file_string) {
+        let mut spec = parse_spec(&file_string, "failed to read parse the spec file")?;
+
+        spec.repo.resolve_channel();
+
+        let dir = path.as_ref().parent().unwrap_or(Path::new(""));
+        spec.resolve_includes(dir)?;
+
+        Ok(spec)
+    }
+
+    /// Merge the fragments that the spec
+    /// includes into it, with the paths of
+    /// the fragments relative to `dir`.
+    ///
+    /// `from_file` does this already. Merging
+    /// again does not add anything twice, as
+    /// entries are replaced by key or name.
+    pub fn resolve_includes<P: AsRef<Path>>(&mut self, dir: P) -> BuilderResult<()> {
+        let mut merged = Vec::new();
+        let mut fragment = SpecFragment::default();
+
+        for path in self.include.iter().flatten() {
+            let path = dir.as_ref().join(path);
+            collect_fragment(&path, &mut Vec::new(), &mut merged, &mut fragment)?;
+        }
+
+        if self.include.is_some() {
+            let vars = self.vars.clone().unwrap_or_default();
+            let mut value = match serde_yaml::to_value(&fragment) {
+                Ok(v) => v,
+                Err(e) => return Err(err!(c_other!(e), "failed to serialize the fragments")),
+            };
+            expand_vars(&mut value, &vars, "include")?;
+
+            fragment = match serde_yaml::from_value(value) {
+                Ok(f) => f,
+                Err(e) => return Err(err!(c_other!(e), "failed to parse the fragments")),
+            };
+
+            self.included = Some(fragment.clone());
+        }
+
+        // the spec's own entries go on top, so
+        // that they win, like those of a config.
+        let mut makeopts = fragment.makeopts;
+        merge_entries(&mut makeopts, self.makeopts.take(), |m| m.key.clone());
+        self.makeopts = makeopts;
+
+        let mut dynos_packs = fragment.dynos_packs;
+        merge_entries(&mut dynos_packs, self.dynos_packs.take(), |p| {
+            p.name.clone()
+        });
+        self.dynos_packs = dynos_packs;
+
+        let mut patches = fragment.patches;
+        merge_entries(&mut patches, self.patches.take(), |p| p.name.clone());
+        self.patches = patches;
+
+        let mut scripts = fragment.scripts;
+        merge_entries(&mut scripts, self.scripts.take(), |s| s.name.clone());
+        self.scripts = scripts;
+
+        Ok(())
+    }
+
+    /// Writes the spec to a file, atomically,
+    /// so that an interrupted save does not
+    /// leave half of a spec behind.
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> BuilderResult<()> {
+        let path = path.as_ref();
+
+        // the included entries stay in their
+        // fragments, so that changes to them
+        // still reach the spec.
+        let mut spec = self.clone();
+        if let Some(included) = spec.included.take() {
+            strip_entries(&mut spec.makeopts, &included.makeopts);
+            strip_entries(&mut spec.dynos_packs, &included.dynos_packs);
+            strip_entries(&mut spec.patches, &included.patches);
+            strip_entries(&mut spec.scripts, &included.scripts);
+        }
+
+        let mut value = match serde_yaml::to_value(&spec) {
+            Ok(v) => v,
+            Err(e) => return Err(err!(c_other!(e), "failed to serialize the spec")),
+        };
+        if let Some(unexpanded) = &spec.unexpanded {
+            let vars = spec.vars.clone().unwrap_or_default();
+            restore_vars(&mut value, unexpanded, &vars);
+        }
+
+        let spec_string = match serde_yaml::to_string(&value) {
+            Ok(s) => s,
+            Err(e) => return Err(err!(c_other!(e), "failed to serialize the spec")),
+        };
+
+        match util::write_atomically(path, spec_string) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let msg = format!("failed to write {}", path.display());
+                Err(err!(c_fs!(e, msg), "whilst saving the spec"))
+            }
+        }
+    }
+
+    /// Downloads a spec from a URL, but
+    /// **doesn't check it**, like `from_file`.
+    ///
+    /// If a SHA-256 `checksum` is given, the
+    /// downloaded spec must match it. Specs from
+    /// the internet may contain post-build scripts;
+    /// consider setting the `approve_script`
+    /// callback before building them.
+    #[cfg(feature = "remote")]
+    pub fn from_url(url: &str, checksum: Option<&str>) -> BuilderResult<Spec> {
+        let spec_string = crate::net::download_string(url)?;
+
+        if let Some(checksum) = checksum {
+            crate::net::verify_sha256(url, &spec_string, checksum)?;
+        }
+
+        let mut spec = parse_spec(&spec_string, "failed to parse the downloaded spec")?;
+
+        // there is nowhere to look for
+        // the fragments of a downloaded spec.
+        if spec.include.as_ref().is_some_and(|i| !i.is_empty()) {
+            return Err(err!(c_invalid_spec!(
+                "include",
+                "cannot be used in a downloaded spec"
+            )));
+        }
+
+        spec.repo.resolve_channel();
+        Ok(spec)
+    }
+
+    /// Sign the spec with an ed25519 key.
+    ///
+    /// The signature covers the spec as smbuilder
+    /// serializes it, so it survives reformatting,
+    /// but not changes to any field. To sign the
+    /// file itself, see `signing::sign_file`.
+    #[cfg(feature = "signing")]
+    pub fn sign(
+        &self,
+        key: &crate::signing::SigningKey,
+    ) -> BuilderResult<crate::signing::Signature> {
+        Ok(key.sign(self.signed_bytes()?.as_bytes()))
+    }
+
+    /// Verify a signature of the spec, from
+    /// `sign`, before building it (and running
+    /// its post-build scripts).
+    #[cfg(feature = "signing")]
+    pub fn verify(
+        &self,
+        key: &crate::signing::PublicKey,
+        signature: &crate::signing::Signature,
+    ) -> BuilderResult<()> {
+        let target = format!(
+            "the spec `{}`",
+            self.name.as_deref().unwrap_or(&self.repo.name)
+        );
+        key.verify(&target, self.signed_bytes()?.as_bytes(), signature)
+    }
+
+    #[cfg(feature = "signing")]
+    fn signed_bytes(&self) -> BuilderResult<String> {
+        match serde_yaml::to_string(self) {
             Ok(s) => Ok(s),
-            Err(e) => return Err(err!(c_other!(e), "failed to read parse the spec file")),
+            Err(e) => Err(err!(c_other!(e), "failed to serialize the spec")),
         }
     }
 
@@ -70,37 +404,137 @@ impl Spec {
     /// imperfection.
     ///
     /// Designed for use with `from_file_checked`.
+    ///
+    /// Without a base dir to look for the repo
+    /// in, a missing `rom.path` is only caught
+    /// when the ROM is copied.
     pub fn check_spec(&mut self, callbacks: &mut Callbacks) -> BuilderResult<()> {
+        self.check_spec_in(None, callbacks)
+    }
+
+    fn check_spec_in(
+        &mut self,
+        base_dir: Option<&Path>,
+        callbacks: &mut Callbacks,
+    ) -> BuilderResult<()> {
         use LogType as L;
 
-        // Check the ROM format and see
-        // if it matches the spec
-        let rom_path = if self.rom.path.exists() {
-            &self.rom.path
-        } else {
-            let file_not_found_error = std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("the file at {} was not found!", &self.rom.path.display()),
-            );
-            let err = err!(c_fs!(file_not_found_error), "the spec file was not found");
-            return Err(err);
-        };
+        let repo_dir = base_dir.map(|dir| dir.join(&self.repo.name));
+        check_roms(self.roms(), repo_dir.as_deref())?;
 
-        let verified_rom_format = match determine_format(rom_path) {
-            Ok(t) => t,
-            Err(e) => return Err(err!(c_other!(e), "failed to verify the format of the ROM")),
-        };
+        // the channel picks the branch,
+        // so it is resolved first.
+        self.repo.resolve_channel();
 
-        if verified_rom_format != self.rom.format {
-            run_callback!(
-                callbacks.log_cb,
-                L::Warn,
-                &format!(
-                    "the ROM format specified in the spec ({:?}) does not match the file ({:?})!",
-                    self.rom.format, verified_rom_format
-                )
-            );
-        };
+        if let Some(channel) = self.repo.channel {
+            if self.repo.port() != Port::Render96 {
+                run_callback!(
+                    callbacks.log_cb,
+                    L::Warn,
+                    "only Render96ex has release channels, so `repo.channel` is ignored!"
+                );
+            } else if Render96Channel::from_branch(&self.repo.branch) != Some(channel) {
+                run_callback!(
+                    callbacks.log_cb,
+                    L::Warn,
+                    &format!(
+                        "the branch {} is not on the {} channel of Render96ex, which is built from {}!",
+                        self.repo.branch,
+                        channel,
+                        channel.branch()
+                    )
+                );
+            }
+        }
+
+        check_repo(&self.repo)?;
+        check_source(&self.source)?;
+        check_configs(self.configs.iter().flatten())?;
+        check_scripts(self.all_scripts())?;
+
+        // Check the ROM formats and see
+        // if they match the spec (unless
+        // the base ROM in the repo is used)
+        for rom in self.roms().filter(|rom| rom.path.is_file()) {
+            let verified_rom_format = match determine_format(&rom.path) {
+                Ok(t) => t,
+                Err(e) => return Err(err!(c_other!(e), "failed to verify the format of the ROM")),
+            };
+
+            if verified_rom_format != rom.format {
+                run_callback!(
+                    callbacks.log_cb,
+                    L::Warn,
+                    &format!(
+                        "the ROM format specified in the spec ({:?}) does not match the file ({:?})!",
+                        rom.format, verified_rom_format
+                    )
+                );
+            };
+        }
+
+        // Region
+
+        if let Some(version) = self.makeopts.iter().flatten().find(|m| m.key == "VERSION") {
+            if version.value != self.rom.region.version() {
+                run_callback!(
+                    callbacks.log_cb,
+                    L::Warn,
+                    &format!(
+                        "the VERSION makeopt ({}) does not match the region of the ROM ({}), so the port will look for baserom.{}.z64 instead of {}!",
+                        version.value,
+                        self.rom.region,
+                        version.value,
+                        self.rom.region.baserom_name()
+                    )
+                );
+            }
+        }
+
+        if self.rom.region.is_multilingual() {
+            for patch in self.patches.iter().flatten().filter(|p| p.touches_text()) {
+                run_callback!(
+                    callbacks.log_cb,
+                    L::Warn,
+                    &format!(
+                        "the patch {} changes the game text, which the {} version has in {} languages with a different encoding; the text may come out broken!",
+                        patch.name,
+                        self.rom.region.to_string().to_uppercase(),
+                        self.rom.region.languages().len()
+                    )
+                );
+            }
+        }
+
+        // Packs
+
+        if let Some(channel) = self.repo.render96_channel() {
+            let packs = self
+                .texture_pack
+                .iter()
+                .map(|p| ("texture pack", &p.name, p.channel))
+                .chain(
+                    self.dynos_packs
+                        .iter()
+                        .flatten()
+                        .map(|p| ("DynOS pack", &p.name, p.channel)),
+                );
+
+            for (kind, name, pack_channel) in packs {
+                let Some(pack_channel) = pack_channel.filter(|c| *c != channel) else {
+                    continue;
+                };
+
+                run_callback!(
+                    callbacks.log_cb,
+                    L::Warn,
+                    &format!(
+                        "the {} {} was made for the {} channel of Render96ex, but the {} channel is being built; it may not load!",
+                        kind, name, pack_channel, channel
+                    )
+                );
+            }
+        }
 
         // Jobs
 
@@ -129,9 +563,10 @@ impl Spec {
         path: P,
         callbacks: &mut Callbacks,
     ) -> BuilderResult<Spec> {
-        let mut spec = Spec::from_file(path)?;
+        let mut spec = Spec::from_file(&path)?;
 
-        let check_result = Spec::check_spec(&mut spec, callbacks);
+        let base_dir = path.as_ref().parent().unwrap_or(Path::new(""));
+        let check_result = spec.check_spec_in(Some(base_dir), callbacks);
 
         if let Err(e) = check_result {
             Err(e)
@@ -140,36 +575,386 @@ impl Spec {
         }
     }
 
+    /// Gets every ROM of the spec: `rom`,
+    /// then the `extra_roms`.
+    pub fn roms(&self) -> impl Iterator<Item = &Rom> {
+        std::iter::once(&self.rom).chain(self.extra_roms.iter().flatten())
+    }
+
+    /// Gets the scripts hooked into `point`,
+    /// in the order that they run in.
+    ///
+    /// The `scripts` of the spec are
+    /// hooked into `after_post_build`.
+    pub fn hook_scripts(&self, point: HookPoint) -> Vec<&PostBuildScript> {
+        let mut scripts: Vec<&PostBuildScript> = self
+            .hooks
+            .iter()
+            .flat_map(|hooks| hooks.at(point))
+            .collect();
+        if point == HookPoint::AfterPostBuild {
+            scripts.extend(self.scripts.iter().flatten());
+        }
+
+        // the sort is stable, so scripts of the
+        // same order keep to the listed order.
+        scripts.sort_by_key(|script| script.order);
+        scripts
+    }
+
+    /// Gets every script of the spec,
+    /// of every hook.
+    pub fn all_scripts(&self) -> impl Iterator<Item = &PostBuildScript> {
+        HookPoint::ALL
+            .into_iter()
+            .flat_map(|point| self.hook_scripts(point))
+    }
+
+    /// Whether the port is downloaded
+    /// prebuilt, rather than compiled.
+    pub fn is_prebuilt(&self) -> bool {
+        matches!(self.source, SourceKind::Prebuilt { .. })
+    }
+
+    /// Whether the spec sets a makeopt
+    /// to a specific value.
+    pub fn has_makeopt(&self, key: &str, value: &str) -> bool {
+        self.makeopts
+            .iter()
+            .flatten()
+            .any(|m| m.key == key && m.value == value)
+    }
+
+    /// Gets the make command that the spec
+    /// overrides, if any.
+    pub fn make_command(&self) -> Option<&str> {
+        self.build
+            .as_ref()
+            .and_then(|b| b.make_command.as_deref())
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+    }
+
+    /// Gets the makeopts that the `debug`
+    /// option adds, unless the spec sets
+    /// them itself.
+    pub fn debug_makeopts(&self) -> Vec<Makeopt> {
+        if !self.debug {
+            return Vec::new();
+        }
+
+        [("DEBUG", "1"), ("COMPARE", "0")]
+            .into_iter()
+            .filter(|(key, _)| !self.makeopts.iter().flatten().any(|m| m.key == *key))
+            .map(|(key, value)| Makeopt::new(key, value))
+            .collect()
+    }
+
+    /// Gets the makeopt that names the build
+    /// inside of the game, if the spec asks
+    /// for one and does not set it itself.
+    ///
+    /// The commit is read from the repo, and
+    /// is `unknown` if it cannot be.
+    pub fn identity_makeopt(&self, repo_path: &Path) -> Option<Makeopt> {
+        let identity = self.identity.as_ref()?;
+
+        if self
+            .makeopts
+            .iter()
+            .flatten()
+            .any(|m| m.key == identity.makeopt)
+        {
+            return None;
+        }
+
+        let commit = util::head_commit(repo_path)
+            .map(|(id, _)| id[..7].to_owned())
+            .unwrap_or_else(|| "unknown".to_owned());
+
+        let text = identity.text(
+            self.name.as_deref().unwrap_or(&self.repo.name),
+            self.version.as_deref().unwrap_or(""),
+            &commit,
+        );
+
+        // the makeopts go into a shell script
+        // as they are, so the text is quoted.
+        Some(Makeopt::new(
+            identity.makeopt.clone(),
+            format!("'{}'", text.trim().replace('\'', "'\\''")),
+        ))
+    }
+
+    /// Gets the directory that the save
+    /// files are installed into: the one
+    /// that the spec gives, or the one that
+    /// the port keeps its saves in.
+    pub fn save_dir(&self) -> Option<PathBuf> {
+        let save_files = self.save_files.as_ref()?;
+
+        save_files
+            .dir
+            .clone()
+            .or_else(|| paths::game_data_dir(self.repo.port().user_data_name()))
+    }
+
+    /// Sets the host that the port is built on,
+    /// whose APIs (see `HostCapabilities::api_makeopts`)
+    /// it is built with, unless the spec's own
+    /// makeopts pick others.
+    pub fn set_host(&mut self, host: &HostCapabilities) {
+        self.host_makeopts = host.api_makeopts();
+    }
+
+    /// Gets every makeopt that the spec
+    /// builds the port with.
+    pub fn build_makeopts(&self) -> Vec<Makeopt> {
+        let mut makeopts = self.host_makeopts.clone();
+        makeopts.extend(self.debug_makeopts());
+        makeopts.extend(self.makeopts.iter().flatten().cloned());
+        makeopts
+    }
+
+    /// Gets the spec with the named
+    /// configuration `name` applied to it.
+    pub fn with_config(&self, name: &str) -> BuilderResult<Spec> {
+        let config = match self.configs.iter().flatten().find(|c| c.name == name) {
+            Some(c) => c.clone(),
+            None => {
+                return Err(err!(c_invalid_spec!(
+                    "configs",
+                    format!("has no configuration named `{}`", name)
+                )))
+            }
+        };
+
+        let mut spec = self.clone();
+
+        // later makeopts win on make's command
+        // line, but replacing them keeps the
+        // build script readable.
+        if let Some(makeopts) = config.makeopts {
+            let mut merged = spec.makeopts.take().unwrap_or_default();
+            merged.retain(|m| !makeopts.iter().any(|n| n.key == m.key));
+            merged.extend(makeopts);
+            spec.makeopts = Some(merged);
+        }
+
+        if let Some(debug) = config.debug {
+            spec.debug = debug;
+        }
+        if config.texture_pack.is_some() {
+            spec.texture_pack = config.texture_pack;
+        }
+        if config.dynos_packs.is_some() {
+            spec.dynos_packs = config.dynos_packs;
+        }
+
+        spec.config = Some(config.name);
+        Ok(spec)
+    }
+
+    /// Gets the name of the configuration that
+    /// the spec was narrowed down to, if any.
+    pub fn config(&self) -> Option<&str> {
+        self.config.as_deref()
+    }
+
+    /// Gets the directory, relative to the repo,
+    /// that make puts the build directories in.
+    pub fn build_dir_base(&self) -> PathBuf {
+        match &self.config {
+            Some(name) => Path::new("build").join(format!("config-{}", name)),
+            None => PathBuf::from("build"),
+        }
+    }
+
+    /// Gets the directory that the
+    /// port is built into.
+    pub fn build_dir<P: AsRef<Path>>(&self, repo_dir: P) -> PathBuf {
+        repo_dir
+            .as_ref()
+            .join(self.build_dir_base())
+            .join(self.rom.region.build_dir_name())
+    }
+
+    /// Gets every pack of the spec, the
+    /// texture pack first, in the order
+    /// that they are installed in.
+    pub fn packs(&self) -> Vec<Box<dyn Pack>> {
+        let mut packs: Vec<Box<dyn Pack>> = Vec::new();
+
+        if let Some(pack) = &self.texture_pack {
+            packs.push(Box::new(pack.clone()));
+        }
+
+        for pack in self.dynos_packs.iter().flatten() {
+            packs.push(Box::new(pack.clone()));
+        }
+
+        packs
+    }
+
+    /// Gets the file name of the build script,
+    /// in the root of the base dir.
+    pub fn build_script_name(&self) -> String {
+        match &self.config {
+            Some(name) => format!("build-{}.sh", name),
+            None => "build.sh".to_owned(),
+        }
+    }
+
+    /// Hashes the parts of the spec that
+    /// affect the different build stages.
+    pub fn hashes(&self) -> SpecHashes {
+        fn hash<T: Serialize>(value: &T) -> String {
+            util::sha256_hex(serde_yaml::to_string(value).unwrap_or_default())
+        }
+
+        SpecHashes {
+            build_script: hash(&(
+                &self.host_makeopts,
+                &self.makeopts,
+                &self.jobs,
+                &self.build,
+                self.debug,
+                &self.identity,
+                self.reproducible,
+                &self.discord_sdk,
+            )),
+            patches: hash(&self.patches),
+            packs: hash(&(&self.texture_pack, &self.dynos_packs)),
+            // the paths of the scripts are only
+            // set once they have been written.
+            scripts: hash(
+                &self
+                    .hook_scripts(HookPoint::AfterPostBuild)
+                    .iter()
+                    .map(|script| (&script.name, &script.contents))
+                    .collect::<Vec<_>>(),
+            ),
+        }
+    }
+
+    /// Gets the build script for the spec: the
+    /// one that the spec provides, if any, or
+    /// else the one from `to_script`.
+    pub fn build_script(&self, repo_path: &Path) -> BuilderResult<String> {
+        let build = match &self.build {
+            Some(build) => build,
+            None => return Ok(self.to_script(repo_path)),
+        };
+
+        if let Some(script) = &build.script {
+            return Ok(script.clone());
+        }
+
+        if let Some(path) = &build.script_path {
+            return match fs::read_to_string(path) {
+                Ok(script) => Ok(script),
+                Err(e) => {
+                    let msg = format!("failed to read {}", path.display());
+                    Err(err!(
+                        c_fs!(e, msg),
+                        "whilst reading the custom build script"
+                    ))
+                }
+            };
+        }
+
+        Ok(self.to_script(repo_path))
+    }
+
     /// Gets a build shell script, ready to be
     /// written to disk.
     ///
     //  TODO: example
     pub fn to_script(&self, repo_path: &Path) -> String {
-        let makeopts_string = if let Some(makeopts) = &self.makeopts {
-            util::get_makeopts_string(makeopts)
-        } else {
-            String::new()
-        };
+        let mut makeopts = self.makeopts.clone().unwrap_or_default();
+
+        // the BSDs ship BSD make as `make`, so
+        // GNU make is `gmake` there. if neither
+        // can be found, assume `gmake` on the BSDs
+        // so that the error makes sense.
+        let make_cmd = self
+            .make_command()
+            .unwrap_or(deps::make_command().unwrap_or(if deps::IS_BSD { "gmake" } else { "make" }));
+
+        let unsupported = self.repo.port().unsupported_makeopts();
+        let mut platform_makeopts = Makeopt::default_makeopts();
+        platform_makeopts.extend(self.host_makeopts.iter().cloned());
+        platform_makeopts.retain(|m| !unsupported.contains(&m.key.as_str()));
+
+        // the makefile defaults to the US version,
+        // so the region is always passed along,
+        // unless the spec sets it itself.
+        if !self.makeopts.iter().flatten().any(|m| m.key == "VERSION") {
+            platform_makeopts.push(Makeopt::new(
+                "VERSION".to_owned(),
+                self.rom.region.version(),
+            ));
+        }
+
+        platform_makeopts.extend(self.debug_makeopts());
+        platform_makeopts.extend(self.identity_makeopt(repo_path));
+
+        if self.discord_sdk.is_some()
+            && !self
+                .makeopts
+                .iter()
+                .flatten()
+                .any(|m| m.key == "DISCORD_SDK")
+        {
+            platform_makeopts.push(Makeopt::new("DISCORD_SDK", "1"));
+        }
 
-        // FreeBSD, macOS and OSes
-        // with BSD make by default
-        #[allow(unused_variables)]
-        let make_cmd = "gmake";
+        // every configuration is built
+        // into a directory of its own.
+        if self.config.is_some() {
+            platform_makeopts.push(Makeopt::new(
+                "BUILD_DIR_BASE".to_owned(),
+                self.build_dir_base().to_string_lossy().to_string(),
+            ));
+        }
 
-        #[cfg(target_os = "linux")]
-        let make_cmd = "make";
+        // the sorts are stable, so a makeopt that
+        // the spec sets twice keeps its last value.
+        if self.reproducible {
+            platform_makeopts.sort_by(|a, b| a.key.cmp(&b.key));
+            makeopts.sort_by(|a, b| a.key.cmp(&b.key));
+        }
 
-        let platform_makeopts = util::get_makeopts_string(&Makeopt::default_makeopts());
+        let platform_makeopts = util::get_makeopts_string(&platform_makeopts);
+        let makeopts_string = if makeopts.is_empty() {
+            String::new()
+        } else {
+            util::get_makeopts_string(&makeopts)
+        };
 
         let jobs = self.jobs.unwrap_or(2);
 
-        let full_repo_dir = fs::canonicalize(repo_path).unwrap_or_else(|e| {
-            panic!(
-                "failed to get the absolute path from {}: {}",
-                &repo_path.display(),
-                e
+        let (env, repo_dir) = if self.reproducible {
+            // the script sits in the base dir, next
+            // to the repo, so it is found from there.
+            let repo_name = repo_path.file_name().unwrap_or_default().to_string_lossy();
+            let epoch = util::head_commit(repo_path)
+                .map(|(_, time)| time)
+                .unwrap_or(0);
+
+            (
+                format!("export SOURCE_DATE_EPOCH={} TZ=UTC LC_ALL=C\n\n", epoch),
+                format!("\"$(dirname \"$0\")/{}\"", repo_name),
             )
-        });
+        } else {
+            // the repo may not be on the real
+            // disk, like with a mock filesystem.
+            let full_repo_dir = fs::canonicalize(repo_path)
+                .or_else(|_| std::path::absolute(repo_path))
+                .unwrap_or_else(|_| repo_path.to_owned());
+
+            (String::new(), full_repo_dir.display().to_string())
+        };
 
         format!(
             "#!/bin/sh
@@ -178,13 +963,670 @@ impl Spec {
 # DO NOT EDIT; YOUR CHANGES
 # WILL NOT BE SAVED.
 
-{} -C {} {} {} -j{}
+{}{} -C {} {} {} -j{} \"$@\"
         ",
-            make_cmd,
-            full_repo_dir.display(),
-            platform_makeopts,
-            makeopts_string,
-            jobs
+            env, make_cmd, repo_dir, platform_makeopts, makeopts_string, jobs
         )
     }
+
+    /// Gets the time that `SOURCE_DATE_EPOCH`
+    /// is pinned to for reproducible builds:
+    /// the time of the commit that is built.
+    pub fn source_date_epoch(&self, repo_path: &Path) -> Option<i64> {
+        if !self.reproducible {
+            return None;
+        }
+
+        util::head_commit(repo_path).map(|(_, time)| time)
+    }
+}
+
+impl SpecBuilder {
+    /// Runs the mandatory checks of
+    /// `Spec::check_spec` on the fields
+    /// that have been set, and requires
+    /// `jobs` to be set.
+    fn validate(&self) -> BuilderResult<()> {
+        if let Some(rom) = &self.rom {
+            let extra_roms = self.extra_roms.iter().flatten().flatten();
+            check_roms(std::iter::once(rom).chain(extra_roms), None)?;
+        }
+
+        if let Some(repo) = &self.repo {
+            check_repo(repo)?;
+        }
+
+        if let Some(source) = &self.source {
+            check_source(source)?;
+        }
+
+        if let Some(configs) = &self.configs {
+            check_configs(configs.iter().flatten())?;
+        }
+
+        let hooks = self
+            .hooks
+            .iter()
+            .flatten()
+            .flat_map(|hooks| HookPoint::ALL.into_iter().flat_map(|point| hooks.at(point)));
+        check_scripts(self.scripts.iter().flatten().flatten().chain(hooks))?;
+
+        match self.jobs.flatten() {
+            None => Err(err!(
+                c_invalid_spec!("jobs", "was not set"),
+                "whilst building the spec"
+            )),
+            Some(0) => Err(err!(
+                c_invalid_spec!("jobs", "must be at least 1"),
+                "whilst building the spec"
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+/// Parse a spec, filling in its `vars`.
+fn parse_spec(contents: &str, failure: &str) -> BuilderResult<Spec> {
+    let mut value = match serde_yaml::from_str::<Value>(contents) {
+        Ok(v) => v,
+        Err(e) => return Err(err!(c_other!(e), failure)),
+    };
+
+    let vars = match value.get("vars") {
+        Some(vars) if !vars.is_null() => {
+            match serde_yaml::from_value::<BTreeMap<String, String>>(vars.clone()) {
+                Ok(vars) => vars,
+                Err(e) => {
+                    return Err(err!(c_invalid_spec!(
+                        "vars",
+                        format!("must map names to strings ({})", e)
+                    )))
+                }
+            }
+        }
+        _ => BTreeMap::new(),
+    };
+
+    let unexpanded = (!vars.is_empty()).then(|| value.clone());
+    if let Value::Mapping(fields) = &mut value {
+        for (key, field) in fields.iter_mut() {
+            let Some(key) = key.as_str().filter(|k| *k != "vars") else {
+                continue;
+            };
+            expand_vars(field, &vars, key)?;
+        }
+    }
+
+    match serde_yaml::from_value::<Spec>(value) {
+        Ok(s) => Ok(Spec { unexpanded, ..s }),
+        Err(e) => Err(err!(c_other!(e), failure)),
+    }
+}
+
+/// Put the `{{name}}`s of `unexpanded` back
+/// into the strings of `value` that they
+/// still fill in to.
+fn restore_vars(value: &mut Value, unexpanded: &Value, vars: &BTreeMap<String, String>) {
+    match (value, unexpanded) {
+        (Value::String(s), Value::String(raw))
+            if raw.contains("{{") && expand_str(raw, vars, "vars").is_ok_and(|e| e == *s) =>
+        {
+            *s = raw.clone();
+        }
+        (Value::Sequence(items), Value::Sequence(raw_items)) => {
+            for (item, raw) in items.iter_mut().zip(raw_items) {
+                restore_vars(item, raw, vars);
+            }
+        }
+        (Value::Mapping(fields), Value::Mapping(raw_fields)) => {
+            for (key, field) in fields.iter_mut() {
+                if let Some(raw) = raw_fields.get(key) {
+                    restore_vars(field, raw, vars);
+                }
+            }
+        }
+        (Value::Tagged(tagged), Value::Tagged(raw)) => {
+            restore_vars(&mut tagged.value, &raw.value, vars)
+        }
+        _ => {}
+    }
+}
+
+/// Fill in the `{{name}}`s in the strings of
+/// `value`, which is the `field` of the spec.
+fn expand_vars(
+    value: &mut Value,
+    vars: &BTreeMap<String, String>,
+    field: &str,
+) -> BuilderResult<()> {
+    match value {
+        Value::String(s) => *s = expand_str(s, vars, field)?,
+        Value::Sequence(items) => {
+            for item in items {
+                expand_vars(item, vars, field)?;
+            }
+        }
+        Value::Mapping(fields) => {
+            for (_, item) in fields.iter_mut() {
+                expand_vars(item, vars, field)?;
+            }
+        }
+        Value::Tagged(tagged) => expand_vars(&mut tagged.value, vars, field)?,
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn expand_str(s: &str, vars: &BTreeMap<String, String>, field: &str) -> BuilderResult<String> {
+    let mut expanded = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+
+        let name = rest[start + 2..start + 2 + len].trim();
+        let Some(var) = vars.get(name) else {
+            return Err(err!(c_invalid_spec!(
+                field,
+                format!("uses `{{{{{}}}}}`, which is not defined in `vars`", name)
+            )));
+        };
+
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(var);
+        rest = &rest[start + 2 + len + 2..];
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Load the fragment at `path`, and the ones
+/// that it includes, into `into`, in order.
+///
+/// `stack` holds the fragments that are being
+/// loaded, which must not include themselves,
+/// and `merged` the ones that were loaded
+/// already, which are only merged once.
+fn collect_fragment(
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    merged: &mut Vec<PathBuf>,
+    into: &mut SpecFragment,
+) -> BuilderResult<()> {
+    let canonical = path.canonicalize().unwrap_or(path.to_owned());
+
+    if stack.contains(&canonical) {
+        return Err(err!(c_invalid_spec!(
+            "include",
+            format!("{} includes itself", path.display())
+        )));
+    }
+    if merged.contains(&canonical) {
+        return Ok(());
+    }
+
+    let fragment = SpecFragment::from_file(path)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    stack.push(canonical.clone());
+    for inner in fragment.include.iter().flatten() {
+        collect_fragment(&dir.join(inner), stack, merged, into)?;
+    }
+    stack.pop();
+    merged.push(canonical);
+
+    // later fragments win over
+    // earlier ones, like the spec.
+    merge_entries(&mut into.makeopts, fragment.makeopts, |m| m.key.clone());
+    merge_entries(&mut into.dynos_packs, fragment.dynos_packs, |p| {
+        p.name.clone()
+    });
+    merge_entries(&mut into.patches, fragment.patches, |p| p.name.clone());
+    merge_entries(&mut into.scripts, fragment.scripts, |s| s.name.clone());
+
+    Ok(())
+}
+
+/// Put the entries of `from` on top of those of
+/// `into`, replacing the ones with the same key.
+fn merge_entries<T, K: PartialEq>(
+    into: &mut Option<Vec<T>>,
+    from: Option<Vec<T>>,
+    key: impl Fn(&T) -> K,
+) {
+    let Some(from) = from else {
+        return;
+    };
+
+    let merged = into.get_or_insert_with(Vec::new);
+    merged.retain(|i| !from.iter().any(|f| key(f) == key(i)));
+    merged.extend(from);
+}
+
+/// Take the entries of `included` back out of
+/// `entries`, unless they have been changed.
+fn strip_entries<T: Serialize>(entries: &mut Option<Vec<T>>, included: &Option<Vec<T>>) {
+    let (Some(list), Some(included)) = (entries.as_mut(), included) else {
+        return;
+    };
+
+    let included: Vec<_> = included
+        .iter()
+        .filter_map(|i| serde_yaml::to_value(i).ok())
+        .collect();
+
+    list.retain(|e| {
+        serde_yaml::to_value(e)
+            .map(|v| !included.contains(&v))
+            .unwrap_or(true)
+    });
+
+    if list.is_empty() {
+        *entries = None;
+    }
+}
+
+/// Check every ROM with `check_rom`, and
+/// that no two are of the same region,
+/// as they would be copied over each other.
+fn check_roms<'r, I>(roms: I, repo_dir: Option<&Path>) -> BuilderResult<()>
+where
+    I: Iterator<Item = &'r Rom>,
+{
+    let mut baseroms: Vec<String> = Vec::new();
+
+    for rom in roms {
+        check_rom(rom, repo_dir)?;
+
+        let baserom = rom.region.baserom_name();
+        if baseroms.contains(&baserom) {
+            return Err(err!(
+                c_invalid_spec!(
+                    "extra_roms",
+                    format!("has more than one ROM of the region {}", rom.region)
+                ),
+                "whilst checking the ROMs"
+            ));
+        }
+        baseroms.push(baserom);
+    }
+
+    Ok(())
+}
+
+/// Check that the ROM exists, or that a base
+/// ROM in `repo_dir` can be used instead.
+///
+/// Without a `repo_dir`, a missing path is let
+/// through if the base ROM may be reused.
+fn check_rom(rom: &Rom, repo_dir: Option<&Path>) -> BuilderResult<()> {
+    if rom.path.is_file() {
+        return Ok(());
+    }
+
+    match repo_dir {
+        Some(dir) if rom.reusable_baserom(dir).is_some() => return Ok(()),
+        None if rom.reuse_baserom && rom.path.as_os_str().is_empty() => return Ok(()),
+        _ => (),
+    }
+
+    if rom.path.as_os_str().is_empty() {
+        let no_rom_error = std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "`rom.path` is not set, and there is no good {} in the repo",
+                rom.region.baserom_name()
+            ),
+        );
+        return Err(err!(c_fs!(no_rom_error), "no ROM was given"));
+    }
+
+    let file_not_found_error = std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("the file at {} was not found!", &rom.path.display()),
+    );
+    Err(err!(
+        c_fs!(file_not_found_error),
+        "the ROM file was not found"
+    ))
+}
+
+/// Check that the repo has a usable name,
+/// and a URL that git could clone from.
+fn check_repo(repo: &Repo) -> BuilderResult<()> {
+    let invalid = |field: &str, reason: &str| {
+        Err(err!(
+            c_invalid_spec!(format!("repo.{}", field), reason),
+            "whilst checking the spec"
+        ))
+    };
+
+    let name = repo.name.trim();
+    if name.is_empty() {
+        return invalid("name", "is empty");
+    }
+    if name.contains(['/', '\\']) || name == "." || name == ".." {
+        return invalid("name", "is not a valid directory name");
+    }
+
+    let url = repo.url.trim();
+    if url.is_empty() {
+        return invalid("url", "is empty");
+    }
+
+    if repo.branch.trim().is_empty() {
+        return invalid("branch", "is empty");
+    }
+    if url.contains(char::is_whitespace) {
+        return invalid("url", "contains whitespace");
+    }
+
+    // `scheme://...`, `user@host:path`
+    // (scp-like ssh), or a local repo.
+    let sane = match url.split_once("://") {
+        Some((scheme, rest)) => {
+            ["http", "https", "git", "ssh", "file"].contains(&scheme) && !rest.is_empty()
+        }
+        None => {
+            url.split_once(':').is_some_and(|(host, path)| {
+                host.contains('@') && !host.contains('/') && !path.is_empty()
+            }) || Path::new(url).is_dir()
+        }
+    };
+
+    if !sane {
+        return invalid("url", "is not a git URL");
+    }
+
+    if let Some(refspec) = &repo.refspec {
+        if refspec.trim().is_empty() {
+            return invalid("refspec", "is empty");
+        }
+        if refspec.contains([':', '*']) || refspec.contains(char::is_whitespace) {
+            return invalid("refspec", "must be a single ref, like refs/pull/123/head");
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that a prebuilt release has a
+/// URL and a well-formed checksum.
+fn check_source(source: &SourceKind) -> BuilderResult<()> {
+    let (url, checksum) = match source {
+        SourceKind::Source => return Ok(()),
+        SourceKind::Prebuilt { url, checksum, .. } => (url.trim(), checksum.trim()),
+    };
+
+    if url.is_empty() {
+        return Err(err!(c_invalid_spec!("source.url", "is empty")));
+    }
+
+    if checksum.len() != 64 || !checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(err!(c_invalid_spec!(
+            "source.checksum",
+            "is not a SHA-256 checksum (64 hex digits)"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Check that no two scripts, of any hook, have
+/// the same name, as they are written to the
+/// same folder under their names.
+fn check_scripts<'a, I: Iterator<Item = &'a PostBuildScript>>(scripts: I) -> BuilderResult<()> {
+    let mut names = Vec::new();
+
+    for script in scripts {
+        if names.contains(&script.name.as_str()) {
+            return Err(err!(c_invalid_spec!(
+                "hooks",
+                format!("has more than one script named `{}`", script.name)
+            )));
+        }
+
+        names.push(script.name.as_str());
+    }
+
+    Ok(())
+}
+
+/// Check that every configuration has a
+/// unique name that can be put in a path.
+fn check_configs<'a, I: Iterator<Item = &'a SpecConfig>>(configs: I) -> BuilderResult<()> {
+    let mut names = Vec::new();
+
+    for config in configs {
+        let name = config.name.as_str();
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+        if !valid {
+            return Err(err!(
+                c_invalid_spec!(
+                    "configs",
+                    format!("has a configuration with an invalid name (`{}`)", name)
+                ),
+                "names may only have letters, numbers, `-` and `_`"
+            ));
+        }
+
+        if names.contains(&name) {
+            return Err(err!(c_invalid_spec!(
+                "configs",
+                format!("has more than one configuration named `{}`", name)
+            )));
+        }
+
+        names.push(name);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: &str = r#"
+rom:
+  region: us
+  path: /roms/baserom.us.z64
+  format: rom_BigEndian
+repo:
+  name: sm64ex
+  url: https://github.com/sm64pc/sm64ex
+  branch: nightly
+  about: ""
+  supports_dynos: false
+name: sm64ex
+"#;
+
+    /// An empty directory for the
+    /// files of the test `name`.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("smbuilder-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn expands_vars_in_strings() {
+        let vars = vars(&[("port", "sm64ex"), ("api", "GL")]);
+
+        assert_eq!(
+            expand_str("{{port}}-{{ api }}/{{port}}", &vars, "name").unwrap(),
+            "sm64ex-GL/sm64ex"
+        );
+        // unclosed braces are left as they are.
+        assert_eq!(
+            expand_str("{{port}} {{api", &vars, "name").unwrap(),
+            "sm64ex {{api"
+        );
+    }
+
+    #[test]
+    fn rejects_undefined_vars() {
+        let err = expand_str("{{branch}}", &vars(&[]), "repo").unwrap_err();
+
+        assert!(matches!(
+            err.cause,
+            ErrorCause::InvalidSpec { ref field, .. } if field == "repo"
+        ));
+    }
+
+    #[test]
+    fn expands_vars_in_nested_values() {
+        let mut value: Value = serde_yaml::from_str(
+            "- key: RENDER_API\n  value: \"{{api}}\"\n- !tagged \"{{api}}\"\n- 1",
+        )
+        .unwrap();
+        expand_vars(&mut value, &vars(&[("api", "D3D11")]), "makeopts").unwrap();
+
+        let expected: Value =
+            serde_yaml::from_str("- key: RENDER_API\n  value: D3D11\n- !tagged D3D11\n- 1")
+                .unwrap();
+        assert_eq!(value, expected);
+    }
+
+    #[test]
+    fn rejects_include_cycles() {
+        let dir = test_dir("include-cycle");
+        fs::write(dir.join("a.yml"), "include: [b.yml]").unwrap();
+        fs::write(dir.join("b.yml"), "include: [a.yml]").unwrap();
+
+        fs::write(dir.join("spec.yml"), format!("{}include: [a.yml]\n", SPEC)).unwrap();
+        let err = Spec::from_file(dir.join("spec.yml")).unwrap_err();
+
+        assert!(matches!(
+            err.cause,
+            ErrorCause::InvalidSpec { ref field, .. } if field == "include"
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn merges_a_shared_include_once() {
+        let dir = test_dir("include-diamond");
+        fs::write(
+            dir.join("base.yml"),
+            "makeopts:\n  - key: DEBUG\n    value: \"0\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("left.yml"),
+            "include: [base.yml]\nmakeopts:\n  - key: DEBUG\n    value: \"1\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("right.yml"), "include: [base.yml]").unwrap();
+
+        // merging base.yml again for right.yml
+        // would undo what left.yml set.
+        for (include, debug) in [
+            ("[left.yml, right.yml]", "1"),
+            ("[base.yml, base.yml]", "0"),
+        ] {
+            fs::write(
+                dir.join("spec.yml"),
+                format!("{}include: {}\n", SPEC, include),
+            )
+            .unwrap();
+            let spec = Spec::from_file(dir.join("spec.yml")).unwrap();
+            let makeopts = spec.makeopts.unwrap_or_default();
+
+            assert_eq!(makeopts.len(), 1);
+            assert_eq!(makeopts[0].value, debug);
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn saves_the_vars_unexpanded() {
+        let dir = test_dir("vars-save");
+        let spec_path = dir.join("spec.yml");
+        fs::write(
+            &spec_path,
+            SPEC.replace("name: sm64ex\n", "name: \"{{port}}\"\n") + "vars:\n  port: sm64ex\n",
+        )
+        .unwrap();
+
+        let mut spec = Spec::from_file(&spec_path).unwrap();
+        assert_eq!(spec.name.as_deref(), Some("sm64ex"));
+        assert_eq!(spec.repo.name, "sm64ex");
+
+        // a changed value is saved as it is.
+        spec.repo.name = "sm64ex-alo".to_owned();
+        spec.to_file(&spec_path).unwrap();
+
+        let saved: Value = serde_yaml::from_str(&fs::read_to_string(&spec_path).unwrap()).unwrap();
+        assert_eq!(saved["name"], Value::from("{{port}}"));
+        assert_eq!(saved["repo"]["name"], Value::from("sm64ex-alo"));
+
+        let reloaded = Spec::from_file(&spec_path).unwrap();
+        assert_eq!(reloaded.name.as_deref(), Some("sm64ex"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn saves_only_the_own_entries() {
+        let dir = test_dir("include-save");
+        fs::write(
+            dir.join("opts.yml"),
+            "makeopts:\n  - key: BETTERCAMERA\n    value: \"1\"\n  - key: DEBUG\n    value: \"0\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("spec.yml"),
+            format!(
+                "{}include: [opts.yml]\nmakeopts:\n  - key: RENDER_API\n    value: GL\n",
+                SPEC
+            ),
+        )
+        .unwrap();
+
+        let mut spec = Spec::from_file(dir.join("spec.yml")).unwrap();
+        let keys = |spec: &Spec| -> Vec<String> {
+            spec.makeopts
+                .iter()
+                .flatten()
+                .map(|m| format!("{}={}", m.key, m.value))
+                .collect()
+        };
+        assert_eq!(keys(&spec), ["BETTERCAMERA=1", "DEBUG=0", "RENDER_API=GL"]);
+
+        // a changed entry is the spec's own now.
+        spec.makeopts.as_mut().unwrap()[1].value = "1".to_owned();
+        spec.to_file(dir.join("spec.yml")).unwrap();
+
+        let saved: Spec =
+            serde_yaml::from_str(&fs::read_to_string(dir.join("spec.yml")).unwrap()).unwrap();
+        assert_eq!(keys(&saved), ["DEBUG=1", "RENDER_API=GL"]);
+        assert_eq!(saved.include, Some(vec![PathBuf::from("opts.yml")]));
+
+        let reloaded = Spec::from_file(dir.join("spec.yml")).unwrap();
+        assert_eq!(
+            keys(&reloaded),
+            ["BETTERCAMERA=1", "DEBUG=1", "RENDER_API=GL"]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
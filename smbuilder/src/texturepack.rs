@@ -0,0 +1,317 @@
+use crate::builder::backend::{self, FsProvider, RealFs};
+use crate::util;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The folders of the asset dump that the
+/// textures of a pack are sorted into.
+const ASSET_DIRS: &[&str] = &["actors", "levels", "textures"];
+
+/// How deep into wrapper folders the
+/// textures of a pack are looked for.
+const MAX_DEPTH: usize = 3;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// How the textures of a pack are laid out,
+/// compared to the `gfx` folder in its root
+/// that every port installs packs from.
+pub enum PackLayout {
+    /// The textures are in `gfx`.
+    Gfx,
+    /// The textures are in `res/gfx`, like
+    /// in a copy of the `res` folder of a
+    /// build (as Render96 packs are often
+    /// shipped).
+    ResGfx,
+    /// The asset folders (`actors`, `levels`
+    /// and `textures`) are in the root,
+    /// without a `gfx` folder around them.
+    Bare,
+    /// The pack is in a folder of its own
+    /// (like the top folder of a zip), laid
+    /// out like the second field.
+    Nested(PathBuf, Box<PackLayout>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A file that is moved to normalize a pack,
+/// relative to the root of the pack.
+pub struct Move {
+    /// Where the file is.
+    pub from: PathBuf,
+    /// Where the file goes.
+    pub to: PathBuf,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// What normalizing a texture pack did,
+/// or would do, to it.
+pub struct NormalizeReport {
+    /// How the pack was laid out.
+    pub layout: PackLayout,
+    /// The files that are moved.
+    pub moves: Vec<Move>,
+}
+
+impl NormalizeReport {
+    /// Whether the pack is laid out
+    /// right already.
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+}
+
+/// Work out how to lay the texture pack in
+/// `root` out the way that ports install
+/// packs from: its PNGs in `gfx`, under the
+/// paths of the asset dump, with lowercase
+/// extensions.
+///
+/// Nothing is moved; see `normalize`.
+pub fn plan<P: AsRef<Path>>(root: P) -> io::Result<NormalizeReport> {
+    let root = root.as_ref();
+
+    let Some(layout) = detect_layout(root, Path::new(""), 0) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no textures were found in {}", root.display()),
+        ));
+    };
+
+    let (source, bare) = textures_dir(&layout);
+    let mut files = Vec::new();
+    util::list_files(&root.join(&source), Path::new(""), &mut files)?;
+    files.sort();
+
+    let moves = files
+        .into_iter()
+        .filter(|file| util::has_extension(file, "png"))
+        .filter(|file| !bare || ASSET_DIRS.iter().any(|name| file.starts_with(name)))
+        .map(|file| Move {
+            from: source.join(&file),
+            to: Path::new("gfx").join(file.with_extension("png")),
+        })
+        .filter(|m| m.from != m.to)
+        .collect();
+
+    Ok(NormalizeReport { layout, moves })
+}
+
+/// Lay the texture pack in `root` out the
+/// way that ports install packs from (see
+/// `plan`), returning what was moved.
+///
+/// With `dry_run`, nothing is moved, and
+/// the report says what would have been.
+pub fn normalize<P: AsRef<Path>>(root: P, dry_run: bool) -> io::Result<NormalizeReport> {
+    let root = root.as_ref();
+    let report = plan(root)?;
+
+    if dry_run {
+        return Ok(report);
+    }
+
+    for m in &report.moves {
+        let to = root.join(&m.to);
+
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // only the case of the extension changes
+        // for some, which some filesystems do not
+        // tell apart, so it goes via another name.
+        let from = root.join(&m.from);
+        let temp = to.with_extension("png.part");
+        fs::rename(&from, &temp)?;
+        fs::rename(&temp, &to)?;
+    }
+
+    let (source, bare) = textures_dir(&report.layout);
+    if bare {
+        for name in ASSET_DIRS {
+            let dir = root.join(&source).join(name);
+            if dir.is_dir() {
+                remove_empty_dirs(&dir)?;
+            }
+        }
+    } else if let Some(top) = source.components().next() {
+        // the folders around the textures,
+        // such as `res` and the pack's own.
+        remove_empty_dirs(&root.join(top))?;
+    }
+
+    Ok(report)
+}
+
+/// Find where the textures of a pack are,
+/// relative to its root.
+fn detect_layout(root: &Path, relative: &Path, depth: usize) -> Option<PackLayout> {
+    let dir = root.join(relative);
+
+    let layout = if dir.join("gfx").is_dir() {
+        Some(PackLayout::Gfx)
+    } else if dir.join("res").join("gfx").is_dir() {
+        Some(PackLayout::ResGfx)
+    } else if ASSET_DIRS.iter().any(|name| dir.join(name).is_dir()) {
+        Some(PackLayout::Bare)
+    } else {
+        None
+    };
+
+    if layout.is_some() || depth >= MAX_DEPTH {
+        return layout;
+    }
+
+    // a pack in a folder of its own has
+    // that folder, and maybe a readme.
+    let dirs: Vec<PathBuf> = fs::read_dir(&dir)
+        .ok()?
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| PathBuf::from(entry.file_name()))
+        .collect();
+
+    match dirs.as_slice() {
+        [only] => {
+            let inner = detect_layout(root, &relative.join(only), depth + 1)?;
+            Some(PackLayout::Nested(only.clone(), Box::new(inner)))
+        }
+        _ => None,
+    }
+}
+
+/// Gets the folder that the textures are in,
+/// relative to the root of the pack, and
+/// whether it is a bare asset dump.
+fn textures_dir(layout: &PackLayout) -> (PathBuf, bool) {
+    match layout {
+        PackLayout::Gfx => (PathBuf::from("gfx"), false),
+        PackLayout::ResGfx => (Path::new("res").join("gfx"), false),
+        PackLayout::Bare => (PathBuf::new(), true),
+        PackLayout::Nested(dir, inner) => {
+            let (inner_dir, bare) = textures_dir(inner);
+            (dir.join(inner_dir), bare)
+        }
+    }
+}
+
+/// Remove the folders under (and including)
+/// `dir` that are left empty.
+fn remove_empty_dirs(dir: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            remove_empty_dirs(&path)?;
+        }
+    }
+
+    if fs::read_dir(dir)?.next().is_none() {
+        fs::remove_dir(dir)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// How the textures of a pack line up
+/// with the textures that were dumped
+/// from the ROM, which are the only ones
+/// that the game loads.
+pub struct ValidationReport {
+    /// The number of dumped textures.
+    pub dumped: usize,
+    /// The number of dumped textures
+    /// that the pack replaces.
+    pub matched: usize,
+    /// Textures of the pack that are not where
+    /// the dumped texture of the same name is,
+    /// with where they should be.
+    pub misplaced: Vec<Move>,
+    /// Textures of the pack that no dumped
+    /// texture is named like, which are never
+    /// loaded.
+    pub orphaned: Vec<PathBuf>,
+}
+
+impl ValidationReport {
+    /// Whether every texture of the
+    /// pack would be loaded.
+    pub fn is_ok(&self) -> bool {
+        self.misplaced.is_empty() && self.orphaned.is_empty()
+    }
+}
+
+/// Check the textures in the `gfx` folder of
+/// the pack in `root` against the ones dumped
+/// from the ROM into the repo at `repo_dir`
+/// (its `actors`, `levels` and `textures`).
+///
+/// The assets must have been extracted
+/// (such as by building the port) first.
+pub fn validate<P, Q>(root: P, repo_dir: Q) -> io::Result<ValidationReport>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    validate_on(&RealFs, root, repo_dir)
+}
+
+/// Like `validate`, but
+/// reading from `fs`.
+pub fn validate_on<P, Q>(fs: &dyn FsProvider, root: P, repo_dir: Q) -> io::Result<ValidationReport>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let gfx = root.as_ref().join("gfx");
+    let repo_dir = repo_dir.as_ref();
+
+    let mut dumped = Vec::new();
+    for name in ASSET_DIRS {
+        if fs.is_dir(&repo_dir.join(name)) {
+            backend::list_files_on(fs, repo_dir, Path::new(name), &mut dumped)?;
+        }
+    }
+    dumped.retain(|file| util::has_extension(file, "png"));
+
+    if dumped.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "no textures have been dumped into {} yet",
+                repo_dir.display()
+            ),
+        ));
+    }
+
+    let mut textures = Vec::new();
+    backend::list_files_on(fs, &gfx, Path::new(""), &mut textures)?;
+    textures.retain(|file| util::has_extension(file, "png"));
+    textures.sort();
+
+    let mut report = ValidationReport {
+        dumped: dumped.len(),
+        ..Default::default()
+    };
+
+    for texture in textures {
+        if dumped.contains(&texture) {
+            report.matched += 1;
+            continue;
+        }
+
+        let name = texture.file_name();
+        match dumped.iter().find(|d| d.file_name() == name) {
+            Some(expected) => report.misplaced.push(Move {
+                from: Path::new("gfx").join(&texture),
+                to: Path::new("gfx").join(expected),
+            }),
+            None => report.orphaned.push(Path::new("gfx").join(texture)),
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,527 @@
+use crate::builder::state::BuildStateHandle;
+use crate::callback_types::{BuildEvent, LogType, OutputSeverity};
+use crate::error::ErrorCause;
+use crate::prelude::builder_types::BuilderResult;
+use crate::prelude::error_macros::*;
+use crate::prelude::{Builder, Error};
+use crate::util;
+
+use ratatui::buffer::Buffer;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Gauge, List, Paragraph, Widget};
+use ratatui::DefaultTerminal;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::mpsc::Receiver;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// The most lines of the log
+/// that the view keeps.
+const MAX_LOG_LINES: usize = 2000;
+
+/// How long to wait for a key
+/// before drawing again.
+const TICK: Duration = Duration::from_millis(50);
+
+/// The label of the compile, which has
+/// no stage of its own in the events.
+const COMPILING: &str = "Compiling the port";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// How far along a stage
+/// in the checklist is.
+pub enum StageStatus {
+    /// The stage is running.
+    Running,
+    /// The stage is done.
+    Done,
+    /// The build failed (or was
+    /// cancelled) in this stage.
+    Failed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    Running,
+    Cancelling,
+    Finished { succeeded: bool },
+}
+
+#[derive(Clone, Debug)]
+/// A view of a build, drawn from the events
+/// that `channel_bridge` sends: the stages as a
+/// checklist, the log, and a gauge of the
+/// progress of the stage that is running.
+///
+/// It can be drawn as a widget in any ratatui
+/// app; `run` draws it on its own.
+///
+/// # Example
+///
+/// ```no_run
+/// # use smbuilder::prelude::*;
+/// # use smbuilder::tui::BuildView;
+/// let (callbacks, events) = channel_bridge();
+/// let mut view = BuildView::new();
+///
+/// // in the app's draw loop:
+/// while let Ok(event) = events.try_recv() {
+///     view.handle(event);
+/// }
+/// // frame.render_widget(&view, frame.area());
+/// ```
+pub struct BuildView {
+    stages: Vec<(String, StageStatus)>,
+    log: VecDeque<(LogType, String)>,
+    percent: Option<f32>,
+    progress: String,
+    scroll: usize,
+    mode: Mode,
+}
+
+impl Default for BuildView {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BuildView {
+    /// Create a view of a build
+    /// that has not started.
+    pub fn new() -> Self {
+        BuildView {
+            stages: Vec::new(),
+            log: VecDeque::new(),
+            percent: None,
+            progress: String::new(),
+            scroll: 0,
+            mode: Mode::Running,
+        }
+    }
+
+    /// Update the view with an event of the build.
+    pub fn handle(&mut self, event: BuildEvent) {
+        match event {
+            BuildEvent::Log { log_type, text } => self.push_log(log_type, text),
+            BuildEvent::SetupStage(stage) => self.start_stage(stage.label()),
+            BuildEvent::PostBuildStage(stage) => self.start_stage(stage.label()),
+            BuildEvent::CustomStage(id) => self.start_stage(&id),
+            BuildEvent::PostBuildScript { name, .. } => {
+                self.progress = format!("Running {}", name);
+            }
+            BuildEvent::RepoCloneProgress {
+                received, total, ..
+            } => {
+                self.set_progress(received, total);
+                self.progress = format!("{}/{} objects", received, total);
+            }
+            BuildEvent::CompileProgress { percent } => {
+                if self.current_stage() != Some(COMPILING) {
+                    self.start_stage(COMPILING);
+                }
+                self.percent = Some(percent);
+            }
+            BuildEvent::PackInstallProgress {
+                pack,
+                installed,
+                total,
+                ..
+            } => {
+                self.set_progress(installed, total);
+                self.progress = format!("{} ({}/{} files)", pack, installed, total);
+            }
+        }
+    }
+
+    /// Mark the build as finished, ticking
+    /// off (or failing) the last stage.
+    pub fn finish(&mut self, succeeded: bool) {
+        if let Some((_, status)) = self.stages.last_mut() {
+            if *status == StageStatus::Running {
+                *status = if succeeded {
+                    StageStatus::Done
+                } else {
+                    StageStatus::Failed
+                };
+            }
+        }
+
+        self.mode = Mode::Finished { succeeded };
+    }
+
+    /// Show that the build
+    /// is being cancelled.
+    pub fn cancelling(&mut self) {
+        if self.mode == Mode::Running {
+            self.mode = Mode::Cancelling;
+        }
+    }
+
+    /// Scroll the log back by `lines`.
+    pub fn scroll_up(&mut self, lines: usize) {
+        self.scroll = (self.scroll + lines).min(self.log.len().saturating_sub(1));
+    }
+
+    /// Scroll the log forward by `lines`,
+    /// following it again at the end.
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.scroll = self.scroll.saturating_sub(lines);
+    }
+
+    /// Gets the stages that have
+    /// started, in order.
+    pub fn stages(&self) -> impl Iterator<Item = (&str, StageStatus)> {
+        self.stages
+            .iter()
+            .map(|(label, status)| (label.as_str(), *status))
+    }
+
+    fn current_stage(&self) -> Option<&str> {
+        self.stages.last().map(|(label, _)| label.as_str())
+    }
+
+    fn start_stage(&mut self, label: &str) {
+        if let Some((_, status)) = self.stages.last_mut() {
+            *status = StageStatus::Done;
+        }
+
+        self.stages.push((label.to_owned(), StageStatus::Running));
+        self.percent = None;
+        self.progress = label.to_owned();
+    }
+
+    fn set_progress(&mut self, done: usize, total: usize) {
+        if total > 0 {
+            self.percent = Some(done as f32 * 100.0 / total as f32);
+        }
+    }
+
+    fn push_log(&mut self, log_type: LogType, text: String) {
+        let text = util::strip_ansi(&text).into_owned();
+
+        if self.log.len() == MAX_LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back((log_type, text));
+
+        // the log stays where it was
+        // scrolled back to.
+        if self.scroll > 0 {
+            self.scroll = (self.scroll + 1).min(self.log.len().saturating_sub(1));
+        }
+    }
+
+    fn render_stages(&self, area: Rect, buf: &mut Buffer) {
+        let items = self.stages.iter().map(|(label, status)| {
+            let (mark, style) = match status {
+                StageStatus::Running => ("▶", Style::new().yellow().bold()),
+                StageStatus::Done => ("✓", Style::new().green()),
+                StageStatus::Failed => ("✗", Style::new().red().bold()),
+            };
+            Line::from(vec![
+                Span::styled(mark, style),
+                Span::raw(" "),
+                Span::raw(label),
+            ])
+        });
+
+        List::new(items)
+            .block(Block::bordered().title(" Stages "))
+            .render(area, buf);
+    }
+
+    fn render_log(&self, area: Rect, buf: &mut Buffer) {
+        let height = area.height.saturating_sub(2) as usize;
+        let end = self.log.len() - self.scroll.min(self.log.len());
+        let start = end.saturating_sub(height);
+
+        let lines: Vec<Line> = self
+            .log
+            .range(start..end)
+            .map(|(log_type, text)| Line::styled(text.as_str(), log_style(*log_type)))
+            .collect();
+
+        let title = if self.scroll > 0 {
+            format!(" Log (-{}) ", self.scroll)
+        } else {
+            " Log ".to_owned()
+        };
+
+        Paragraph::new(lines)
+            .block(Block::bordered().title(title))
+            .render(area, buf);
+    }
+
+    fn render_gauge(&self, area: Rect, buf: &mut Buffer) {
+        let label = match self.percent {
+            Some(percent) => format!("{:.0}%", percent),
+            None => String::new(),
+        };
+
+        Gauge::default()
+            .block(Block::bordered().title(format!(" {} ", self.progress)))
+            .gauge_style(Style::new().cyan())
+            .ratio((self.percent.unwrap_or_default() as f64 / 100.0).clamp(0.0, 1.0))
+            .label(label)
+            .render(area, buf);
+    }
+
+    fn render_footer(&self, area: Rect, buf: &mut Buffer) {
+        let footer = match self.mode {
+            Mode::Running => "q: cancel  ↑/↓ PgUp/PgDn: scroll the log".dim(),
+            Mode::Cancelling => "cancelling the build...".yellow(),
+            Mode::Finished { succeeded: true } => {
+                "the build finished! press any key to close".green()
+            }
+            Mode::Finished { succeeded: false } => "the build failed! press any key to close".red(),
+        };
+
+        Line::from(footer).render(area, buf);
+    }
+}
+
+impl Widget for &BuildView {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let [main, gauge, footer] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .areas(area);
+        let [stages, log] =
+            Layout::horizontal([Constraint::Length(36), Constraint::Min(10)]).areas(main);
+
+        self.render_stages(stages, buf);
+        self.render_log(log, buf);
+        self.render_gauge(gauge, buf);
+        self.render_footer(footer, buf);
+    }
+}
+
+fn log_style(log_type: LogType) -> Style {
+    match log_type {
+        LogType::Error
+        | LogType::BuildOutput {
+            severity: OutputSeverity::Error,
+        } => Style::new().fg(Color::Red),
+        LogType::Warn => Style::new().fg(Color::Magenta),
+        LogType::BuildOutput {
+            severity: OutputSeverity::Warning,
+        } => Style::new().fg(Color::Yellow),
+        LogType::Debug => Style::new().add_modifier(Modifier::DIM),
+        LogType::Info | LogType::BuildOutput { .. } => Style::new(),
+    }
+}
+
+/// Run a build with `build`, on another thread,
+/// whilst drawing a `BuildView` of it over the
+/// whole terminal.
+///
+/// `builder` must have been made with the
+/// callbacks of `channel_bridge`, whose receiver
+/// is `events`. `q`, escape or control-c cancel
+/// the build. Once it is done, the view stays
+/// up until a key is pressed.
+///
+/// # Example
+///
+/// ```no_run
+/// # use smbuilder::prelude::*;
+/// let (callbacks, events) = channel_bridge();
+/// let builder = Builder::new(Spec::default(), "base", callbacks).unwrap();
+/// let build = smbuilder::tui::run(builder, events, |builder| builder.build());
+/// ```
+pub fn run<T, F>(
+    builder: Builder<'static>,
+    events: Receiver<BuildEvent>,
+    build: F,
+) -> BuilderResult<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut Builder<'static>) -> BuilderResult<T> + Send + 'static,
+{
+    let state = builder.state_handle();
+
+    let mut terminal = match ratatui::try_init() {
+        Ok(t) => t,
+        Err(e) => {
+            ratatui::restore();
+            return Err(err!(c_other!(e), "failed to start the terminal UI"));
+        }
+    };
+
+    let mut builder = builder;
+    let thread = thread::spawn(move || build(&mut builder));
+
+    let mut view = BuildView::new();
+    let drawn = draw_build(&mut terminal, &mut view, &events, &state, &thread);
+    if drawn.is_err() {
+        // the build cannot be seen anymore,
+        // so it is stopped, rather than left
+        // to run on in the background.
+        state.cancel();
+    }
+
+    let result = match thread.join() {
+        Ok(result) => result,
+        Err(panic) => {
+            ratatui::restore();
+            std::panic::resume_unwind(panic)
+        }
+    };
+
+    let drawn = drawn.and_then(|_| {
+        while let Ok(event) = events.try_recv() {
+            view.handle(event);
+        }
+        view.finish(result.is_ok());
+        wait_for_key(&mut terminal, &view)
+    });
+    ratatui::restore();
+
+    match drawn {
+        Ok(_) => result,
+        Err(e) => Err(err!(c_other!(e), "failed to draw the terminal UI")),
+    }
+}
+
+/// Draw the view until the build is done,
+/// handling the keys that are pressed.
+fn draw_build<T>(
+    terminal: &mut DefaultTerminal,
+    view: &mut BuildView,
+    events: &Receiver<BuildEvent>,
+    state: &BuildStateHandle,
+    thread: &JoinHandle<T>,
+) -> io::Result<()> {
+    while !thread.is_finished() {
+        while let Ok(event) = events.try_recv() {
+            view.handle(event);
+        }
+        terminal.draw(|frame| frame.render_widget(&*view, frame.area()))?;
+
+        let Some(key) = next_key()? else {
+            continue;
+        };
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => cancel(view, state),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                cancel(view, state)
+            }
+            KeyCode::Up => view.scroll_up(1),
+            KeyCode::Down => view.scroll_down(1),
+            KeyCode::PageUp => view.scroll_up(10),
+            KeyCode::PageDown => view.scroll_down(10),
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Keep the finished view up
+/// until a key is pressed.
+fn wait_for_key(terminal: &mut DefaultTerminal, view: &BuildView) -> io::Result<()> {
+    loop {
+        terminal.draw(|frame| frame.render_widget(view, frame.area()))?;
+        if next_key()?.is_some() {
+            return Ok(());
+        }
+    }
+}
+
+fn cancel(view: &mut BuildView, state: &BuildStateHandle) {
+    view.cancelling();
+    state.cancel();
+}
+
+/// Wait a tick for a key to be pressed.
+fn next_key() -> io::Result<Option<KeyEvent>> {
+    if !event::poll(TICK)? {
+        return Ok(None);
+    }
+
+    match event::read()? {
+        Event::Key(key) if key.kind == KeyEventKind::Press => Ok(Some(key)),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder_types::{PostBuildStage, SetupStage};
+
+    fn rendered(view: &BuildView) -> String {
+        let area = Rect::new(0, 0, 100, 12);
+        let mut buf = Buffer::empty(area);
+        view.render(area, &mut buf);
+
+        (0..area.height)
+            .map(|y| {
+                (0..area.width)
+                    .map(|x| buf[(x, y)].symbol())
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn ticks_off_the_stages() {
+        let mut view = BuildView::new();
+
+        view.handle(BuildEvent::SetupStage(SetupStage::CloneRepo));
+        view.handle(BuildEvent::CompileProgress { percent: 42.0 });
+        view.handle(BuildEvent::Log {
+            log_type: LogType::BuildOutput {
+                severity: OutputSeverity::Normal,
+            },
+            text: "\x1b[32mcc -c -o a.o a.c\x1b[0m".to_owned(),
+        });
+
+        let screen = rendered(&view);
+        assert!(screen.contains("✓ Cloning the repository"));
+        assert!(screen.contains("▶ Compiling the port"));
+        assert!(screen.contains("cc -c -o a.o a.c"));
+        assert!(screen.contains("42%"));
+
+        view.handle(BuildEvent::PostBuildStage(PostBuildStage::TexturePack));
+        view.finish(false);
+        assert_eq!(
+            view.stages().map(|(_, status)| status).collect::<Vec<_>>(),
+            [StageStatus::Done, StageStatus::Done, StageStatus::Failed]
+        );
+        assert!(rendered(&view).contains("the build failed!"));
+    }
+
+    #[test]
+    fn keeps_the_log_where_it_was_scrolled() {
+        let mut view = BuildView::new();
+        let log = |view: &mut BuildView, text: &str| {
+            view.handle(BuildEvent::Log {
+                log_type: LogType::Info,
+                text: text.to_owned(),
+            })
+        };
+
+        for i in 0..20 {
+            log(&mut view, &format!("line {}", i));
+        }
+        view.scroll_up(15);
+        log(&mut view, "line 20");
+
+        let screen = rendered(&view);
+        assert!(screen.contains("Log (-16)"));
+        assert!(screen.contains("line 4"));
+        assert!(!screen.contains("line 20"));
+
+        view.scroll_down(100);
+        assert!(rendered(&view).contains("line 20"));
+    }
+}
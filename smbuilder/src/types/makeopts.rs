@@ -27,7 +27,6 @@ pub enum BaseMakeopts {
 }
 
 #[allow(missing_docs)]
-
 /// An extension of `BaseMakeopts`
 /// but with sm64ex-coop specific
 /// options.
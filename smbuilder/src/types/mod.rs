@@ -3,16 +3,19 @@
 /// generally set.
 pub mod makeopts;
 
-use crate::prelude::{builder_types::BuilderResult, Error};
-use crate::{c_fs, prelude::*};
-use std::{
-    fmt::Debug,
-    fs,
-    io::{self, BufWriter, Write},
-    path::Path,
+/// The `Pack` trait, shared by
+/// every kind of pack.
+pub mod pack;
+pub use pack::Pack;
+
+use crate::builder::backend::{FsProvider, RealFs};
+use crate::prelude::{
+    builder_types::{BuilderResult, InstallMode},
+    Error,
 };
+use crate::{c_fs, c_other, prelude::*, util};
+use std::{fmt::Debug, fs, io, path::Path};
 
-use fs_extra::dir::CopyOptions;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -31,6 +34,70 @@ pub enum Region {
     Jp,
     /// A rom pulled from a Japanese Shindou cartridge.
     Sh,
+    /// A rom pulled from an iQue Player
+    /// (Chinese), where the port supports it.
+    Cn,
+}
+
+impl Region {
+    /// Gets the value of the `VERSION`
+    /// makeopt for this region.
+    pub fn version(&self) -> String {
+        self.to_string()
+    }
+
+    /// Gets the file name that the port
+    /// expects the base ROM to have.
+    pub fn baserom_name(&self) -> String {
+        format!("baserom.{}.z64", self)
+    }
+
+    /// Gets the SHA-1 checksum of a
+    /// good, big endian dump of the
+    /// ROM for this region.
+    pub fn baserom_sha1(&self) -> &'static str {
+        use Region::*;
+
+        match self {
+            Us => "9bef1128717f958171a4afac3ed78ee2bb4e86ce",
+            Eu => "4ac5721683d0e0b6bbb561b58a71740845dceea9",
+            Jp => "8a20a5c83d6ceb0f0506cfc9fa20d8f438cafe51",
+            Sh => "3f319ae697533a255a1003d09202379d78d5a2e0",
+            Cn => "2e1db2780985a1f068077dc0444b685f39cd90ec",
+        }
+    }
+
+    /// Gets the name of the directory,
+    /// under `build`, that the port is
+    /// built into.
+    pub fn build_dir_name(&self) -> String {
+        format!("{}_pc", self)
+    }
+
+    /// Gets the languages that the game
+    /// text comes in, for this region.
+    ///
+    /// Only the EU version has more than one
+    /// (picked in the file select menu), and
+    /// its text is encoded differently, so
+    /// patches made against the US text tend
+    /// to break it.
+    pub fn languages(&self) -> &'static [&'static str] {
+        use Region::*;
+
+        match self {
+            Eu => &["en", "fr", "de"],
+            Jp | Sh => &["jp"],
+            Cn => &["cn"],
+            Us => &["en"],
+        }
+    }
+
+    /// Whether the region has more
+    /// than one language.
+    pub fn is_multilingual(&self) -> bool {
+        self.languages().len() > 1
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -40,9 +107,18 @@ pub struct Rom {
     /// the ROM was pulled from.
     pub region: Region,
     /// The path of the ROM file on disk.
+    ///
+    /// Can be left out if the repo already
+    /// has a good base ROM (see `reuse_baserom`).
+    #[serde(default)]
     pub path: PathBuf,
     /// The format of the ROM file.
     pub format: RomType,
+    /// Use the base ROM that is already in
+    /// the repo, if its checksum is right,
+    /// rather than requiring `path`.
+    #[serde(default = "default_true")]
+    pub reuse_baserom: bool,
 }
 
 impl Default for Rom {
@@ -51,6 +127,7 @@ impl Default for Rom {
             region: Region::Us,
             path: PathBuf::new(),
             format: RomType::BigEndian,
+            reuse_baserom: true,
         }
     }
 }
@@ -64,11 +141,23 @@ impl Rom {
             region,
             path: path.as_ref().to_owned(),
             format: rom_format,
+            reuse_baserom: true,
         }
     }
+
+    /// Gets the base ROM in `repo_dir` that
+    /// can be reused instead of `path`, if
+    /// reusing it is allowed, and it exists
+    /// with the right checksum.
+    pub fn reusable_baserom<P: AsRef<Path>>(&self, repo_dir: P) -> Option<PathBuf> {
+        let baserom_path = repo_dir.as_ref().join(self.region.baserom_name());
+
+        (self.reuse_baserom && crate::builder::baserom_is_good(&baserom_path, &self.region))
+            .then_some(baserom_path)
+    }
 }
 
-#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 /// Represents a git repository with the
 /// source code of the a port.
 pub struct Repo {
@@ -81,12 +170,665 @@ pub struct Repo {
     /// The link to the repository.
     pub url: String,
     /// The branch to clone from.
+    ///
+    /// `latest-release` clones the tag of the
+    /// newest release instead (see
+    /// `release::latest_release_tag`), which is
+    /// recorded in the lockfile.
+    ///
+    /// Can be left out for Render96ex,
+    /// if `channel` is set.
+    #[serde(default)]
     pub branch: String,
     /// The description of what the
     /// repo is, useful for launchers.
     pub about: String,
     /// Does this repo support DynOS packs?
     pub supports_dynos: bool,
+    /// Only fetch `branch`, rather than
+    /// every branch of the repo.
+    #[serde(default = "default_true")]
+    pub single_branch: bool,
+    /// Only fetch this many commits of
+    /// history, for a shallow clone.
+    ///
+    /// Needs the `git` command, as libgit2
+    /// cannot make shallow clones; the whole
+    /// history is fetched without it.
+    #[serde(default)]
+    pub depth: Option<u32>,
+    /// Make a partial clone, leaving out the
+    /// objects that the filter matches (like
+    /// `blob:none`, for every file that is not
+    /// checked out) until they are needed.
+    ///
+    /// Needs the `git` command, like `depth`.
+    /// Servers that do not support filters
+    /// send everything, and it is not used
+    /// with shared repos.
+    #[serde(default)]
+    pub filter: Option<String>,
+    /// Fetch the tags that point into the
+    /// fetched history.
+    #[serde(default = "default_true")]
+    pub tags: bool,
+    /// Build an arbitrary ref, such as a
+    /// pull request (`refs/pull/123/head`),
+    /// instead of the tip of `branch`.
+    ///
+    /// The ref is checked out on a local
+    /// branch named after `branch`.
+    #[serde(default)]
+    pub refspec: Option<String>,
+    /// The release channel of Render96ex to
+    /// build, which picks `branch` if it is
+    /// left out.
+    #[serde(default)]
+    pub channel: Option<Render96Channel>,
+}
+
+impl Default for Repo {
+    fn default() -> Self {
+        Repo {
+            name: String::new(),
+            url: String::new(),
+            branch: String::new(),
+            about: String::new(),
+            supports_dynos: false,
+            single_branch: true,
+            depth: None,
+            filter: None,
+            tags: true,
+            refspec: None,
+            channel: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// A port that needs building, running or
+/// installing into differently from sm64ex,
+/// as told from the URL of its repo.
+pub enum Port {
+    /// sm64ex, or a fork that
+    /// works the same way.
+    #[default]
+    Generic,
+    /// sm64ex-coop, which keeps its
+    /// saves apart from sm64ex's.
+    Coop,
+    /// sm64coopdx, which names its executable
+    /// after itself, has no `EXTERNAL_DATA`
+    /// option and always supports DynOS.
+    CoopDx,
+    /// Render96ex, which has a stable and an
+    /// alpha channel, whose packs are not
+    /// always interchangeable.
+    Render96,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+/// A release channel of Render96ex.
+pub enum Render96Channel {
+    /// The `master` branch.
+    Stable,
+    /// The `alpha` branch.
+    Alpha,
+}
+
+impl Render96Channel {
+    /// Gets the branch of the channel.
+    pub fn branch(&self) -> &'static str {
+        match self {
+            Render96Channel::Stable => "master",
+            Render96Channel::Alpha => "alpha",
+        }
+    }
+
+    /// Gets the channel that a branch is
+    /// of, for branches of Render96ex (and
+    /// its forks, like `alpha_macos`).
+    pub fn from_branch(branch: &str) -> Option<Render96Channel> {
+        match branch.trim() {
+            "master" | "main" => Some(Render96Channel::Stable),
+            b if b.starts_with("alpha") => Some(Render96Channel::Alpha),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Render96Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Render96Channel::Stable => write!(f, "stable"),
+            Render96Channel::Alpha => write!(f, "alpha"),
+        }
+    }
+}
+
+impl Port {
+    /// Gets the makeopts (from
+    /// `Makeopt::default_makeopts`) that
+    /// the port does not know about.
+    pub fn unsupported_makeopts(&self) -> &'static [&'static str] {
+        match self {
+            Port::Generic | Port::Coop | Port::Render96 => &[],
+            Port::CoopDx => &["EXTERNAL_DATA"],
+        }
+    }
+
+    /// Gets the name of the executable that the
+    /// port builds, if it does not follow the
+    /// `sm64.<region>.<microcode>` scheme.
+    pub fn executable_name(&self, windows: bool) -> Option<String> {
+        let name = match self {
+            Port::Generic | Port::Coop | Port::Render96 => return None,
+            Port::CoopDx => "sm64coopdx",
+        };
+
+        if windows {
+            Some(format!("{}.exe", name))
+        } else {
+            Some(name.to_owned())
+        }
+    }
+
+    /// Gets the name of the directory that
+    /// the port keeps its saves and settings
+    /// in (see `paths::game_data_dir`).
+    pub fn user_data_name(&self) -> &'static str {
+        match self {
+            Port::Generic | Port::Render96 => "sm64ex",
+            Port::Coop => "sm64ex-coop",
+            Port::CoopDx => "sm64coopdx",
+        }
+    }
+}
+
+impl Repo {
+    /// Gets the port that the repo is of.
+    pub fn port(&self) -> Port {
+        let url = self.url.to_lowercase();
+        let url = url.trim_end_matches('/').trim_end_matches(".git");
+
+        if url.ends_with("/sm64coopdx") {
+            Port::CoopDx
+        } else if url.ends_with("/sm64ex-coop") {
+            Port::Coop
+        } else if url.contains("/render96ex") {
+            Port::Render96
+        } else {
+            Port::Generic
+        }
+    }
+
+    /// Fill `branch` in from `channel`, if
+    /// it was left out (for Render96ex).
+    pub fn resolve_channel(&mut self) {
+        if let (Some(channel), Port::Render96) = (self.channel, self.port()) {
+            if self.branch.trim().is_empty() {
+                self.branch = channel.branch().to_owned();
+            }
+        }
+    }
+
+    /// Gets the channel of Render96ex that
+    /// is built: `channel`, or else the one
+    /// that `branch` is of.
+    ///
+    /// `None` for other ports.
+    pub fn render96_channel(&self) -> Option<Render96Channel> {
+        if self.port() != Port::Render96 {
+            return None;
+        }
+
+        self.channel
+            .or_else(|| Render96Channel::from_branch(&self.branch))
+    }
+
+    /// Whether DynOS packs can be installed
+    /// into the port, either because the repo
+    /// says so or the port is known to.
+    pub fn has_dynos(&self) -> bool {
+        self.supports_dynos || self.port() == Port::CoopDx
+    }
+
+    /// Gets the refspecs to fetch
+    /// the repo with.
+    pub fn refspecs(&self) -> Vec<String> {
+        if let Some(refspec) = self.full_refspec() {
+            vec![format!("+{}:{}", refspec, self.remote_ref())]
+        } else if self.single_branch {
+            vec![format!(
+                "+refs/heads/{0}:refs/remotes/origin/{0}",
+                self.branch
+            )]
+        } else {
+            vec!["+refs/heads/*:refs/remotes/origin/*".to_owned()]
+        }
+    }
+
+    /// Gets the ref that the fetched
+    /// commit ends up in.
+    pub fn remote_ref(&self) -> String {
+        match self.full_refspec() {
+            Some(_) => "refs/smbuilder/fetched".to_owned(),
+            None => format!("refs/remotes/origin/{}", self.branch),
+        }
+    }
+
+    /// `refspec`, with `refs/` in
+    /// front if it was left out.
+    fn full_refspec(&self) -> Option<String> {
+        let refspec = self.refspec.as_deref()?.trim();
+
+        if refspec.starts_with("refs/") {
+            Some(refspec.to_owned())
+        } else {
+            Some(format!("refs/{}", refspec))
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// Where the port comes from.
+pub enum SourceKind {
+    /// Compile it from the repository.
+    #[default]
+    Source,
+    /// Download a release that the port
+    /// publishes prebuilt (like coopdx),
+    /// skipping compilation entirely, for
+    /// devices that cannot compile it.
+    ///
+    /// The archive (`.zip`, or a tarball) is
+    /// unpacked into the build directory, and
+    /// the post-build stages run as usual.
+    Prebuilt {
+        /// The URL of the release archive.
+        url: String,
+        /// The SHA-256 checksum of
+        /// the release archive.
+        checksum: String,
+        /// The path of the executable within
+        /// the archive, if it cannot be found
+        /// by looking for executable files.
+        #[serde(default)]
+        executable: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// A named configuration of a spec, such
+/// as `vanilla`, `hd` or `debug`.
+///
+/// Its settings are applied on top of
+/// the rest of the spec.
+pub struct SpecConfig {
+    /// The name of the configuration.
+    pub name: String,
+    /// Makeopts to add to (or override
+    /// those of) the spec.
+    pub makeopts: Option<Vec<Makeopt>>,
+    /// Overrides `debug`.
+    pub debug: Option<bool>,
+    /// Overrides the texture pack.
+    pub texture_pack: Option<TexturePack>,
+    /// Overrides the DynOS packs.
+    pub dynos_packs: Option<Vec<DynosPack>>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// A file of lists that are shared between
+/// many specs, which name it in `include`.
+///
+/// ```yaml
+/// makeopts:
+///   - key: BETTERCAMERA
+///     value: "1"
+/// dynos_packs:
+///   - name: Mario
+///     path: /packs/mario
+/// ```
+pub struct SpecFragment {
+    /// Other fragments to include,
+    /// relative to this one.
+    #[serde(default)]
+    pub include: Option<Vec<PathBuf>>,
+    /// Makeopts to add.
+    #[serde(default)]
+    pub makeopts: Option<Vec<Makeopt>>,
+    /// DynOS packs to add.
+    #[serde(default)]
+    pub dynos_packs: Option<Vec<DynosPack>>,
+    /// Patches to add.
+    #[serde(default)]
+    pub patches: Option<Vec<Patch>>,
+    /// Post-build scripts to add.
+    #[serde(default)]
+    pub scripts: Option<Vec<PostBuildScript>>,
+}
+
+impl SpecFragment {
+    /// Load a fragment from a file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> BuilderResult<SpecFragment> {
+        let path = path.as_ref();
+
+        let contents = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) => {
+                let msg = format!("failed to read {}", path.display());
+                return Err(err!(c_fs!(e, msg), "whilst including a spec fragment"));
+            }
+        };
+
+        match serde_yaml::from_str::<SpecFragment>(&contents) {
+            Ok(f) => Ok(f),
+            Err(e) => {
+                let msg = format!("failed to parse the spec fragment {}", path.display());
+                Err(err!(c_other!(e), msg))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// Settings for shrinking the executable
+/// once it has been built, say for handhelds
+/// with little storage.
+pub struct StripConfig {
+    /// Also compress the executable
+    /// with UPX, at the cost of it
+    /// starting a little slower.
+    #[serde(default)]
+    pub compress: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Settings for naming the build inside
+/// of the game, so that players can tell
+/// which build they are running when they
+/// report a problem.
+///
+/// ```yaml
+/// identity:
+///   makeopt: VERSION_TEXT
+///   format: "{name} {version} ({commit})"
+/// ```
+pub struct IdentityConfig {
+    /// The makeopt (or define) that the
+    /// port reads the build string from.
+    #[serde(default = "IdentityConfig::default_makeopt")]
+    pub makeopt: String,
+    /// The build string, where `{name}`,
+    /// `{version}` and `{commit}` are the
+    /// name and version of the spec, and the
+    /// short hash of the commit that is built.
+    #[serde(default = "IdentityConfig::default_format")]
+    pub format: String,
+}
+
+impl IdentityConfig {
+    fn default_makeopt() -> String {
+        "VERSION_TEXT".to_owned()
+    }
+
+    fn default_format() -> String {
+        "{name} ({commit})".to_owned()
+    }
+
+    /// Fill in the build string.
+    pub fn text(&self, name: &str, version: &str, commit: &str) -> String {
+        self.format
+            .replace("{name}", name)
+            .replace("{version}", version)
+            .replace("{commit}", commit)
+    }
+}
+
+impl Default for IdentityConfig {
+    fn default() -> Self {
+        IdentityConfig {
+            makeopt: IdentityConfig::default_makeopt(),
+            format: IdentityConfig::default_format(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// The Discord game SDK, which sm64ex-coop
+/// links against when it is built with
+/// `DISCORD_SDK=1`, but does not ship.
+///
+/// It is downloaded (and cached) before the
+/// build, its library for the host is placed
+/// where the makefile looks for it, and
+/// `DISCORD_SDK=1` is set.
+///
+/// ```yaml
+/// discord_sdk:
+///   checksum: <sha256 of the zip>
+/// ```
+pub struct DiscordSdk {
+    /// Where the zip of the SDK is
+    /// downloaded from.
+    #[serde(default = "DiscordSdk::default_url")]
+    pub url: String,
+    /// The SHA-256 checksum of the zip.
+    pub checksum: String,
+}
+
+impl DiscordSdk {
+    fn default_url() -> String {
+        "https://dl-game-sdk.discordapp.net/2.5.6/discord_game_sdk.zip".to_owned()
+    }
+
+    /// Gets the directory of the repo that
+    /// the makefile looks for the SDK in.
+    pub fn install_dir<P: AsRef<Path>>(repo_dir: P) -> PathBuf {
+        repo_dir.as_ref().join("lib").join("discordsdk")
+    }
+
+    /// Gets the files that are needed from the
+    /// zip for the host, as pairs of their path
+    /// in the zip and the name that the makefile
+    /// expects them under.
+    pub fn host_files() -> Vec<(PathBuf, &'static str)> {
+        let arch = if cfg!(target_arch = "aarch64") {
+            "aarch64"
+        } else if cfg!(target_arch = "x86") {
+            "x86"
+        } else {
+            "x86_64"
+        };
+        let lib_dir = Path::new("lib").join(arch);
+
+        let mut files = vec![(
+            Path::new("c").join("discord_game_sdk.h"),
+            "discord_game_sdk.h",
+        )];
+
+        if cfg!(windows) {
+            files.push((lib_dir.join("discord_game_sdk.dll"), "discord_game_sdk.dll"));
+            files.push((
+                lib_dir.join("discord_game_sdk.dll.lib"),
+                "discord_game_sdk.dll.lib",
+            ));
+        } else if cfg!(target_os = "macos") {
+            files.push((
+                lib_dir.join("discord_game_sdk.dylib"),
+                "libdiscord_game_sdk.dylib",
+            ));
+        } else {
+            files.push((
+                lib_dir.join("discord_game_sdk.so"),
+                "libdiscord_game_sdk.so",
+            ));
+        }
+
+        files
+    }
+
+    /// Whether the files for the host are
+    /// in the repo at `repo_dir` already.
+    pub fn is_installed<P: AsRef<Path>>(repo_dir: P) -> bool {
+        DiscordSdk::is_installed_on(&RealFs, repo_dir)
+    }
+
+    /// Like `is_installed`, but
+    /// looking on `fs`.
+    pub fn is_installed_on<P: AsRef<Path>>(fs: &dyn FsProvider, repo_dir: P) -> bool {
+        let install_dir = DiscordSdk::install_dir(repo_dir);
+
+        DiscordSdk::host_files()
+            .iter()
+            .all(|(_, name)| fs.exists(&install_dir.join(name)))
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// Controller mappings to install next to
+/// the executable after building, so that
+/// gamepads work without any setting up.
+///
+/// ```yaml
+/// controllers:
+///   db: gamecontrollerdb.txt
+///   config: [controls/sm64config.txt]
+/// ```
+pub struct ControllerConfig {
+    /// An SDL `gamecontrollerdb.txt`, installed
+    /// under that name whatever it is called.
+    #[serde(default)]
+    pub db: Option<PathBuf>,
+    /// Controller config files of the port,
+    /// installed with the names they have.
+    #[serde(default)]
+    pub config: Option<Vec<PathBuf>>,
+}
+
+impl ControllerConfig {
+    /// The name that SDL ports look for
+    /// their controller mappings under.
+    pub const DB_FILE_NAME: &'static str = "gamecontrollerdb.txt";
+
+    /// Gets the files to install, as pairs of
+    /// their path and the name that they are
+    /// installed under.
+    pub fn files(&self) -> Vec<(PathBuf, PathBuf)> {
+        let db = self
+            .db
+            .iter()
+            .map(|db| (db.clone(), PathBuf::from(Self::DB_FILE_NAME)));
+
+        let config = self.config.iter().flatten().map(|file| {
+            let name = PathBuf::from(file.file_name().unwrap_or_default());
+            (file.clone(), name)
+        });
+
+        db.chain(config).collect()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Save files to start the game with, such
+/// as a 120 star save for a modpack, which
+/// are copied into the directory that the
+/// game keeps its saves in after building.
+///
+/// Saves that are already there are backed up
+/// into `save_backups` in the base dir first.
+/// They are only installed again when they
+/// change, so that the player's progress is
+/// not reset by every build.
+///
+/// ```yaml
+/// save_files:
+///   path: saves/120-stars.bin
+/// ```
+pub struct SaveFiles {
+    /// A save file, or a folder of them,
+    /// which are installed with the same
+    /// names (and folders) as they have.
+    pub path: PathBuf,
+    /// The directory that the game keeps
+    /// its saves in, if it is not the
+    /// usual one of the port.
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+}
+
+impl SaveFiles {
+    /// Gets the paths of the save files,
+    /// relative to `path` (or its name,
+    /// if it is a single file).
+    pub fn files(&self) -> io::Result<Vec<PathBuf>> {
+        if self.path.is_file() {
+            let name = self.path.file_name().unwrap_or_default();
+            return Ok(vec![PathBuf::from(name)]);
+        }
+
+        let mut files = Vec::new();
+        util::list_files(&self.path, Path::new(""), &mut files)?;
+        files.sort();
+
+        Ok(files)
+    }
+
+    /// Gets the path of one of the
+    /// save files from `files`.
+    pub fn source(&self, file: &Path) -> PathBuf {
+        if self.path.is_file() {
+            self.path.clone()
+        } else {
+            self.path.join(file)
+        }
+    }
+
+    /// Hash the names and the contents of
+    /// the save files, to tell when they
+    /// have changed.
+    pub fn hash(&self) -> io::Result<String> {
+        let mut hashes = String::new();
+
+        for file in self.files()? {
+            let hash = util::sha256_file_hex(self.source(&file))?;
+            hashes.push_str(&format!("{} {}\n", hash, file.display()));
+        }
+
+        Ok(util::sha256_hex(hashes))
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// Settings for how the port is built.
+pub struct BuildConfig {
+    /// The command that make is run with,
+    /// such as `gmake`, `bear -- make` (to
+    /// record the compile commands), or a
+    /// wrapper script.
+    ///
+    /// `-C`, the makeopts and `-j` are still
+    /// appended to it by smbuilder.
+    pub make_command: Option<String>,
+    /// A complete build script, in shell
+    /// format, to use instead of the one that
+    /// smbuilder generates, for ports that are
+    /// not built with a single make invocation.
+    ///
+    /// It is run from the base directory, with
+    /// the make targets to build (if any) as its
+    /// arguments, and `SMBUILDER_REPO_DIR`,
+    /// `SMBUILDER_JOBS` and `SMBUILDER_MAKEOPTS`
+    /// in its environment.
+    pub script: Option<String>,
+    /// Like `script`, but read from a file.
+    pub script_path: Option<PathBuf>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -111,6 +853,61 @@ pub struct Patch {
     /// The location of the
     /// path file on disk.
     pub path: PathBuf,
+
+    /// Where to download the patch
+    /// to `path` from, if it is not
+    /// there yet.
+    #[serde(default)]
+    pub remote: Option<RemoteResource>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// A file that a pack or patch is downloaded
+/// from, before building, if it is not on
+/// disk yet.
+///
+/// Packs are downloaded as zips, and unpacked
+/// into their `path`.
+///
+/// ```yaml
+/// texture_pack:
+///   name: Render96 HD
+///   path: packs/render96-hd
+///   remote:
+///     url: https://example.com/render96-hd.zip
+///     checksum: <sha256 of the zip>
+/// ```
+pub struct RemoteResource {
+    /// Where the file is
+    /// downloaded from.
+    pub url: String,
+    /// The SHA-256 checksum of the file.
+    pub checksum: String,
+}
+
+impl Patch {
+    /// Whether the patch changes the game
+    /// text (the dialogs, course names and
+    /// the like), going by the files that
+    /// its diff headers name.
+    ///
+    /// Returns `false` if the patch
+    /// cannot be read.
+    pub fn touches_text(&self) -> bool {
+        let Ok(contents) = fs::read(&self.path) else {
+            return false;
+        };
+
+        String::from_utf8_lossy(&contents)
+            .lines()
+            .filter(|ln| ln.starts_with("+++ ") || ln.starts_with("diff --git "))
+            .any(|ln| {
+                ln.contains("/text/")
+                    || ln.contains(" text/")
+                    || ln.contains("dialog")
+                    || ln.contains("charmap")
+            })
+    }
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -124,6 +921,17 @@ pub struct TexturePack {
     /// The location of the
     /// texture pack on disk,
     pub path: PathBuf,
+
+    /// The channel of Render96ex that
+    /// the pack was made for, if any.
+    #[serde(default)]
+    pub channel: Option<Render96Channel>,
+
+    /// Where to download the pack
+    /// from, if `path` does not
+    /// exist yet.
+    #[serde(default)]
+    pub remote: Option<RemoteResource>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -147,6 +955,17 @@ pub struct DynosPack {
     /// The location of
     /// the pack, on disk.
     pub path: PathBuf,
+
+    /// The channel of Render96ex that
+    /// the pack was made for, if any.
+    #[serde(default)]
+    pub channel: Option<Render96Channel>,
+
+    /// Where to download the pack
+    /// from, if `path` does not
+    /// exist yet.
+    #[serde(default)]
+    pub remote: Option<RemoteResource>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -163,6 +982,13 @@ pub struct PostBuildScript {
     /// The contents of the
     /// script, in shell format.
     pub contents: String,
+    /// Where the script runs among the
+    /// others of its hook, lowest first.
+    ///
+    /// Scripts of the same order run in
+    /// the order that they are listed in.
+    #[serde(default)]
+    pub order: i32,
     /// The path to the build
     /// script on disk. Will be
     /// `None` if the script
@@ -171,6 +997,62 @@ pub struct PostBuildScript {
     pub path: Option<PathBuf>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// A point of the build that
+/// scripts can be hooked into.
+pub enum HookPoint {
+    /// After the repo is cloned
+    /// or updated.
+    AfterClone,
+    /// Before the port is compiled,
+    /// every time that it is.
+    BeforeCompile,
+    /// At the end of the build, after
+    /// the other post-build stages.
+    AfterPostBuild,
+}
+
+impl HookPoint {
+    /// Every hook point, in the
+    /// order that they run in.
+    pub const ALL: [HookPoint; 3] = [
+        HookPoint::AfterClone,
+        HookPoint::BeforeCompile,
+        HookPoint::AfterPostBuild,
+    ];
+}
+
+impl std::fmt::Display for HookPoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let result = match self {
+            HookPoint::AfterClone => "after_clone",
+            HookPoint::BeforeCompile => "before_compile",
+            HookPoint::AfterPostBuild => "after_post_build",
+        };
+
+        write!(f, "{}", result)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// Scripts to run at points of the build,
+/// keyed by the `HookPoint` in a spec.
+pub struct Hooks {
+    /// The scripts to run after the
+    /// repo is cloned or updated.
+    #[serde(default)]
+    pub after_clone: Option<Vec<PostBuildScript>>,
+    /// The scripts to run before the
+    /// port is compiled.
+    #[serde(default)]
+    pub before_compile: Option<Vec<PostBuildScript>>,
+    /// The scripts to run at the
+    /// end of the build.
+    #[serde(default)]
+    pub after_post_build: Option<Vec<PostBuildScript>>,
+}
+
 impl Makeopt {
     /// Creates a new `Makeopt`.
     ///
@@ -185,6 +1067,9 @@ impl Makeopt {
     /// Gets a list of default makeopts with
     /// sane defaults, and options for the
     /// current OS.
+    ///
+    /// The APIs that suit the host are picked
+    /// by the builder (see `Spec::set_host`).
     // TODO: example
     pub fn default_makeopts() -> Vec<Self> {
         let mut makeopts: Vec<Makeopt> = Vec::new();
@@ -199,12 +1084,6 @@ impl Makeopt {
         // enable external data
         push_makeopt!("EXTERNAL_DATA", "1");
 
-        // force the modern APIs
-        push_makeopt!("RENDER_API", "GL");
-        push_makeopt!("WINDOW_API", "SDL2");
-        push_makeopt!("AUDIO_API", "SDL2");
-        push_makeopt!("CONTROLLER_API", "SDL2");
-
         // macOS stuff
         #[cfg(target_os = "macos")]
         {
@@ -229,6 +1108,7 @@ impl PostBuildScript {
             name: name.to_string(),
             description: description.to_string(),
             contents: contents.to_string(),
+            order: 0,
             path: None,
         }
     }
@@ -254,6 +1134,7 @@ impl PostBuildScript {
             name: name.to_string(),
             description: description.to_string(),
             contents: file_contents,
+            order: 0,
             path: None,
         };
         Ok(res)
@@ -268,38 +1149,47 @@ impl PostBuildScript {
         let mut script_path = scripts_dir.as_ref().join(&self.name);
         script_path.set_extension("sh");
 
-        let script_file = match fs::File::create(&script_path) {
-            Ok(f) => f,
-            Err(e) => {
-                let err = err!(
-                    c_fs!(
-                        e,
-                        format!("whilst trying to create {}", script_path.display())
-                    ),
-                    "failed to create the script file"
-                );
-                return Err(err);
-            }
-        };
+        // an interrupted write would leave a truncated
+        // script behind, which would still be run.
+        if let Err(e) = util::write_atomically(&script_path, &self.contents) {
+            return Err(err!(
+                c_fs!(
+                    e,
+                    format!("whilst trying to write to {}", script_path.display())
+                ),
+                "failed to write to the script file"
+            ));
+        }
 
-        let mut script_file = BufWriter::new(script_file);
+        self.path = Some(script_path.clone());
+        Ok(script_path)
+    }
+}
 
-        match script_file.write_all(self.contents.as_bytes()) {
-            Ok(_) => (),
-            Err(e) => {
-                let err = err!(
-                    c_fs!(
-                        e,
-                        format!("whilst trying to write to {}", script_path.display())
-                    ),
-                    "failed to write to the script file"
-                );
-                return Err(err);
-            }
+impl Hooks {
+    /// Gets the scripts hooked
+    /// into `point`, unordered.
+    pub fn at(&self, point: HookPoint) -> &[PostBuildScript] {
+        let scripts = match point {
+            HookPoint::AfterClone => &self.after_clone,
+            HookPoint::BeforeCompile => &self.before_compile,
+            HookPoint::AfterPostBuild => &self.after_post_build,
         };
 
-        self.path = Some(script_path.clone());
-        Ok(script_path)
+        scripts.as_deref().unwrap_or_default()
+    }
+
+    /// Gets every script of
+    /// every hook, mutably.
+    pub fn scripts_mut(&mut self) -> impl Iterator<Item = &mut PostBuildScript> {
+        [
+            &mut self.after_clone,
+            &mut self.before_compile,
+            &mut self.after_post_build,
+        ]
+        .into_iter()
+        .flatten()
+        .flatten()
     }
 }
 
@@ -315,6 +1205,8 @@ impl DynosPack {
         DynosPack {
             name: name.to_string(),
             path: path.into(),
+            channel: None,
+            remote: None,
         }
     }
 
@@ -328,7 +1220,7 @@ impl DynosPack {
         repo_dir: P,
         callbacks: &mut Callbacks,
     ) -> BuilderResult<()> {
-        if !spec.repo.supports_dynos {
+        if !spec.repo.has_dynos() {
             run_callback!(
                 callbacks.log_cb,
                 types::LogType::Warn,
@@ -337,50 +1229,30 @@ impl DynosPack {
             return Ok(());
         }
 
-        let target_path = repo_dir
-            .as_ref()
-            .join("build")
-            .join(format!("{}_pc", spec.rom.region.to_string()))
-            .join("dynos")
-            .join("packs");
-
-        match fs_extra::dir::copy(&self.path, &target_path, &CopyOptions::new()) {
-            Ok(_) => (),
-            Err(e) => {
-                let msg = format!(
-                    "whilst copying the DynOS pack from {} to {}: {}",
-                    &self.path.display(),
-                    &target_path.display(),
-                    e
-                );
-                let err = err!(c_fs!(e, msg), "failed to copy the DynOS pack");
-                return Err(err);
-            }
-        };
+        Pack::install(
+            self,
+            spec,
+            repo_dir.as_ref(),
+            InstallMode::default(),
+            &mut |_, _, _| (),
+        )
+    }
 
-        Ok(())
+    /// Gets the directory that DynOS packs
+    /// are installed into, for a spec.
+    pub fn install_dir<P: AsRef<Path>>(spec: &Spec, repo_dir: P) -> PathBuf {
+        spec.build_dir(repo_dir).join("dynos").join("packs")
     }
 
     /// Permanently removes the pack
     /// from disk, effectively uninstalling
     /// it.
+    ///
+    /// Like `Pack::uninstall`, but panics
+    /// if the pack cannot be removed.
     pub fn remove<P: AsRef<Path>>(&self, spec: &Spec, repo_dir: P) {
-        let pack_filename = self
-            .path
-            .iter()
-            .last()
-            .expect("the DynOS pack should have a filename!");
-
-        let target_path = repo_dir
-            .as_ref()
-            .join("build")
-            .join(format!("{}_pc", spec.rom.region.to_string()))
-            .join("dynos")
-            .join("packs")
-            .join(pack_filename);
-
-        fs_extra::dir::remove(target_path)
-            .unwrap_or_else(|e| panic!("failed to remove the directory: {}", e));
+        Pack::uninstall(self, &spec.build_dir(repo_dir))
+            .unwrap_or_else(|e| panic!("failed to remove the DynOS pack: {}", e));
     }
 }
 
@@ -396,6 +1268,8 @@ impl TexturePack {
         TexturePack {
             name: name.to_string(),
             path: path.into(),
+            channel: None,
+            remote: None,
         }
     }
 
@@ -404,51 +1278,45 @@ impl TexturePack {
     ///
     // TODO: example
     pub fn install<P: AsRef<Path>>(&self, spec: &Spec, repo_dir: P) -> Result<(), Error> {
-        let target_path = repo_dir
-            .as_ref()
-            .join("build")
-            .join(format!("{}_pc", spec.rom.region.to_string()))
-            .join("res");
-        //.join("gfx")
-        // {repo_dir}/build/{region}_pc/res/gfx
-
-        let pack_path = &self.path.join("gfx");
-
-        if !pack_path.exists() {
-            let inner_err = io::Error::new(
-                io::ErrorKind::NotFound,
-                "could not find the gfx directory in the texture pack path!",
-            );
-
-            let err = err!(c_fs!(inner_err), "invalid texture pack"); // TODO:
-            return Err(err);
-        };
+        self.install_with_progress(spec, repo_dir, InstallMode::default(), &mut |_, _, _| ())
+    }
 
-        fs_extra::dir::copy(pack_path, &target_path, &CopyOptions::new()).unwrap_or_else(|e| {
-            panic!(
-                "failed to copy the texture pack from {} to {}: {}",
-                &pack_path.display(),
-                &target_path.display(),
-                e
-            )
-        });
+    /// Installs the texture pack, like `install`,
+    /// with the given `InstallMode`, calling
+    /// `progress` after every file with the files
+    /// copied so far, the total number of files,
+    /// and the bytes copied so far.
+    pub fn install_with_progress<P: AsRef<Path>>(
+        &self,
+        spec: &Spec,
+        repo_dir: P,
+        mode: InstallMode,
+        progress: &mut dyn FnMut(usize, usize, u64),
+    ) -> Result<(), Error> {
+        // {repo_dir}/build/{region}_pc/res/gfx
+        let build_dir = spec.build_dir(repo_dir);
+        let target_path = build_dir.join("res").join("gfx");
 
-        Ok(())
+        Pack::validate(self)?;
+        pack::install_tracked(
+            self,
+            &self.path.join("gfx"),
+            &target_path,
+            &build_dir,
+            mode,
+            progress,
+        )
     }
 
     /// Permanently removes the texture
     /// pack from disk, effectively
     /// uninstalling it.
+    ///
+    /// Like `Pack::uninstall`, but panics
+    /// if the pack cannot be removed.
     pub fn remove<P: AsRef<Path>>(&self, spec: &Spec, repo_dir: P) {
-        let target_path = repo_dir
-            .as_ref()
-            .join("build")
-            .join(format!("{}_pc", spec.rom.region.to_string()))
-            .join("res")
-            .join("gfx");
-
-        fs_extra::dir::remove(target_path)
-            .unwrap_or_else(|e| panic!("could not find the texture pack to remove: {}", e));
+        Pack::uninstall(self, &spec.build_dir(repo_dir))
+            .unwrap_or_else(|e| panic!("could not remove the texture pack: {}", e));
     }
 }
 /*
@@ -0,0 +1,418 @@
+use crate::prelude::builder_types::{BuilderResult, InstallMode, PostBuildStage};
+use crate::prelude::error_macros::*;
+use crate::prelude::{DynosPack, Error, ErrorCause, Lockfile, Spec, TexturePack};
+use crate::util;
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The name of the directory, in the build
+/// directory, that the manifests of the
+/// installed packs are kept in.
+pub const PACK_MANIFEST_DIR: &str = "smbuilder-packs";
+
+/// Something that is installed into a build
+/// after it is compiled, like a texture pack
+/// or a DynOS pack.
+///
+/// The post-build phase installs every pack
+/// of a spec (from `Spec::packs`) through
+/// this trait, so a new kind of pack only
+/// has to implement it.
+pub trait Pack: Send + Sync {
+    /// The name of the pack,
+    /// for use with launchers.
+    fn name(&self) -> &str;
+
+    /// Where the pack is on disk.
+    fn source(&self) -> &Path;
+
+    /// The post-build stage that
+    /// installs the pack.
+    fn stage(&self) -> PostBuildStage;
+
+    /// Whether the port that `spec`
+    /// builds can use the pack at all.
+    fn is_supported(&self, _spec: &Spec) -> bool {
+        true
+    }
+
+    /// Checks that the pack is laid out
+    /// like a pack of its kind, before
+    /// anything is installed.
+    fn validate(&self) -> BuilderResult<()>;
+
+    /// Installs the pack into the build of
+    /// `spec` in `repo_dir`, calling `progress`
+    /// after every file like in
+    /// `util::copy_dir_into`.
+    ///
+    /// The files that are installed are
+    /// recorded in a `PackManifest`, for
+    /// `uninstall`.
+    fn install(
+        &self,
+        spec: &Spec,
+        repo_dir: &Path,
+        mode: InstallMode,
+        progress: &mut dyn FnMut(usize, usize, u64),
+    ) -> BuilderResult<()>;
+
+    /// Removes the pack from the build in
+    /// `build_dir` (see `Build::build_dir`),
+    /// putting back the files of the build
+    /// that it replaced.
+    ///
+    /// Returns the manifest of what was
+    /// removed. Fails if the pack is not
+    /// installed into the build.
+    fn uninstall(&self, build_dir: &Path) -> BuilderResult<PackManifest> {
+        let Some(manifest) = PackManifest::load(build_dir, self)? else {
+            let e = io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not installed into this build", self.name()),
+            );
+            return Err(err!(c_other!(e), "cannot uninstall the pack"));
+        };
+
+        manifest.remove_files(build_dir, &manifest_key(self))?;
+        Ok(manifest)
+    }
+
+    /// The size of the pack on disk, in bytes.
+    fn size(&self) -> u64 {
+        util::dir_size(self.source())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// The files that a pack installed
+/// into a build, so that it can be
+/// uninstalled again.
+///
+/// Kept in `PACK_MANIFEST_DIR`, in the
+/// build directory.
+pub struct PackManifest {
+    /// The name of the pack.
+    pub name: String,
+    /// The files that the pack installed,
+    /// relative to the build directory.
+    pub files: Vec<PathBuf>,
+    /// The files of the build that the
+    /// pack replaced, which are kept to be
+    /// put back when it is uninstalled.
+    pub replaced: Vec<PathBuf>,
+}
+
+/// Gets the name that the manifest
+/// of a pack is kept under.
+fn manifest_key(pack: &(impl Pack + ?Sized)) -> String {
+    let kind = match pack.stage() {
+        PostBuildStage::TexturePack => "texture",
+        PostBuildStage::DynOSPacks => "dynos",
+        _ => "pack",
+    };
+
+    let name: String = pack
+        .name()
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+
+    format!("{}-{}", kind, name)
+}
+
+impl PackManifest {
+    /// Load the manifest of `pack` from the
+    /// build in `build_dir`, if it is installed.
+    pub fn load(build_dir: &Path, pack: &(impl Pack + ?Sized)) -> BuilderResult<Option<Self>> {
+        let path = manifest_path(build_dir, &manifest_key(pack));
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                let msg = format!("failed to read {}", path.display());
+                return Err(err!(c_fs!(e, msg), "whilst loading a pack manifest"));
+            }
+        };
+
+        match serde_yaml::from_str::<PackManifest>(&contents) {
+            Ok(m) => Ok(Some(m)),
+            Err(e) => Err(err!(c_other!(e), "failed to parse a pack manifest")),
+        }
+    }
+
+    fn save(&self, build_dir: &Path, key: &str) -> BuilderResult<()> {
+        let path = manifest_path(build_dir, key);
+
+        let contents = match serde_yaml::to_string(self) {
+            Ok(s) => s,
+            Err(e) => return Err(err!(c_other!(e), "failed to serialize a pack manifest")),
+        };
+
+        let result = fs::create_dir_all(build_dir.join(PACK_MANIFEST_DIR))
+            .and_then(|_| util::write_atomically(&path, contents));
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let msg = format!("failed to write {}", path.display());
+                Err(err!(c_fs!(e, msg), "whilst saving a pack manifest"))
+            }
+        }
+    }
+
+    /// Remove the installed files, put the
+    /// replaced ones back, and forget about
+    /// the pack.
+    fn remove_files(&self, build_dir: &Path, key: &str) -> BuilderResult<()> {
+        let fs_err = |e: io::Error, path: &Path| {
+            let msg = format!("failed to remove {}", path.display());
+            err!(c_fs!(e, msg), format!("whilst uninstalling {}", self.name))
+        };
+
+        for file in &self.files {
+            let path = build_dir.join(file);
+
+            match fs::remove_file(&path) {
+                Ok(_) => (),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => return Err(fs_err(e, &path)),
+            }
+
+            // directories that only the pack had
+            // go with it. this stops at the first
+            // directory that is not empty.
+            for dir in path.ancestors().skip(1) {
+                if dir == build_dir || fs::remove_dir(dir).is_err() {
+                    break;
+                }
+            }
+        }
+
+        let backup_dir = backup_dir(build_dir, key);
+        for file in &self.replaced {
+            let (backup, path) = (backup_dir.join(file), build_dir.join(file));
+
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|e| fs_err(e, parent))?;
+            }
+            fs::rename(&backup, &path).map_err(|e| fs_err(e, &backup))?;
+        }
+
+        match fs::remove_dir_all(&backup_dir) {
+            Ok(_) => (),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(fs_err(e, &backup_dir)),
+        }
+
+        let path = manifest_path(build_dir, key);
+        fs::remove_file(&path).map_err(|e| fs_err(e, &path))
+    }
+}
+
+fn manifest_path(build_dir: &Path, key: &str) -> PathBuf {
+    build_dir
+        .join(PACK_MANIFEST_DIR)
+        .join(format!("{}.yaml", key))
+}
+
+fn backup_dir(build_dir: &Path, key: &str) -> PathBuf {
+    build_dir.join(PACK_MANIFEST_DIR).join(key)
+}
+
+/// Install the files under `src` into `dest`
+/// (in the build in `build_dir`), moving any
+/// file that is replaced aside, and record
+/// them in the manifest of `pack`.
+///
+/// An earlier install of the pack is
+/// uninstalled first.
+pub(crate) fn install_tracked(
+    pack: &(impl Pack + ?Sized),
+    src: &Path,
+    dest: &Path,
+    build_dir: &Path,
+    mode: InstallMode,
+    progress: &mut dyn FnMut(usize, usize, u64),
+) -> BuilderResult<()> {
+    let key = manifest_key(pack);
+
+    if let Some(manifest) = PackManifest::load(build_dir, pack)? {
+        manifest.remove_files(build_dir, &key)?;
+    }
+
+    let fs_err = |e: io::Error, path: &Path| {
+        let msg = format!("failed to install {}", path.display());
+        err!(c_fs!(e, msg), format!("whilst installing {}", pack.name()))
+    };
+
+    let mut files = Vec::new();
+    util::list_files(src, Path::new(""), &mut files).map_err(|e| fs_err(e, src))?;
+
+    let mut manifest = PackManifest {
+        name: pack.name().to_owned(),
+        ..Default::default()
+    };
+    let backup_dir = backup_dir(build_dir, &key);
+
+    let total = files.len();
+    let mut bytes = 0;
+
+    let install_one = |relative: &Path, manifest: &mut PackManifest| -> io::Result<u64> {
+        let target = dest.join(relative);
+        let in_build = target.strip_prefix(build_dir).unwrap_or(&target).to_owned();
+
+        if target.symlink_metadata().is_ok() {
+            let backup = backup_dir.join(&in_build);
+            if let Some(parent) = backup.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(&target, &backup)?;
+            manifest.replaced.push(in_build.clone());
+        } else if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        manifest.files.push(in_build);
+        util::install_file(&src.join(relative), &target, mode)
+    };
+
+    for (done, relative) in files.iter().enumerate() {
+        match install_one(relative, &mut manifest) {
+            Ok(size) => bytes += size,
+            Err(e) => {
+                // what was installed so far
+                // can still be uninstalled.
+                manifest.save(build_dir, &key)?;
+                return Err(fs_err(e, &dest.join(relative)));
+            }
+        }
+
+        progress(done + 1, total, bytes);
+    }
+
+    manifest.save(build_dir, &key)
+}
+
+/// Uninstall the pack of `spec` named `name` from
+/// its build in `base_dir`, and forget its files
+/// in the lockfile, so that they are not reported
+/// as missing when the build is verified.
+pub(crate) fn uninstall_from_build(
+    spec: &Spec,
+    base_dir: &Path,
+    name: &str,
+) -> BuilderResult<PackManifest> {
+    let Some(pack) = spec.packs().into_iter().find(|p| p.name() == name) else {
+        let e = io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("the spec has no pack named {}", name),
+        );
+        return Err(err!(c_other!(e), "cannot uninstall the pack"));
+    };
+
+    let build_dir = spec.build_dir(base_dir.join(&spec.repo.name));
+    let manifest = pack.uninstall(&build_dir)?;
+
+    let in_base_dir = build_dir.strip_prefix(base_dir).unwrap_or(&build_dir);
+    let files: Vec<PathBuf> = manifest.files.iter().map(|f| in_base_dir.join(f)).collect();
+
+    let mut lockfile = Lockfile::load(base_dir)?;
+    lockfile.forget_files(&files);
+    lockfile.save(base_dir)?;
+
+    Ok(manifest)
+}
+
+impl Pack for TexturePack {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn source(&self) -> &Path {
+        &self.path
+    }
+
+    fn stage(&self) -> PostBuildStage {
+        PostBuildStage::TexturePack
+    }
+
+    fn validate(&self) -> BuilderResult<()> {
+        if self.path.join("gfx").is_dir() {
+            return Ok(());
+        }
+
+        let inner_err = io::Error::new(
+            io::ErrorKind::NotFound,
+            "could not find the gfx directory in the texture pack path!",
+        );
+        Err(err!(c_fs!(inner_err), "invalid texture pack"))
+    }
+
+    fn install(
+        &self,
+        spec: &Spec,
+        repo_dir: &Path,
+        mode: InstallMode,
+        progress: &mut dyn FnMut(usize, usize, u64),
+    ) -> BuilderResult<()> {
+        self.install_with_progress(spec, repo_dir, mode, progress)
+    }
+}
+
+impl Pack for DynosPack {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn source(&self) -> &Path {
+        &self.path
+    }
+
+    fn stage(&self) -> PostBuildStage {
+        PostBuildStage::DynOSPacks
+    }
+
+    fn is_supported(&self, spec: &Spec) -> bool {
+        spec.repo.has_dynos()
+    }
+
+    fn validate(&self) -> BuilderResult<()> {
+        if self.path.is_dir() {
+            return Ok(());
+        }
+
+        let inner_err = io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not a directory", self.path.display()),
+        );
+        Err(err!(c_fs!(inner_err), "invalid DynOS pack"))
+    }
+
+    fn install(
+        &self,
+        spec: &Spec,
+        repo_dir: &Path,
+        mode: InstallMode,
+        progress: &mut dyn FnMut(usize, usize, u64),
+    ) -> BuilderResult<()> {
+        let dest =
+            DynosPack::install_dir(spec, repo_dir).join(self.path.file_name().unwrap_or_default());
+
+        install_tracked(
+            self,
+            &self.path,
+            &dest,
+            &spec.build_dir(repo_dir),
+            mode,
+            progress,
+        )
+    }
+}
@@ -1,7 +1,18 @@
+use crate::error::ErrorCause;
+use crate::prelude::builder_types::{BuilderResult, InstallMode};
+use crate::prelude::error_macros::*;
 use crate::prelude::*;
-use std::{fs, os::unix::prelude::PermissionsExt, path::Path};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 
 #[macro_export]
+/// Unwrap a `Result`, or return
+/// early with the given error.
 macro_rules! try_or_return {
     ($result:expr, $or:expr) => {
         match $result {
@@ -27,26 +38,414 @@ pub fn get_makeopts_string(makeopts: &[Makeopt]) -> String {
 
 /// Make a file executable.
 /// Equivalent to `chmod +x`.
-pub fn make_file_executable(path: &Path) {
-    let file_metadata = fs::metadata(path).unwrap_or_else(|e| {
-        panic!(
-            "failed to get the metadata of the file at {}: {}",
-            &path.display(),
-            e
-        )
+///
+/// Does nothing on platforms without
+/// an executable bit, like Windows.
+#[cfg(unix)]
+pub fn make_file_executable(path: &Path) -> BuilderResult<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let file_metadata = match fs::metadata(path) {
+        Ok(m) => m,
+        Err(e) => {
+            let msg = format!("failed to get the metadata of {}", path.display());
+            return Err(err!(c_fs!(e, msg), "whilst making a file executable"));
+        }
+    };
+
+    let mode = file_metadata.permissions().mode();
+    if mode & 0o111 == 0o111 {
+        return Ok(());
+    }
+
+    match fs::set_permissions(path, fs::Permissions::from_mode(mode | 0o111)) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let msg = format!("failed to set the permissions of {}", path.display());
+            Err(err!(c_fs!(e, msg), "whilst making a file executable"))
+        }
+    }
+}
+
+/// Make a file executable.
+/// Equivalent to `chmod +x`.
+///
+/// Does nothing on platforms without
+/// an executable bit, like Windows.
+#[cfg(not(unix))]
+pub fn make_file_executable(_path: &Path) -> BuilderResult<()> {
+    Ok(())
+}
+
+/// Whether a path has an extension,
+/// ignoring case.
+pub fn has_extension(path: &Path, ext: &str) -> bool {
+    path.extension()
+        .map(|e| e.eq_ignore_ascii_case(ext))
+        .unwrap_or(false)
+}
+
+/// Whether a file looks like a program:
+/// it has the executable bit set (and is not
+/// a shared library), or is an `.exe`.
+pub fn is_program(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    if cfg!(windows) || name.ends_with(".exe") {
+        return name.ends_with(".exe");
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let executable = fs::metadata(path)
+            .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false);
+        let library = name.ends_with(".so") || name.contains(".so.") || name.ends_with(".dylib");
+
+        executable && !library && !name.ends_with(".sh")
+    }
+
+    #[cfg(not(unix))]
+    false
+}
+
+/// Write a file through a temporary file next
+/// to it, which is renamed over it once it is
+/// complete, so that an interrupted write never
+/// leaves half of a file (like a truncated
+/// shell script) behind.
+pub fn write_atomically<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+    let path = path.as_ref();
+
+    let mut temp_name = OsString::from(".");
+    temp_name.push(path.file_name().unwrap_or_default());
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = fs::File::create(&temp_path).and_then(|mut file| {
+        file.write_all(contents.as_ref())?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&temp_path, path)
     });
 
-    fs::set_permissions(
-        path,
-        fs::Permissions::from_mode(
-            file_metadata.permissions().mode() + 0o111, // equivalent of a chmod +x.
-        ),
-    )
-    .unwrap_or_else(|e| {
-        panic!(
-            "failed to set permissions on the file at {}: {}",
-            &path.display(),
-            e
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+/// Check that files can be created in a
+/// directory, by creating (and removing)
+/// an empty one.
+pub fn check_dir_writable<P: AsRef<Path>>(dir: P) -> io::Result<()> {
+    let probe = dir
+        .as_ref()
+        .join(format!(".smbuilder-write-test-{}", std::process::id()));
+
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    fs::remove_file(&probe)
+}
+
+/// Make `link` point to `target`, replacing
+/// whatever is at `link` already.
+///
+/// Creates a symlink on unix, and falls
+/// back to copying the file on platforms
+/// without (unprivileged) symlinks.
+pub fn link_or_copy(target: &Path, link: &Path) -> io::Result<()> {
+    if link.symlink_metadata().is_ok() {
+        fs::remove_file(link)?;
+    }
+
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(target, link)?;
+
+    #[cfg(not(unix))]
+    fs::copy(target, link).map(|_| ())?;
+
+    Ok(())
+}
+
+/// Copy the directory `src` into `dest_dir`
+/// (as `dest_dir/<name of src>`), merging it
+/// with whatever is already there.
+///
+/// `progress` is called after every file,
+/// with the number of files copied so far,
+/// the total number of files, and the number
+/// of bytes copied so far.
+///
+/// Files are reflinked or hard linked
+/// instead of copied, depending on `mode`.
+pub fn copy_dir_into(
+    src: &Path,
+    dest_dir: &Path,
+    mode: InstallMode,
+    progress: &mut dyn FnMut(usize, usize, u64),
+) -> io::Result<()> {
+    let name = src.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} has no file name", src.display()),
         )
-    });
+    })?;
+
+    let mut files = Vec::new();
+    list_files(src, Path::new(""), &mut files)?;
+
+    let dest = dest_dir.join(name);
+    fs::create_dir_all(&dest)?;
+
+    let total = files.len();
+    let mut bytes = 0;
+
+    for (done, relative) in files.iter().enumerate() {
+        let target = dest.join(relative);
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        bytes += install_file(&src.join(relative), &target, mode)?;
+        progress(done + 1, total, bytes);
+    }
+
+    Ok(())
+}
+
+/// Install a single file, replacing whatever
+/// is at `dest`, and return its size.
+pub fn install_file(src: &Path, dest: &Path, mode: InstallMode) -> io::Result<u64> {
+    if mode == InstallMode::Copy {
+        return fs::copy(src, dest);
+    }
+
+    // a link cannot replace an existing
+    // file, unlike a copy.
+    if dest.symlink_metadata().is_ok() {
+        fs::remove_file(dest)?;
+    }
+
+    if reflink(src, dest).is_ok() {
+        return fs::metadata(dest).map(|m| m.len());
+    }
+
+    if mode == InstallMode::HardLink && fs::hard_link(src, dest).is_ok() {
+        return fs::metadata(dest).map(|m| m.len());
+    }
+
+    fs::copy(src, dest)
+}
+
+/// Clone `src` to `dest` with the FICLONE
+/// ioctl, which shares the file's extents.
+#[cfg(target_os = "linux")]
+fn reflink(src: &Path, dest: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let src_file = fs::File::open(src)?;
+    let dest_file = fs::File::create(dest)?;
+
+    // SAFETY: both descriptors are open
+    // for as long as the call runs.
+    let result = unsafe { libc::ioctl(dest_file.as_raw_fd(), libc::FICLONE, src_file.as_raw_fd()) };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        let e = io::Error::last_os_error();
+        drop(dest_file);
+        let _ = fs::remove_file(dest);
+        Err(e)
+    }
+}
+
+/// Clone `src` to `dest` with `clonefile`.
+#[cfg(target_os = "macos")]
+fn reflink(src: &Path, dest: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let to_c = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    };
+    let (src, dest) = (to_c(src)?, to_c(dest)?);
+
+    // SAFETY: both strings are valid and
+    // NUL-terminated for the whole call.
+    match unsafe { libc::clonefile(src.as_ptr(), dest.as_ptr(), 0) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn reflink(_src: &Path, _dest: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflinks are not supported on this platform",
+    ))
+}
+
+/// List the files under `root`, relative
+/// to it, recursively.
+pub(crate) fn list_files(root: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(root.join(relative))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            list_files(root, &path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the SHA-256 hash of some
+/// bytes, as a lowercase hex string.
+pub fn sha256_hex<B: AsRef<[u8]>>(bytes: B) -> String {
+    format!("{:x}", Sha256::digest(bytes.as_ref()))
+}
+
+/// Get the SHA-256 hash of a file, as
+/// a lowercase hex string, without
+/// reading all of it into memory.
+pub fn sha256_file_hex<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    sha256_read_hex(fs::File::open(path)?)
+}
+
+/// Get the SHA-256 hash of everything
+/// that `reader` reads, as a lowercase
+/// hex string.
+pub fn sha256_read_hex<R: Read>(mut reader: R) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Get the commit that a repo has checked
+/// out, and the time it was committed at
+/// (in seconds since the epoch).
+pub fn head_commit(repo_path: &Path) -> Option<(String, i64)> {
+    let repo = git2::Repository::open(repo_path).ok()?;
+    let commit = repo.head().ok()?.peel_to_commit().ok()?;
+
+    Some((commit.id().to_string(), commit.time().seconds()))
+}
+
+/// Get the SHA-1 hash of a file, as
+/// a lowercase hex string, without
+/// reading all of it into memory.
+pub fn sha1_file_hex<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    sha1_read_hex(fs::File::open(path)?)
+}
+
+/// Get the SHA-1 hash of everything
+/// that `reader` reads, as a lowercase
+/// hex string.
+pub fn sha1_read_hex<R: Read>(mut reader: R) -> io::Result<String> {
+    let mut hasher = Sha1::new();
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Get the total size of a file, or of all
+/// the files in a directory, recursively.
+///
+/// Unreadable entries are skipped.
+pub fn dir_size<P: AsRef<Path>>(path: P) -> u64 {
+    let path = path.as_ref();
+
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(_) => return 0,
+    };
+
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+
+    match fs::read_dir(path) {
+        Ok(entries) => entries.flatten().map(|e| dir_size(e.path())).sum(),
+        Err(_) => 0,
+    }
+}
+
+/// Strip the ANSI escape sequences (colors,
+/// cursor movement, window titles...) from
+/// a line of output.
+pub fn strip_ansi(line: &str) -> Cow<'_, str> {
+    if !line.contains('\x1b') {
+        return Cow::Borrowed(line);
+    }
+
+    let mut stripped = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            // CSI: parameters, then a final
+            // byte from `@` to `~`.
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC: ended by BEL or ST (`ESC \`).
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // nF escapes (like charset selection):
+            // intermediate bytes, then a final byte.
+            Some(' '..='/') => {
+                for c in chars.by_ref() {
+                    if !(' '..='/').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // anything else is a two
+            // character sequence.
+            _ => (),
+        }
+    }
+
+    Cow::Owned(stripped)
 }
@@ -0,0 +1,284 @@
+use crate::builder::state::{BuildPhase, SavedProgress};
+use crate::error::ErrorCause;
+use crate::lockfile::Lockfile;
+use crate::manage::{list_builds, SPEC_FILE_NAME};
+use crate::prelude::builder_types::BuilderResult;
+use crate::prelude::error_macros::*;
+use crate::prelude::{Error, Spec};
+use crate::util;
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The name of the index file that lives
+/// in the root of a workspace.
+pub const WORKSPACE_INDEX_NAME: &str = "workspace.yaml";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// Where a build in a workspace is at.
+pub enum BuildStatus {
+    /// It has never been built.
+    #[default]
+    NotBuilt,
+    /// The last build of it succeeded.
+    Built,
+    /// The last build of it failed.
+    Failed,
+    /// The last build of it stopped partway
+    /// through, such as when the launcher
+    /// was closed.
+    Interrupted,
+}
+
+impl BuildStatus {
+    /// Work out the status of the
+    /// build in a base dir.
+    pub fn of<P: AsRef<Path>>(base_dir: P) -> BuildStatus {
+        let base_dir = base_dir.as_ref();
+
+        match SavedProgress::load(base_dir) {
+            Some(progress) if progress.phase == BuildPhase::Failed => BuildStatus::Failed,
+            Some(progress) if progress.interrupted() => BuildStatus::Interrupted,
+            _ => {
+                let built = Lockfile::load(base_dir)
+                    .map(|l| l.spec_hashes.is_some() || l.prebuilt.is_some())
+                    .unwrap_or(false);
+
+                if built {
+                    BuildStatus::Built
+                } else {
+                    BuildStatus::NotBuilt
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A build in a workspace.
+pub struct WorkspaceEntry {
+    /// The name that the build
+    /// is listed under.
+    pub name: String,
+    /// The path of the spec file, relative
+    /// to the root of the workspace if it
+    /// is inside of it.
+    pub spec_path: PathBuf,
+    /// The status of the build, as of
+    /// the last `refresh`.
+    #[serde(default)]
+    pub status: BuildStatus,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct Index {
+    builds: Vec<WorkspaceEntry>,
+}
+
+#[derive(Clone, Debug)]
+/// A directory of many builds, with an index
+/// file (`workspace.yaml`) listing them by name,
+/// for CLIs and launchers to list them from
+/// without loading every spec.
+///
+/// Changes are only written to the index
+/// with `save`.
+pub struct Workspace {
+    root: PathBuf,
+    index: Index,
+}
+
+impl Workspace {
+    /// Open the workspace in `root`.
+    ///
+    /// If it has no index yet, one is made
+    /// (and saved) from the builds that are
+    /// already in it, like `list_builds`.
+    pub fn open<P: Into<PathBuf>>(root: P) -> BuilderResult<Workspace> {
+        let root = root.into();
+        let index_path = root.join(WORKSPACE_INDEX_NAME);
+
+        if !index_path.exists() {
+            let mut workspace = Workspace {
+                index: Index::default(),
+                root,
+            };
+
+            for build in list_builds(&workspace.root)? {
+                let spec_path = build.base_dir.join(SPEC_FILE_NAME);
+                workspace.insert(build.name(), &spec_path);
+            }
+
+            workspace.save()?;
+            return Ok(workspace);
+        }
+
+        let contents = match fs::read_to_string(&index_path) {
+            Ok(s) => s,
+            Err(e) => {
+                let msg = format!("failed to read {}", index_path.display());
+                return Err(err!(c_fs!(e, msg), "whilst opening the workspace"));
+            }
+        };
+
+        match serde_yaml::from_str::<Index>(&contents) {
+            Ok(index) => Ok(Workspace { root, index }),
+            Err(e) => Err(err!(c_other!(e), "failed to parse the workspace index")),
+        }
+    }
+
+    /// Gets the root directory
+    /// of the workspace.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Gets the builds in the
+    /// workspace, sorted by name.
+    pub fn entries(&self) -> &[WorkspaceEntry] {
+        &self.index.builds
+    }
+
+    /// Gets a build by its name.
+    pub fn get(&self, name: &str) -> Option<&WorkspaceEntry> {
+        self.index.builds.iter().find(|entry| entry.name == name)
+    }
+
+    /// Gets the absolute path of the
+    /// spec file of a build.
+    pub fn spec_path(&self, entry: &WorkspaceEntry) -> PathBuf {
+        self.root.join(&entry.spec_path)
+    }
+
+    /// Gets the base directory of a build:
+    /// the directory of its spec file.
+    pub fn base_dir(&self, entry: &WorkspaceEntry) -> PathBuf {
+        let spec_path = self.spec_path(entry);
+        spec_path
+            .parent()
+            .map(Path::to_owned)
+            .unwrap_or(self.root.clone())
+    }
+
+    /// Add a build to the workspace, by the path
+    /// of its spec file, under `name`.
+    ///
+    /// Fails if the spec cannot be loaded,
+    /// or the name is already taken.
+    pub fn add<S: ToString, P: AsRef<Path>>(&mut self, name: S, spec_path: P) -> BuilderResult<()> {
+        let name = name.to_string();
+        let spec_path = spec_path.as_ref();
+
+        self.check_name_free(&name)?;
+        Spec::from_file(spec_path)?;
+
+        self.insert(name, spec_path);
+        Ok(())
+    }
+
+    /// Remove a build from the workspace,
+    /// returning it if it was there.
+    ///
+    /// Its files are left alone.
+    pub fn remove(&mut self, name: &str) -> Option<WorkspaceEntry> {
+        let i = self.index.builds.iter().position(|e| e.name == name)?;
+        Some(self.index.builds.remove(i))
+    }
+
+    /// List a build under another name.
+    ///
+    /// Fails if there is no build called
+    /// `old`, or `new` is already taken.
+    pub fn rename(&mut self, old: &str, new: &str) -> BuilderResult<()> {
+        if old == new {
+            return Ok(());
+        }
+
+        self.check_name_free(new)?;
+
+        match self.index.builds.iter_mut().find(|e| e.name == old) {
+            Some(entry) => entry.name = new.to_owned(),
+            None => {
+                let e = io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("there is no build called {}", old),
+                );
+                return Err(err!(c_other!(e), "failed to rename the build"));
+            }
+        }
+
+        self.sort();
+        Ok(())
+    }
+
+    /// Update the status of every build.
+    pub fn refresh(&mut self) {
+        let statuses: Vec<BuildStatus> = self
+            .index
+            .builds
+            .iter()
+            .map(|entry| BuildStatus::of(self.base_dir(entry)))
+            .collect();
+
+        for (entry, status) in self.index.builds.iter_mut().zip(statuses) {
+            entry.status = status;
+        }
+    }
+
+    /// Write the index into the
+    /// root of the workspace.
+    pub fn save(&self) -> BuilderResult<()> {
+        let path = self.root.join(WORKSPACE_INDEX_NAME);
+
+        let contents = match serde_yaml::to_string(&self.index) {
+            Ok(s) => s,
+            Err(e) => return Err(err!(c_other!(e), "failed to serialize the workspace index")),
+        };
+
+        match util::write_atomically(&path, contents) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let msg = format!("failed to write {}", path.display());
+                Err(err!(c_fs!(e, msg), "whilst saving the workspace"))
+            }
+        }
+    }
+
+    fn insert(&mut self, name: String, spec_path: &Path) {
+        let spec_path = spec_path
+            .strip_prefix(&self.root)
+            .unwrap_or(spec_path)
+            .to_owned();
+
+        let mut entry = WorkspaceEntry {
+            name,
+            spec_path,
+            status: BuildStatus::NotBuilt,
+        };
+        entry.status = BuildStatus::of(self.base_dir(&entry));
+
+        self.index.builds.push(entry);
+        self.sort();
+    }
+
+    fn sort(&mut self) {
+        self.index
+            .builds
+            .sort_by_key(|entry| entry.name.to_lowercase());
+    }
+
+    fn check_name_free(&self, name: &str) -> BuilderResult<()> {
+        if self.get(name).is_none() {
+            return Ok(());
+        }
+
+        let e = io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("there is already a build called {}", name),
+        );
+        Err(err!(c_other!(e), "the name is taken"))
+    }
+}